@@ -0,0 +1,234 @@
+use core::{
+    alloc::{Allocator, Layout},
+    cell::Cell,
+    marker::PhantomData,
+    ptr::{self, NonNull},
+    slice,
+};
+
+use super::bump_alloc::BumpAlloc;
+
+/// Allocates values of a single type `T` from a [`BumpAlloc`], handing out references that live
+/// as long as the arena. Since every allocation is a `T`, the values lie right after each other,
+/// and the arena runs all their destructors when it is reset or dropped.
+///
+/// ```ignore
+/// let arena: Arena<Node, RawSpinlock> = unsafe { Arena::new(start, end) };
+/// let root = arena.alloc(Node::new());
+/// let children = arena.alloc_iter((0..4).map(|_| Node::new()));
+/// ```
+///
+/// The arena isn't `Sync`, like a `RefCell`, so it can only be allocated from on one thread.
+pub struct Arena<T, R: lock_api::RawMutex> {
+    bump: BumpAlloc<R>,
+    // The first value, and the number of values after it, all of them initialized
+    first: Cell<NonNull<T>>,
+    len: Cell<usize>,
+    // Set while `alloc_iter` is pulling values from its iterator
+    filling: Cell<bool>,
+    values: PhantomData<T>,
+}
+
+impl<T, R: lock_api::RawMutex> Arena<T, R> {
+    /// Creates an arena allocating from the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the arena.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        Self::with_bump(BumpAlloc::new(start, end))
+    }
+
+    /// Creates an arena allocating from `bump`, which must not have any allocations yet.
+    pub fn with_bump(bump: BumpAlloc<R>) -> Self {
+        debug_assert_eq!(
+            bump.used_bytes(),
+            0,
+            "The bump allocator is already in use!"
+        );
+        Arena {
+            bump,
+            first: Cell::new(NonNull::dangling()),
+            len: Cell::new(0),
+            filling: Cell::new(false),
+            values: PhantomData,
+        }
+    }
+
+    /// Moves `value` into the arena.
+    ///
+    /// # Panics
+    /// Panics if the arena is full, or if called from the iterator of
+    /// [`alloc_iter`](Arena::alloc_iter).
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc(&self, value: T) -> &mut T {
+        match self.try_alloc(value) {
+            Ok(value) => value,
+            Err(_) => panic!("The arena is full!"),
+        }
+    }
+
+    /// Moves `value` into the arena, or hands it back if the arena is full.
+    ///
+    /// # Panics
+    /// Panics if called from the iterator of [`alloc_iter`](Arena::alloc_iter).
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc(&self, value: T) -> Result<&mut T, T> {
+        assert!(
+            !self.filling.get(),
+            "Can't allocate from an arena while it is filling a slice!"
+        );
+        match self.push(value) {
+            Ok(ptr) => Ok(unsafe { &mut *ptr.as_ptr() }),
+            Err(value) => Err(value),
+        }
+    }
+
+    /// Moves all values of `iter` into the arena, as a single slice.
+    ///
+    /// # Panics
+    /// Panics if the arena runs full, or if the iterator allocates from the arena itself. The
+    /// values taken from the iterator until then stay in the arena.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_iter(&self, iter: impl IntoIterator<Item = T>) -> &mut [T] {
+        assert!(
+            !self.filling.replace(true),
+            "Can't allocate from an arena while it is filling a slice!"
+        );
+        // Clears the flag again, even if the iterator panics
+        struct Filling<'a>(&'a Cell<bool>);
+        impl Drop for Filling<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+        let _filling = Filling(&self.filling);
+
+        let mut start = None;
+        let mut len = 0;
+        for value in iter {
+            let Ok(ptr) = self.push(value) else {
+                panic!("The arena is full!");
+            };
+            start.get_or_insert(ptr);
+            len += 1;
+        }
+
+        match start {
+            Some(start) => unsafe { slice::from_raw_parts_mut(start.as_ptr(), len) },
+            None => &mut [],
+        }
+    }
+
+    /// The number of values in the arena.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    /// Drops all values and frees their memory.
+    pub fn reset(&mut self) {
+        self.drop_values();
+        self.bump.reset();
+    }
+
+    fn push(&self, value: T) -> Result<NonNull<T>, T> {
+        let Ok(ptr) = self.bump.allocate(Layout::new::<T>()) else {
+            return Err(value);
+        };
+        let ptr = ptr.cast::<T>();
+        unsafe { ptr.write(value) };
+        if self.len.get() == 0 {
+            self.first.set(ptr);
+        }
+        self.len.set(self.len.get() + 1);
+        Ok(ptr)
+    }
+
+    fn drop_values(&mut self) {
+        let values = ptr::slice_from_raw_parts_mut(self.first.get().as_ptr(), self.len.get());
+        self.len.set(0);
+        unsafe { ptr::drop_in_place(values) };
+    }
+}
+
+impl<R: lock_api::RawMutex> Arena<u8, R> {
+    /// Copies `string` into the arena.
+    ///
+    /// # Panics
+    /// Panics if the arena runs full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, string: &str) -> &mut str {
+        let bytes = self.alloc_iter(string.bytes());
+        unsafe { core::str::from_utf8_unchecked_mut(bytes) }
+    }
+}
+
+impl<T, R: lock_api::RawMutex> Drop for Arena<T, R> {
+    fn drop(&mut self) {
+        self.drop_values();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{mem::size_of, rc::Rc};
+
+    use super::*;
+
+    const SIZE: usize = 1024;
+
+    #[test]
+    fn arena() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let drops = Rc::new(());
+        let mut arena: Arena<(u64, Rc<()>), parking_lot::RawMutex> =
+            unsafe { Arena::new(mem, mem.add(SIZE)) };
+
+        let a = arena.alloc((1, drops.clone()));
+        let b = arena.alloc((2, drops.clone()));
+        a.0 += 10;
+        assert_eq!((a.0, b.0), (11, 2));
+        let slice = arena.alloc_iter((3..6).map(|i| (i, drops.clone())));
+        assert_eq!(
+            slice.iter().map(|value| value.0).collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        assert!(arena.alloc_iter([]).is_empty());
+        assert_eq!(arena.len(), 5);
+        assert_eq!(Rc::strong_count(&drops), 6);
+
+        // Values are dropped on reset, and the space is reused
+        arena.reset();
+        assert_eq!(Rc::strong_count(&drops), 1);
+        let capacity = SIZE / size_of::<(u64, Rc<()>)>();
+        for i in 0..capacity {
+            arena.alloc((i as u64, drops.clone()));
+        }
+        assert!(arena.try_alloc((0, drops.clone())).is_err());
+        drop(arena);
+        assert_eq!(Rc::strong_count(&drops), 1);
+
+        let strings: Arena<u8, parking_lot::RawMutex> = unsafe { Arena::new(mem, mem.add(SIZE)) };
+        let hello = strings.alloc_str("hello");
+        let world = strings.alloc_str("world");
+        hello.make_ascii_uppercase();
+        assert_eq!((&*hello, &*world), ("HELLO", "world"));
+        drop(strings);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    #[should_panic(expected = "filling a slice")]
+    fn arena_reentrant_iter() {
+        let mut mem = [0u64; 16];
+        let range = mem.as_mut_ptr_range();
+        let arena: Arena<u64, parking_lot::RawMutex> =
+            unsafe { Arena::new(range.start.cast(), range.end.cast()) };
+        arena.alloc_iter((0..4).map(|i| *arena.alloc(i)));
+    }
+}
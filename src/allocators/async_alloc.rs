@@ -0,0 +1,254 @@
+use core::{
+    alloc::{Allocator, Layout},
+    future::poll_fn,
+    ptr::NonNull,
+    task::{Poll, Waker},
+};
+
+/// A registration of a task waiting for memory.
+struct Waiter {
+    // The value of the freed byte counter at which it is worth retrying
+    threshold: usize,
+    waker: Option<Waker>,
+}
+
+struct Waiters<const N: usize> {
+    slots: [Option<Waiter>; N],
+    // Bytes deallocated since creation, wrapping
+    freed: usize,
+}
+
+/// Wraps an allocator to let tasks await memory instead of failing when the heap is exhausted.
+///
+/// A waiting task is woken once enough bytes have been deallocated since its attempt that its
+/// request could plausibly succeed. At most `N` tasks wait at once; any more fall back to being
+/// woken right away, which amounts to polling. Deallocations must go through the wrapper to wake
+/// anybody.
+pub struct AsyncAlloc<A: Allocator, R: lock_api::RawMutex, const N: usize = 8> {
+    inner: A,
+    waiters: lock_api::Mutex<R, Waiters<N>>,
+}
+
+/// Removes the registration of a waiting task if its future is dropped before completing.
+struct Registration<'a, A: Allocator, R: lock_api::RawMutex, const N: usize> {
+    alloc: &'a AsyncAlloc<A, R, N>,
+    slot: Option<usize>,
+}
+
+impl<A: Allocator, R: lock_api::RawMutex, const N: usize> Drop for Registration<'_, A, R, N> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.alloc.waiters.lock().slots[slot] = None;
+        }
+    }
+}
+
+impl<A: Allocator, R: lock_api::RawMutex, const N: usize> AsyncAlloc<A, R, N> {
+    pub const fn new(inner: A) -> Self {
+        AsyncAlloc {
+            inner,
+            waiters: lock_api::Mutex::new(Waiters {
+                slots: [const { None }; N],
+                freed: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Allocates memory for `layout`, waiting for deallocations for as long as the wrapped
+    /// allocator fails. A request that can never be served waits forever.
+    pub async fn allocate(&self, layout: Layout) -> NonNull<[u8]> {
+        let mut registration = Registration {
+            alloc: self,
+            slot: None,
+        };
+
+        poll_fn(|cx| {
+            let freed_before = self.waiters.lock().freed;
+            if let Ok(ptr) = self.inner.allocate(layout) {
+                return Poll::Ready(ptr);
+            }
+
+            let mut waiters = self.waiters.lock();
+            let threshold = freed_before.wrapping_add(layout.size().max(1));
+            // Memory may have been freed while we were trying
+            if waiters.freed.wrapping_sub(freed_before) >= layout.size().max(1) {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let slot = registration
+                .slot
+                .or_else(|| waiters.slots.iter().position(Option::is_none));
+            match slot {
+                Some(slot) => {
+                    waiters.slots[slot] = Some(Waiter {
+                        threshold,
+                        waker: Some(cx.waker().clone()),
+                    });
+                    registration.slot = Some(slot);
+                }
+                // Too many waiters, fall back to polling
+                None => cx.waker().wake_by_ref(),
+            }
+
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Deallocates the memory at `ptr`, waking every waiting task whose request may now succeed.
+    ///
+    /// # Safety
+    /// See [`Allocator::deallocate`].
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.deallocate(ptr, layout);
+
+        let mut woken: [Option<Waker>; N] = [const { None }; N];
+        {
+            let mut waiters = self.waiters.lock();
+            waiters.freed = waiters.freed.wrapping_add(layout.size());
+            let freed = waiters.freed;
+            for (slot, waker) in waiters.slots.iter_mut().zip(&mut woken) {
+                // Thresholds are compared on the wrapping counter, so that it can run indefinitely
+                if let Some(waiter) = slot {
+                    if (freed.wrapping_sub(waiter.threshold) as isize) >= 0 {
+                        *waker = waiter.waker.take();
+                    }
+                }
+            }
+        }
+
+        // Wakers may run arbitrary code, including code using this allocator, so the lock must
+        // not be held while they do
+        for waker in woken.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::{sync::Arc, task::Wake};
+    use core::{
+        future::Future,
+        pin::pin,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        task::Context,
+    };
+
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn async_alloc() {
+        const SIZE: usize = 4096;
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(SIZE, 16);
+        let allocator: AsyncAlloc<_, parking_lot::RawMutex> = AsyncAlloc::new(heap);
+
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let wakes = || counter.0.load(Ordering::Relaxed);
+
+        let half = Layout::from_size_align(SIZE / 2 - 128, 16).unwrap();
+        let first = allocator.inner().allocate(half).unwrap();
+        let second = allocator.inner().allocate(half).unwrap();
+
+        // With the heap full the future waits
+        let mut future = pin!(allocator.allocate(half));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(wakes(), 0);
+
+        // A small deallocation doesn't help
+        let small = Layout::new::<[u8; 16]>();
+        let tiny = allocator.inner().allocate(small).unwrap();
+        unsafe { allocator.deallocate(tiny.cast(), small) };
+        assert_eq!(wakes(), 0);
+
+        // A large one does
+        unsafe { allocator.deallocate(first.cast(), half) };
+        assert_eq!(wakes(), 1);
+        let ptr = match future.as_mut().poll(&mut cx) {
+            Poll::Ready(ptr) => ptr,
+            Poll::Pending => panic!("Allocation should have succeeded"),
+        };
+        assert!(allocator.waiters.lock().slots.iter().all(Option::is_none));
+
+        // Dropping a waiting future removes its registration
+        {
+            let mut future = pin!(allocator.allocate(half));
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            assert!(allocator.waiters.lock().slots.iter().any(Option::is_some));
+        }
+        assert!(allocator.waiters.lock().slots.iter().all(Option::is_none));
+
+        unsafe {
+            allocator.deallocate(ptr.cast(), half);
+            allocator.deallocate(second.cast(), half);
+        }
+    }
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    /// Records whether the waiters of the allocator are locked while it is woken.
+    struct ReentrantWaker {
+        allocator: Arc<AsyncAlloc<Heap, parking_lot::RawMutex>>,
+        woken: AtomicBool,
+        locked: AtomicBool,
+    }
+
+    impl Wake for ReentrantWaker {
+        fn wake(self: Arc<Self>) {
+            self.woken.store(true, Ordering::Relaxed);
+            let locked = self.allocator.waiters.is_locked();
+            self.locked.store(locked, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn async_alloc_wakes_unlocked() {
+        const SIZE: usize = 4096;
+        let allocator = Arc::new(AsyncAlloc::new(Heap::with_capacity(SIZE, 16)));
+        let recorder = Arc::new(ReentrantWaker {
+            allocator: allocator.clone(),
+            woken: AtomicBool::new(false),
+            locked: AtomicBool::new(false),
+        });
+        let waker = Waker::from(recorder.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let half = Layout::from_size_align(SIZE / 2 - 128, 16).unwrap();
+        let first = allocator.inner().allocate(half).unwrap();
+        let second = allocator.inner().allocate(half).unwrap();
+        let mut future = pin!(allocator.allocate(half));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+
+        unsafe { allocator.deallocate(first.cast(), half) };
+        assert!(recorder.woken.load(Ordering::Relaxed));
+        assert!(!recorder.locked.load(Ordering::Relaxed));
+        let ptr = match future.as_mut().poll(&mut cx) {
+            Poll::Ready(ptr) => ptr,
+            Poll::Pending => panic!("Allocation should have succeeded"),
+        };
+
+        unsafe {
+            allocator.deallocate(ptr.cast(), half);
+            allocator.deallocate(second.cast(), half);
+        }
+    }
+}
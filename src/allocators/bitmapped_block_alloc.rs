@@ -0,0 +1,247 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+const BITS: usize = usize::BITS as usize;
+
+/// Tracks which of up to `WORDS` words of bits worth of equally sized blocks are in use.
+pub(crate) struct BlockMap<const WORDS: usize> {
+    base: *mut u8,
+    blocks: usize,
+    // One bit per block, set while the block is in use
+    used: [usize; WORDS],
+}
+
+// SAFETY: The map owns its region exclusively, so it may be moved to another thread
+unsafe impl<const WORDS: usize> Send for BlockMap<WORDS> {}
+
+impl<const WORDS: usize> BlockMap<WORDS> {
+    /// Creates a map of the blocks of `BLOCK` bytes between `start` and `end`, all free. Blocks
+    /// that don't fit the bitmap are left out.
+    pub fn new<const BLOCK: usize>(start: *mut u8, end: *mut u8) -> Self {
+        let blocks = ((end as usize).saturating_sub(start as usize) / BLOCK).min(WORDS * BITS);
+        BlockMap {
+            base: start,
+            blocks,
+            used: [0; WORDS],
+        }
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    pub fn free_blocks(&self) -> usize {
+        let used: u32 = self.used.iter().map(|word| word.count_ones()).sum();
+        self.blocks - used as usize
+    }
+
+    fn is_used(&self, block: usize) -> bool {
+        self.used[block / BITS] & (1 << (block % BITS)) != 0
+    }
+
+    fn set_used(&mut self, blocks: Range<usize>, used: bool) {
+        for block in blocks {
+            if used {
+                self.used[block / BITS] |= 1 << (block % BITS);
+            } else {
+                self.used[block / BITS] &= !(1 << (block % BITS));
+            }
+        }
+    }
+
+    /// Finds the first run of `count` free blocks whose first block is aligned to `align`.
+    fn find_run<const BLOCK: usize>(&self, count: usize, align: usize) -> Option<usize> {
+        let (mut block, mut start, mut run) = (0, 0, 0);
+        while block < self.blocks {
+            // Skip whole words of used blocks at once
+            if block % BITS == 0 && self.used[block / BITS] == usize::MAX {
+                run = 0;
+                block += BITS;
+                continue;
+            }
+
+            if self.is_used(block) {
+                run = 0;
+            } else {
+                if run == 0 {
+                    let ptr = unsafe { self.base.add(block * BLOCK) };
+                    if ptr.align_offset(align) != 0 {
+                        block += 1;
+                        continue;
+                    }
+                    start = block;
+                }
+                run += 1;
+                if run == count {
+                    return Some(start);
+                }
+            }
+            block += 1;
+        }
+        None
+    }
+
+    pub fn allocate<const BLOCK: usize>(&mut self, layout: Layout) -> Option<*mut u8> {
+        let count = layout.size().div_ceil(BLOCK).max(1);
+        let start = self.find_run::<BLOCK>(count, layout.align())?;
+        self.set_used(start..start + count, true);
+        Some(unsafe { self.base.add(start * BLOCK) })
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`BlockMap::allocate`] for the same `layout`.
+    pub unsafe fn deallocate<const BLOCK: usize>(&mut self, ptr: *mut u8, layout: Layout) {
+        let start = (ptr as usize - self.base as usize) / BLOCK;
+        let count = layout.size().div_ceil(BLOCK).max(1);
+        self.set_used(start..start + count, false);
+    }
+}
+
+/// Manages a region as equally sized blocks of `BLOCK` bytes, tracking which are in use in a
+/// bitmap of `WORDS` words that is part of the allocator itself. The region holds nothing but the
+/// blocks handed out, not a single byte of metadata, which makes it suitable for memory that is
+/// visible to devices, such as DMA buffers.
+///
+/// Requests larger than a block are served by the first run of contiguous free blocks that is
+/// long enough, so allocation is linear in the number of blocks. The layout passed to
+/// `deallocate` determines how many blocks are freed.
+pub struct BitmappedBlockAlloc<R: lock_api::RawMutex, const BLOCK: usize, const WORDS: usize> {
+    map: lock_api::Mutex<R, BlockMap<WORDS>>,
+}
+
+impl<R: lock_api::RawMutex, const BLOCK: usize, const WORDS: usize>
+    BitmappedBlockAlloc<R, BLOCK, WORDS>
+{
+    /// Creates an allocator managing the memory between `start` and `end`, as blocks starting at
+    /// `start`. Blocks that don't fit the bitmap, and bytes after the last whole block, are left
+    /// unused.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        const {
+            assert!(BLOCK != 0, "Blocks can't be empty!");
+        }
+
+        BitmappedBlockAlloc {
+            map: lock_api::Mutex::new(BlockMap::new::<BLOCK>(start, end)),
+        }
+    }
+
+    /// The number of blocks managed.
+    pub fn blocks(&self) -> usize {
+        self.map.lock().blocks()
+    }
+
+    /// The number of blocks not in use. Counts the bits of the bitmap.
+    pub fn free_blocks(&self) -> usize {
+        self.map.lock().free_blocks()
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const BLOCK: usize, const WORDS: usize> Allocator
+    for BitmappedBlockAlloc<R, BLOCK, WORDS>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self
+            .map
+            .lock()
+            .allocate::<BLOCK>(layout)
+            .ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size().div_ceil(BLOCK).max(1) * BLOCK,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.map.lock().deallocate::<BLOCK>(ptr.as_ptr(), layout);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const BLOCK: usize, const WORDS: usize> GlobalAlloc
+    for BitmappedBlockAlloc<R, BLOCK, WORDS>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.map
+            .lock()
+            .allocate::<BLOCK>(layout)
+            .unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.map.lock().deallocate::<BLOCK>(ptr, layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 64 * 256;
+
+    #[test]
+    fn bitmapped_block_alloc() {
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let allocator: BitmappedBlockAlloc<parking_lot::RawMutex, 64, 4> =
+            unsafe { BitmappedBlockAlloc::new(mem, mem.add(SIZE)) };
+        assert_eq!(allocator.blocks(), 256);
+
+        // The first allocation starts right at the region, so nothing is kept inside it
+        let one = Layout::new::<[u8; 10]>();
+        let a = allocator.allocate(one).unwrap();
+        assert_eq!(a.cast::<u8>().as_ptr(), mem);
+        assert_eq!(a.len(), 64);
+
+        // Larger requests take a run of blocks, aligned ones skip blocks until one is aligned
+        let three = Layout::new::<[u8; 150]>();
+        let b = allocator.allocate(three).unwrap();
+        assert_eq!(b.len(), 192);
+        let aligned = Layout::from_size_align(64, 1024).unwrap();
+        let c = allocator.allocate(aligned).unwrap();
+        assert_eq!(c.cast::<u8>().as_ptr(), unsafe { mem.add(1024) });
+        assert_eq!(allocator.free_blocks(), 256 - 5);
+
+        // A freed run is found again by a request of the same size
+        unsafe { allocator.deallocate(b.cast(), three) };
+        assert_eq!(allocator.allocate(three).unwrap(), b);
+        assert!(allocator.allocate(Layout::new::<[u8; SIZE]>()).is_err());
+        unsafe {
+            allocator.deallocate(a.cast(), one);
+            allocator.deallocate(b.cast(), three);
+            allocator.deallocate(c.cast(), aligned);
+        }
+        assert_eq!(allocator.free_blocks(), 256);
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..2000 {
+            if rng.gen_bool(0.55) {
+                let align = 1 << rng.gen_range(0..9);
+                let layout = Layout::from_size_align(rng.gen_range(1..512), align).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(align), 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        assert_eq!(allocator.free_blocks(), 256);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
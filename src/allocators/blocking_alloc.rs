@@ -0,0 +1,133 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+    time::Duration,
+};
+use std::{
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+/// Wraps an allocator to let threads wait for memory to be freed instead of failing when the heap
+/// is exhausted. Deallocations must go through the wrapper to wake anybody.
+pub struct BlockingAlloc<A: Allocator> {
+    inner: A,
+    // Counts deallocations, so waiters can tell whether anything was freed since their attempt
+    generation: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl<A: Allocator> BlockingAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        BlockingAlloc {
+            inner,
+            generation: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Allocates memory for `layout`, parking the thread until memory is deallocated for as long as
+    /// the wrapped allocator fails. Gives up once `timeout` has passed, or never if it is too long to
+    /// be represented, like [`Duration::MAX`].
+    pub fn allocate_blocking(
+        &self,
+        layout: Layout,
+        timeout: Duration,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let deadline = Instant::now().checked_add(timeout);
+
+        loop {
+            let generation = *self.generation.lock().unwrap();
+            if let Ok(ptr) = self.inner.allocate(layout) {
+                return Ok(ptr);
+            }
+
+            let guard = self.generation.lock().unwrap();
+            let unchanged = |current: &mut usize| *current == generation;
+            match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    let (guard, result) = self
+                        .freed
+                        .wait_timeout_while(guard, remaining, unchanged)
+                        .unwrap();
+                    drop(guard);
+                    if result.timed_out() {
+                        return Err(AllocError);
+                    }
+                }
+                None => drop(self.freed.wait_while(guard, unchanged).unwrap()),
+            }
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for BlockingAlloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.deallocate(ptr, layout);
+
+        *self.generation.lock().unwrap() += 1;
+        self.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    #[test]
+    fn blocking_alloc() {
+        const SIZE: usize = 4096;
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(SIZE, 16);
+        let allocator = BlockingAlloc::new(heap);
+
+        let half = Layout::from_size_align(SIZE / 2 - 64, 16).unwrap();
+        let first = allocator.allocate(half).unwrap();
+        let second = allocator.allocate(half).unwrap();
+
+        // Nobody frees anything
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(allocator.allocate_blocking(half, timeout).is_err());
+        assert!(start.elapsed() >= timeout);
+
+        // Another thread frees memory while we wait
+        let first_addr = first.cast::<u8>().as_ptr() as usize;
+        let ptr = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                unsafe { allocator.deallocate(NonNull::new(first_addr as *mut u8).unwrap(), half) };
+            });
+            allocator
+                .allocate_blocking(half, Duration::from_secs(10))
+                .unwrap()
+        });
+
+        // A timeout too long for a deadline waits for as long as it takes
+        let ptr_addr = ptr.cast::<u8>().as_ptr() as usize;
+        let ptr = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                unsafe { allocator.deallocate(NonNull::new(ptr_addr as *mut u8).unwrap(), half) };
+            });
+            allocator.allocate_blocking(half, Duration::MAX).unwrap()
+        });
+
+        unsafe {
+            allocator.deallocate(ptr.cast(), half);
+            allocator.deallocate(second.cast(), half);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use crate::memory_segmenter::SegmentMetadata;
+
+struct BootstrapState {
+    start: *mut u8,
+    end: *mut u8,
+    // Everything below has been handed out
+    watermark: *mut u8,
+    retired: bool,
+}
+
+// SAFETY: The state owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for BootstrapState {}
+
+/// A watermark allocator for the time before the real heap exists, e.g. while the memory map is
+/// parsed. Allocations are carved out of the region one after the other, and never freed.
+///
+/// Once the real heap is up, it takes over the whole region with
+/// [`LinkedListAlloc::init_from_bootstrap`](super::linked_list_allocator::LinkedListAlloc::init_from_bootstrap),
+/// keeping everything allocated so far as a reserved segment, so nothing has to be copied:
+///
+/// ```ignore
+/// static BOOT: BootstrapAlloc<RawSpinlock> = unsafe { BootstrapAlloc::new(START, END) };
+/// static HEAP: LinkedListAlloc<RawSpinlock> = LinkedListAlloc::empty();
+///
+/// let regions = parse_memory_map(&BOOT);
+/// unsafe { HEAP.init_from_bootstrap(&BOOT) };
+/// ```
+///
+/// The metadata of the reserved segment goes at the start of the region, so the first
+/// allocation starts [`SegmentMetadata::SIZE`] bytes in.
+pub struct BootstrapAlloc<R: lock_api::RawMutex> {
+    state: lock_api::Mutex<R, BootstrapState>,
+}
+
+impl<R: lock_api::RawMutex> BootstrapAlloc<R> {
+    /// Creates an allocator managing the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator and the heap taking it over. `start` must be aligned to the
+    /// granularity of that heap.
+    pub const unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        BootstrapAlloc {
+            state: lock_api::Mutex::new(BootstrapState {
+                start,
+                end,
+                watermark: start.wrapping_add(SegmentMetadata::SIZE),
+                retired: false,
+            }),
+        }
+    }
+
+    /// The bytes allocated so far, including padding and the room for the segment metadata.
+    pub fn used_bytes(&self) -> usize {
+        let state = self.state.lock();
+        state.watermark as usize - state.start as usize
+    }
+
+    /// Whether a heap has taken over the region, after which every allocation fails.
+    pub fn is_retired(&self) -> bool {
+        self.state.lock().retired
+    }
+
+    /// Stops serving allocations, and returns the start of the region, the watermark and the end
+    /// of the region.
+    ///
+    /// # Panics
+    /// Panics if the allocator was already retired.
+    pub(crate) fn retire(&self) -> (*mut u8, *mut u8, *mut u8) {
+        let mut state = self.state.lock();
+        assert!(
+            !state.retired,
+            "The bootstrap allocator was already handed off!"
+        );
+        state.retired = true;
+        (state.start, state.watermark, state.end)
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> Allocator for BootstrapAlloc<R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut state = self.state.lock();
+        if state.retired {
+            return Err(AllocError);
+        }
+        let ptr = state
+            .watermark
+            .wrapping_add(state.watermark.align_offset(layout.align()));
+        let end = (ptr as usize)
+            .checked_add(layout.size())
+            .ok_or(AllocError)?;
+        if ptr < state.watermark || end > state.end as usize {
+            return Err(AllocError);
+        }
+
+        state.watermark = ptr.wrapping_add(layout.size());
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size(),
+        ))
+    }
+
+    /// Bootstrap allocations are never freed, so this does nothing.
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+unsafe impl<R: lock_api::RawMutex> GlobalAlloc for BootstrapAlloc<R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::{LeakPolicy, LinkedListAlloc};
+
+    const SIZE: usize = 4096;
+
+    #[test]
+    fn bootstrap_handoff() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let boot: BootstrapAlloc<parking_lot::RawMutex> =
+            unsafe { BootstrapAlloc::new(mem, mem.add(SIZE)) };
+
+        let mut early = Vec::new_in(&boot);
+        early.extend(0..100u32);
+        let early = early.leak();
+        let flag = boot.allocate(Layout::new::<u8>()).unwrap().cast::<u8>();
+        unsafe { flag.write(0xAA) };
+        assert!(boot.used_bytes() >= SegmentMetadata::SIZE + 401);
+
+        let heap: LinkedListAlloc<parking_lot::RawMutex> = LinkedListAlloc::empty();
+        heap.set_leak_policy(LeakPolicy::Ignore);
+        unsafe { heap.init_from_bootstrap(&boot) };
+        assert!(boot.is_retired());
+        assert!(boot.allocate(Layout::new::<u8>()).is_err());
+
+        // The bootstrap allocations are intact and stay out of the heap's way
+        assert_eq!(heap.stats().live_allocations, 1);
+        let reserved = mem as usize..mem as usize + boot.used_bytes();
+        let layout = Layout::new::<[u8; 256]>();
+        let mut ptrs = Vec::new();
+        while let Ok(mut ptr) = heap.allocate(layout) {
+            let addr = ptr.cast::<u8>().as_ptr() as usize;
+            assert!(!reserved.contains(&addr) && !reserved.contains(&(addr + 255)));
+            unsafe { ptr.as_mut() }.fill(0xFF);
+            ptrs.push(ptr);
+        }
+        assert!(!ptrs.is_empty());
+        assert!(early.iter().copied().eq(0..100));
+        assert!(reserved.contains(&(flag.as_ptr() as usize)));
+        assert_eq!(unsafe { flag.read() }, 0xAA);
+
+        for ptr in ptrs {
+            unsafe { heap.deallocate(ptr.cast(), layout) };
+        }
+        drop(heap);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
@@ -0,0 +1,107 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::RangeInclusive,
+    ptr::{null_mut, NonNull},
+};
+
+/// Wraps an allocator to round the sizes of requests in a range up to the next multiple of a
+/// fixed step, so the inner allocator only ever sees a handful of distinct sizes there. Blocks
+/// freed for one request then fit any other request of the same bucket exactly, which keeps
+/// small leftovers from piling up in the inner allocator. Requests outside the range are passed
+/// through unchanged.
+///
+/// Allocations report the full size of their bucket, so callers can use the slack.
+pub struct BucketizerAlloc<A: Allocator> {
+    inner: A,
+    sizes: RangeInclusive<usize>,
+    step: usize,
+}
+
+impl<A: Allocator> BucketizerAlloc<A> {
+    /// Wraps `inner`, rounding the sizes of requests in `sizes` up to multiples of `step`.
+    ///
+    /// # Panics
+    /// Panics if `step` is zero, or if the end of `sizes` isn't a multiple of it.
+    pub const fn new(inner: A, sizes: RangeInclusive<usize>, step: usize) -> Self {
+        assert!(
+            step != 0 && sizes.end().is_multiple_of(step),
+            "The largest size must be a multiple of the step!"
+        );
+        BucketizerAlloc { inner, sizes, step }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The layout requests for `layout` are actually served with. Sizes in the range are rounded
+    /// up to the next step, everything else is left as is.
+    pub fn bucket(&self, layout: Layout) -> Layout {
+        if !self.sizes.contains(&layout.size()) {
+            return layout;
+        }
+
+        // The end of the range is a multiple of the step, so the bucket can't overflow
+        let size = layout.size().next_multiple_of(self.step);
+        unsafe { Layout::from_size_align_unchecked(size, layout.align()) }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for BucketizerAlloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate(self.bucket(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Any size between the request and its bucket falls into the same bucket
+        self.inner.deallocate(ptr, self.bucket(layout));
+    }
+}
+
+unsafe impl<A: Allocator> GlobalAlloc for BucketizerAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn bucketizer_alloc() {
+        let heap = Heap::new(16 * 1024);
+        let allocator = BucketizerAlloc::new(&*heap, 1..=256, 64);
+        assert_eq!(allocator.bucket(Layout::new::<[u8; 65]>()).size(), 128);
+        assert_eq!(allocator.bucket(Layout::new::<[u8; 128]>()).size(), 128);
+        assert_eq!(allocator.bucket(Layout::new::<[u8; 300]>()).size(), 300);
+
+        // Requests of the same bucket reuse each other's blocks exactly
+        let free = heap.free_bytes();
+        let a = allocator.allocate(Layout::new::<[u8; 70]>()).unwrap();
+        assert_eq!(a.len(), 128);
+        unsafe { allocator.deallocate(a.cast(), Layout::new::<[u8; 70]>()) };
+        let b = allocator.allocate(Layout::new::<[u8; 100]>()).unwrap();
+        assert_eq!(b, a);
+
+        // Blocks may be freed with any size up to their bucket
+        unsafe { allocator.deallocate(b.cast(), Layout::new::<[u8; 128]>()) };
+        assert_eq!(heap.free_bytes(), free);
+
+        // The slack of the bucket can be used
+        let mut c = allocator.allocate(Layout::new::<[u8; 10]>()).unwrap();
+        unsafe { c.as_mut() }.fill(0xAA);
+        unsafe { allocator.deallocate(c.cast(), Layout::new::<[u8; 64]>()) };
+        assert_eq!(heap.free_bytes(), free);
+    }
+}
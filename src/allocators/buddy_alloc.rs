@@ -0,0 +1,516 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    marker::PhantomData,
+    mem::size_of,
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+/// Enough orders for any heap of a sensible size. Larger heaps are covered by several blocks of
+/// the largest order.
+const MAX_ORDERS: usize = 32;
+/// Set in the state byte of the first minimum block of every free block, whose low bits hold the
+/// order of the free block.
+const FREE: u8 = 0x80;
+/// Set in the state byte of blocks that are the upper part of a split block.
+const RIGHT: u8 = 0x40;
+/// Whether the block a left part was split from was itself a right part, or for right parts, what
+/// that block inherited. Restores the side of a block when its parts merge again.
+const INHERIT: u8 = 0x20;
+
+/// How blocks of a [`BuddyAlloc`] are sized and split. Orders must be ascending in size, and
+/// every block splits into a larger or equally sized lower part and an upper part.
+pub trait SplitScheme {
+    /// Whether blocks are a power of two multiple of the minimum block and aligned to their size,
+    /// so alignments are served by rounding up the size.
+    const SIZE_ALIGNED: bool;
+
+    /// The number of minimum blocks in a block of `order`.
+    fn units(order: usize) -> usize;
+
+    /// The orders of the lower and upper part of a block of `order`, if it can be split at all.
+    fn split(order: usize) -> Option<(usize, usize)>;
+}
+
+/// Blocks split into halves, so block sizes are the powers of two.
+#[derive(Debug)]
+pub struct Binary;
+
+impl SplitScheme for Binary {
+    const SIZE_ALIGNED: bool = true;
+
+    fn units(order: usize) -> usize {
+        1 << order
+    }
+
+    fn split(order: usize) -> Option<(usize, usize)> {
+        (order >= 1).then(|| (order - 1, order - 1))
+    }
+}
+
+/// Block sizes follow the Fibonacci sequence, 1, 2, 3, 5, 8 and so on minimum blocks, and a block
+/// splits into the two sizes before it. Sizes are much closer together than powers of two, which
+/// wastes less of every block on requests that don't happen to be a power of two. Blocks are only
+/// aligned to the minimum block, though.
+#[derive(Debug)]
+pub struct Fibonacci;
+
+impl Fibonacci {
+    const UNITS: [usize; MAX_ORDERS] = {
+        let mut units = [1; MAX_ORDERS];
+        units[1] = 2;
+        let mut order = 2;
+        while order < MAX_ORDERS {
+            units[order] = units[order - 1] + units[order - 2];
+            order += 1;
+        }
+        units
+    };
+}
+
+impl SplitScheme for Fibonacci {
+    const SIZE_ALIGNED: bool = false;
+
+    fn units(order: usize) -> usize {
+        Self::UNITS[order]
+    }
+
+    fn split(order: usize) -> Option<(usize, usize)> {
+        (order >= 2).then(|| (order - 1, order - 2))
+    }
+}
+
+/// Links the free blocks of one order. Written into the free blocks themselves.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    prev: *mut FreeBlock,
+}
+
+/// The state shared by the buddy based allocators. Blocks of order `k` are `S::units(k)` minimum
+/// blocks of `MIN_BLOCK` bytes, and every block but the initial ones was split from a larger
+/// block, so it has exactly one buddy to merge with.
+///
+/// Whether a block is free, and which part of its parent it is, is tracked in a state byte per
+/// minimum block, kept in front of the blocks, so no block carries a header.
+pub(crate) struct BuddyHeap<const MIN_BLOCK: usize, S: SplitScheme = Binary> {
+    base: *mut u8,
+    // Number of minimum blocks in the heap
+    blocks: usize,
+    states: *mut u8,
+    free_lists: [*mut FreeBlock; MAX_ORDERS],
+    free_bytes: usize,
+    scheme: PhantomData<S>,
+}
+
+// SAFETY: The heap owns its region exclusively, so it may be moved to another thread
+unsafe impl<const MIN_BLOCK: usize, S: SplitScheme> Send for BuddyHeap<MIN_BLOCK, S> {}
+
+impl<const MIN_BLOCK: usize, S: SplitScheme> BuddyHeap<MIN_BLOCK, S> {
+    /// Creates a heap over the memory between `start` and `end`, placing the state bytes at the
+    /// start and the blocks after them, from an address aligned to `base_align`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the heap. `base_align` must be a power of two of at least `MIN_BLOCK`.
+    pub unsafe fn new(start: *mut u8, end: *mut u8, base_align: usize) -> Self {
+        const {
+            assert!(
+                MIN_BLOCK.is_power_of_two() && MIN_BLOCK >= size_of::<FreeBlock>(),
+                "The minimum block must be a power of two able to hold two pointers!"
+            );
+        }
+
+        let total = (end as usize).saturating_sub(start as usize);
+        let states = start;
+        let reserved = total / (MIN_BLOCK + 1);
+        let base = states.add(reserved);
+        let base = base.add(base.align_offset(base_align));
+        let blocks = reserved.min((end as usize).saturating_sub(base as usize) / MIN_BLOCK);
+        states.write_bytes(0, blocks);
+
+        let mut heap = BuddyHeap {
+            base,
+            blocks,
+            states,
+            free_lists: [null_mut(); MAX_ORDERS],
+            free_bytes: 0,
+            scheme: PhantomData,
+        };
+        // Cover the heap with the largest blocks that fit, from the base up
+        let mut offset = 0;
+        while offset < blocks {
+            let order = (0..MAX_ORDERS)
+                .rev()
+                .find(|&order| {
+                    let units = S::units(order);
+                    units <= blocks - offset && (!S::SIZE_ALIGNED || offset % units == 0)
+                })
+                .unwrap();
+            heap.push(offset, order, 0);
+            offset += S::units(order);
+        }
+
+        heap
+    }
+
+    pub fn block_size(order: usize) -> usize {
+        S::units(order) * MIN_BLOCK
+    }
+
+    /// The order of the smallest block able to serve `layout`, ignoring the alignment of the base.
+    pub fn order_for(layout: Layout) -> Option<usize> {
+        let size = if S::SIZE_ALIGNED {
+            layout.size().max(layout.align())
+        } else if layout.align() <= MIN_BLOCK {
+            layout.size()
+        } else {
+            return None;
+        };
+        let units = size.div_ceil(MIN_BLOCK);
+        (0..MAX_ORDERS)
+            .find(|&order| S::units(order) >= units)
+            .filter(|&order| S::units(order).checked_mul(MIN_BLOCK).is_some())
+    }
+
+    /// The alignment every block is guaranteed to have, whatever its order.
+    pub fn base_align(&self) -> usize {
+        1 << (self.base as usize).trailing_zeros()
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    /// The bytes managed, not counting the state bytes.
+    pub fn size(&self) -> usize {
+        self.blocks * MIN_BLOCK
+    }
+
+    /// The memory covered by the blocks, not including the state bytes.
+    pub fn region(&self) -> Range<*mut u8> {
+        self.base..self.base.wrapping_add(self.size())
+    }
+
+    /// Whether `ptr` lies in one of the blocks.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.region().contains(&ptr.cast_mut())
+    }
+
+    /// Takes a free block of `order`, splitting a larger one if necessary.
+    pub fn allocate(&mut self, order: usize) -> Option<*mut u8> {
+        let mut from = (order..MAX_ORDERS)
+            .find(|&from| !self.free_lists[from].is_null() && Self::reaches(from, order))?;
+        let mut offset = self.pop(from);
+        let mut state = self.state(offset);
+        // Keep the smaller part of every split that can still be split down to `order`, and free
+        // the other
+        while from > order {
+            let (left, right) = S::split(from).unwrap();
+            let left_state = if state & RIGHT != 0 { INHERIT } else { 0 };
+            let right_state = RIGHT | (state & INHERIT);
+            let right_offset = offset + S::units(left);
+            if S::units(right) < S::units(left) && Self::reaches(right, order) {
+                self.push(offset, left, left_state);
+                (offset, from, state) = (right_offset, right, right_state);
+            } else {
+                self.push(right_offset, right, right_state);
+                (from, state) = (left, left_state);
+            }
+        }
+        self.set_state(offset, state);
+
+        Some(unsafe { self.base.add(offset * MIN_BLOCK) })
+    }
+
+    /// Frees the block of `order` at `ptr`, merging it with its buddy for as long as the buddy is
+    /// free too.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`BuddyHeap::allocate`] for the same `order`, and not been
+    /// freed since.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, mut order: usize) {
+        let mut offset = (ptr as usize - self.base as usize) / MIN_BLOCK;
+        let mut state = self.state(offset);
+        while let Some(parent) = Self::parent(order, state & RIGHT != 0) {
+            let (left, right) = S::split(parent).unwrap();
+            let (buddy, buddy_order, buddy_side) = if state & RIGHT != 0 {
+                (offset - S::units(left), left, 0)
+            } else {
+                (offset + S::units(order), right, RIGHT)
+            };
+            // The initial blocks have no buddy. What follows them is another initial block, or
+            // lies outside the heap
+            if buddy + S::units(buddy_order) > self.blocks
+                || self.state(buddy) & !INHERIT != FREE | buddy_side | buddy_order as u8
+            {
+                break;
+            }
+
+            let buddy_state = self.state(buddy);
+            self.remove(buddy, buddy_order);
+            let (left_state, right_state) = if buddy_side == 0 {
+                (buddy_state, state)
+            } else {
+                (state, buddy_state)
+            };
+            offset = offset.min(buddy);
+            order = parent;
+            state = if left_state & INHERIT != 0 { RIGHT } else { 0 } | (right_state & INHERIT);
+        }
+        self.push(offset, order, state);
+    }
+
+    /// Whether a block of `from` can be split down to a block of `to`.
+    fn reaches(from: usize, to: usize) -> bool {
+        from == to
+            || from > to
+                && S::split(from).is_some_and(|(left, right)| {
+                    Self::reaches(left, to) || Self::reaches(right, to)
+                })
+    }
+
+    /// The order of the block a block of `order` was split from, given which part of it it is.
+    fn parent(order: usize, right: bool) -> Option<usize> {
+        (order + 1..MAX_ORDERS).find(|&parent| {
+            S::split(parent).is_some_and(|parts| if right { parts.1 } else { parts.0 } == order)
+        })
+    }
+
+    fn state(&self, offset: usize) -> u8 {
+        unsafe { self.states.add(offset).read() }
+    }
+
+    fn set_state(&mut self, offset: usize, state: u8) {
+        unsafe { self.states.add(offset).write(state) };
+    }
+
+    fn block(&self, offset: usize) -> *mut FreeBlock {
+        unsafe { self.base.add(offset * MIN_BLOCK) }.cast()
+    }
+
+    fn push(&mut self, offset: usize, order: usize, state: u8) {
+        let block = self.block(offset);
+        let head = self.free_lists[order];
+        unsafe {
+            block.write(FreeBlock {
+                next: head,
+                prev: null_mut(),
+            });
+            if let Some(head) = head.as_mut() {
+                head.prev = block;
+            }
+        }
+        self.free_lists[order] = block;
+        self.set_state(offset, FREE | (state & (RIGHT | INHERIT)) | order as u8);
+        self.free_bytes += Self::block_size(order);
+    }
+
+    fn pop(&mut self, order: usize) -> usize {
+        let offset = (self.free_lists[order] as usize - self.base as usize) / MIN_BLOCK;
+        self.remove(offset, order);
+        offset
+    }
+
+    /// Unlinks the free block at `offset`, keeping which part of its parent it is.
+    fn remove(&mut self, offset: usize, order: usize) {
+        let block = unsafe { &*self.block(offset) };
+        unsafe {
+            match block.prev.as_mut() {
+                Some(prev) => prev.next = block.next,
+                None => self.free_lists[order] = block.next,
+            }
+            if let Some(next) = block.next.as_mut() {
+                next.prev = block.prev;
+            }
+        }
+        self.set_state(offset, self.state(offset) & (RIGHT | INHERIT));
+        self.free_bytes -= Self::block_size(order);
+    }
+}
+
+/// A buddy allocator. Every request is rounded up to the size of a block of `MIN_BLOCK` bytes
+/// times the sizes of the [`SplitScheme`], and blocks are split to serve it and merged with their
+/// buddy again when freed. Both are logarithmic in the heap size, and external fragmentation stays
+/// low, at the cost of some of every block going to waste: up to half with the default [`Binary`]
+/// scheme, and up to about a third with [`Fibonacci`].
+///
+/// The heap reserves one byte per `MIN_BLOCK` bytes at its start to track free blocks. With the
+/// binary scheme, blocks are aligned to their size, up to the alignment of the first block.
+pub struct BuddyAlloc<R: lock_api::RawMutex, const MIN_BLOCK: usize = 16, S: SplitScheme = Binary> {
+    heap: lock_api::Mutex<R, BuddyHeap<MIN_BLOCK, S>>,
+}
+
+impl<R: lock_api::RawMutex, const MIN_BLOCK: usize, S: SplitScheme> BuddyAlloc<R, MIN_BLOCK, S> {
+    /// Creates an allocator managing the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        BuddyAlloc {
+            heap: lock_api::Mutex::new(BuddyHeap::new(start, end, MIN_BLOCK)),
+        }
+    }
+
+    /// The bytes of all free blocks.
+    pub fn free_bytes(&self) -> usize {
+        self.heap.lock().free_bytes()
+    }
+
+    /// The bytes managed, not counting the state bytes.
+    pub fn size(&self) -> usize {
+        self.heap.lock().size()
+    }
+
+    /// The memory blocks are allocated from, not including the state bytes.
+    pub fn region(&self) -> Range<*mut u8> {
+        self.heap.lock().region()
+    }
+
+    /// Whether `ptr` lies in the memory blocks are allocated from.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.heap.lock().contains(ptr)
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const MIN_BLOCK: usize, S: SplitScheme> Allocator
+    for BuddyAlloc<R, MIN_BLOCK, S>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let order = BuddyHeap::<MIN_BLOCK, S>::order_for(layout).ok_or(AllocError)?;
+        let mut heap = self.heap.lock();
+        if layout.align() > heap.base_align() {
+            return Err(AllocError);
+        }
+
+        let ptr = heap.allocate(order).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            BuddyHeap::<MIN_BLOCK, S>::block_size(order),
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let order = BuddyHeap::<MIN_BLOCK, S>::order_for(layout).unwrap();
+        self.heap.lock().deallocate(ptr.as_ptr(), order);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const MIN_BLOCK: usize, S: SplitScheme> GlobalAlloc
+    for BuddyAlloc<R, MIN_BLOCK, S>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 64 * 1024;
+
+    #[test]
+    fn buddy_alloc() {
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let allocator: BuddyAlloc<parking_lot::RawMutex> =
+            unsafe { BuddyAlloc::new(mem, mem.add(SIZE)) };
+        let size = allocator.size();
+        assert!(size > SIZE - SIZE / 16 - 16);
+        assert_eq!(allocator.free_bytes(), size);
+
+        // Requests are rounded up to a power of two
+        let ptr = allocator.allocate(Layout::new::<[u8; 100]>()).unwrap();
+        assert_eq!(ptr.len(), 128);
+        assert!(allocator
+            .allocate(Layout::from_size_align(64, 4096).unwrap())
+            .is_err());
+        unsafe { allocator.deallocate(ptr.cast(), Layout::new::<[u8; 100]>()) };
+        assert_eq!(allocator.free_bytes(), size);
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..2000 {
+            if rng.gen_bool(0.55) {
+                let layout = Layout::from_size_align(rng.gen_range(1..2048), 16).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        // Every split was merged again, so the largest block is available once more
+        assert_eq!(allocator.free_bytes(), size);
+        let largest = Layout::from_size_align(32 * 1024, 16).unwrap();
+        let ptr = allocator.allocate(largest).unwrap();
+        unsafe { allocator.deallocate(ptr.cast(), largest) };
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn fibonacci_buddy_alloc() {
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let allocator: BuddyAlloc<parking_lot::RawMutex, 16, Fibonacci> =
+            unsafe { BuddyAlloc::new(mem, mem.add(SIZE)) };
+        let size = allocator.size();
+        assert_eq!(allocator.free_bytes(), size);
+
+        // Requests are rounded up to a Fibonacci number of minimum blocks, and only aligned to one
+        let ptr = allocator.allocate(Layout::new::<[u8; 70]>()).unwrap();
+        assert_eq!(ptr.len(), 80);
+        assert!(allocator
+            .allocate(Layout::from_size_align(16, 32).unwrap())
+            .is_err());
+        unsafe { allocator.deallocate(ptr.cast(), Layout::new::<[u8; 70]>()) };
+        assert_eq!(allocator.free_bytes(), size);
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..2000 {
+            if rng.gen_bool(0.55) {
+                let layout = Layout::from_size_align(rng.gen_range(1..2048), 16).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    assert!(ptr.len() >= layout.size());
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        // Every split was merged again, back into the initial blocks
+        assert_eq!(allocator.free_bytes(), size);
+        let heap = allocator.heap.lock();
+        let initial = (0..MAX_ORDERS)
+            .rev()
+            .find(|&order| Fibonacci::units(order) * 16 <= size);
+        assert!(!heap.free_lists[initial.unwrap()].is_null());
+        drop(heap);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
@@ -0,0 +1,505 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::size_of,
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+use crate::memory_source::MemorySource;
+
+struct BumpState {
+    start: *mut u8,
+    end: *mut u8,
+    // Start of the free part of the region
+    next: *mut u8,
+}
+
+// SAFETY: The state owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for BumpState {}
+
+impl BumpState {
+    fn allocate(&mut self, layout: Layout) -> Option<*mut u8> {
+        let ptr = self
+            .next
+            .wrapping_add(self.next.align_offset(layout.align()));
+        let new_next = (ptr as usize).checked_add(layout.size())?;
+        if ptr < self.next || new_next > self.end as usize {
+            return None;
+        }
+
+        self.next = ptr.wrapping_add(layout.size());
+        Some(ptr)
+    }
+
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // Only the most recent allocation can be given back
+        if ptr.wrapping_add(layout.size()) == self.next {
+            self.next = ptr;
+        }
+    }
+}
+
+/// The simplest allocator there is: allocations are carved out of the region one after the
+/// other, by moving a pointer forward. Memory is only reclaimed all at once by
+/// [`BumpAlloc::reset`], except for the most recent allocation, which is given back when freed.
+///
+/// Allocation is a few instructions and allocations carry no metadata at all, which suits
+/// memory that lives and dies together, such as the temporaries of a single frame or request.
+pub struct BumpAlloc<R: lock_api::RawMutex> {
+    state: lock_api::Mutex<R, BumpState>,
+}
+
+impl<R: lock_api::RawMutex> BumpAlloc<R> {
+    /// Creates an allocator managing the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub const unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        BumpAlloc {
+            state: lock_api::Mutex::new(BumpState {
+                start,
+                end,
+                next: start,
+            }),
+        }
+    }
+
+    /// Frees all allocations at once. Taking `&mut self` ensures no allocation borrowed from the
+    /// allocator, such as a collection using it, is still around.
+    pub fn reset(&mut self) {
+        let state = self.state.get_mut();
+        state.next = state.start;
+    }
+
+    /// The bytes allocated since the last reset, including padding.
+    pub fn used_bytes(&self) -> usize {
+        let state = self.state.lock();
+        state.next as usize - state.start as usize
+    }
+
+    /// The bytes left at the end of the region.
+    pub fn free_bytes(&self) -> usize {
+        let state = self.state.lock();
+        state.end as usize - state.next as usize
+    }
+
+    /// The managed region.
+    pub fn region(&self) -> Range<*mut u8> {
+        let state = self.state.lock();
+        state.start..state.end
+    }
+
+    /// Whether `ptr` lies in the managed region.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.region().contains(&ptr.cast_mut())
+    }
+
+    /// Opens a scope whose allocations are all freed when it is dropped.
+    pub fn scope(&mut self) -> ArenaScope<'_, R> {
+        let mark = self.state.get_mut().next;
+        ArenaScope { bump: self, mark }
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> Allocator for BumpAlloc<R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.state.lock().allocate(layout).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size(),
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.state.lock().deallocate(ptr.as_ptr(), layout);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> GlobalAlloc for BumpAlloc<R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.state.lock().allocate(layout).unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.state.lock().deallocate(ptr, layout);
+    }
+}
+
+/// A scope of temporary allocations on a [`BumpAlloc`], opened with [`BumpAlloc::scope`]. When
+/// the scope is dropped, the allocator is rewound to where it was when the scope was opened.
+///
+/// Everything allocated in the scope borrows it, so the borrow checker rejects any use of those
+/// allocations after the scope has ended:
+///
+/// ```ignore
+/// let mut scope = bump.scope();
+/// let path = scope.alloc_str("/tmp/scratch");
+/// let mut parts = Vec::new_in(&scope);
+/// parts.extend(path.split('/'));
+/// ```
+///
+/// Scopes nest, but opening an inner scope borrows the outer one mutably, so the allocations of
+/// the outer scope can't be used until the inner one is dropped. Destructors of values allocated
+/// in a scope aren't run.
+pub struct ArenaScope<'a, R: lock_api::RawMutex> {
+    bump: &'a mut BumpAlloc<R>,
+    mark: *mut u8,
+}
+
+impl<R: lock_api::RawMutex> ArenaScope<'_, R> {
+    /// Moves `value` into the scope.
+    ///
+    /// # Panics
+    /// Panics if the allocator is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        match self.try_alloc(value) {
+            Ok(value) => value,
+            Err(_) => panic!("The bump allocator is full!"),
+        }
+    }
+
+    /// Moves `value` into the scope, or hands it back if the allocator is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn try_alloc<T>(&self, value: T) -> Result<&mut T, T> {
+        match self.bump.allocate(Layout::new::<T>()) {
+            Ok(ptr) => {
+                let ptr = ptr.cast::<T>().as_ptr();
+                unsafe {
+                    ptr.write(value);
+                    Ok(&mut *ptr)
+                }
+            }
+            Err(_) => Err(value),
+        }
+    }
+
+    /// Copies `values` into the scope.
+    ///
+    /// # Panics
+    /// Panics if the allocator is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, values: &[T]) -> &mut [T] {
+        let layout = Layout::for_value(values);
+        let Ok(ptr) = self.bump.allocate(layout) else {
+            panic!("The bump allocator is full!");
+        };
+        let ptr = ptr.cast::<T>().as_ptr();
+        unsafe {
+            ptr.copy_from_nonoverlapping(values.as_ptr(), values.len());
+            core::slice::from_raw_parts_mut(ptr, values.len())
+        }
+    }
+
+    /// Copies `string` into the scope.
+    ///
+    /// # Panics
+    /// Panics if the allocator is full.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_str(&self, string: &str) -> &mut str {
+        let bytes = self.alloc_slice_copy(string.as_bytes());
+        unsafe { core::str::from_utf8_unchecked_mut(bytes) }
+    }
+
+    /// Opens a scope within this one.
+    pub fn scope(&mut self) -> ArenaScope<'_, R> {
+        self.bump.scope()
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> Allocator for ArenaScope<'_, R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.bump.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.bump.deallocate(ptr, layout);
+    }
+}
+
+impl<R: lock_api::RawMutex> Drop for ArenaScope<'_, R> {
+    fn drop(&mut self) {
+        self.bump.state.get_mut().next = self.mark;
+    }
+}
+
+/// Header at the start of every chunk of a [`ChainedBumpAlloc`].
+struct Chunk {
+    // The chunk acquired before this one
+    prev: *mut Chunk,
+    // The layout the chunk was acquired with
+    layout: Layout,
+}
+
+struct ChainState<S: MemorySource> {
+    source: S,
+    // The most recent chunk, which is allocated from
+    current: *mut Chunk,
+    bump: BumpState,
+    // Size of the next chunk to acquire
+    chunk_size: usize,
+}
+
+// SAFETY: The state owns its chunks exclusively, so it may be moved to another thread as long as
+// the source may be
+unsafe impl<S: MemorySource + Send> Send for ChainState<S> {}
+
+impl<S: MemorySource> ChainState<S> {
+    fn allocate(&mut self, layout: Layout) -> Option<*mut u8> {
+        if let Some(ptr) = self.bump.allocate(layout) {
+            return Some(ptr);
+        }
+
+        // Chunks double in size, and are at least large enough for the request
+        let header = Layout::new::<Chunk>();
+        let needed = header
+            .size()
+            .checked_add(layout.size())?
+            .checked_add(layout.align())?;
+        let chunk_layout =
+            Layout::from_size_align(self.chunk_size.max(needed), header.align()).ok()?;
+        let region = self.source.acquire(chunk_layout)?;
+        self.chunk_size = self.chunk_size.saturating_mul(2);
+
+        let chunk = region.cast::<Chunk>().as_ptr();
+        unsafe {
+            chunk.write(Chunk {
+                prev: self.current,
+                layout: chunk_layout,
+            })
+        };
+        self.current = chunk;
+        let start = unsafe { chunk.cast::<u8>().add(size_of::<Chunk>()) };
+        self.bump = BumpState {
+            start,
+            end: unsafe { region.cast::<u8>().as_ptr().add(region.len()) },
+            next: start,
+        };
+        self.bump.allocate(layout)
+    }
+
+    /// Releases every chunk before the current one to the source.
+    fn release_previous(&mut self) {
+        let Some(current) = (unsafe { self.current.as_mut() }) else {
+            return;
+        };
+        let mut chunk = core::mem::replace(&mut current.prev, null_mut());
+        while let Some(previous) = unsafe { chunk.as_ref() } {
+            let prev = previous.prev;
+            unsafe {
+                self.source
+                    .release(NonNull::new_unchecked(chunk).cast(), previous.layout)
+            };
+            chunk = prev;
+        }
+    }
+}
+
+/// A bump allocator that never runs out of room, as long as its [`MemorySource`] doesn't. When
+/// the current chunk is exhausted, a new one twice the size is acquired from the source and
+/// allocated from, while the previous chunks stay around until [`ChainedBumpAlloc::reset`].
+///
+/// To layer it over another allocator, use an
+/// [`AllocatorSource`](crate::memory_source::AllocatorSource).
+pub struct ChainedBumpAlloc<R: lock_api::RawMutex, S: MemorySource> {
+    state: lock_api::Mutex<R, ChainState<S>>,
+}
+
+impl<R: lock_api::RawMutex, S: MemorySource> ChainedBumpAlloc<R, S> {
+    /// Creates an allocator acquiring chunks from `source`, the first of them `chunk_size`
+    /// bytes. Nothing is acquired until the first allocation.
+    pub const fn new(source: S, chunk_size: usize) -> Self {
+        ChainedBumpAlloc {
+            state: lock_api::Mutex::new(ChainState {
+                source,
+                current: null_mut(),
+                bump: BumpState {
+                    start: null_mut(),
+                    end: null_mut(),
+                    next: null_mut(),
+                },
+                chunk_size,
+            }),
+        }
+    }
+
+    /// Frees all allocations at once. Every chunk but the most recent one, which is also the
+    /// largest, is released to the source, and allocation starts over in the most recent one.
+    pub fn reset(&mut self) {
+        let state = self.state.get_mut();
+        state.release_previous();
+        state.bump.next = state.bump.start;
+    }
+
+    /// The number of chunks acquired from the source.
+    pub fn chunks(&self) -> usize {
+        let state = self.state.lock();
+        let mut chunks = 0;
+        let mut chunk = state.current;
+        while let Some(current) = unsafe { chunk.as_ref() } {
+            chunks += 1;
+            chunk = current.prev;
+        }
+        chunks
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, S: MemorySource> Allocator for ChainedBumpAlloc<R, S> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.state.lock().allocate(layout).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size(),
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.state.lock().bump.deallocate(ptr.as_ptr(), layout);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, S: MemorySource> GlobalAlloc for ChainedBumpAlloc<R, S> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.state.lock().allocate(layout).unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.state.lock().bump.deallocate(ptr, layout);
+    }
+}
+
+impl<R: lock_api::RawMutex, S: MemorySource> Drop for ChainedBumpAlloc<R, S> {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        state.release_previous();
+        if let Some(current) = NonNull::new(state.current) {
+            let layout = unsafe { current.as_ref() }.layout;
+            unsafe { state.source.release(current.cast(), layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_source::{AllocatorSource, SystemSource};
+
+    const SIZE: usize = 1024;
+
+    #[test]
+    fn bump_alloc() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let mut allocator: BumpAlloc<parking_lot::RawMutex> =
+            unsafe { BumpAlloc::new(mem, mem.add(SIZE)) };
+
+        // Allocations follow each other, padded to their alignment
+        let a = allocator.allocate(Layout::new::<u8>()).unwrap();
+        let b = allocator.allocate(Layout::new::<u64>()).unwrap();
+        assert_eq!(a.cast::<u8>().as_ptr(), mem);
+        assert_eq!(b.cast::<u8>().as_ptr(), unsafe { mem.add(8) });
+        assert_eq!(allocator.used_bytes(), 16);
+
+        // Only the last allocation is given back when freed
+        unsafe { allocator.deallocate(a.cast(), Layout::new::<u8>()) };
+        assert_eq!(allocator.used_bytes(), 16);
+        unsafe { allocator.deallocate(b.cast(), Layout::new::<u64>()) };
+        assert_eq!(allocator.used_bytes(), 8);
+
+        assert!(allocator.allocate(Layout::new::<[u8; SIZE]>()).is_err());
+        let rest = Layout::from_size_align(allocator.free_bytes(), 1).unwrap();
+        allocator.allocate(rest).unwrap();
+        assert_eq!(allocator.free_bytes(), 0);
+
+        allocator.reset();
+        assert_eq!(allocator.used_bytes(), 0);
+        assert_eq!(
+            allocator
+                .allocate(Layout::new::<[u8; SIZE]>())
+                .unwrap()
+                .cast::<u8>()
+                .as_ptr(),
+            mem
+        );
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn arena_scope() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let mut allocator: BumpAlloc<parking_lot::RawMutex> =
+            unsafe { BumpAlloc::new(mem, mem.add(SIZE)) };
+        allocator.allocate(Layout::new::<u64>()).unwrap();
+
+        {
+            let mut scope = allocator.scope();
+            let number = scope.alloc(5u32);
+            *number += 1;
+            let name = scope.alloc_str("scratch");
+            assert_eq!((*number, &*name), (6, "scratch"));
+            let mut list = Vec::new_in(&scope);
+            list.extend([1u16, 2, 3]);
+            assert_eq!(list, [1, 2, 3]);
+            drop(list);
+
+            // An inner scope only rewinds its own allocations
+            let used = scope.bump.used_bytes();
+            {
+                let inner = scope.scope();
+                inner.alloc_slice_copy(&[0u8; 100]);
+                assert_eq!(inner.bump.used_bytes(), used + 100);
+            }
+            assert_eq!(scope.bump.used_bytes(), used);
+        }
+        assert_eq!(allocator.used_bytes(), 8);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn chained_bump_alloc() {
+        let mut allocator: ChainedBumpAlloc<parking_lot::RawMutex, _> =
+            ChainedBumpAlloc::new(SystemSource::new(), 4096);
+        assert_eq!(allocator.chunks(), 0);
+
+        // Chunks are chained as the previous ones run full, and allocations in them stay valid
+        let layout = Layout::new::<[u64; 64]>();
+        let mut blocks = Vec::new();
+        for i in 0..40 {
+            let mut ptr = allocator.allocate(layout).unwrap();
+            unsafe { ptr.as_mut() }.fill(i);
+            blocks.push((ptr, i));
+        }
+        assert_eq!(allocator.chunks(), 3);
+        for (ptr, fill) in blocks {
+            assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+        }
+
+        // Requests larger than the next chunk get a chunk big enough
+        let huge = Layout::from_size_align(100_000, 4096).unwrap();
+        let ptr = allocator.allocate(huge).unwrap();
+        assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(4096), 0);
+        assert_eq!(allocator.chunks(), 4);
+
+        // Resetting keeps only the most recent chunk
+        allocator.reset();
+        assert_eq!(allocator.chunks(), 1);
+        assert_eq!(allocator.allocate(huge).unwrap(), ptr);
+
+        // The chunks may come from any other allocator
+        let region = Layout::from_size_align(SIZE * 16, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let parent: BumpAlloc<parking_lot::RawMutex> =
+            unsafe { BumpAlloc::new(mem, mem.add(region.size())) };
+        let allocator: ChainedBumpAlloc<parking_lot::RawMutex, _> =
+            ChainedBumpAlloc::new(AllocatorSource::new(&parent, 256), SIZE);
+        let ptr = allocator.allocate(layout).unwrap().cast::<u8>().as_ptr();
+        assert!((mem..unsafe { mem.add(region.size()) }).contains(&ptr));
+        allocator.allocate(Layout::new::<[u8; SIZE]>()).unwrap();
+        assert_eq!(allocator.chunks(), 2);
+        assert!(parent.used_bytes() >= SIZE + 2 * SIZE);
+        drop(allocator);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
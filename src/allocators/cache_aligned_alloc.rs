@@ -0,0 +1,133 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+/// The size of a cache line on most current CPUs.
+pub const CACHE_LINE: usize = 64;
+
+/// Wraps an allocator to start every allocation on a cache line of `LINE` bytes, and round its
+/// size up to whole lines. The metadata the inner allocator keeps in front of an allocation then
+/// never shares a line with the data of another one, so writing it doesn't steal the line from a
+/// thread working on its neighbour. The wrapper itself is aligned to [`CACHE_LINE`], keeping the
+/// lock and state of the inner allocator clear of unrelated statics:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static HEAP: CacheAlignedAlloc<LinkedListAlloc<RawSpinlock>> =
+///     CacheAlignedAlloc::new(LinkedListAlloc::empty());
+/// ```
+///
+/// This costs up to two lines of padding per allocation, so it pays off for data shared between
+/// threads rather than for many small allocations.
+#[repr(align(64))]
+pub struct CacheAlignedAlloc<A: Allocator, const LINE: usize = CACHE_LINE> {
+    inner: A,
+}
+
+impl<A: Allocator, const LINE: usize> CacheAlignedAlloc<A, LINE> {
+    pub const fn new(inner: A) -> Self {
+        const {
+            assert!(
+                LINE.is_power_of_two(),
+                "The cache line size must be a power of two!"
+            )
+        };
+        CacheAlignedAlloc { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The layout requests for `layout` are actually served with.
+    pub fn padded(&self, layout: Layout) -> Result<Layout, AllocError> {
+        let layout = layout.align_to(LINE).map_err(|_| AllocError)?;
+        Ok(layout.pad_to_align())
+    }
+}
+
+unsafe impl<A: Allocator, const LINE: usize> Allocator for CacheAlignedAlloc<A, LINE> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate(self.padded(layout)?)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // The layout was padded once already, so it can be again
+        self.inner.deallocate(ptr, self.padded(layout).unwrap());
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_layout = self.padded(old_layout)?;
+        self.inner.grow(ptr, old_layout, self.padded(new_layout)?)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_layout = self.padded(old_layout)?;
+        self.inner.shrink(ptr, old_layout, self.padded(new_layout)?)
+    }
+}
+
+unsafe impl<A: Allocator, const LINE: usize> GlobalAlloc for CacheAlignedAlloc<A, LINE> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocators::linked_list_allocator::LinkedListAlloc, memory_segmenter::SegmentMetadata,
+        testing::TestHeap,
+    };
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn cache_aligned_alloc() {
+        let heap = Heap::new(16 * 1024);
+        let allocator: CacheAlignedAlloc<_> = CacheAlignedAlloc::new(&*heap);
+        assert_eq!(core::mem::align_of_val(&allocator), CACHE_LINE);
+
+        // No header lies on a line holding data of another allocation
+        let layout = Layout::new::<[u8; 24]>();
+        let mut ptrs: Vec<_> = (0..16)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        ptrs.sort_by_key(|ptr| ptr.cast::<u8>());
+        for pair in ptrs.windows(2) {
+            let (low, high) = (pair[0].cast::<u8>(), pair[1].cast::<u8>());
+            assert_eq!(high.align_offset(CACHE_LINE), 0);
+            let header = high.as_ptr() as usize - SegmentMetadata::SIZE;
+            assert!(low.as_ptr() as usize + layout.size() <= header / CACHE_LINE * CACHE_LINE);
+        }
+
+        let grown = Layout::new::<[u8; 100]>();
+        let ptr = ptrs.pop().unwrap().cast();
+        let ptr = unsafe { allocator.grow(ptr, layout, grown) }.unwrap();
+        assert_eq!(ptr.cast::<u8>().align_offset(CACHE_LINE), 0);
+        unsafe { allocator.deallocate(ptr.cast(), grown) };
+        for ptr in ptrs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert!(heap.is_drained());
+    }
+}
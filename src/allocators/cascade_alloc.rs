@@ -0,0 +1,253 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use super::{
+    buddy_alloc::{BuddyAlloc, SplitScheme},
+    bump_alloc::BumpAlloc,
+    hybrid_alloc::HybridAlloc,
+    linked_list_allocator::LinkedListAlloc,
+    slob_alloc::SlobAlloc,
+};
+use crate::{
+    memory_segmenter::FreeIndex,
+    memory_source::{MemorySource, NoSource},
+};
+
+/// An allocator that can be constructed over a region of memory, so combinators can build
+/// instances of it over regions of their own.
+pub trait FromRegion {
+    /// Creates the allocator over the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self;
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, I: FreeIndex> FromRegion
+    for LinkedListAlloc<R, GRANULE, NoSource, I>
+{
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self {
+        LinkedListAlloc::with_granularity(start, end)
+    }
+}
+
+impl<R: lock_api::RawMutex, const MIN_BLOCK: usize, S: SplitScheme> FromRegion
+    for BuddyAlloc<R, MIN_BLOCK, S>
+{
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self {
+        BuddyAlloc::new(start, end)
+    }
+}
+
+impl<R: lock_api::RawMutex> FromRegion for HybridAlloc<R> {
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self {
+        HybridAlloc::new(start, end)
+    }
+}
+
+impl<R: lock_api::RawMutex> FromRegion for SlobAlloc<R> {
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self {
+        SlobAlloc::new(start, end)
+    }
+}
+
+impl<R: lock_api::RawMutex> FromRegion for BumpAlloc<R> {
+    unsafe fn from_region(start: *mut u8, end: *mut u8) -> Self {
+        BumpAlloc::new(start, end)
+    }
+}
+
+/// A sub-heap of a [`CascadeAlloc`] and the chunk it manages.
+struct SubHeap<A> {
+    heap: A,
+    chunk: NonNull<u8>,
+    layout: Layout,
+}
+
+impl<A> SubHeap<A> {
+    fn contains(&self, ptr: NonNull<u8>) -> bool {
+        let start = self.chunk.as_ptr() as usize;
+        (start..start + self.layout.size()).contains(&(ptr.as_ptr() as usize))
+    }
+}
+
+struct CascadeState<A, S: MemorySource, const N: usize> {
+    source: S,
+    heaps: [Option<SubHeap<A>>; N],
+    chunk_size: usize,
+}
+
+// SAFETY: The state owns its chunks exclusively, so it may be moved to another thread as long as
+// the sub-heaps and the source may be
+unsafe impl<A: Send, S: MemorySource + Send, const N: usize> Send for CascadeState<A, S, N> {}
+
+impl<A: Allocator + FromRegion, S: MemorySource, const N: usize> CascadeState<A, S, N> {
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        for heap in self.heaps.iter().map_while(Option::as_ref) {
+            if let Ok(ptr) = heap.heap.allocate(layout) {
+                return Ok(ptr);
+            }
+        }
+
+        // Every sub-heap is full, so build another one
+        let slot = self
+            .heaps
+            .iter()
+            .position(Option::is_none)
+            .ok_or(AllocError)?;
+        let size = self
+            .chunk_size
+            .max(layout.size().checked_mul(2).ok_or(AllocError)?);
+        let chunk_layout =
+            Layout::from_size_align(size, layout.align().max(self.source.page_size()))
+                .map_err(|_| AllocError)?;
+        let region = self.source.acquire(chunk_layout).ok_or(AllocError)?;
+        let chunk = region.cast::<u8>();
+        let heap = unsafe { A::from_region(chunk.as_ptr(), chunk.as_ptr().add(region.len())) };
+        let heap = self.heaps[slot].insert(SubHeap {
+            heap,
+            chunk,
+            layout: chunk_layout,
+        });
+        heap.heap.allocate(layout)
+    }
+
+    /// # Safety
+    /// `ptr` must have been allocated from a sub-heap for `layout`, and not freed since.
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let heap = self
+            .heaps
+            .iter()
+            .map_while(Option::as_ref)
+            .find(|heap| heap.contains(ptr))
+            .expect("Pointer wasn't allocated from any sub-heap!");
+        heap.heap.deallocate(ptr, layout);
+    }
+}
+
+/// Grows a heap out of fixed size allocators of type `A`: up to `N` sub-heaps are built lazily,
+/// each over a chunk acquired from a [`MemorySource`]. Requests are served by the first sub-heap
+/// that succeeds, and a new sub-heap is built once all of them are full.
+///
+/// Chunks are `chunk_size` bytes, or twice the size of the request that needed them if that is
+/// larger. Deallocations are routed to the sub-heap whose chunk contains the pointer. The chunks
+/// are only released to the source when the allocator is dropped.
+pub struct CascadeAlloc<A, S: MemorySource, R: lock_api::RawMutex, const N: usize = 8> {
+    state: lock_api::Mutex<R, CascadeState<A, S, N>>,
+}
+
+impl<A: Allocator + FromRegion, S: MemorySource, R: lock_api::RawMutex, const N: usize>
+    CascadeAlloc<A, S, R, N>
+{
+    /// Creates an allocator building sub-heaps over chunks of at least `chunk_size` bytes from
+    /// `source`. Nothing is acquired until the first allocation.
+    pub const fn new(source: S, chunk_size: usize) -> Self {
+        CascadeAlloc {
+            state: lock_api::Mutex::new(CascadeState {
+                source,
+                heaps: [const { None }; N],
+                chunk_size,
+            }),
+        }
+    }
+
+    /// The number of sub-heaps built so far.
+    pub fn heaps(&self) -> usize {
+        self.state
+            .lock()
+            .heaps
+            .iter()
+            .map_while(Option::as_ref)
+            .count()
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, S: MemorySource, R: lock_api::RawMutex, const N: usize>
+    Allocator for CascadeAlloc<A, S, R, N>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.state.lock().allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.state.lock().deallocate(ptr, layout);
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, S: MemorySource, R: lock_api::RawMutex, const N: usize>
+    GlobalAlloc for CascadeAlloc<A, S, R, N>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.state.lock().allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.state
+            .lock()
+            .deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+impl<A, S: MemorySource, R: lock_api::RawMutex, const N: usize> Drop for CascadeAlloc<A, S, R, N> {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        for heap in state.heaps.iter_mut().filter_map(Option::take) {
+            drop(heap.heap);
+            unsafe { state.source.release(heap.chunk, heap.layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_source::SystemSource;
+
+    type SubHeap = LinkedListAlloc<parking_lot::RawMutex, 16>;
+
+    #[test]
+    fn cascade_alloc() {
+        let allocator: CascadeAlloc<SubHeap, _, parking_lot::RawMutex, 4> =
+            CascadeAlloc::new(SystemSource::new(), 4096);
+        assert_eq!(allocator.heaps(), 0);
+
+        // Sub-heaps are added as the previous ones run full
+        let layout = Layout::new::<[u8; 1000]>();
+        let mut blocks = Vec::new();
+        while let Ok(mut ptr) = allocator.allocate(layout) {
+            let fill = blocks.len() as u8;
+            unsafe { ptr.as_mut() }.fill(fill);
+            blocks.push((ptr, fill));
+        }
+        assert_eq!(allocator.heaps(), 4);
+        assert!(blocks.len() >= 4 * 3);
+
+        // Frees go back to the sub-heap they came from, where the space is reused
+        let (ptr, _) = blocks.swap_remove(0);
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+        assert_eq!(allocator.allocate(layout).unwrap(), ptr);
+        for (ptr, fill) in blocks {
+            assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn cascade_alloc_large_request() {
+        let allocator: CascadeAlloc<SubHeap, _, parking_lot::RawMutex, 2> =
+            CascadeAlloc::new(SystemSource::new(), 4096);
+
+        // Requests too large for a chunk get a chunk of their own
+        let layout = Layout::new::<[u8; 10_000]>();
+        let ptr = allocator.allocate(layout).unwrap();
+        assert_eq!(allocator.heaps(), 1);
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+    }
+}
@@ -0,0 +1,205 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+    ops::RangeInclusive,
+    ptr::{null_mut, NonNull},
+};
+
+use crate::shrinker::Shrinker;
+
+/// A cached block, linked into the free list.
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+struct FreeList {
+    head: *mut FreeBlock,
+    len: usize,
+}
+
+// SAFETY: The list owns the blocks on it exclusively, so it may be moved to another thread
+unsafe impl Send for FreeList {}
+
+/// Wraps an allocator to keep freed blocks of one size range on a list of its own, and hand them
+/// out again right away, without going through the inner allocator. Everything else is passed
+/// through.
+///
+/// All requests in the range are served with blocks of the largest size in it, so every cached
+/// block fits any of them. At most `capacity` blocks are cached; any more are freed to the inner
+/// allocator, like all cached blocks are when the wrapper is dropped. This suits workloads
+/// dominated by a single kind of object, where the inner allocator's search and bookkeeping
+/// can be skipped for nearly every request.
+pub struct FreelistAlloc<A: Allocator, R: lock_api::RawMutex> {
+    inner: A,
+    list: lock_api::Mutex<R, FreeList>,
+    sizes: RangeInclusive<usize>,
+    block: Layout,
+    capacity: usize,
+}
+
+impl<A: Allocator, R: lock_api::RawMutex> FreelistAlloc<A, R> {
+    /// Wraps `inner`, caching up to `capacity` blocks for requests whose size is in `sizes` and
+    /// whose alignment is at most `align`.
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two.
+    pub fn new(inner: A, sizes: RangeInclusive<usize>, align: usize, capacity: usize) -> Self {
+        let block = Layout::from_size_align(
+            (*sizes.end()).max(size_of::<FreeBlock>()),
+            align.max(align_of::<FreeBlock>()),
+        )
+        .expect("Invalid block layout!")
+        .pad_to_align();
+
+        FreelistAlloc {
+            inner,
+            list: lock_api::Mutex::new(FreeList {
+                head: null_mut(),
+                len: 0,
+            }),
+            sizes,
+            block,
+            capacity,
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The number of blocks currently cached.
+    pub fn cached(&self) -> usize {
+        self.list.lock().len
+    }
+
+    /// Frees all cached blocks to the inner allocator, and returns the number of bytes freed.
+    pub fn flush(&self) -> usize {
+        let mut list = self.list.lock();
+        while let Some(block) = unsafe { list.head.as_mut() } {
+            list.head = block.next;
+            unsafe {
+                self.inner
+                    .deallocate(NonNull::from(block).cast(), self.block)
+            };
+        }
+        let freed = list.len * self.block.size();
+        list.len = 0;
+        freed
+    }
+
+    fn is_cached(&self, layout: Layout) -> bool {
+        self.sizes.contains(&layout.size()) && layout.align() <= self.block.align()
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex> Allocator for FreelistAlloc<A, R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.is_cached(layout) {
+            return self.inner.allocate(layout);
+        }
+
+        let mut list = self.list.lock();
+        if let Some(block) = NonNull::new(list.head) {
+            list.head = unsafe { block.as_ref() }.next;
+            list.len -= 1;
+            return Ok(NonNull::slice_from_raw_parts(
+                block.cast(),
+                self.block.size(),
+            ));
+        }
+        drop(list);
+        self.inner.allocate(self.block)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if !self.is_cached(layout) {
+            self.inner.deallocate(ptr, layout);
+            return;
+        }
+
+        let mut list = self.list.lock();
+        if list.len == self.capacity {
+            drop(list);
+            self.inner.deallocate(ptr, self.block);
+            return;
+        }
+        let block = ptr.cast::<FreeBlock>();
+        block.write(FreeBlock { next: list.head });
+        list.head = block.as_ptr();
+        list.len += 1;
+    }
+}
+
+/// Shrinking flushes the cache.
+impl<A: Allocator, R: lock_api::RawMutex> Shrinker for FreelistAlloc<A, R>
+where
+    Self: Sync,
+{
+    fn shrink(&self) -> usize {
+        self.flush()
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex> GlobalAlloc for FreelistAlloc<A, R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+impl<A: Allocator, R: lock_api::RawMutex> Drop for FreelistAlloc<A, R> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn freelist_alloc() {
+        let heap = Heap::new(16 * 1024);
+        let allocator: FreelistAlloc<_, parking_lot::RawMutex> =
+            FreelistAlloc::new(&*heap, 33..=48, 16, 2);
+
+        // Requests in the range get the largest size, and freed blocks are reused right away
+        let small = Layout::new::<[u8; 40]>();
+        let a = allocator.allocate(small).unwrap();
+        let b = allocator.allocate(small).unwrap();
+        let c = allocator.allocate(Layout::new::<[u8; 48]>()).unwrap();
+        assert_eq!(a.len(), 48);
+        let free = heap.free_bytes();
+        unsafe {
+            allocator.deallocate(a.cast(), small);
+            allocator.deallocate(b.cast(), small);
+        }
+        assert_eq!(allocator.cached(), 2);
+        assert_eq!(heap.free_bytes(), free);
+        assert_eq!(allocator.allocate(Layout::new::<[u8; 33]>()).unwrap(), b);
+
+        // Beyond the capacity, and outside the range, blocks go back to the inner allocator
+        unsafe {
+            allocator.deallocate(b.cast(), small);
+            allocator.deallocate(c.cast(), Layout::new::<[u8; 48]>());
+        }
+        assert_eq!(allocator.cached(), 2);
+        assert!(heap.free_bytes() > free);
+        let large = Layout::new::<[u8; 64]>();
+        let d = allocator.allocate(large).unwrap();
+        assert_eq!(d.len(), 64);
+        unsafe { allocator.deallocate(d.cast(), large) };
+        assert_eq!(allocator.cached(), 2);
+
+        drop(allocator);
+    }
+}
@@ -0,0 +1,443 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::{align_of, size_of},
+    ptr::{self, null_mut, NonNull},
+};
+
+use super::timed_alloc::Clock;
+use crate::migrate::Relocation;
+
+/// The bookkeeping in front of every block of the young region. Blocks are linked in address
+/// order, so a collection can walk them.
+#[repr(C)]
+struct BlockHeader {
+    next: *mut BlockHeader,
+    size: usize,
+    // The clock reading when the block was allocated, if there is a clock
+    born: u64,
+    // The number of collections the block has survived
+    age: u32,
+    align_log2: u8,
+    live: bool,
+}
+
+const HEADER: usize = size_of::<BlockHeader>();
+
+struct YoungState {
+    start: *mut u8,
+    end: *mut u8,
+    // Start of the free part of the region
+    next: *mut u8,
+    first: *mut BlockHeader,
+    last: *mut BlockHeader,
+    live: usize,
+}
+
+// SAFETY: The state owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for YoungState {}
+
+impl YoungState {
+    /// Places a block for `layout` at `cursor`, returning its header, or `None` if it doesn't fit.
+    /// The data of the block must lie before `end`, even if it is empty, or freeing it would be
+    /// routed to the tenured allocator.
+    fn place(cursor: *mut u8, end: *mut u8, layout: Layout) -> Option<*mut BlockHeader> {
+        let align = layout.align().max(align_of::<BlockHeader>());
+        let data = (cursor as usize)
+            .checked_add(HEADER)?
+            .checked_next_multiple_of(align)?;
+        (data < end as usize && data.checked_add(layout.size())? <= end as usize)
+            .then(|| cursor.wrapping_add(data - HEADER - cursor as usize).cast())
+    }
+
+    fn allocate(&mut self, layout: Layout, born: u64) -> Option<NonNull<u8>> {
+        let header = Self::place(self.next, self.end, layout)?;
+        unsafe {
+            header.write(BlockHeader {
+                next: null_mut(),
+                size: layout.size(),
+                born,
+                age: 0,
+                align_log2: layout.align().trailing_zeros() as u8,
+                live: true,
+            });
+            self.link(header);
+        }
+        self.live += 1;
+
+        NonNull::new(data_of(header))
+    }
+
+    /// Appends `header` to the blocks, and moves the free part of the region past it.
+    ///
+    /// # Safety
+    /// The header must be initialized, and lie at or above the end of the last block.
+    unsafe fn link(&mut self, header: *mut BlockHeader) {
+        if self.last.is_null() {
+            self.first = header;
+        } else {
+            (*self.last).next = header;
+        }
+        self.last = header;
+        self.next = data_of(header).add((*header).size);
+    }
+
+    fn reset(&mut self) {
+        self.next = self.start;
+        self.first = null_mut();
+        self.last = null_mut();
+        self.live = 0;
+    }
+
+    fn contains(&self, ptr: *const u8) -> bool {
+        (self.start.cast_const()..self.end.cast_const()).contains(&ptr)
+    }
+}
+
+fn data_of(header: *mut BlockHeader) -> *mut u8 {
+    header.cast::<u8>().wrapping_add(HEADER)
+}
+
+/// What a [`GenerationalAlloc::collect`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectStats {
+    /// The live blocks that stayed in the young region.
+    pub survivors: usize,
+    /// The blocks moved to the tenured allocator, and their bytes.
+    pub promoted: usize,
+    pub promoted_bytes: usize,
+    /// The blocks old enough to be promoted, which the tenured allocator couldn't serve. They stay
+    /// in the young region, and are tried again in the next collection.
+    pub failed_promotions: usize,
+    /// The bytes of the young region in use after the collection.
+    pub young_bytes: usize,
+}
+
+/// Splits allocations by age, for programs mixing short lived data with data that outlives many
+/// cycles of work, like the frames of a simulation. New allocations come from a young region that
+/// is reset every cycle with [`GenerationalAlloc::collect`]. Blocks still live at that point
+/// survive the cycle, and are slid down to the start of the region, so it stays compact. Once a
+/// block has survived `tenure_after` cycles, or has lived for a number of clock ticks, it is moved
+/// to the tenured allocator instead, and never moves again.
+///
+/// Every block that moves is reported as a [`Relocation`], so the caller can fix up references to
+/// it, like with [`migrate`](crate::migrate::migrate). Requests the young region can't fit go to
+/// the tenured allocator right away.
+///
+/// ```ignore
+/// let heap = unsafe { GenerationalAlloc::<RawSpinlock, _>::new(start, end, &HEAP, 3) };
+/// loop {
+///     step(&world, &heap);
+///     heap.collect(|moved| world.fix_up(moved));
+/// }
+/// ```
+///
+/// Each block of the young region takes a header of 32 bytes on 64 bit targets.
+pub struct GenerationalAlloc<'a, R: lock_api::RawMutex, A: Allocator + ?Sized> {
+    young: lock_api::Mutex<R, YoungState>,
+    tenured: &'a A,
+    tenure_after: u32,
+    tenure_time: Option<(&'a (dyn Clock + Sync), u64)>,
+}
+
+impl<'a, R: lock_api::RawMutex, A: Allocator + ?Sized> GenerationalAlloc<'a, R, A> {
+    /// Creates an allocator whose young region is the memory between `start` and `end`, promoting
+    /// blocks to `tenured` once they have survived `tenure_after` collections.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub const unsafe fn new(
+        start: *mut u8,
+        end: *mut u8,
+        tenured: &'a A,
+        tenure_after: u32,
+    ) -> Self {
+        GenerationalAlloc {
+            young: lock_api::Mutex::new(YoungState {
+                start,
+                end,
+                next: start,
+                first: null_mut(),
+                last: null_mut(),
+                live: 0,
+            }),
+            tenured,
+            tenure_after,
+            tenure_time: None,
+        }
+    }
+
+    /// Also promotes blocks that have lived for `ticks` ticks of `clock` when they survive a
+    /// collection, however few collections that took.
+    pub fn with_tenure_time(mut self, clock: &'a (dyn Clock + Sync), ticks: u64) -> Self {
+        self.tenure_time = Some((clock, ticks));
+        self
+    }
+
+    pub fn tenured(&self) -> &'a A {
+        self.tenured
+    }
+
+    /// Whether `ptr` lies in the young region.
+    pub fn is_young(&self, ptr: *const u8) -> bool {
+        self.young.lock().contains(ptr)
+    }
+
+    /// The bytes of the young region in use, including headers and padding.
+    pub fn young_bytes(&self) -> usize {
+        let young = self.young.lock();
+        young.next as usize - young.start as usize
+    }
+
+    /// The live blocks in the young region.
+    pub fn young_allocations(&self) -> usize {
+        self.young.lock().live
+    }
+
+    /// Ends a cycle: frees the dead blocks of the young region, promotes the live ones old enough
+    /// to the tenured allocator, and compacts the rest at the start of the region. `relocated` is
+    /// called for every block that moved, and the old address must not be used after that.
+    /// Taking `&mut self` ensures no allocation is made while blocks are moving.
+    pub fn collect(&mut self, mut relocated: impl FnMut(Relocation)) -> CollectStats {
+        let now = self.tenure_time.map(|(clock, _)| clock.now());
+        let young = self.young.get_mut();
+        let mut header = young.first;
+        young.reset();
+        let mut stats = CollectStats::default();
+
+        while !header.is_null() {
+            let BlockHeader {
+                next,
+                size,
+                born,
+                age,
+                align_log2,
+                live,
+            } = unsafe { header.read() };
+            let old = data_of(header);
+            header = next;
+            if !live {
+                continue;
+            }
+
+            let layout = unsafe { Layout::from_size_align_unchecked(size, 1 << align_log2) };
+            let age = age.saturating_add(1);
+            let expired = match (now, self.tenure_time) {
+                (Some(now), Some((_, ticks))) => now.wrapping_sub(born) >= ticks,
+                _ => false,
+            };
+            if age >= self.tenure_after || expired {
+                if let Ok(new) = self.tenured.allocate(layout) {
+                    unsafe { old.copy_to_nonoverlapping(new.cast().as_ptr(), size) };
+                    relocated(Relocation {
+                        old: NonNull::new(old).unwrap(),
+                        new,
+                        layout,
+                    });
+                    stats.promoted += 1;
+                    stats.promoted_bytes += size;
+                    continue;
+                }
+                stats.failed_promotions += 1;
+            }
+
+            // Blocks only ever move down, and the headers are read before they are overwritten
+            let moved = YoungState::place(young.next, young.end, layout)
+                .expect("A surviving block doesn't fit below where it was!");
+            let new = data_of(moved);
+            unsafe {
+                ptr::copy(old, new, size);
+                moved.write(BlockHeader {
+                    next: null_mut(),
+                    size,
+                    born,
+                    age,
+                    align_log2,
+                    live: true,
+                });
+                young.link(moved);
+            }
+            young.live += 1;
+            stats.survivors += 1;
+            if new != old {
+                relocated(Relocation {
+                    old: NonNull::new(old).unwrap(),
+                    new: NonNull::slice_from_raw_parts(NonNull::new(new).unwrap(), size),
+                    layout,
+                });
+            }
+        }
+        stats.young_bytes = young.next as usize - young.start as usize;
+
+        stats
+    }
+
+    /// Frees every block of the young region at once, without promoting any. Tenured blocks are
+    /// untouched.
+    pub fn reset(&mut self) {
+        self.young.get_mut().reset();
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, A: Allocator + ?Sized> Allocator
+    for GenerationalAlloc<'_, R, A>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let born = self.tenure_time.map_or(0, |(clock, _)| clock.now());
+        match self.young.lock().allocate(layout, born) {
+            Some(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
+            None => self.tenured.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut young = self.young.lock();
+        if young.contains(ptr.as_ptr()) {
+            let header = ptr.as_ptr().sub(HEADER).cast::<BlockHeader>();
+            (*header).live = false;
+            young.live -= 1;
+        } else {
+            drop(young);
+            self.tenured.deallocate(ptr, layout);
+        }
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, A: Allocator + ?Sized> GlobalAlloc
+    for GenerationalAlloc<'_, R, A>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    const SIZE: usize = 4096;
+
+    #[test]
+    fn generational_alloc() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let tenured = Heap::with_capacity(16 * 1024, 16);
+        let mut heap: GenerationalAlloc<parking_lot::RawMutex, _> =
+            unsafe { GenerationalAlloc::new(mem, mem.add(SIZE), &tenured, 2) };
+
+        let small = Layout::new::<[u8; 100]>();
+        let aligned = Layout::from_size_align(64, 64).unwrap();
+        let dead = heap.allocate(small).unwrap().cast::<u8>();
+        let mut a = heap.allocate(aligned).unwrap().cast::<u8>();
+        unsafe {
+            a.as_ptr().write_bytes(0xA, 64);
+            heap.deallocate(dead, small);
+        }
+        assert_eq!(heap.young_allocations(), 1);
+
+        // Survivors are compacted, keeping their alignment and contents
+        let mut moves = Vec::new();
+        let stats = heap.collect(|moved| moves.push(moved));
+        assert_eq!(stats.survivors, 1);
+        assert_eq!(stats.promoted, 0);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].old, a);
+        a = moves[0].new.cast();
+        assert!(a < moves[0].old);
+        assert_eq!(a.align_offset(64), 0);
+        assert!(unsafe { a.as_ref() } == &0xA);
+        assert_eq!(stats.young_bytes, a.as_ptr() as usize + 64 - mem as usize);
+
+        // Blocks surviving enough cycles are promoted
+        moves.clear();
+        let b = heap.allocate(small).unwrap().cast::<u8>();
+        let stats = heap.collect(|moved| moves.push(moved));
+        assert_eq!((stats.promoted, stats.survivors), (1, 1));
+        assert_eq!(moves[0].old, a);
+        a = moves[0].new.cast();
+        assert!(!heap.is_young(a.as_ptr()) && tenured.contains(a.as_ptr()));
+        assert!(unsafe { a.as_ref() } == &0xA);
+        assert_eq!(heap.young_allocations(), 1);
+
+        // Requests the young region can't fit go to the tenured allocator
+        let large = Layout::new::<[u8; 2 * SIZE]>();
+        let c = heap.allocate(large).unwrap().cast::<u8>();
+        assert!(tenured.contains(c.as_ptr()));
+        unsafe {
+            heap.deallocate(a, aligned);
+            heap.deallocate(c, large);
+            heap.deallocate(moves.get(1).map_or(b, |moved| moved.new.cast()), small);
+        }
+        assert_eq!(tenured.stats().live_allocations, 0);
+        assert_eq!(heap.collect(|_| {}), CollectStats::default());
+    }
+
+    #[test]
+    fn generational_alloc_empty_at_end() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let tenured = Heap::with_capacity(16 * 1024, 16);
+        let heap: GenerationalAlloc<parking_lot::RawMutex, _> =
+            unsafe { GenerationalAlloc::new(mem, mem.add(SIZE), &tenured, 2) };
+
+        // Fill the region up to the room for exactly one more header
+        let fill = Layout::from_size_align(SIZE - 2 * HEADER, 1).unwrap();
+        let a = heap.allocate(fill).unwrap().cast::<u8>();
+        assert!(heap.is_young(a.as_ptr()));
+
+        // An empty block would start at the end of the region, so it goes to the tenured allocator
+        let empty = Layout::new::<()>();
+        let b = heap.allocate(empty).unwrap().cast::<u8>();
+        assert!(!heap.is_young(b.as_ptr()));
+        assert_eq!(tenured.stats().live_allocations, 1);
+        unsafe {
+            heap.deallocate(b, empty);
+            heap.deallocate(a, fill);
+        }
+        assert_eq!(tenured.stats().live_allocations, 0);
+        assert_eq!(heap.young_allocations(), 0);
+    }
+
+    #[test]
+    fn generational_alloc_tenure_time() {
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        let clock = || TICKS.load(Ordering::Relaxed);
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let tenured = Heap::with_capacity(16 * 1024, 16);
+        let mut heap: GenerationalAlloc<parking_lot::RawMutex, _> =
+            unsafe { GenerationalAlloc::new(mem, mem.add(SIZE), &tenured, u32::MAX) }
+                .with_tenure_time(&clock, 100);
+
+        let layout = Layout::new::<u64>();
+        let old = heap.allocate(layout).unwrap().cast::<u8>();
+        TICKS.store(60, Ordering::Relaxed);
+        let young = heap.allocate(layout).unwrap().cast::<u8>();
+        assert_eq!(heap.collect(|_| {}).promoted, 0);
+
+        TICKS.store(120, Ordering::Relaxed);
+        let mut moves = Vec::new();
+        let stats = heap.collect(|moved| moves.push(moved));
+        assert_eq!((stats.promoted, stats.survivors), (1, 1));
+        assert_eq!(moves[0].old, old);
+        assert_eq!(moves[1].old, young);
+        unsafe {
+            heap.deallocate(moves[0].new.cast(), layout);
+            heap.deallocate(moves[1].new.cast(), layout);
+        }
+        heap.reset();
+        assert_eq!(heap.young_bytes(), 0);
+    }
+}
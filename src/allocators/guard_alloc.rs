@@ -0,0 +1,138 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+use crate::memory_source::PageProtection;
+
+/// A debugging allocator giving every allocation its own region of the source, placed right at the
+/// end of the region's accessible pages, with an inaccessible guard page after it. Writing past the
+/// end of an allocation faults on the spot, instead of silently corrupting whatever follows.
+///
+/// Every allocation costs at least two pages and a round trip to the source, so this is only meant
+/// for tracking down memory corruption.
+pub struct GuardAlloc<S: PageProtection, R: lock_api::RawMutex> {
+    source: lock_api::Mutex<R, S>,
+}
+
+impl<S: PageProtection, R: lock_api::RawMutex> GuardAlloc<S, R> {
+    pub const fn new(source: S) -> Self {
+        GuardAlloc {
+            source: lock_api::Mutex::new(source),
+        }
+    }
+
+    /// The region backing an allocation: the accessible pages, followed by the guard page.
+    fn region_layout(layout: Layout, page_size: usize) -> Option<(Layout, usize)> {
+        let data_size = layout.size().max(1).checked_next_multiple_of(page_size)?;
+        let region = Layout::from_size_align(
+            data_size.checked_add(page_size)?,
+            layout.align().max(page_size),
+        )
+        .ok()?;
+
+        Some((region, data_size))
+    }
+
+    /// Where the allocation starts within a region starting at `region`. Requests aligned to a
+    /// page or more can't be moved back from the guard page without losing their alignment, so
+    /// they start at the region instead, and may leave a gap in front of the guard page.
+    fn alloc_ptr(region: *mut u8, data_size: usize, layout: Layout, page_size: usize) -> *mut u8 {
+        if layout.align() >= page_size {
+            return region;
+        }
+
+        let end = region as usize + data_size;
+        ((end - layout.size()) & !(layout.align() - 1)) as *mut u8
+    }
+
+    /// The start of the region an allocation lives in, undoing [`GuardAlloc::alloc_ptr`].
+    fn region_ptr(ptr: *mut u8, data_size: usize, layout: Layout, page_size: usize) -> *mut u8 {
+        if layout.align() >= page_size {
+            return ptr;
+        }
+
+        // The allocation ends less than one alignment (and thus one page) before the guard page
+        let end = (ptr as usize + layout.size()).next_multiple_of(page_size);
+        (end - data_size) as *mut u8
+    }
+}
+
+unsafe impl<S: PageProtection, R: lock_api::RawMutex> Allocator for GuardAlloc<S, R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut source = self.source.lock();
+        let page_size = source.page_size();
+        let (region_layout, data_size) =
+            Self::region_layout(layout, page_size).ok_or(AllocError)?;
+
+        let region = source
+            .acquire(region_layout)
+            .ok_or(AllocError)?
+            .cast::<u8>();
+        let guard = unsafe { region.add(data_size) };
+        if !unsafe { source.set_accessible(guard, page_size, false) } {
+            unsafe { source.release(region, region_layout) };
+            return Err(AllocError);
+        }
+
+        // Hand out everything up to the guard page, so that even writes within the returned
+        // capacity can't go unnoticed past it
+        let ptr = Self::alloc_ptr(region.as_ptr(), data_size, layout, page_size);
+        let len = guard.as_ptr() as usize - ptr as usize;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            len,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut source = self.source.lock();
+        let page_size = source.page_size();
+        let (region_layout, data_size) = Self::region_layout(layout, page_size).unwrap();
+
+        let region = Self::region_ptr(ptr.as_ptr(), data_size, layout, page_size);
+        let region = NonNull::new(region).unwrap();
+        let restored = source.set_accessible(region.add(data_size), page_size, true);
+        assert!(restored, "Failed to restore access to a guard page!");
+        source.release(region, region_layout);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::memory_source::SystemSource;
+
+    #[test]
+    fn guard_alloc() {
+        const PAGE_SIZE: usize = SystemSource::DEFAULT_PAGE_SIZE;
+        let allocator: GuardAlloc<SystemSource, parking_lot::RawMutex> =
+            GuardAlloc::new(SystemSource::new());
+
+        let layouts = [
+            Layout::from_size_align(1, 1).unwrap(),
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(100, 64).unwrap(),
+            Layout::from_size_align(PAGE_SIZE, 16).unwrap(),
+            Layout::from_size_align(5000, PAGE_SIZE).unwrap(),
+            Layout::from_size_align(0, 16).unwrap(),
+        ];
+        for layout in layouts {
+            let mut block = allocator.allocate(layout).unwrap();
+            let ptr = block.cast::<u8>().as_ptr();
+            assert_eq!(ptr.align_offset(layout.align()), 0);
+            assert!(block.len() >= layout.size());
+            // The allocation ends at the guard page, up to its alignment
+            let end = ptr as usize + block.len();
+            assert!(end.is_multiple_of(PAGE_SIZE));
+            if layout.align() < PAGE_SIZE {
+                assert!(block.len() - layout.size() < layout.align());
+            }
+
+            unsafe {
+                block.as_mut().fill(0xA5);
+                allocator.deallocate(block.cast(), layout);
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::linked_list_allocator::{AllocStats, CacheStats, LinkedListAlloc};
+use crate::{hooks::AllocHooks, memory_segmenter::FreeIndex, memory_source::MemorySource};
+
+/// Picks the heap of a [`HeapRegistry`] requests are served by. Deallocations are routed the same
+/// way, so the choice may only depend on the layout, and must not change while allocations made
+/// through the registry are live. Any `Fn(Layout) -> Option<&'static str>` is a policy, e.g.
+///
+/// ```ignore
+/// let registry = HeapRegistry::new(|layout: Layout| (layout.size() > 4096).then_some("bulk"));
+/// ```
+pub trait RoutePolicy {
+    /// The name of the heap serving requests for `layout`, or `None` for the first heap
+    /// registered.
+    fn route(&self, layout: Layout) -> Option<&str>;
+}
+
+/// Routes every request to the first heap registered.
+impl RoutePolicy for () {
+    fn route(&self, _layout: Layout) -> Option<&str> {
+        None
+    }
+}
+
+impl<F: Fn(Layout) -> Option<&'static str>> RoutePolicy for F {
+    fn route(&self, layout: Layout) -> Option<&str> {
+        self(layout)
+    }
+}
+
+/// Up to `N` allocators of type `A`, each registered under a name, for programs giving every
+/// subsystem a heap of its own. Subsystems look their heap up by name, while the registry itself
+/// is an allocator routing requests to one of its heaps with a [`RoutePolicy`], and aggregates the
+/// statistics of heaps that keep them. Allocations through the registry are handed out at exactly
+/// the size requested, so a block freed with any size the [`Allocator`] contract allows is routed
+/// to the heap that served it.
+///
+/// ```ignore
+/// let registry = HeapRegistry::<LinkedListAlloc<RawSpinlock>, _, 4>::new(())
+///     .with_heap("render", render_heap)
+///     .with_heap("audio", audio_heap);
+/// let audio = registry.get("audio").unwrap();
+/// ```
+pub struct HeapRegistry<A: Allocator, P: RoutePolicy = (), const N: usize = 8> {
+    // The registered heaps come first, in the order they were registered in
+    heaps: [Option<Slot<A>>; N],
+    policy: P,
+}
+
+struct Slot<A> {
+    name: &'static str,
+    heap: A,
+    // Allocations made from the heap through the registry and not freed yet
+    live: AtomicUsize,
+}
+
+impl<A: Allocator, P: RoutePolicy, const N: usize> HeapRegistry<A, P, N> {
+    pub const fn new(policy: P) -> Self {
+        HeapRegistry {
+            heaps: [const { None }; N],
+            policy,
+        }
+    }
+
+    /// Registers `heap` under `name`, see [`HeapRegistry::register`].
+    ///
+    /// # Panics
+    /// Panics if the heap can't be registered.
+    pub fn with_heap(mut self, name: &'static str, heap: A) -> Self {
+        if self.register(name, heap).is_err() {
+            panic!("The heap couldn't be registered!");
+        }
+        self
+    }
+
+    /// Registers `heap` under `name`. Returns the heap if the name is taken, or `N` heaps are
+    /// registered already.
+    pub fn register(&mut self, name: &'static str, heap: A) -> Result<(), A> {
+        if self.get(name).is_some() {
+            return Err(heap);
+        }
+        match self.heaps.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Slot {
+                    name,
+                    heap,
+                    live: AtomicUsize::new(0),
+                });
+                Ok(())
+            }
+            None => Err(heap),
+        }
+    }
+
+    /// Takes the heap registered under `name` out of the registry, e.g. once the subsystem using
+    /// it shuts down. Requests the policy routes to it fail from then on, and if it was the first
+    /// heap, the next one takes its place for requests the policy doesn't route by name.
+    ///
+    /// Returns `None` if no heap is registered under `name`, or if memory allocated from it
+    /// through the registry hasn't been freed yet, as freeing it afterwards would go elsewhere.
+    pub fn unregister(&mut self, name: &str) -> Option<A> {
+        let index = self
+            .heaps
+            .iter()
+            .position(|slot| matches!(slot, Some(slot) if slot.name == name))?;
+        if *self.heaps[index].as_mut()?.live.get_mut() != 0 {
+            return None;
+        }
+        let slot = self.heaps[index].take();
+        // Keep the remaining heaps in registration order, ahead of the free slots
+        self.heaps[index..].rotate_left(1);
+        slot.map(|slot| slot.heap)
+    }
+
+    /// The heap registered under `name`.
+    pub fn get(&self, name: &str) -> Option<&A> {
+        self.iter()
+            .find(|(heap_name, _)| *heap_name == name)
+            .map(|(_, heap)| heap)
+    }
+
+    /// The registered heaps and their names, in the order they were registered in.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &A)> {
+        self.slots().map(|slot| (slot.name, &slot.heap))
+    }
+
+    fn slots(&self) -> impl Iterator<Item = &Slot<A>> {
+        self.heaps.iter().map_while(Option::as_ref)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// The heap requests for `layout` are routed to, if it is registered.
+    pub fn route(&self, layout: Layout) -> Option<&A> {
+        self.route_slot(layout).map(|slot| &slot.heap)
+    }
+
+    fn route_slot(&self, layout: Layout) -> Option<&Slot<A>> {
+        match self.policy.route(layout) {
+            Some(name) => self.slots().find(|slot| slot.name == name),
+            None => self.slots().next(),
+        }
+    }
+}
+
+impl<
+        R: lock_api::RawMutex,
+        const GRANULE: usize,
+        S: MemorySource,
+        I: FreeIndex,
+        H: AllocHooks,
+        P: RoutePolicy,
+        const N: usize,
+    > HeapRegistry<LinkedListAlloc<R, GRANULE, S, I, H>, P, N>
+{
+    /// The statistics of all heaps added up, without a name. Peaks are the sum of the peaks of
+    /// each heap, which may not have been reached at the same time, so they are an upper bound.
+    pub fn stats(&self) -> AllocStats {
+        self.iter().fold(AllocStats::default(), |total, (_, heap)| {
+            add_stats(total, heap.stats())
+        })
+    }
+}
+
+fn add_stats(a: AllocStats, b: AllocStats) -> AllocStats {
+    AllocStats {
+        name: None,
+        used_bytes: a.used_bytes + b.used_bytes,
+        live_allocations: a.live_allocations + b.live_allocations,
+        peak_used_bytes: a.peak_used_bytes + b.peak_used_bytes,
+        peak_live_allocations: a.peak_live_allocations + b.peak_live_allocations,
+        allocations: a.allocations + b.allocations,
+        deallocations: a.deallocations + b.deallocations,
+        failed_allocations: a.failed_allocations + b.failed_allocations,
+        grows: a.grows + b.grows,
+        shrinks: a.shrinks + b.shrinks,
+        shrinker_runs: a.shrinker_runs + b.shrinker_runs,
+        reclaimed_bytes: a.reclaimed_bytes + b.reclaimed_bytes,
+        failed_frees: a.failed_frees + b.failed_frees,
+        quick_lists: add_cache_stats(a.quick_lists, b.quick_lists),
+        reserves: add_cache_stats(a.reserves, b.reserves),
+    }
+}
+
+fn add_cache_stats(a: CacheStats, b: CacheStats) -> CacheStats {
+    CacheStats {
+        hits: a.hits + b.hits,
+        misses: a.misses + b.misses,
+        refills: a.refills + b.refills,
+        flushes: a.flushes + b.flushes,
+    }
+}
+
+unsafe impl<A: Allocator, P: RoutePolicy, const N: usize> Allocator for HeapRegistry<A, P, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let slot = self.route_slot(layout).ok_or(AllocError)?;
+        let ptr = slot.heap.allocate(layout)?;
+        slot.live.fetch_add(1, Ordering::Relaxed);
+        // Any spare room the heap hands out could be freed with a size routed elsewhere
+        Ok(NonNull::slice_from_raw_parts(ptr.cast(), layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match self.route_slot(layout) {
+            Some(slot) => {
+                slot.heap.deallocate(ptr, layout);
+                slot.live.fetch_sub(1, Ordering::Relaxed);
+            }
+            None => unregistered_heap(),
+        }
+    }
+}
+
+/// Aborts on memory freed while no heap is registered to take it, which can only happen if it
+/// wasn't allocated through the registry. Unwinding out of a deallocation isn't allowed, and
+/// panicking out of an `extern "C"` function aborts, even without `std`.
+#[cold]
+extern "C" fn unregistered_heap() -> ! {
+    panic!("Freed memory whose heap is no longer registered!");
+}
+
+unsafe impl<A: Allocator, P: RoutePolicy, const N: usize> GlobalAlloc for HeapRegistry<A, P, N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_source::SystemSource;
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    #[test]
+    fn heap_registry() {
+        let policy = |layout: Layout| (layout.size() > 256).then_some("bulk");
+        let mut registry = HeapRegistry::<Heap, _, 2>::new(policy)
+            .with_heap("small", Heap::with_capacity(16 * 1024, 16))
+            .with_heap("bulk", Heap::with_capacity(64 * 1024, 16));
+        assert!(registry
+            .register("extra", Heap::with_capacity(1024, 16))
+            .is_err());
+        assert_eq!(registry.len(), 2);
+
+        let small = Layout::new::<[u8; 64]>();
+        let large = Layout::new::<[u8; 1024]>();
+        let a = registry.allocate(small).unwrap();
+        let b = registry.allocate(large).unwrap();
+        assert!(registry.get("small").unwrap().contains(a.cast().as_ptr()));
+        assert!(registry.get("bulk").unwrap().contains(b.cast().as_ptr()));
+        assert!(registry.get("audio").is_none());
+
+        // Blocks come back at their requested size, so freeing them with it routes them back
+        let edge = registry.allocate(Layout::new::<[u8; 250]>()).unwrap();
+        assert_eq!(edge.len(), 250);
+        unsafe { registry.deallocate(edge.cast(), Layout::from_size_align(250, 1).unwrap()) };
+        assert_eq!(registry.get("small").unwrap().stats().live_allocations, 1);
+
+        let stats = registry.stats();
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.allocations, 3);
+        unsafe {
+            registry.deallocate(a.cast(), small);
+            registry.deallocate(b.cast(), large);
+        }
+        assert_eq!(registry.stats().live_allocations, 0);
+        assert_eq!(registry.stats().deallocations, 3);
+
+        // Requests routed to a heap that is gone fail
+        let bulk = registry.unregister("bulk").unwrap();
+        assert_eq!(bulk.stats().deallocations, 1);
+        assert!(registry.allocate(large).is_err());
+        assert!(registry.register("bulk", bulk).is_ok());
+        assert_eq!(registry.iter().map(|(name, _)| name).last(), Some("bulk"));
+    }
+
+    #[test]
+    fn heap_registry_unregister() {
+        let mut registry = HeapRegistry::<Heap, _, 3>::new(())
+            .with_heap("render", Heap::with_capacity(4096, 16))
+            .with_heap("audio", Heap::with_capacity(4096, 16))
+            .with_heap("network", Heap::with_capacity(4096, 16));
+
+        // A heap with live allocations stays, as they would be freed into the next heap otherwise
+        let layout = Layout::new::<u64>();
+        let a = registry.allocate(layout).unwrap();
+        assert!(registry.unregister("render").is_none());
+        assert_eq!(registry.len(), 3);
+        unsafe { registry.deallocate(a.cast(), layout) };
+        let render = registry.unregister("render").unwrap();
+        assert_eq!(render.stats().live_allocations, 0);
+
+        // The next heap takes over requests not routed by name, and the order of the rest is kept
+        let b = registry.allocate(layout).unwrap();
+        assert!(registry.get("audio").unwrap().contains(b.cast().as_ptr()));
+        unsafe { registry.deallocate(b.cast(), layout) };
+        registry.register("render", render).ok().unwrap();
+        let names: Vec<_> = registry.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["audio", "network", "render"]);
+        assert!(registry.unregister("video").is_none());
+    }
+}
@@ -0,0 +1,492 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::bitmapped_block_alloc::BlockMap;
+
+/// Size (and alignment) of the superblocks heaps hold their blocks in.
+pub const SUPERBLOCK_SIZE: usize = 16 * 1024;
+/// Number of size classes. Class `i` serves requests of up to `MIN_SIZE << i` bytes, so the
+/// largest class is an eighth of a superblock. Larger requests get whole superblocks.
+pub const CLASSES: usize = 8;
+const MIN_SIZE: usize = 16;
+/// Superblocks a local heap may hold beyond what it needs before it has to return some.
+pub const SLACK_SUPERBLOCKS: usize = 4;
+/// The owner of superblocks held by the global heap.
+const GLOBAL: usize = usize::MAX;
+
+/// A free block, linked into the free list of its superblock.
+struct Block {
+    next: *mut Block,
+}
+
+/// Header at the start of every superblock. The blocks follow it.
+struct Superblock {
+    // Index of the heap holding this superblock, or GLOBAL. Only changed with both the old and
+    // the new heap locked
+    owner: AtomicUsize,
+    class: usize,
+    free: *mut Block,
+    used: usize,
+    capacity: usize,
+    // Links of the superblocks of the same class with free blocks, held by the same heap
+    next: *mut Superblock,
+    prev: *mut Superblock,
+}
+
+impl Superblock {
+    /// Whether at least a quarter of the superblock is free.
+    fn is_empty_enough(&self) -> bool {
+        self.used * 4 <= self.capacity * 3
+    }
+}
+
+/// The superblocks held by one heap.
+struct HoardHeap {
+    // The superblocks of every class with at least one free block
+    partial: [*mut Superblock; CLASSES],
+    // Bytes of blocks in use
+    used: usize,
+    // Bytes of superblocks held
+    held: usize,
+}
+
+// SAFETY: The heap only refers to superblocks it holds, so it may be moved to another thread
+unsafe impl Send for HoardHeap {}
+
+impl HoardHeap {
+    const EMPTY: Self = HoardHeap {
+        partial: [null_mut(); CLASSES],
+        used: 0,
+        held: 0,
+    };
+
+    /// Whether the heap holds more than a quarter and more than [`SLACK_SUPERBLOCKS`] worth of
+    /// memory beyond what is in use, and has to return a superblock.
+    fn is_too_empty(&self) -> bool {
+        self.used + SLACK_SUPERBLOCKS * SUPERBLOCK_SIZE < self.held && self.used * 4 < self.held * 3
+    }
+
+    fn take_block(&mut self, class: usize) -> Option<*mut u8> {
+        let superblock_ptr = self.partial[class];
+        let superblock = unsafe { superblock_ptr.as_mut() }?;
+        let block = superblock.free;
+        superblock.free = unsafe { (*block).next };
+        superblock.used += 1;
+        self.used += block_size(class);
+        if superblock.free.is_null() {
+            self.unlink(superblock_ptr);
+        }
+        Some(block.cast())
+    }
+
+    /// # Safety
+    /// `ptr` must be a block in use of `superblock_ptr`, which must be held by this heap.
+    unsafe fn put_block(&mut self, superblock_ptr: *mut Superblock, ptr: *mut u8) {
+        let superblock = &mut *superblock_ptr;
+        let was_full = superblock.free.is_null();
+        let block = ptr.cast::<Block>();
+        block.write(Block {
+            next: superblock.free,
+        });
+        superblock.free = block;
+        superblock.used -= 1;
+        self.used -= block_size(superblock.class);
+        if was_full {
+            self.push_front(superblock_ptr);
+        }
+    }
+
+    fn adopt(&mut self, superblock_ptr: *mut Superblock, owner: usize) {
+        let superblock = unsafe { &mut *superblock_ptr };
+        superblock.owner.store(owner, Ordering::Relaxed);
+        self.held += SUPERBLOCK_SIZE;
+        self.used += superblock.used * block_size(superblock.class);
+        if !superblock.free.is_null() {
+            self.push_front(superblock_ptr);
+        }
+    }
+
+    fn release(&mut self, superblock_ptr: *mut Superblock) {
+        let superblock = unsafe { &mut *superblock_ptr };
+        self.held -= SUPERBLOCK_SIZE;
+        self.used -= superblock.used * block_size(superblock.class);
+        if !superblock.free.is_null() {
+            self.unlink(superblock_ptr);
+        }
+    }
+
+    /// Finds a superblock that is at least a quarter free, looking at the superblocks of `class`
+    /// first.
+    fn find_empty_enough(&self, class: usize) -> Option<*mut Superblock> {
+        (class..CLASSES).chain(0..class).find_map(|class| {
+            let mut superblock = self.partial[class];
+            while let Some(current) = unsafe { superblock.as_ref() } {
+                if current.is_empty_enough() {
+                    return Some(superblock);
+                }
+                superblock = current.next;
+            }
+            None
+        })
+    }
+
+    fn push_front(&mut self, superblock_ptr: *mut Superblock) {
+        let superblock = unsafe { &mut *superblock_ptr };
+        superblock.prev = null_mut();
+        superblock.next = self.partial[superblock.class];
+        if let Some(next) = unsafe { superblock.next.as_mut() } {
+            next.prev = superblock_ptr;
+        }
+        self.partial[superblock.class] = superblock_ptr;
+    }
+
+    fn unlink(&mut self, superblock_ptr: *mut Superblock) {
+        let superblock = unsafe { &mut *superblock_ptr };
+        if let Some(prev) = unsafe { superblock.prev.as_mut() } {
+            prev.next = superblock.next;
+        } else {
+            self.partial[superblock.class] = superblock.next;
+        }
+        if let Some(next) = unsafe { superblock.next.as_mut() } {
+            next.prev = superblock.prev;
+        }
+        superblock.next = null_mut();
+        superblock.prev = null_mut();
+    }
+}
+
+struct GlobalHeap<const WORDS: usize> {
+    heap: HoardHeap,
+    superblocks: BlockMap<WORDS>,
+}
+
+// SAFETY: The global heap owns its region exclusively, and only refers to superblocks in it
+unsafe impl<const WORDS: usize> Send for GlobalHeap<WORDS> {}
+
+fn class_for(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_SIZE);
+    let class = size.next_power_of_two().trailing_zeros() - MIN_SIZE.trailing_zeros();
+    (class < CLASSES as u32).then_some(class as usize)
+}
+
+const fn block_size(class: usize) -> usize {
+    MIN_SIZE << class
+}
+
+fn superblock_of(ptr: *mut u8) -> *mut Superblock {
+    ptr.map_addr(|addr| addr & !(SUPERBLOCK_SIZE - 1)).cast()
+}
+
+fn superblock_layout() -> Layout {
+    Layout::from_size_align(SUPERBLOCK_SIZE, SUPERBLOCK_SIZE).unwrap()
+}
+
+/// An allocator in the style of Hoard, with `HEAPS` local heaps and a global heap above them.
+/// Every thread, or every CPU, allocates from the local heap the `current_heap` function passed
+/// to [`HoardAlloc::new`] picks for it, so threads on different heaps never contend for a lock.
+///
+/// Local heaps hold superblocks of [`SUPERBLOCK_SIZE`] bytes, each of which serves a single size
+/// class. Heaps take superblocks from the global heap when they run out of blocks, and blocks are
+/// always freed to the heap holding their superblock, whichever thread frees them. Once a heap
+/// holds more than a quarter and more than [`SLACK_SUPERBLOCKS`] superblocks worth of memory
+/// beyond what is in use, it returns a superblock that is at least a quarter free to the global
+/// heap, where other heaps may pick it up. This bounds the memory held but unused by every heap,
+/// even when one thread keeps allocating what another one frees.
+///
+/// Requests larger than the largest class get a run of whole superblocks from the global heap,
+/// which tracks them in a bitmap of `WORDS` words, so the region holds at most
+/// `WORDS * usize::BITS` superblocks.
+pub struct HoardAlloc<R: lock_api::RawMutex, const HEAPS: usize = 4, const WORDS: usize = 4> {
+    heaps: [lock_api::Mutex<R, HoardHeap>; HEAPS],
+    global: lock_api::Mutex<R, GlobalHeap<WORDS>>,
+    current_heap: fn() -> usize,
+}
+
+impl<R: lock_api::RawMutex, const HEAPS: usize, const WORDS: usize> HoardAlloc<R, HEAPS, WORDS> {
+    /// Creates an allocator managing the memory between `start` and `end`. Superblocks start at
+    /// the first superblock aligned address. `current_heap` returns the local heap to use for the
+    /// calling thread, typically the thread or CPU id, and is taken modulo `HEAPS`.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub unsafe fn new(start: *mut u8, end: *mut u8, current_heap: fn() -> usize) -> Self {
+        let base = start.add(start.align_offset(SUPERBLOCK_SIZE));
+        HoardAlloc {
+            heaps: core::array::from_fn(|_| lock_api::Mutex::new(HoardHeap::EMPTY)),
+            global: lock_api::Mutex::new(GlobalHeap {
+                heap: HoardHeap::EMPTY,
+                superblocks: BlockMap::new::<SUPERBLOCK_SIZE>(base, end),
+            }),
+            current_heap,
+        }
+    }
+
+    /// The bytes of superblocks held by local heap `heap`.
+    pub fn held_bytes(&self, heap: usize) -> usize {
+        self.heaps[heap].lock().held
+    }
+
+    /// The bytes of blocks in use from local heap `heap`, wherever they were allocated.
+    pub fn used_bytes(&self, heap: usize) -> usize {
+        self.heaps[heap].lock().used
+    }
+
+    /// The number of superblocks neither held by a heap nor part of a large allocation.
+    pub fn free_superblocks(&self) -> usize {
+        self.global.lock().superblocks.free_blocks()
+    }
+
+    fn allocate_small(&self, class: usize) -> Option<*mut u8> {
+        let index = (self.current_heap)() % HEAPS;
+        let mut heap = self.heaps[index].lock();
+        if let Some(ptr) = heap.take_block(class) {
+            return Some(ptr);
+        }
+
+        let mut global = self.global.lock();
+        let superblock = global.heap.partial[class];
+        if superblock.is_null() {
+            let superblock = Self::new_superblock(&mut global, class)?;
+            heap.adopt(superblock, index);
+        } else {
+            global.heap.release(superblock);
+            heap.adopt(superblock, index);
+        }
+        drop(global);
+        heap.take_block(class)
+    }
+
+    fn new_superblock(global: &mut GlobalHeap<WORDS>, class: usize) -> Option<*mut Superblock> {
+        let base = global
+            .superblocks
+            .allocate::<SUPERBLOCK_SIZE>(superblock_layout())?;
+
+        // Blocks are aligned to their size, so the first one comes after a whole block or more
+        let size = block_size(class);
+        let first = size_of::<Superblock>().next_multiple_of(size);
+        let capacity = (SUPERBLOCK_SIZE - first) / size;
+        let mut free = null_mut();
+        for i in (0..capacity).rev() {
+            unsafe {
+                let block = base.add(first + i * size).cast::<Block>();
+                block.write(Block { next: free });
+                free = block;
+            }
+        }
+
+        let superblock = base.cast::<Superblock>();
+        unsafe {
+            superblock.write(Superblock {
+                owner: AtomicUsize::new(GLOBAL),
+                class,
+                free,
+                used: 0,
+                capacity,
+                next: null_mut(),
+                prev: null_mut(),
+            })
+        };
+        Some(superblock)
+    }
+
+    /// # Safety
+    /// `ptr` must be a block in use, allocated for `class`.
+    unsafe fn deallocate_small(&self, ptr: *mut u8, class: usize) {
+        let superblock = superblock_of(ptr);
+        loop {
+            // The superblock may move to another heap until the heap holding it is locked
+            let owner = (*superblock).owner.load(Ordering::Relaxed);
+            if owner == GLOBAL {
+                let mut global = self.global.lock();
+                if (*superblock).owner.load(Ordering::Relaxed) != owner {
+                    continue;
+                }
+                global.heap.put_block(superblock, ptr);
+                if (*superblock).used == 0 {
+                    global.heap.release(superblock);
+                    global
+                        .superblocks
+                        .deallocate::<SUPERBLOCK_SIZE>(superblock.cast(), superblock_layout());
+                }
+                return;
+            }
+
+            let mut heap = self.heaps[owner].lock();
+            if (*superblock).owner.load(Ordering::Relaxed) != owner {
+                continue;
+            }
+            heap.put_block(superblock, ptr);
+            if !heap.is_too_empty() {
+                return;
+            }
+            let Some(victim) = heap.find_empty_enough(class) else {
+                return;
+            };
+            heap.release(victim);
+            let mut global = self.global.lock();
+            if (*victim).used == 0 {
+                global
+                    .superblocks
+                    .deallocate::<SUPERBLOCK_SIZE>(victim.cast(), superblock_layout());
+            } else {
+                global.heap.adopt(victim, GLOBAL);
+            }
+            return;
+        }
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const HEAPS: usize, const WORDS: usize> Allocator
+    for HoardAlloc<R, HEAPS, WORDS>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (ptr, size) = match class_for(layout) {
+            Some(class) => (self.allocate_small(class), block_size(class)),
+            None => (
+                self.global
+                    .lock()
+                    .superblocks
+                    .allocate::<SUPERBLOCK_SIZE>(layout),
+                layout.size().next_multiple_of(SUPERBLOCK_SIZE),
+            ),
+        };
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr.ok_or(AllocError)?).unwrap(),
+            size,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match class_for(layout) {
+            Some(class) => self.deallocate_small(ptr.as_ptr(), class),
+            None => self
+                .global
+                .lock()
+                .superblocks
+                .deallocate::<SUPERBLOCK_SIZE>(ptr.as_ptr(), layout),
+        }
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const HEAPS: usize, const WORDS: usize> GlobalAlloc
+    for HoardAlloc<R, HEAPS, WORDS>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, sync::mpsc, thread};
+
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 128 * SUPERBLOCK_SIZE;
+
+    thread_local! {
+        static HEAP: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn current_heap() -> usize {
+        HEAP.with(|heap| heap.get())
+    }
+
+    fn region() -> (*mut u8, Layout) {
+        let layout = Layout::from_size_align(SIZE, SUPERBLOCK_SIZE).unwrap();
+        (unsafe { std::alloc::alloc(layout) }, layout)
+    }
+
+    #[test]
+    fn hoard_alloc() {
+        let (mem, region) = region();
+        let allocator: HoardAlloc<parking_lot::RawMutex, 2, 2> =
+            unsafe { HoardAlloc::new(mem, mem.add(SIZE), current_heap) };
+
+        let small = Layout::new::<[u8; 24]>();
+        let a = allocator.allocate(small).unwrap();
+        assert_eq!(a.len(), 32);
+        assert_eq!(allocator.held_bytes(0), SUPERBLOCK_SIZE);
+        assert_eq!(allocator.used_bytes(0), 32);
+        assert_eq!(allocator.held_bytes(1), 0);
+        unsafe { allocator.deallocate(a.cast(), small) };
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..4000 {
+            if rng.gen_bool(0.55) {
+                let size = if rng.gen_bool(0.9) {
+                    rng.gen_range(1..2048)
+                } else {
+                    rng.gen_range(2048..3 * SUPERBLOCK_SIZE)
+                };
+                let align = 1 << rng.gen_range(0..7);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(align), 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        // The heap gave back all but a few superblocks once it was empty
+        assert_eq!(allocator.used_bytes(0), 0);
+        assert!(allocator.held_bytes(0) <= SLACK_SUPERBLOCKS * SUPERBLOCK_SIZE);
+        assert!(allocator.free_superblocks() >= 128 - SLACK_SUPERBLOCKS);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn hoard_alloc_producer_consumer() {
+        let (mem, region) = region();
+        let allocator: HoardAlloc<parking_lot::RawMutex, 2, 2> =
+            unsafe { HoardAlloc::new(mem, mem.add(SIZE), current_heap) };
+        let layout = Layout::new::<[u8; 256]>();
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel::<usize>(64);
+            let allocator = &allocator;
+            scope.spawn(move || {
+                HEAP.with(|heap| heap.set(1));
+                for addr in receiver {
+                    let ptr = NonNull::new(addr as *mut u8).unwrap();
+                    unsafe { allocator.deallocate(ptr, layout) };
+                }
+            });
+
+            for _ in 0..20_000 {
+                let ptr = allocator.allocate(layout).unwrap();
+                sender.send(ptr.cast::<u8>().as_ptr() as usize).unwrap();
+            }
+        });
+
+        // Blocks went back to the producer's heap, which reused them rather than growing without
+        // bounds, and the consumer's heap never held anything
+        assert_eq!(allocator.used_bytes(0), 0);
+        assert_eq!(allocator.held_bytes(1), 0);
+        assert!(allocator.held_bytes(0) <= SLACK_SUPERBLOCKS * SUPERBLOCK_SIZE);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
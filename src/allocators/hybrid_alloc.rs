@@ -0,0 +1,294 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    mem::size_of,
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+use super::buddy_alloc::BuddyHeap;
+
+/// The smallest block of the buddy heap.
+const MIN_BLOCK: usize = 16;
+/// Size (and alignment) of the slabs, each of which is a single block of the buddy heap.
+pub const SLAB_SIZE: usize = 4096;
+/// Number of slab size classes. Class `i` serves requests of up to `MIN_BLOCK << i` bytes, so the
+/// largest class is a quarter of a slab.
+pub const SLAB_CLASSES: usize = 7;
+
+/// Header at the start of every slab. The slots follow it.
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    free_slots: *mut u8,
+    used: usize,
+    capacity: usize,
+}
+
+struct HybridHeap {
+    buddy: BuddyHeap<MIN_BLOCK>,
+    // The slabs of every class with at least one free slot
+    partial: [*mut Slab; SLAB_CLASSES],
+}
+
+// SAFETY: The heap owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for HybridHeap {}
+
+impl HybridHeap {
+    const SLAB_ORDER: usize = (SLAB_SIZE / MIN_BLOCK).trailing_zeros() as usize;
+
+    fn class_for(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align()).max(MIN_BLOCK);
+        let class = size.next_power_of_two().trailing_zeros() - MIN_BLOCK.trailing_zeros();
+        (class < SLAB_CLASSES as u32).then_some(class as usize)
+    }
+
+    const fn slot_size(class: usize) -> usize {
+        MIN_BLOCK << class
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<*mut u8> {
+        let Some(class) = Self::class_for(layout) else {
+            let order = BuddyHeap::<MIN_BLOCK>::order_for(layout)?;
+            if layout.align() > self.buddy.base_align() {
+                return None;
+            }
+            return self.buddy.allocate(order);
+        };
+
+        if self.partial[class].is_null() {
+            let slab = self.create_slab(class)?;
+            self.push_front(class, slab);
+        }
+        let slab_ptr = self.partial[class];
+        let slab = unsafe { slab_ptr.as_mut() }.unwrap();
+        let slot = slab.free_slots;
+        slab.free_slots = unsafe { slot.cast::<*mut u8>().read() };
+        slab.used += 1;
+        if slab.free_slots.is_null() {
+            self.unlink(class, slab_ptr);
+        }
+
+        Some(slot)
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`HybridHeap::allocate`] for the same `layout`.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let Some(class) = Self::class_for(layout) else {
+            let order = BuddyHeap::<MIN_BLOCK>::order_for(layout).unwrap();
+            self.buddy.deallocate(ptr, order);
+            return;
+        };
+
+        let slab_ptr = ptr.map_addr(|addr| addr & !(SLAB_SIZE - 1)) as *mut Slab;
+        let slab = slab_ptr.as_mut().unwrap();
+        let was_full = slab.used == slab.capacity;
+        ptr.cast::<*mut u8>().write(slab.free_slots);
+        slab.free_slots = ptr;
+        slab.used -= 1;
+        if was_full {
+            self.push_front(class, slab_ptr);
+        }
+
+        // Return empty slabs, but keep the last one of each class around so a single object being
+        // allocated and freed repeatedly doesn't create and destroy a slab every time
+        if slab.used == 0 && !(slab.next.is_null() && slab.prev.is_null()) {
+            self.unlink(class, slab_ptr);
+            self.buddy.deallocate(slab_ptr.cast(), Self::SLAB_ORDER);
+        }
+    }
+
+    fn create_slab(&mut self, class: usize) -> Option<*mut Slab> {
+        let base = self.buddy.allocate(Self::SLAB_ORDER)?;
+
+        // Slots are aligned to their size, so the first one comes after a whole slot or more
+        let slot_size = Self::slot_size(class);
+        let first = size_of::<Slab>().next_multiple_of(slot_size);
+        let capacity = (SLAB_SIZE - first) / slot_size;
+        let mut free_slots = null_mut();
+        for i in (0..capacity).rev() {
+            unsafe {
+                let slot = base.add(first + i * slot_size);
+                slot.cast::<*mut u8>().write(free_slots);
+                free_slots = slot;
+            }
+        }
+
+        let slab = base as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                next: null_mut(),
+                prev: null_mut(),
+                free_slots,
+                used: 0,
+                capacity,
+            })
+        };
+        Some(slab)
+    }
+
+    fn push_front(&mut self, class: usize, slab_ptr: *mut Slab) {
+        let slab = unsafe { slab_ptr.as_mut() }.unwrap();
+        slab.prev = null_mut();
+        slab.next = self.partial[class];
+        if let Some(next) = unsafe { slab.next.as_mut() } {
+            next.prev = slab_ptr;
+        }
+        self.partial[class] = slab_ptr;
+    }
+
+    fn unlink(&mut self, class: usize, slab_ptr: *mut Slab) {
+        let slab = unsafe { slab_ptr.as_mut() }.unwrap();
+        if let Some(prev) = unsafe { slab.prev.as_mut() } {
+            prev.next = slab.next;
+        } else {
+            self.partial[class] = slab.next;
+        }
+        if let Some(next) = unsafe { slab.next.as_mut() } {
+            next.prev = slab.prev;
+        }
+        slab.next = null_mut();
+        slab.prev = null_mut();
+    }
+}
+
+/// A general purpose heap composed of a buddy allocator and slab caches. Requests of up to a
+/// quarter of a [`SLAB_SIZE`] are served from slabs of equally sized slots, one size class per
+/// slab, which are themselves blocks of the buddy heap. Larger requests get a block of the buddy
+/// heap of their own.
+///
+/// Small objects thus carry no per-allocation metadata and are packed tightly, while large ones
+/// get the logarithmic allocation and merging of the buddy heap.
+pub struct HybridAlloc<R: lock_api::RawMutex> {
+    heap: lock_api::Mutex<R, HybridHeap>,
+}
+
+impl<R: lock_api::RawMutex> HybridAlloc<R> {
+    /// Creates an allocator managing the memory between `start` and `end`. The buddy heap starts
+    /// at the first slab aligned address after its state bytes.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        HybridAlloc {
+            heap: lock_api::Mutex::new(HybridHeap {
+                buddy: BuddyHeap::new(start, end, SLAB_SIZE),
+                partial: [null_mut(); SLAB_CLASSES],
+            }),
+        }
+    }
+
+    /// The bytes of all free blocks of the buddy heap. Free slots of the slabs aren't counted.
+    pub fn free_bytes(&self) -> usize {
+        self.heap.lock().buddy.free_bytes()
+    }
+
+    /// The memory the slabs and larger blocks are allocated from.
+    pub fn region(&self) -> Range<*mut u8> {
+        self.heap.lock().buddy.region()
+    }
+
+    /// Whether `ptr` lies in the memory the slabs and larger blocks are allocated from.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.heap.lock().buddy.contains(ptr)
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> Allocator for HybridAlloc<R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.heap.lock().allocate(layout).ok_or(AllocError)?;
+        let size = match HybridHeap::class_for(layout) {
+            Some(class) => HybridHeap::slot_size(class),
+            None => BuddyHeap::<MIN_BLOCK>::block_size(
+                BuddyHeap::<MIN_BLOCK>::order_for(layout).unwrap(),
+            ),
+        };
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            size,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.heap.lock().deallocate(ptr.as_ptr(), layout);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> GlobalAlloc for HybridAlloc<R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.lock().allocate(layout).unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(ptr, layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 256 * 1024;
+
+    #[test]
+    fn hybrid_alloc() {
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let allocator: HybridAlloc<parking_lot::RawMutex> =
+            unsafe { HybridAlloc::new(mem, mem.add(SIZE)) };
+        let free = allocator.free_bytes();
+
+        // Small objects share a slab, large ones get a block of their own
+        let small = Layout::new::<[u8; 24]>();
+        let a = allocator.allocate(small).unwrap();
+        let b = allocator.allocate(small).unwrap();
+        assert_eq!(a.len(), 32);
+        assert_eq!(
+            b.cast::<u8>().as_ptr() as usize - a.cast::<u8>().as_ptr() as usize,
+            32
+        );
+        assert_eq!(allocator.free_bytes(), free - SLAB_SIZE);
+        let large = Layout::new::<[u8; 5000]>();
+        let c = allocator.allocate(large).unwrap();
+        assert_eq!(c.len(), 8192);
+        assert_eq!(c.cast::<u8>().as_ptr().align_offset(SLAB_SIZE), 0);
+        unsafe {
+            allocator.deallocate(a.cast(), small);
+            allocator.deallocate(b.cast(), small);
+            allocator.deallocate(c.cast(), large);
+        }
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..4000 {
+            if rng.gen_bool(0.55) {
+                let size = if rng.gen_bool(0.8) {
+                    rng.gen_range(1..1024)
+                } else {
+                    rng.gen_range(1024..16384)
+                };
+                let align = 1 << rng.gen_range(0..7);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(align), 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        // At most one empty slab per class is kept around
+        assert!(allocator.free_bytes() >= free - SLAB_CLASSES * SLAB_SIZE);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
@@ -1,216 +1,3561 @@
 use core::{
-    alloc::{AllocError, Allocator, Layout},
-    ptr::NonNull,
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    cell::UnsafeCell,
+    ffi::CStr,
+    fmt,
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::Range,
+    ptr::{null_mut, NonNull},
     slice::from_raw_parts_mut,
 };
 
-use crate::memory_segmenter::{MemorySegmenter, SegmentMetadata};
+use crate::{
+    hooks::{AllocHooks, NoHooks},
+    memory_segmenter::{
+        FitPolicy, FreeIndex, LinearIndex, MemorySegmenter, Placement, SegmentFit, SegmentMetadata,
+        DEFAULT_GRANULARITY,
+    },
+    memory_source::{MemorySource, NoSource},
+    migrate::{self, MigrationError, Relocation},
+    shrinker::Shrinker,
+    snapshot::HeapEntry,
+};
+
+#[cfg(any(feature = "heap-map", test))]
+use crate::heap_map;
+#[cfg(feature = "pointer-mangling")]
+use crate::memory_segmenter::LinkKey;
+#[cfg(any(feature = "std", test))]
+use crate::{
+    memory_source::SystemSource,
+    svg::{SvgMap, SvgSegment},
+};
+
+use super::{
+    bootstrap_alloc::BootstrapAlloc, quick_lists::QuickLists, reserves::Reserves,
+    small_bins::SmallBins,
+};
+
+pub use super::reserves::MAX_RESERVES;
+
+/// Requests of at least this many bytes are served directly by the [`MemorySource`] of
+/// allocators constructed over one, unless configured otherwise.
+pub const DEFAULT_HUGE_THRESHOLD: usize = 128 * 1024;
+
+#[derive(Debug)]
+struct LinkedListAllocImpl<const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks> {
+    segmenter_list: MemorySegmenter<GRANULE, I>,
+    source: S,
+    // The region the segmenter manages, if it was acquired from the source
+    source_region: Option<(NonNull<u8>, Layout)>,
+    // The size of the region to acquire from the source on first use
+    heap_size: usize,
+    huge_threshold: usize,
+    policy: FitPolicy,
+    quick_lists: QuickLists<GRANULE>,
+    quick_lists_enabled: bool,
+    reserves: Reserves,
+    small_bins: SmallBins<GRANULE>,
+    small_bins_enabled: bool,
+    deferred_coalescing: bool,
+    // Segments released without being coalesced since the last coalescing pass
+    pending_coalesce: usize,
+    // Replaces the fit policy while set
+    random_placement: Option<(XorShift64, bool)>,
+    // Replaces the fit policy and the random placement while set, for allocations from either
+    // end of the heap
+    heap_end: Option<Placement>,
+    preserve_wilderness: bool,
+    leak_policy: LeakPolicy,
+    free_error_policy: FreeErrorPolicy,
+    // Mangles the links of the heap, kept for heaps set up later
+    #[cfg(feature = "pointer-mangling")]
+    link_key: Option<LinkKey>,
+    // The tag given to allocations made through the plain allocation interfaces
+    #[cfg(feature = "tagging")]
+    current_tag: u8,
+    // Usable bytes of the live allocations of each tag, and the most they may reach
+    #[cfg(feature = "tagging")]
+    tag_usage: [usize; 256],
+    #[cfg(feature = "tagging")]
+    tag_budgets: [usize; 256],
+    #[cfg(feature = "tagging")]
+    budget_handler: Option<BudgetHandler>,
+    shrinkers: Shrinkers,
+    stats: AllocStats,
+    hooks: H,
+}
+
+/// Usage statistics of a [`LinkedListAlloc`], see [`LinkedListAlloc::stats`]. Sizes are usable
+/// sizes, so they include the padding of each allocation, but not its metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// The name of the allocator, see [`LinkedListAlloc::with_name`].
+    pub name: Option<&'static CStr>,
+    /// The bytes of all live allocations.
+    pub used_bytes: usize,
+    pub live_allocations: usize,
+    /// The most `used_bytes` has been since construction or [`LinkedListAlloc::reset_peaks`].
+    pub peak_used_bytes: usize,
+    /// The most `live_allocations` has been since construction or
+    /// [`LinkedListAlloc::reset_peaks`].
+    pub peak_live_allocations: usize,
+    /// The number of successful allocations since construction, not counting resizes.
+    pub allocations: u64,
+    pub deallocations: u64,
+    /// The number of allocations and resizes that couldn't be served.
+    pub failed_allocations: u64,
+    pub grows: u64,
+    pub shrinks: u64,
+    /// The number of times the registered shrinkers were run, and the bytes they reclaimed.
+    pub shrinker_runs: u64,
+    pub reclaimed_bytes: u64,
+    /// The number of blocks that couldn't be freed and were leaked instead, see
+    /// [`FreeErrorPolicy`].
+    pub failed_frees: u64,
+    /// How well the quick lists do, see [`LinkedListAlloc::set_quick_lists_enabled`].
+    pub quick_lists: CacheStats,
+    /// How well the reserves do, see [`LinkedListAlloc::reserve`].
+    pub reserves: CacheStats,
+}
+
+impl AllocStats {
+    const fn new() -> Self {
+        AllocStats {
+            name: None,
+            used_bytes: 0,
+            live_allocations: 0,
+            peak_used_bytes: 0,
+            peak_live_allocations: 0,
+            allocations: 0,
+            deallocations: 0,
+            failed_allocations: 0,
+            grows: 0,
+            shrinks: 0,
+            shrinker_runs: 0,
+            reclaimed_bytes: 0,
+            failed_frees: 0,
+            quick_lists: CacheStats::new(),
+            reserves: CacheStats::new(),
+        }
+    }
+}
+
+/// Counters of a cache in front of an allocator, such as the quick lists of a
+/// [`LinkedListAlloc`], for telling whether it is doing anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The requests served by the cache.
+    pub hits: u64,
+    /// The requests the cache could have served, but had nothing for, which went to the allocator
+    /// behind it instead.
+    pub misses: u64,
+    /// The blocks put into the cache, e.g. when freed.
+    pub refills: u64,
+    /// The blocks handed back from the cache to the allocator behind it.
+    pub flushes: u64,
+}
+
+impl CacheStats {
+    pub const fn new() -> Self {
+        CacheStats {
+            hits: 0,
+            misses: 0,
+            refills: 0,
+            flushes: 0,
+        }
+    }
+}
+
+/// Decides whether an allocation that would take `tag` over its budget may go ahead anyway. Called
+/// with the tag, the usage it would reach, and the layout of the request, with the allocator
+/// locked, so it must not allocate from it. See [`ReentrancyCheck`](crate::reentrancy::ReentrancyCheck)
+/// for catching handlers that do.
+#[cfg(feature = "tagging")]
+pub type BudgetHandler = fn(tag: u8, usage: usize, layout: Layout) -> bool;
+
+/// How long an allocation is expected to live, see [`LinkedListAlloc::allocate_with_hint`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LifetimeHint {
+    /// Placed according to the fit policy, like any other allocation.
+    #[default]
+    Unknown,
+    /// Freed again soon, like a scratch buffer.
+    Short,
+    /// Kept for most of the lifetime of the program, like a table built at startup.
+    Long,
+}
+
+/// The number of tags with the most memory listed by [`LinkedListAlloc::report`].
+#[cfg(feature = "tagging")]
+pub const REPORTED_TAGS: usize = 5;
+
+/// The most shrinkers that can be registered with a [`LinkedListAlloc`].
+pub const MAX_SHRINKERS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Shrinkers([Option<&'static dyn Shrinker>; MAX_SHRINKERS]);
+
+impl core::fmt::Debug for Shrinkers {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} shrinkers", self.0.iter().flatten().count())
+    }
+}
+
+/// What a [`LinkedListAlloc`] does about allocations that are still live when it is dropped.
+///
+/// Defaults to reporting leaks on stderr in debug builds with the standard library, and to
+/// ignoring them otherwise. Huge allocations served directly by the source aren't tracked.
+#[derive(Debug, Clone, Copy)]
+pub enum LeakPolicy {
+    Ignore,
+    /// Calls the function with the address and usable size of every leaked allocation. Leaks from
+    /// a small bin are reported as the whole slab. The allocator is locked meanwhile, so the
+    /// function must not allocate from it.
+    Report(fn(*const u8, usize)),
+    /// Panics if anything leaked, unless the thread is already panicking.
+    Panic,
+}
+
+#[cfg(all(debug_assertions, any(feature = "std", test)))]
+const DEFAULT_LEAK_POLICY: LeakPolicy = LeakPolicy::Report(print_leak);
+#[cfg(not(all(debug_assertions, any(feature = "std", test))))]
+const DEFAULT_LEAK_POLICY: LeakPolicy = LeakPolicy::Ignore;
+
+#[cfg(all(debug_assertions, any(feature = "std", test)))]
+fn print_leak(ptr: *const u8, size: usize) {
+    std::eprintln!("Leaked an allocation of {size} bytes at {ptr:?}");
+}
+
+/// What a [`LinkedListAlloc`] does about a block it can't free, because its metadata says it
+/// isn't in use, e.g. after a double free or a buffer overflow from its neighbour.
+///
+/// Defaults to panicking. A kernel may prefer to leak the block and keep running instead of
+/// panicking within `free`.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum FreeErrorPolicy {
+    #[default]
+    Panic,
+    /// Leaves the block alone, printing its address on stderr if the standard library is
+    /// available.
+    Leak,
+    /// Leaves the block alone after calling the function with its address. The allocator is
+    /// locked meanwhile, so the function must not allocate from it.
+    Handler(fn(*const u8)),
+}
+
+impl FreeErrorPolicy {
+    /// Deals with the block at `ptr` that couldn't be freed, counting it in `stats` unless the
+    /// policy is to panic.
+    fn apply(self, ptr: *const u8, stats: &mut AllocStats) {
+        match self {
+            FreeErrorPolicy::Panic => panic!("Failed to free data at {ptr:?}!"),
+            FreeErrorPolicy::Leak => {
+                #[cfg(any(feature = "std", test))]
+                std::eprintln!("Failed to free data at {ptr:?}, leaking it");
+            }
+            FreeErrorPolicy::Handler(handler) => handler(ptr),
+        }
+        stats.failed_frees += 1;
+    }
+}
+
+// SAFETY: The raw pointers all point into the heap region, which the allocator owns exclusively,
+// or into regions of the source, so moving the state to another thread moves that ownership with
+// it. Nothing is tied to the thread that created it except possibly the source and the hooks,
+// hence the bounds.
+//
+// `LinkedListAlloc` keeps this state behind a `lock_api::Mutex`, which makes it `Send` if `R` is,
+// and `Sync` if `R` is, since all access goes through the lock. A lock that isn't `Sync` (one only
+// meant for a single thread) thus correctly keeps the allocator from being shared.
+unsafe impl<const GRANULE: usize, S: MemorySource + Send, I: FreeIndex, H: AllocHooks + Send> Send
+    for LinkedListAllocImpl<GRANULE, S, I, H>
+{
+}
+
+/// Hardening configuration for [`LinkedListAlloc::set_random_placement`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomPlacement {
+    /// Seeds the random number generator. Should come from a source of entropy.
+    pub seed: u64,
+    /// Whether to also place allocations at random offsets within the chosen free segment. This
+    /// makes layouts far less predictable, at the cost of a lot more fragmentation.
+    pub randomize_offset: bool,
+}
+
+/// A small, fast pseudo random number generator. Not cryptographically secure, but enough to keep
+/// an attacker from predicting where allocations land without knowing the seed.
+#[derive(Debug)]
+struct XorShift64(u64);
+
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero
+        XorShift64((seed ^ 0x9E37_79B9_7F4A_7C15).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl<const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    LinkedListAllocImpl<GRANULE, S, I, H>
+{
+    const fn new(segmenter_list: MemorySegmenter<GRANULE, I>, source: S, hooks: H) -> Self {
+        LinkedListAllocImpl {
+            segmenter_list,
+            source,
+            source_region: None,
+            heap_size: 0,
+            huge_threshold: usize::MAX,
+            policy: FitPolicy::LastFit,
+            quick_lists: QuickLists::new(),
+            quick_lists_enabled: false,
+            reserves: Reserves::new(),
+            small_bins: SmallBins::new(),
+            small_bins_enabled: false,
+            deferred_coalescing: false,
+            pending_coalesce: 0,
+            random_placement: None,
+            heap_end: None,
+            preserve_wilderness: false,
+            leak_policy: DEFAULT_LEAK_POLICY,
+            free_error_policy: FreeErrorPolicy::Panic,
+            #[cfg(feature = "pointer-mangling")]
+            link_key: None,
+            #[cfg(feature = "tagging")]
+            current_tag: 0,
+            #[cfg(feature = "tagging")]
+            tag_usage: [0; 256],
+            #[cfg(feature = "tagging")]
+            tag_budgets: [usize::MAX; 256],
+            #[cfg(feature = "tagging")]
+            budget_handler: None,
+            shrinkers: Shrinkers([None; MAX_SHRINKERS]),
+            stats: AllocStats::new(),
+            hooks,
+        }
+    }
+
+    /// Moves the state over to different hooks.
+    fn replace_hooks<H2: AllocHooks>(self, hooks: H2) -> LinkedListAllocImpl<GRANULE, S, I, H2> {
+        LinkedListAllocImpl {
+            segmenter_list: self.segmenter_list,
+            source: self.source,
+            source_region: self.source_region,
+            heap_size: self.heap_size,
+            huge_threshold: self.huge_threshold,
+            policy: self.policy,
+            quick_lists: self.quick_lists,
+            quick_lists_enabled: self.quick_lists_enabled,
+            reserves: self.reserves,
+            small_bins: self.small_bins,
+            small_bins_enabled: self.small_bins_enabled,
+            deferred_coalescing: self.deferred_coalescing,
+            pending_coalesce: self.pending_coalesce,
+            random_placement: self.random_placement,
+            heap_end: self.heap_end,
+            preserve_wilderness: self.preserve_wilderness,
+            leak_policy: self.leak_policy,
+            free_error_policy: self.free_error_policy,
+            #[cfg(feature = "pointer-mangling")]
+            link_key: self.link_key,
+            #[cfg(feature = "tagging")]
+            current_tag: self.current_tag,
+            #[cfg(feature = "tagging")]
+            tag_usage: self.tag_usage,
+            #[cfg(feature = "tagging")]
+            tag_budgets: self.tag_budgets,
+            #[cfg(feature = "tagging")]
+            budget_handler: self.budget_handler,
+            shrinkers: self.shrinkers,
+            stats: self.stats,
+            hooks,
+        }
+    }
+
+    /// A segmenter managing a new heap region, whose links are mangled with the secret set by
+    /// [`LinkedListAlloc::set_link_secret`] if there is one.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
+    unsafe fn segmenter_over(&self, start: *mut u8, end: *mut u8) -> MemorySegmenter<GRANULE, I> {
+        #[cfg(feature = "pointer-mangling")]
+        if let Some(key) = self.link_key {
+            return MemorySegmenter::with_key(start, end, key);
+        }
+        MemorySegmenter::with_granularity(start, end)
+    }
+
+    /// Acquires the heap region of an allocator constructed over a source.
+    fn acquire_heap(&mut self) -> Option<()> {
+        let align = self.source.page_size().max(GRANULE);
+        let layout = Layout::from_size_align(self.heap_size, align).ok()?;
+        let region = self.source.acquire(layout)?;
+        let start = region.cast::<u8>().as_ptr();
+        // The heap size has to be a multiple of the granularity
+        let size = region.len() - region.len() % GRANULE;
+
+        self.segmenter_list = unsafe { self.segmenter_over(start, start.add(size)) };
+        self.source_region = Some((region.cast(), layout));
+        Some(())
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.try_allocate(layout)
+            .inspect_err(|_| self.record_failure(layout))
+    }
+
+    /// Like [`LinkedListAllocImpl::allocate`], but leaves recording a failure to the caller, which
+    /// may still retry.
+    fn try_allocate(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.hooks.may_allocate(layout, &self.stats) {
+            return Err(AllocError);
+        }
+        let block = self.allocate_live(layout)?;
+        self.stats.allocations += 1;
+        self.hooks.on_alloc(layout, block, &self.stats);
+
+        Ok(block)
+    }
+
+    fn record_failure(&mut self, layout: Layout) {
+        self.stats.failed_allocations += 1;
+        self.hooks.on_fail(layout, &self.stats);
+    }
+
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.hooks.on_dealloc(ptr, layout, &self.stats);
+        self.stats.deallocations += 1;
+        self.deallocate_live(ptr, layout);
+    }
+
+    /// Moves the allocation at `ptr` to a new one for `new_layout`, preserving as much of its
+    /// contents as fits. Resizes always move, since the block serving the new size may come from a
+    /// different place than the old one, e.g. the small bins rather than the segment list.
+    unsafe fn resize(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.try_resize(ptr, old_layout, new_layout)
+            .inspect_err(|_| self.record_failure(new_layout))
+    }
+
+    /// Like [`LinkedListAllocImpl::resize`], but leaves recording a failure to the caller, which
+    /// may still retry.
+    unsafe fn try_resize(
+        &mut self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.hooks.may_allocate(new_layout, &self.stats) {
+            return Err(AllocError);
+        }
+        let new_ptr = self.allocate_live(new_layout)?;
+        core::ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.cast::<u8>().as_ptr(),
+            old_layout.size().min(new_layout.size()),
+        );
+        #[cfg(feature = "user-data")]
+        if let (Some(old), Some(new)) = (
+            self.segment_of(ptr, old_layout),
+            self.segment_of(new_ptr.cast(), new_layout),
+        ) {
+            (*new).set_user_data((*old).user_data());
+        }
+        self.deallocate_live(ptr, old_layout);
+
+        if new_layout.size() >= old_layout.size() {
+            self.stats.grows += 1;
+        } else {
+            self.stats.shrinks += 1;
+        }
+        self.hooks
+            .on_realloc(ptr, old_layout, new_layout, new_ptr, &self.stats);
+        Ok(new_ptr)
+    }
+
+    /// Allocates a block and accounts it as live, without counting the operation.
+    fn allocate_live(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.allocate_block(layout)?;
+
+        let usable_size = unsafe { self.usable_size(ptr.cast(), layout) };
+        let stats = &mut self.stats;
+        stats.used_bytes += usable_size;
+        stats.live_allocations += 1;
+        stats.peak_used_bytes = stats.peak_used_bytes.max(stats.used_bytes);
+        stats.peak_live_allocations = stats.peak_live_allocations.max(stats.live_allocations);
+
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate_live(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let usable_size = self.usable_size(ptr, layout);
+        self.stats.used_bytes -= usable_size;
+        self.stats.live_allocations -= 1;
+
+        self.deallocate_block(ptr, layout);
+    }
+
+    /// The usable size of the live allocation at `ptr`, as accounted in the stats. Computed the
+    /// same way on allocation and deallocation, whatever size within the bounds of the
+    /// [`Allocator`] contract is passed in.
+    unsafe fn usable_size(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        if self.is_huge(ptr, layout) {
+            return layout.size().next_multiple_of(self.source.page_size());
+        }
+        if let Some(class) = self.small_bin_class(layout) {
+            return SmallBins::<GRANULE>::slot_size(class);
+        }
+
+        (*SegmentMetadata::from_alloc_ptr(ptr.as_ptr())).size_allocable()
+    }
+
+    /// Whether the live allocation at `ptr` was served directly by the source. Heap blocks may be
+    /// freed with any size up to their usable size, which can reach the threshold, so the block is
+    /// told apart by where it lies.
+    fn is_huge(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        layout.size() >= self.huge_threshold && !self.segmenter_list.contains(ptr.as_ptr())
+    }
+
+    /// The segment of the live allocation at `ptr`, unless it was served by the small bins or
+    /// directly by the source, which don't give allocations a segment of their own.
+    #[cfg(feature = "user-data")]
+    fn segment_of(&self, ptr: NonNull<u8>, layout: Layout) -> Option<*mut SegmentMetadata> {
+        (!self.is_huge(ptr, layout) && self.small_bin_class(layout).is_none())
+            .then(|| SegmentMetadata::from_alloc_ptr(ptr.as_ptr()))
+    }
+
+    fn allocate_block(&mut self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() >= self.huge_threshold {
+            return self.source.acquire(layout).ok_or(AllocError);
+        }
+
+        if self.segmenter_list.num_segments() == 0 {
+            self.acquire_heap().ok_or(AllocError)?;
+        }
+
+        if let Some(class) = self.small_bin_class(layout) {
+            let mut slot = self
+                .small_bins
+                .allocate(&mut self.segmenter_list, self.policy, class);
+            if slot.is_none() {
+                self.reclaim();
+                slot = self
+                    .small_bins
+                    .allocate(&mut self.segmenter_list, self.policy, class);
+            }
+            if slot.is_none() && self.grow_heap(SmallBins::<GRANULE>::slab_layout()) {
+                slot = self
+                    .small_bins
+                    .allocate(&mut self.segmenter_list, self.policy, class);
+            }
+
+            let slot_size = SmallBins::<GRANULE>::slot_size(class);
+            let user_slice = unsafe { from_raw_parts_mut(slot.ok_or(AllocError)?, slot_size) };
+            return Ok(NonNull::from(user_slice));
+        }
+
+        // Reserves and quick lists hand out blocks from anywhere in the heap
+        if self.heap_end.is_none() {
+            let usable_size = Self::reserved_size(layout);
+            if let Some(user_ptr) = self.reserves.pop(usable_size, layout.align()) {
+                // A block the budget rejects goes back, without counting as a hit or a refill
+                #[cfg(feature = "tagging")]
+                if !self.charge_tag(user_ptr, layout) {
+                    self.uncharge_tag(user_ptr);
+                    unsafe { self.reserves.push(user_ptr, usable_size) };
+                    return Err(AllocError);
+                }
+                self.stats.reserves.hits += 1;
+                let user_slice = unsafe { from_raw_parts_mut(user_ptr, usable_size) };
+                return Ok(NonNull::from(user_slice));
+            }
+            if self.reserves.covers(usable_size, layout.align()) {
+                self.stats.reserves.misses += 1;
+            }
+        }
+        if self.quick_lists_enabled && self.heap_end.is_none() {
+            let usable_size = MemorySegmenter::<GRANULE, I>::subsegment_size_for(layout.size())
+                - SegmentMetadata::SIZE;
+            if let Some(user_ptr) = self.quick_lists.pop(usable_size, layout.align()) {
+                #[cfg(feature = "tagging")]
+                if !self.charge_tag(user_ptr, layout) {
+                    self.uncharge_tag(user_ptr);
+                    unsafe { self.quick_lists.push(user_ptr, usable_size) };
+                    return Err(AllocError);
+                }
+                self.stats.quick_lists.hits += 1;
+                let user_slice = unsafe { from_raw_parts_mut(user_ptr, usable_size) };
+                return Ok(NonNull::from(user_slice));
+            }
+            if QuickLists::<GRANULE>::caches(usable_size) {
+                self.stats.quick_lists.misses += 1;
+            }
+        }
+
+        let mut fit = self.find_fit(layout);
+        // The blocks parked on the quick lists, in empty slabs, or waiting to be coalesced may be
+        // all that stands in the way of success
+        if fit.is_none()
+            && (!self.quick_lists.is_empty()
+                || self.small_bins_enabled
+                || self.pending_coalesce != 0)
+        {
+            self.reclaim();
+            fit = self.find_fit(layout);
+        }
+        if fit.is_none() && self.grow_heap(layout) {
+            fit = self.find_fit(layout);
+        }
+        let fit = fit.ok_or(AllocError)?;
+
+        let segment = unsafe {
+            self.segmenter_list
+                .create_used_segment_at(fit, layout.size())
+        }
+        .map_err(|_| AllocError)?;
+        let segment = unsafe { segment.as_ref() }.unwrap();
+
+        // Hand out everything the segment can hold, which may be more than what was requested
+        let user_ptr = segment.alloc_start_ptr();
+        let user_slice = unsafe { from_raw_parts_mut(user_ptr, segment.size_allocable()) };
+        #[cfg(feature = "tagging")]
+        self.charge(user_ptr, layout)?;
+
+        Ok(NonNull::from(user_slice))
+    }
+
+    /// Tags the fresh allocation at `user_ptr` with the current tag and charges it to the tag's
+    /// budget. If that exceeds the budget and the handler doesn't allow it, the allocation is freed
+    /// again.
+    #[cfg(feature = "tagging")]
+    fn charge(&mut self, user_ptr: *mut u8, layout: Layout) -> Result<(), AllocError> {
+        if !self.charge_tag(user_ptr, layout) {
+            unsafe { self.deallocate_block(NonNull::new(user_ptr).unwrap(), layout) };
+            return Err(AllocError);
+        }
+        Ok(())
+    }
+
+    /// Tags the allocation at `user_ptr` with the current tag and charges it to the tag's budget.
+    /// Returns whether the budget or the handler allows it, though it is charged either way.
+    #[cfg(feature = "tagging")]
+    fn charge_tag(&mut self, user_ptr: *mut u8, layout: Layout) -> bool {
+        let tag = self.current_tag;
+        let segment = unsafe { &mut *SegmentMetadata::from_alloc_ptr(user_ptr) };
+        segment.set_tag(tag);
+        let usage = &mut self.tag_usage[tag as usize];
+        *usage += segment.size_allocable();
+
+        let usage = *usage;
+        usage <= self.tag_budgets[tag as usize]
+            || self
+                .budget_handler
+                .is_some_and(|handler| handler(tag, usage, layout))
+    }
+
+    /// Takes the allocation at `user_ptr` off the usage of its tag.
+    #[cfg(feature = "tagging")]
+    fn uncharge_tag(&mut self, user_ptr: *mut u8) {
+        let segment = unsafe { &*SegmentMetadata::from_alloc_ptr(user_ptr) };
+        self.tag_usage[segment.tag() as usize] -= segment.size_allocable();
+    }
+
+    /// Grows the heap in place by enough to serve `layout` at its end, if the source can extend
+    /// the heap region, see [`MemorySource::grow_in_place`]. Returns whether it grew.
+    fn grow_heap(&mut self, layout: Layout) -> bool {
+        let Some((region, region_layout)) = self.source_region else {
+            return false;
+        };
+
+        // Room for the segment and its alignment padding, beyond the free segment at the top
+        let needed = MemorySegmenter::<GRANULE, I>::subsegment_size_for(layout.size())
+            + MemorySegmenter::<GRANULE, I>::alloc_align_for(layout.align())
+            + SegmentMetadata::SIZE;
+        let wilderness = self
+            .segmenter_list
+            .wilderness()
+            .map_or(0, |free| free.size());
+        let additional = needed.saturating_sub(wilderness).max(SegmentMetadata::SIZE);
+        let Some(grown) = (unsafe { self.source.grow_in_place(region, region_layout, additional) })
+        else {
+            return false;
+        };
+        self.source_region = Some((region, grown));
+
+        // The heap size has to stay a multiple of the granularity
+        let end = self.segmenter_list.region().end as usize;
+        let grown_end = region.as_ptr() as usize + grown.size();
+        let additional = (grown_end - end) - (grown_end - end) % GRANULE;
+        if additional < SegmentMetadata::SIZE {
+            return false;
+        }
+        unsafe { self.segmenter_list.extend(additional) };
+        true
+    }
+
+    fn find_fit(&mut self, layout: Layout) -> Option<SegmentFit> {
+        let (policy, placement) = match self.heap_end {
+            Some(Placement::Bottom) => (FitPolicy::FirstFit, Placement::Bottom),
+            Some(Placement::Top) => (FitPolicy::LastFit, Placement::Top),
+            None => match &mut self.random_placement {
+                Some((rng, randomize_offset)) => {
+                    return self.segmenter_list.find_random_fit(
+                        layout,
+                        || rng.next(),
+                        *randomize_offset,
+                    );
+                }
+                None => (self.policy, Placement::Bottom),
+            },
+        };
+
+        if self.preserve_wilderness {
+            self.segmenter_list
+                .find_fit_sparing_wilderness(layout, policy, placement)
+        } else {
+            self.segmenter_list
+                .find_placed_fit(layout, policy, placement)
+        }
+    }
+
+    unsafe fn deallocate_block(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_huge(ptr, layout) {
+            // Regions are whole pages, every one of which the user may have written to
+            #[cfg(feature = "zero-on-free")]
+            wipe(
+                ptr.as_ptr(),
+                layout.size().next_multiple_of(self.source.page_size()),
+            );
+            self.source.release(ptr, layout);
+            return;
+        }
+
+        if let Some(class) = self.small_bin_class(layout) {
+            #[cfg(feature = "zero-on-free")]
+            wipe(ptr.as_ptr(), SmallBins::<GRANULE>::slot_size(class));
+            self.small_bins
+                .deallocate(&mut self.segmenter_list, class, ptr.as_ptr());
+            return;
+        }
+
+        // Get segment start
+        let segment_start_ptr = SegmentMetadata::from_alloc_ptr(ptr.as_ptr());
+        #[cfg(feature = "zero-on-free")]
+        wipe(
+            ptr.as_ptr(),
+            segment_start_ptr.as_ref().unwrap().size_allocable(),
+        );
+
+        #[cfg(feature = "tagging")]
+        self.uncharge_tag(ptr.as_ptr());
+
+        if self
+            .reserves
+            .push(ptr.as_ptr(), Self::reserved_size(layout))
+        {
+            self.stats.reserves.refills += 1;
+            return;
+        }
+        if self.quick_lists_enabled {
+            let usable_size = segment_start_ptr.as_ref().unwrap().size_allocable();
+            if self.quick_lists.push(ptr.as_ptr(), usable_size) {
+                self.stats.quick_lists.refills += 1;
+                return;
+            }
+        }
+
+        let mut cursor = self.segmenter_list.cursor_at(segment_start_ptr);
+        let freed = if self.deferred_coalescing {
+            cursor.release().inspect(|_| self.pending_coalesce += 1)
+        } else {
+            cursor.try_coalesce()
+        };
+        if freed.is_err() {
+            self.free_error_policy.apply(ptr.as_ptr(), &mut self.stats);
+        }
+    }
+
+    fn small_bin_class(&self, layout: Layout) -> Option<usize> {
+        self.small_bins_enabled
+            .then(|| SmallBins::<GRANULE>::class_for(layout))
+            .flatten()
+    }
+
+    fn flush_quick_lists(&mut self) {
+        while let Some(ptr) = self.quick_lists.pop_any() {
+            self.stats.quick_lists.flushes += 1;
+            if unsafe { Self::release_block(&mut self.segmenter_list, ptr) }.is_err() {
+                self.free_error_policy.apply(ptr, &mut self.stats);
+            }
+        }
+    }
+
+    /// The usable size of the blocks reserved for `layout`, which may be less than the segment
+    /// holding them offers.
+    fn reserved_size(layout: Layout) -> usize {
+        MemorySegmenter::<GRANULE, I>::subsegment_size_for(layout.size()) - SegmentMetadata::SIZE
+    }
+
+    fn reserve(&mut self, layout: Layout, count: usize) -> Result<(), AllocError> {
+        // Only requests with a segment of their own can be reserved
+        let usable_size = Self::reserved_size(layout);
+        if layout.size() >= self.huge_threshold
+            || self.small_bin_class(layout).is_some()
+            || !Reserves::fits(usable_size)
+        {
+            return Err(AllocError);
+        }
+        if self.segmenter_list.num_segments() == 0 {
+            self.acquire_heap().ok_or(AllocError)?;
+        }
+
+        let align = MemorySegmenter::<GRANULE, I>::alloc_align_for(layout.align());
+        self.reserves
+            .insert(usable_size, align)
+            .map_err(|_| AllocError)?;
+        for _ in 0..count {
+            let fit = self.find_fit(layout).ok_or(AllocError)?;
+            let segment = unsafe {
+                self.segmenter_list
+                    .create_used_segment_at(fit, layout.size())
+            }
+            .map_err(|_| AllocError)?;
+            unsafe {
+                self.reserves
+                    .add((*segment).alloc_start_ptr(), usable_size, align)
+            };
+            self.stats.reserves.refills += 1;
+        }
+        Ok(())
+    }
+
+    fn release_reserve(&mut self, layout: Layout) {
+        let align = MemorySegmenter::<GRANULE, I>::alloc_align_for(layout.align());
+        let (segmenter, stats, policy) = (
+            &mut self.segmenter_list,
+            &mut self.stats,
+            self.free_error_policy,
+        );
+        self.reserves
+            .remove(Self::reserved_size(layout), align, |ptr| {
+                stats.reserves.flushes += 1;
+                if unsafe { Self::release_block(segmenter, ptr) }.is_err() {
+                    policy.apply(ptr, stats);
+                }
+            });
+    }
+
+    fn release_reserves(&mut self) {
+        let (segmenter, stats, policy) = (
+            &mut self.segmenter_list,
+            &mut self.stats,
+            self.free_error_policy,
+        );
+        self.reserves.clear(|ptr| {
+            stats.reserves.flushes += 1;
+            if unsafe { Self::release_block(segmenter, ptr) }.is_err() {
+                policy.apply(ptr, stats);
+            }
+        });
+    }
+
+    /// Frees the used segment of a cached block, coalescing it with its free neighbours.
+    unsafe fn release_block(
+        segmenter: &mut MemorySegmenter<GRANULE, I>,
+        ptr: *mut u8,
+    ) -> Result<(), ()> {
+        segmenter
+            .cursor_at(SegmentMetadata::from_alloc_ptr(ptr))
+            .try_coalesce()
+    }
+
+    fn coalesce_all(&mut self) {
+        if self.pending_coalesce != 0 {
+            self.segmenter_list.coalesce_all();
+            self.pending_coalesce = 0;
+        }
+    }
+
+    /// Counts the segments still in use once cached memory is reclaimed, reporting each of them
+    /// if the leak policy says so.
+    fn check_leaks(&mut self) -> usize {
+        if self.segmenter_list.num_segments() == 0 {
+            return 0;
+        }
+
+        self.release_reserves();
+        self.reclaim();
+        if let LeakPolicy::Report(report) = self.leak_policy {
+            for segment in self.segmenter_list.iter_used() {
+                report(segment.usable_range().start, segment.usable_size());
+            }
+        }
+        self.segmenter_list.num_used_segments()
+    }
+
+    unsafe fn deallocate_many(
+        &mut self,
+        layout: Layout,
+        ptrs: impl IntoIterator<Item = NonNull<u8>>,
+    ) {
+        // Free everything without coalescing, then merge the free segments in a single pass
+        let deferred_coalescing = core::mem::replace(&mut self.deferred_coalescing, true);
+        for ptr in ptrs {
+            self.deallocate(ptr, layout);
+        }
+        self.deferred_coalescing = deferred_coalescing;
+        if !deferred_coalescing {
+            self.coalesce_all();
+        }
+    }
+
+    fn for_each_allocation(&mut self, mut f: impl FnMut(NonNull<u8>, usize, Option<u8>)) {
+        // Blocks on the quick lists and in reserves are used segments too, but not live
+        self.flush_quick_lists();
+        self.release_reserves();
+
+        for segment in self.segmenter_list.iter_used() {
+            let ptr = segment.usable_range().start;
+            if segment.is_container() {
+                unsafe {
+                    SmallBins::<GRANULE>::for_each_used_slot(ptr, |slot, size| {
+                        f(NonNull::new(slot).unwrap(), size, None)
+                    })
+                };
+                continue;
+            }
+
+            #[cfg(feature = "tagging")]
+            let tag = Some(segment.tag());
+            #[cfg(not(feature = "tagging"))]
+            let tag = None;
+            f(NonNull::new(ptr).unwrap(), segment.usable_size(), tag);
+        }
+    }
+
+    /// Returns all memory that is cached rather than in use to the segment list.
+    fn reclaim(&mut self) {
+        self.flush_quick_lists();
+        self.small_bins.release_empty(&mut self.segmenter_list);
+        self.coalesce_all();
+    }
+}
+
+/// Overwrites freed memory with zeroes. The writes are volatile, so they can't be optimized away
+/// even though the memory is never read again.
+#[cfg(feature = "zero-on-free")]
+unsafe fn wipe(ptr: *mut u8, len: usize) {
+    for i in 0..len {
+        ptr.add(i).write_volatile(0);
+    }
+    core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+}
+
+/// A general purpose allocator backed by a [`MemorySegmenter`].
+///
+/// `GRANULE` is the granularity every allocation (including its metadata) is rounded up to. A
+/// smaller granularity wastes less memory on padding for tiny allocations. It is limited like the
+/// granularity of a [`MemorySegmenter`], to 8 or 16 on most 64 bit targets.
+///
+/// When constructed over a [`MemorySource`] with [`LinkedListAlloc::from_source`], the heap
+/// region is obtained from the source, and huge requests bypass the heap to be served by dedicated
+/// regions of the source instead, so a single big buffer can't ruin the heap layout.
+///
+/// `I` picks how free segments are found, see [`FreeIndex`], and `H` is called on every
+/// operation, see [`LinkedListAlloc::with_hooks`].
+#[derive(Debug)]
+pub struct LinkedListAlloc<
+    R: lock_api::RawMutex,
+    const GRANULE: usize = DEFAULT_GRANULARITY,
+    S: MemorySource = NoSource,
+    I: FreeIndex = LinearIndex,
+    H: AllocHooks = NoHooks,
+>(lock_api::Mutex<R, LinkedListAllocImpl<GRANULE, S, I, H>>);
+
+impl<R: lock_api::RawMutex> LinkedListAlloc<R> {
+    /// Creates an allocator with the default granularity managing the memory between `start` and
+    /// `end`.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        Self::with_granularity(start, end)
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, I: FreeIndex>
+    LinkedListAlloc<R, GRANULE, NoSource, I>
+{
+    /// Creates an allocator managing the memory between `start` and `end`, rounding every
+    /// allocation to `GRANULE` bytes.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
+    pub unsafe fn with_granularity(start: *mut u8, end: *mut u8) -> Self {
+        let internal = LinkedListAllocImpl::new(
+            MemorySegmenter::with_granularity(start, end),
+            NoSource,
+            NoHooks,
+        );
+
+        LinkedListAlloc(lock_api::Mutex::new(internal))
+    }
+
+    /// Creates an allocator managing `region`. Since the region is borrowed exclusively for
+    /// `'static`, nothing else can ever touch it, which makes this safe. The region is trimmed to
+    /// the granularity if necessary.
+    ///
+    /// # Panics
+    /// Panics if the trimmed region can't hold a single segment.
+    pub fn from_static(region: &'static mut [MaybeUninit<u8>]) -> Self {
+        let range = region.as_mut_ptr_range();
+        let (start, end) = Self::trim_region(range.start.cast(), range.end.cast());
+
+        unsafe { Self::with_granularity(start, end) }
+    }
+
+    /// Creates an allocator without any memory, on which every allocation fails until
+    /// [`LinkedListAlloc::init`] hands it a region. Since this is a `const fn`, the allocator can be
+    /// placed in a static before the heap region is known.
+    pub const fn empty() -> Self {
+        Self::empty_with_hooks(NoHooks)
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, I: FreeIndex, H: AllocHooks>
+    LinkedListAlloc<R, GRANULE, NoSource, I, H>
+{
+    fn trim_region(start: *mut u8, end: *mut u8) -> (*mut u8, *mut u8) {
+        let start = start.wrapping_add(start.align_offset(GRANULE));
+        let end = end.wrapping_sub(end as usize % GRANULE);
+        assert!(
+            (start as usize)
+                .checked_add(SegmentMetadata::SIZE)
+                .is_some_and(|min_end| end as usize >= min_end),
+            "The heap region is too small!"
+        );
+
+        (start, end)
+    }
+
+    /// Like [`LinkedListAlloc::empty`], but calling `hooks` on every operation. Unlike
+    /// [`LinkedListAlloc::with_hooks`], this can be used in a static.
+    pub const fn empty_with_hooks(hooks: H) -> Self {
+        LinkedListAlloc(lock_api::Mutex::new(LinkedListAllocImpl::new(
+            MemorySegmenter::empty(),
+            NoSource,
+            hooks,
+        )))
+    }
+
+    /// Hands the memory between `start` and `end` to an allocator created with
+    /// [`LinkedListAlloc::empty`]. The bounds are shrunk to the granularity if necessary.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    ///
+    /// # Panics
+    /// Panics if the allocator already manages a region, before touching the new one, or if the
+    /// trimmed region can't hold a single segment.
+    pub unsafe fn init(&self, start: *mut u8, end: *mut u8) {
+        let mut internal = self.0.lock();
+        assert_eq!(
+            internal.segmenter_list.num_segments(),
+            0,
+            "The allocator has already been initialized!"
+        );
+        let (start, end) = Self::trim_region(start, end);
+        internal.segmenter_list = internal.segmenter_over(start, end);
+    }
+
+    /// Like [`LinkedListAlloc::init`], for bounds taken from linker symbols, see
+    /// [`linker_heap!`](crate::linker_heap). Rather than trimming misaligned bounds, which usually
+    /// point at a mistake in the linker script, they are refused.
+    ///
+    /// # Safety
+    /// See [`LinkedListAlloc::init`].
+    ///
+    /// # Panics
+    /// Panics if the allocator already manages a region, if `end` lies before `start`, or if either
+    /// bound isn't aligned to the granularity.
+    pub unsafe fn init_from_linker(&self, start: *mut u8, end: *mut u8) {
+        assert!(
+            start <= end,
+            "The heap end symbol lies before the heap start symbol!"
+        );
+        assert!(
+            start.align_offset(GRANULE) == 0 && end.align_offset(GRANULE) == 0,
+            "The heap symbols aren't aligned to the granularity!"
+        );
+        self.init(start, end);
+    }
+
+    /// Takes over the region of `bootstrap`, like [`LinkedListAlloc::init`]. Everything it
+    /// allocated stays where it is, in a used segment at the start of the region that is never
+    /// freed, and the bootstrap allocator fails every allocation from then on. The reserved
+    /// segment counts as a live allocation, and is reported by leak checks if the heap is dropped.
+    ///
+    /// # Safety
+    /// See [`BootstrapAlloc::new`]. None of the bootstrap allocations may ever be freed.
+    ///
+    /// # Panics
+    /// Panics if the allocator already manages a region, if `bootstrap` was already handed off, if
+    /// its region isn't aligned to the granularity, or if the trimmed region can't hold the
+    /// bootstrap allocations.
+    pub unsafe fn init_from_bootstrap<R2: lock_api::RawMutex>(
+        &self,
+        bootstrap: &BootstrapAlloc<R2>,
+    ) {
+        let mut internal = self.0.lock();
+        assert_eq!(
+            internal.segmenter_list.num_segments(),
+            0,
+            "The allocator has already been initialized!"
+        );
+        let (start, watermark, end) = bootstrap.retire();
+        assert!(
+            start.align_offset(GRANULE) == 0,
+            "The bootstrap region isn't aligned to the granularity!"
+        );
+        let (start, end) = Self::trim_region(start, end);
+        internal.segmenter_list = internal.segmenter_over(start, end);
+
+        let reserved = watermark as usize - start as usize - SegmentMetadata::SIZE;
+        if reserved == 0 {
+            return;
+        }
+        let fit = SegmentFit {
+            segment: start.cast(),
+            alloc_ptr: start.add(SegmentMetadata::SIZE),
+            subsegment_size: MemorySegmenter::<GRANULE, I>::subsegment_size_for(reserved),
+            align: GRANULE,
+        };
+        let segment = internal
+            .segmenter_list
+            .create_used_segment_at(fit, reserved)
+            .expect("The heap region can't hold the bootstrap allocations!");
+
+        let stats = &mut internal.stats;
+        stats.used_bytes += segment.as_ref().unwrap().size_allocable();
+        stats.live_allocations += 1;
+        stats.peak_used_bytes = stats.peak_used_bytes.max(stats.used_bytes);
+        stats.peak_live_allocations = stats.peak_live_allocations.max(stats.live_allocations);
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<R: lock_api::RawMutex, const GRANULE: usize, I: FreeIndex>
+    LinkedListAlloc<R, GRANULE, SystemSource, I>
+{
+    /// Creates an allocator owning a region of at least `size` bytes, aligned to `align`, which is
+    /// obtained from the global allocator of the standard library and freed again when the
+    /// allocator is dropped. Unlike with [`LinkedListAlloc::from_source`], every request is served
+    /// from that region.
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two, or the region can't be allocated.
+    pub fn with_capacity(size: usize, align: usize) -> Self {
+        let source = SystemSource::with_page_size(align.max(GRANULE));
+
+        Self::from_source(source, size)
+            .expect("Failed to allocate the heap region!")
+            .with_huge_threshold(usize::MAX)
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex>
+    LinkedListAlloc<R, GRANULE, S, I>
+{
+    /// Creates an allocator over a heap of at least `heap_size` bytes acquired from `source`.
+    /// Requests of [`DEFAULT_HUGE_THRESHOLD`] bytes or more are served by dedicated regions of the
+    /// source. The heap region is given back to the source when the allocator is dropped.
+    ///
+    /// Returns `None` if the source can't provide the heap region.
+    pub fn from_source(source: S, heap_size: usize) -> Option<Self> {
+        let allocator = Self::from_source_lazy(source, heap_size);
+        allocator.0.lock().acquire_heap()?;
+
+        Some(allocator)
+    }
+
+    /// Like [`LinkedListAlloc::from_source`], but the heap region is only acquired on the first
+    /// allocation. Since this is a `const fn`, the allocator can be placed in a static and serve as
+    /// the `#[global_allocator]`:
+    ///
+    /// ```ignore
+    /// #[global_allocator]
+    /// static HEAP: LinkedListAlloc<RawSpinlock, 16, WasmSource> =
+    ///     LinkedListAlloc::from_source_lazy(WasmSource::new(), 1024 * 1024);
+    /// ```
+    pub const fn from_source_lazy(source: S, heap_size: usize) -> Self {
+        let mut internal = LinkedListAllocImpl::new(MemorySegmenter::empty(), source, NoHooks);
+        internal.heap_size = heap_size;
+        internal.huge_threshold = DEFAULT_HUGE_THRESHOLD;
+
+        LinkedListAlloc(lock_api::Mutex::new(internal))
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    /// Calls `hooks` on every operation from now on, see [`AllocHooks`]. The current hooks are
+    /// dropped.
+    pub fn with_hooks<H2: AllocHooks>(self, hooks: H2) -> LinkedListAlloc<R, GRANULE, S, I, H2> {
+        // The state moves over to the new allocator, so this one must not be dropped
+        let this = ManuallyDrop::new(self);
+        let internal = unsafe { core::ptr::read(&this.0) }.into_inner();
+
+        LinkedListAlloc(lock_api::Mutex::new(internal.replace_hooks(hooks)))
+    }
+
+    /// Runs `f` on the hooks while holding the lock, e.g. to read what they collected.
+    pub fn with_hooks_mut<T>(&self, f: impl FnOnce(&mut H) -> T) -> T {
+        f(&mut self.0.lock().hooks)
+    }
+
+    /// Sets the size from which requests are served directly by the source instead of the heap.
+    /// Only has an effect on allocators constructed with [`LinkedListAlloc::from_source`].
+    ///
+    /// Huge allocations are recognized by their layout, so the threshold can't change while
+    /// allocations are live.
+    ///
+    /// # Panics
+    /// Panics if any allocations are live.
+    pub fn with_huge_threshold(self, huge_threshold: usize) -> Self {
+        {
+            let mut internal = self.0.lock();
+            internal.flush_quick_lists();
+            assert_eq!(
+                internal.segmenter_list.num_used_segments(),
+                0,
+                "The huge threshold must be set before allocating!"
+            );
+            if internal.source_region.is_some() {
+                internal.huge_threshold = huge_threshold;
+            }
+        }
+
+        self
+    }
+
+    /// Sets the policy used to pick a free segment for future allocations.
+    pub fn set_fit_policy(&self, policy: FitPolicy) {
+        self.0.lock().policy = policy;
+    }
+
+    pub fn fit_policy(&self) -> FitPolicy {
+        self.0.lock().policy
+    }
+
+    /// Enables or disables randomized placement. While enabled, the fit policy is ignored, and
+    /// every allocation is placed in a free segment chosen at random among all that fit, which
+    /// makes the heap layout much harder to predict for an attacker. Small bins and quick lists are
+    /// not affected.
+    pub fn set_random_placement(&self, placement: Option<RandomPlacement>) {
+        self.0.lock().random_placement = placement
+            .map(|placement| (XorShift64::new(placement.seed), placement.randomize_offset));
+    }
+
+    /// Enables or disables the quick lists. While enabled, freed blocks of a few small sizes are
+    /// kept aside (without being coalesced) and handed straight back out to later requests of the
+    /// same size, which makes churn of identically sized allocations much cheaper. Disabling the
+    /// quick lists flushes them.
+    pub fn set_quick_lists_enabled(&self, enabled: bool) {
+        let mut internal = self.0.lock();
+        internal.quick_lists_enabled = enabled;
+        if !enabled {
+            internal.flush_quick_lists();
+        }
+    }
+
+    /// Enables the small object bins: requests of up to 8 granules that need no more than `GRANULE`
+    /// alignment are served from slabs carved out of the same region, without any per allocation
+    /// metadata. Everything else keeps going through the segment list.
+    ///
+    /// Bins are picked based on the layout alone, so deallocations must pass the same layout the
+    /// [`Allocator`] contract requires.
+    ///
+    /// # Panics
+    /// Panics if any allocations are live, since they could no longer be told apart from binned ones.
+    pub fn with_small_bins(self) -> Self {
+        {
+            let mut internal = self.0.lock();
+            internal.flush_quick_lists();
+            assert_eq!(
+                internal.segmenter_list.num_used_segments(),
+                0,
+                "Small bins must be enabled before allocating!"
+            );
+            internal.small_bins_enabled = true;
+        }
+
+        self
+    }
+
+    /// Returns every block parked on the quick lists to the segment list, coalescing them with
+    /// their free neighbours.
+    pub fn flush_quick_lists(&self) {
+        self.0.lock().flush_quick_lists();
+    }
+
+    /// Splits off `count` blocks for `layout` ahead of time, e.g. before a latency critical phase.
+    /// Later requests for the layout are then served by popping one of them, without searching
+    /// the heap, and freed blocks refill the reserve rather than being coalesced. Reserving more
+    /// for a layout adds to its reserve. Up to [`MAX_RESERVES`] layouts can be reserved for.
+    ///
+    /// Fails for requests served by the small bins or directly by the source, or if the heap runs
+    /// out, in which case the blocks split off so far stay reserved. Listing the allocations of the
+    /// heap, e.g. for a snapshot or leak check, releases every reserve.
+    pub fn reserve(&self, layout: Layout, count: usize) -> Result<(), AllocError> {
+        self.0.lock().reserve(layout, count)
+    }
+
+    /// The number of blocks left in the reserve for `layout`.
+    pub fn reserved(&self, layout: Layout) -> usize {
+        let align = MemorySegmenter::<GRANULE, I>::alloc_align_for(layout.align());
+        let usable_size = LinkedListAllocImpl::<GRANULE, S, I, H>::reserved_size(layout);
+        self.0.lock().reserves.available(usable_size, align)
+    }
+
+    /// Frees every block left in the reserve for `layout`. Blocks handed out from it are freed as
+    /// usual from now on.
+    pub fn release_reserve(&self, layout: Layout) {
+        self.0.lock().release_reserve(layout);
+    }
+
+    /// Enables or disables wilderness preservation. While enabled, the free segment at the top of
+    /// the heap is only split if no other segment can serve a request, keeping a large contiguous
+    /// block available for big allocations. Random placement doesn't take it into account.
+    pub fn set_preserve_wilderness(&self, enabled: bool) {
+        self.0.lock().preserve_wilderness = enabled;
+    }
+
+    /// Enables or disables deferred coalescing. While enabled, deallocation only marks segments as
+    /// free, and adjacent free segments are merged in a single pass once a fit search fails or
+    /// [`LinkedListAlloc::coalesce_all`] is called. This makes freeing much cheaper, at the cost of
+    /// fragmentation in between passes. Disabling it coalesces immediately.
+    pub fn set_deferred_coalescing(&self, enabled: bool) {
+        let mut internal = self.0.lock();
+        internal.deferred_coalescing = enabled;
+        if !enabled {
+            internal.coalesce_all();
+        }
+    }
+
+    /// Merges all adjacent free segments left behind by deferred coalescing.
+    pub fn coalesce_all(&self) {
+        self.0.lock().coalesce_all();
+    }
+
+    /// The size of the largest request (with no more than `GRANULE` alignment) the heap could
+    /// serve right now, without reclaiming cached memory. Requests served by the small bins or the
+    /// source may succeed regardless.
+    pub fn largest_free_block(&self) -> usize {
+        self.0.lock().segmenter_list.largest_free_segment()
+    }
+
+    /// Allocates up to `n` blocks for `layout` under a single lock acquisition, passing each one to
+    /// `f`. Stops at the first allocation that fails, and returns the number of blocks allocated.
+    ///
+    /// The allocator is locked throughout, so `f` must not use it.
+    pub fn allocate_many(
+        &self,
+        layout: Layout,
+        n: usize,
+        mut f: impl FnMut(NonNull<[u8]>),
+    ) -> usize {
+        let mut internal = self.0.lock();
+        for i in 0..n {
+            match internal.allocate(layout) {
+                Ok(ptr) => f(ptr),
+                Err(_) => return i,
+            }
+        }
+        n
+    }
+
+    /// Frees every block in `ptrs` under a single lock acquisition, coalescing the freed segments
+    /// in one pass at the end rather than one at a time.
+    ///
+    /// # Safety
+    /// Every block must be currently allocated by this allocator, with `layout`.
+    pub unsafe fn deallocate_many(
+        &self,
+        layout: Layout,
+        ptrs: impl IntoIterator<Item = NonNull<u8>>,
+    ) {
+        self.0.lock().deallocate_many(layout, ptrs);
+    }
+
+    /// Calls `f` with the pointer, usable size and tag of every live allocation, in address order
+    /// except for small objects, which are reported slab by slab. Tags are only recorded with the
+    /// `tagging` feature, and never for small objects. Huge allocations served directly by the
+    /// source aren't reported.
+    ///
+    /// The allocator is locked throughout, so `f` must not use it.
+    pub fn for_each_allocation(&self, f: impl FnMut(NonNull<u8>, usize, Option<u8>)) {
+        self.0.lock().for_each_allocation(f);
+    }
+
+    /// Copies every live allocation reported by [`LinkedListAlloc::for_each_allocation`] into
+    /// `target`, see [`migrate`](crate::migrate::migrate). The allocations are migrated with their
+    /// usable size, and the alignment of their address, up to
+    /// [`MAX_INFERRED_ALIGN`](crate::migrate::MAX_INFERRED_ALIGN).
+    ///
+    /// The allocator is locked throughout, so `target` must not be this allocator.
+    pub fn migrate_to<A: Allocator + ?Sized>(
+        &self,
+        target: &A,
+        mut relocated: impl FnMut(Relocation),
+    ) -> Result<usize, MigrationError> {
+        let mut result = Ok(0);
+        self.for_each_allocation(|ptr, size, _| {
+            let Ok(migrated) = result else {
+                return;
+            };
+            let layout = Layout::from_size_align(size, migrate::inferred_align(ptr)).unwrap();
+            // SAFETY: Live allocations are valid for reads of their usable size
+            result = unsafe { migrate::migrate([(ptr, layout)], target, &mut relocated) }
+                .map(|_| migrated + 1)
+                .map_err(|err| MigrationError { migrated, ..err });
+        });
+
+        result
+    }
+
+    /// Captures the live allocations reported by [`LinkedListAlloc::for_each_allocation`] into
+    /// `out`, sorted by address, for comparing with [`diff`](crate::snapshot::diff). Returns the number of live
+    /// allocations, of which only as many as fit are captured.
+    pub fn snapshot_into(&self, out: &mut [HeapEntry]) -> usize {
+        let mut count = 0;
+        self.for_each_allocation(|ptr, size, tag| {
+            if let Some(entry) = out.get_mut(count) {
+                *entry = HeapEntry {
+                    addr: ptr.as_ptr() as usize,
+                    size,
+                    tag,
+                };
+            }
+            count += 1;
+        });
+        let captured = count.min(out.len());
+        out[..captured].sort_unstable_by_key(|entry| entry.addr);
+
+        count
+    }
+
+    /// Like [`LinkedListAlloc::snapshot_into`], but captures every live allocation into a vector
+    /// of the global allocator.
+    #[cfg(any(feature = "std", test))]
+    pub fn snapshot(&self) -> std::vec::Vec<HeapEntry> {
+        let mut entries = std::vec::Vec::new();
+        self.for_each_allocation(|ptr, size, tag| {
+            entries.push(HeapEntry {
+                addr: ptr.as_ptr() as usize,
+                size,
+                tag,
+            })
+        });
+        entries.sort_unstable_by_key(|entry| entry.addr);
+
+        entries
+    }
+
+    /// Draws the segments of the heap as an SVG picture, see [`svg`](crate::svg). Huge
+    /// allocations served directly by the source aren't drawn, and slabs of small objects are
+    /// drawn as a single allocation.
+    #[cfg(any(feature = "std", test))]
+    pub fn render_svg(&self, map: &SvgMap) -> std::string::String {
+        let internal = self.0.lock();
+        let heap = &internal.segmenter_list;
+        let segments: std::vec::Vec<_> = heap
+            .iter()
+            .map(|segment| SvgSegment {
+                offset: segment.offset(),
+                size: segment.size(),
+                in_use: segment.in_use(),
+                #[cfg(feature = "tagging")]
+                tag: (segment.in_use() && !segment.is_container()).then(|| segment.tag()),
+                #[cfg(not(feature = "tagging"))]
+                tag: None,
+            })
+            .collect();
+        let size = heap.size();
+        drop(internal);
+
+        map.render(size, &segments)
+    }
+
+    /// The bytes of all live allocations, like [`AllocStats::used_bytes`].
+    pub fn used_bytes(&self) -> usize {
+        self.0.lock().stats.used_bytes
+    }
+
+    /// The bytes of the heap in free segments, including their metadata. Memory cached by the
+    /// quick lists and small bins, or held by the source for huge allocations, isn't counted.
+    pub fn free_bytes(&self) -> usize {
+        self.0.lock().segmenter_list.free_bytes()
+    }
+
+    /// The region the heap manages, which is empty until the heap is initialized or has acquired
+    /// its memory from the source. Huge allocations served by the source directly lie outside of
+    /// it.
+    pub fn region(&self) -> Range<*mut u8> {
+        self.0.lock().segmenter_list.region()
+    }
+
+    /// Whether `ptr` lies in the region the heap manages, see [`LinkedListAlloc::region`].
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.0.lock().segmenter_list.contains(ptr)
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        self.0.lock().stats
+    }
+
+    /// Writes a short summary of the heap to `out` without allocating, for panic and out of memory
+    /// handlers: its size, the live allocations, how its free memory is split up, and the
+    /// [`REPORTED_TAGS`] tags holding the most memory. Blocks cached by the quick lists count
+    /// towards the tag they were allocated with.
+    ///
+    /// Rather than waiting for the lock, e.g. after a panic within the allocator, a locked heap is
+    /// only reported as such.
+    pub fn report(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let Some(internal) = self.0.try_lock() else {
+            return writeln!(out, "heap: locked, no report available");
+        };
+        let stats = &internal.stats;
+        let heap = &internal.segmenter_list;
+
+        match stats.name {
+            Some(name) => write!(out, "heap {name:?}")?,
+            None => write!(out, "heap")?,
+        }
+        writeln!(
+            out,
+            ": {} bytes in {} segments",
+            heap.size(),
+            heap.num_segments()
+        )?;
+        writeln!(
+            out,
+            "  used: {} bytes in {} allocations, peak {} bytes in {} allocations",
+            stats.used_bytes,
+            stats.live_allocations,
+            stats.peak_used_bytes,
+            stats.peak_live_allocations
+        )?;
+        writeln!(
+            out,
+            "  free: {} bytes in {} segments, largest {} bytes",
+            heap.free_bytes(),
+            heap.num_free_segments(),
+            heap.largest_free_segment()
+        )?;
+        writeln!(out, "  failed allocations: {}", stats.failed_allocations)?;
+
+        #[cfg(feature = "tagging")]
+        {
+            // Bytes and allocations by tag. Slots of the small bins have no tag
+            let mut usage = [(0, 0); 256];
+            for segment in heap.iter_used().filter(|segment| !segment.is_container()) {
+                let (bytes, allocations) = &mut usage[segment.tag() as usize];
+                *bytes += segment.usable_size();
+                *allocations += 1;
+            }
+            for _ in 0..REPORTED_TAGS {
+                let (tag, (bytes, allocations)) = usage
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .max_by_key(|(_, (bytes, _))| *bytes)
+                    .unwrap();
+                if bytes == 0 {
+                    break;
+                }
+                writeln!(
+                    out,
+                    "  tag {tag}: {bytes} bytes in {allocations} allocations"
+                )?;
+                usage[tag] = (0, 0);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Registers a cache to be shrunk when an allocation fails, after which the allocation is tried
+    /// once more. Fails if [`MAX_SHRINKERS`] shrinkers are already registered.
+    #[allow(clippy::result_unit_err)]
+    pub fn register_shrinker(&self, shrinker: &'static dyn Shrinker) -> Result<(), ()> {
+        let mut internal = self.0.lock();
+        let slot = internal.shrinkers.0.iter_mut().find(|slot| slot.is_none());
+        *slot.ok_or(())? = Some(shrinker);
+        Ok(())
+    }
+
+    /// Runs every registered shrinker, e.g. when the system is low on memory, and returns the
+    /// number of bytes they reclaimed. The allocator isn't locked meanwhile.
+    pub fn run_shrinkers(&self) -> usize {
+        let shrinkers = self.0.lock().shrinkers;
+        if shrinkers.0.iter().all(Option::is_none) {
+            return 0;
+        }
+        let reclaimed = shrinkers.0.iter().flatten().map(|s| s.shrink()).sum();
+
+        let stats = &mut self.0.lock().stats;
+        stats.shrinker_runs += 1;
+        stats.reclaimed_bytes += reclaimed as u64;
+        reclaimed
+    }
+
+    /// Runs `op` on the locked allocator, and once more after shrinking the registered caches if
+    /// it fails and they reclaim anything. The request for `layout` only counts as failed if the
+    /// retry fails too.
+    fn retry_after_shrinking<T>(
+        &self,
+        layout: Layout,
+        mut op: impl FnMut(&mut LinkedListAllocImpl<GRANULE, S, I, H>) -> Result<T, AllocError>,
+    ) -> Result<T, AllocError> {
+        // The lock must be released before the shrinkers run, as they may deallocate
+        let result = op(&mut self.0.lock());
+        let result = match result {
+            Err(AllocError) if self.run_shrinkers() != 0 => op(&mut self.0.lock()),
+            result => result,
+        };
+        result.inspect_err(|_| self.0.lock().record_failure(layout))
+    }
+
+    /// Restarts peak tracking from the current usage.
+    pub fn reset_peaks(&self) {
+        let stats = &mut self.0.lock().stats;
+        stats.peak_used_bytes = stats.used_bytes;
+        stats.peak_live_allocations = stats.live_allocations;
+    }
+
+    /// Names the allocator, to tell its statistics and profiler events apart from those of other
+    /// heaps. Hooks see the name in the [`AllocStats`] they are given.
+    pub fn with_name(self, name: &'static CStr) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    /// Like [`LinkedListAlloc::with_name`], for allocators already in use, e.g. in a static.
+    pub fn set_name(&self, name: &'static CStr) {
+        self.0.lock().stats.name = Some(name);
+    }
+
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.0.lock().stats.name
+    }
+
+    /// Sets what happens to allocations still live when the allocator is dropped.
+    pub fn set_leak_policy(&self, policy: LeakPolicy) {
+        self.0.lock().leak_policy = policy;
+    }
+
+    /// Sets what happens to blocks that can't be freed.
+    pub fn set_free_error_policy(&self, policy: FreeErrorPolicy) {
+        self.0.lock().free_error_policy = policy;
+    }
+
+    /// Mangles the links stored in the heap with `secret` from now on, see [`LinkKey`]. Without
+    /// one, the key is derived from addresses an attacker may know, so the secret should come from
+    /// a source of entropy such as a hardware random number generator. It is kept for a heap that
+    /// is only set up later.
+    #[cfg(feature = "pointer-mangling")]
+    pub fn set_link_secret(&self, secret: usize) {
+        let mut internal = self.0.lock();
+        let key = LinkKey::new(secret);
+        internal.link_key = Some(key);
+        internal.segmenter_list.rekey(key);
+    }
+
+    /// Allocates memory for `layout` like [`Allocator::allocate`], tagging the allocation with
+    /// `tag` so that [`LinkedListAlloc::usage_by_tag`] accounts it separately.
+    ///
+    /// Only allocations with a segment of their own carry a tag. Those served by the small bins or
+    /// directly by the source aren't accounted at all.
+    #[cfg(feature = "tagging")]
+    pub fn allocate_tagged(&self, layout: Layout, tag: u8) -> Result<NonNull<[u8]>, AllocError> {
+        let mut internal = self.0.lock();
+        let current_tag = core::mem::replace(&mut internal.current_tag, tag);
+        let result = internal.allocate(layout);
+        internal.current_tag = current_tag;
+
+        result
+    }
+
+    /// Sets the tag of every allocation made through [`Allocator`] or [`GlobalAlloc`] from now on,
+    /// e.g. for the duration of a subsystem's work. Starts out as 0.
+    #[cfg(feature = "tagging")]
+    pub fn set_current_tag(&self, tag: u8) {
+        self.0.lock().current_tag = tag;
+    }
+
+    #[cfg(feature = "tagging")]
+    pub fn current_tag(&self) -> u8 {
+        self.0.lock().current_tag
+    }
+
+    /// The usable bytes of all live allocations, by tag.
+    #[cfg(feature = "tagging")]
+    pub fn usage_by_tag(&self) -> [usize; 256] {
+        self.0.lock().tag_usage
+    }
+
+    /// Limits the usable bytes of the live allocations of `tag`, regardless of how much of the heap
+    /// is free. Allocations that would exceed the budget fail, unless the budget handler allows
+    /// them. `None` lifts the limit.
+    #[cfg(feature = "tagging")]
+    pub fn set_tag_budget(&self, tag: u8, budget: Option<usize>) {
+        self.0.lock().tag_budgets[tag as usize] = budget.unwrap_or(usize::MAX);
+    }
+
+    /// Sets the function consulted when an allocation would exceed its tag's budget.
+    #[cfg(feature = "tagging")]
+    pub fn set_budget_handler(&self, handler: Option<BudgetHandler>) {
+        self.0.lock().budget_handler = handler;
+    }
+
+    /// Allocates memory for `layout` like [`Allocator::allocate`], attaching `user_data` to the
+    /// allocation, e.g. an owner or type id. It stays attached across resizes, and can be read back
+    /// with [`LinkedListAlloc::user_data`].
+    ///
+    /// Only allocations with a segment of their own carry user data. For those served by the small
+    /// bins or directly by the source, it is dropped.
+    #[cfg(feature = "user-data")]
+    pub fn allocate_with_user_data(
+        &self,
+        layout: Layout,
+        user_data: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut internal = self.0.lock();
+        let ptr = internal.allocate(layout)?;
+        if let Some(segment) = internal.segment_of(ptr.cast(), layout) {
+            unsafe { (*segment).set_user_data(user_data) };
+        }
+
+        Ok(ptr)
+    }
+
+    /// The user data attached to the live allocation at `ptr`, 0 if none was. `None` if the
+    /// allocation can't carry any, see [`LinkedListAlloc::allocate_with_user_data`].
+    ///
+    /// # Safety
+    /// `ptr` must denote a live allocation from this allocator, and `layout` must fit it.
+    #[cfg(feature = "user-data")]
+    pub unsafe fn user_data(&self, ptr: NonNull<u8>, layout: Layout) -> Option<usize> {
+        let internal = self.0.lock();
+        internal
+            .segment_of(ptr, layout)
+            .map(|segment| (*segment).user_data())
+    }
+
+    /// Allocates memory for `layout` like [`Allocator::allocate`], but from the lowest address that
+    /// fits, regardless of the fit policy. Together with [`LinkedListAlloc::allocate_high`], this
+    /// keeps allocations of different lifetimes at opposite ends of the heap, so the long lived
+    /// ones don't pin down the space between short lived ones.
+    ///
+    /// Requests served by the small bins or directly by the source are made as usual.
+    pub fn allocate_low(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_at(layout, Placement::Bottom)
+    }
+
+    /// Allocates memory for `layout` like [`Allocator::allocate`], but from the highest address
+    /// that fits, see [`LinkedListAlloc::allocate_low`]. The allocation is carved from the end of
+    /// a free segment, which only shrinks the segment rather than splitting it.
+    pub fn allocate_high(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_at(layout, Placement::Top)
+    }
+
+    /// Allocates memory for `layout`, placed according to how long it is expected to live: long
+    /// lived allocations from the top of the heap like [`LinkedListAlloc::allocate_high`], short
+    /// lived ones from the bottom like [`LinkedListAlloc::allocate_low`]. The long lived ones thus
+    /// pile up at one end rather than pinning down fragments all over the heap when the short lived
+    /// ones around them are freed.
+    pub fn allocate_with_hint(
+        &self,
+        layout: Layout,
+        hint: LifetimeHint,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match hint {
+            LifetimeHint::Unknown => self.allocate(layout),
+            LifetimeHint::Short => self.allocate_low(layout),
+            LifetimeHint::Long => self.allocate_high(layout),
+        }
+    }
+
+    fn allocate_at(
+        &self,
+        layout: Layout,
+        placement: Placement,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut internal = self.0.lock();
+        internal.heap_end = Some(placement);
+        let result = internal.allocate(layout);
+        internal.heap_end = None;
+
+        result
+    }
+
+    /// Runs `f` on the segment list while holding the lock.
+    pub(crate) fn with_segmenter<T>(&self, f: impl FnOnce(&MemorySegmenter<GRANULE, I>) -> T) -> T {
+        f(&self.0.lock().segmenter_list)
+    }
+
+    /// Whether the heap is back to a single free segment once all cached memory is reclaimed, i.e.
+    /// whether everything allocated from it has been freed.
+    #[cfg(any(feature = "testing", test))]
+    pub(crate) fn is_drained(&self) -> bool {
+        let mut internal = self.0.lock();
+        internal.release_reserves();
+        internal.reclaim();
+        internal.segmenter_list.num_segments() == 1
+            && internal.segmenter_list.num_used_segments() == 0
+    }
+}
+
+/// Maps the segments of the heap itself. Huge allocations served directly by the source aren't
+/// part of the map, and neither are the blocks within slabs of small objects, whose slab shows up
+/// as a single used segment.
+#[cfg(any(feature = "heap-map", test))]
+impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    heap_map::MappableHeap for LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    fn write_map(&self, seq: u32, sink: &mut dyn heap_map::MapSink) -> bool {
+        let Some(internal) = self.0.try_lock() else {
+            return false;
+        };
+        let heap = &internal.segmenter_list;
+        let segments = heap
+            .iter()
+            .map(|segment| (segment.size(), segment.in_use()));
+        heap_map::encode(sink, seq, heap.size(), segments);
+        true
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    Allocator for LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
+        self.retry_after_shrinking(layout, |internal| internal.try_allocate(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.lock().deallocate(ptr, layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.retry_after_shrinking(new_layout, |internal| {
+            internal.try_resize(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = self.retry_after_shrinking(new_layout, |internal| {
+            internal.try_resize(ptr, old_layout, new_layout)
+        })?;
+        let tail = new_ptr.cast::<u8>().as_ptr().add(old_layout.size());
+        tail.write_bytes(0, new_ptr.len() - old_layout.size());
+        Ok(new_ptr)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.lock().resize(ptr, old_layout, new_layout)
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    GlobalAlloc for LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.retry_after_shrinking(layout, |internal| internal.try_allocate(layout)) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let ptr = NonNull::new(ptr).unwrap();
+        match self.retry_after_shrinking(new_layout, |internal| {
+            internal.try_resize(ptr, layout, new_layout)
+        }) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks> Drop
+    for LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    fn drop(&mut self) {
+        let internal = self.0.get_mut();
+        let leaked = match internal.leak_policy {
+            LeakPolicy::Ignore => 0,
+            _ => internal.check_leaks(),
+        };
+        if let Some((region, layout)) = internal.source_region.take() {
+            unsafe { internal.source.release(region, layout) };
+        }
+
+        #[cfg(any(feature = "std", test))]
+        let panicking = std::thread::panicking();
+        #[cfg(not(any(feature = "std", test)))]
+        let panicking = false;
+        if leaked != 0 && matches!(internal.leak_policy, LeakPolicy::Panic) && !panicking {
+            panic!("{leaked} allocations were leaked!");
+        }
+    }
+}
+
+/// The backing memory of a heap declared with [`static_heap!`](crate::static_heap), aligned to
+/// [`DEFAULT_GRANULARITY`]. Only ever accessed through raw pointers.
+#[doc(hidden)]
+#[repr(C, align(16))]
+pub struct StaticHeapRegion<const SIZE: usize>(pub UnsafeCell<[MaybeUninit<u8>; SIZE]>);
+
+unsafe impl<const SIZE: usize> Sync for StaticHeapRegion<SIZE> {}
+
+/// Declares a static [`LinkedListAlloc`] together with a static region of `$size` bytes backing
+/// it, and a safe `init` function handing the region to the allocator. The allocator fails every
+/// allocation until `init` has been called, and `init` panics if called more than once.
+///
+/// Since the function is always called `init`, only one heap can be declared per module.
+///
+/// ```ignore
+/// allocators::static_heap! {
+///     #[global_allocator]
+///     static HEAP: LinkedListAlloc<CriticalSectionLock> = 64 * 1024;
+/// }
+///
+/// fn main() {
+///     init();
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! static_heap {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $size:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $ty = <$ty>::empty();
+
+        /// Hands the static heap region to the heap.
+        ///
+        /// # Panics
+        /// Panics if called more than once.
+        $vis fn init() {
+            const SIZE: usize = $size;
+            static REGION: $crate::allocators::linked_list_allocator::StaticHeapRegion<SIZE> =
+                $crate::allocators::linked_list_allocator::StaticHeapRegion(
+                    ::core::cell::UnsafeCell::new([::core::mem::MaybeUninit::uninit(); SIZE]),
+                );
+
+            // The region is private to this function, and the heap refuses it if it was already
+            // initialized, so it can only ever be handed out once
+            let start = REGION.0.get().cast::<u8>();
+            unsafe { $name.init(start, start.add(SIZE)) };
+        }
+    };
+}
+
+/// Declares a static [`LinkedListAlloc`] over the memory between two linker symbols, and an unsafe
+/// `init` function handing that memory to the allocator. The allocator fails every allocation until
+/// `init` has been called, and `init` panics if called more than once, or if the symbols are out of
+/// order or not aligned to the granularity.
+///
+/// Since the function is always called `init`, only one heap can be declared per module.
+///
+/// ```ignore
+/// allocators::linker_heap! {
+///     #[global_allocator]
+///     static HEAP: LinkedListAlloc<RawSpinlock> = __heap_start..__heap_end;
+/// }
+///
+/// fn main() {
+///     // SAFETY: The linker script reserves the memory between the symbols for the heap
+///     unsafe { init() };
+///     // ...
+/// }
+/// ```
+#[macro_export]
+macro_rules! linker_heap {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $start:ident..$end:ident;) => {
+        $(#[$attr])*
+        $vis static $name: $ty = <$ty>::empty();
+
+        /// Hands the memory between the linker symbols to the heap.
+        ///
+        /// # Safety
+        /// The linker script must reserve the memory between the symbols for the heap, and nothing
+        /// else may use it.
+        ///
+        /// # Panics
+        /// Panics if called more than once, or if the symbols are out of order or misaligned.
+        $vis unsafe fn init() {
+            extern "C" {
+                static mut $start: u8;
+                static mut $end: u8;
+            }
+            $name.init_from_linker(&raw mut $start, &raw mut $end);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    use core::mem::size_of;
+
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    // The exact segment sizes below assume the default two word header
+    #[cfg(not(any(feature = "requested-size", feature = "tagging", feature = "user-data")))]
+    #[test]
+    fn ll_allocator_tests() {
+        const MIB: usize = 1048576;
+        const SIZE: usize = 2 * MIB;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        {
+            let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+                unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+            // Attempt to allocate larger than we can hold
+            let res = allocator.allocate(Layout::from_size_align(SIZE, 16).unwrap());
+            assert!(res.is_err());
+
+            // Attempt to allocate exactly as much as we can hold
+            let res = unsafe {
+                allocator
+                    .allocate(Layout::from_size_align(SIZE - SegmentMetadata::SIZE, 16).unwrap())
+                    .unwrap()
+                    .as_mut()
+            };
+            res.fill(0);
+            assert_eq!(res.as_ptr().align_offset(16), 0);
+            assert_eq!(res.len(), SIZE - SegmentMetadata::SIZE);
+        }
+
+        {
+            let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+                unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+            let mut allocs = Vec::new();
+
+            // Allocate randomly until we no longer can:
+            let mut rng = thread_rng();
+            loop {
+                let mut random_size: usize = rng.gen_range(8..=1024);
+                random_size = random_size.next_multiple_of(SegmentMetadata::SIZE);
+                let random_alignment: usize = 2usize.pow(rng.gen_range(3..=10));
+
+                let res = allocator
+                    .allocate(Layout::from_size_align(random_size, random_alignment).unwrap());
+
+                if res.is_err() {
+                    break;
+                }
+
+                let mem = unsafe { res.unwrap().as_mut() };
+                mem.fill(0);
+                assert_eq!(mem.as_ptr().align_offset(random_alignment), 0);
+                assert_eq!(mem.len(), random_size);
+                allocs.push(mem.as_ptr());
+
+                // Metadata should be immediately before the ptr...
+                let metadata = mem.as_mut_ptr() as *mut SegmentMetadata;
+                let metadata = unsafe { metadata.sub(1) };
+                let metadata_mut = unsafe { metadata.as_mut().unwrap() };
+                assert_eq!(metadata_mut.size_allocable(), random_size);
+            }
+
+            // If we didnt allocate at least this many times, something very likely went wrong...
+            assert!(allocs.len() > 1000);
+
+            // Deallocate in a random order
+            while !allocs.is_empty() {
+                let idx = rng.gen_range(0..allocs.len());
+                let ptr = allocs.swap_remove(idx);
+
+                unsafe {
+                    allocator.deallocate(
+                        NonNull::new(ptr.cast_mut()).unwrap(),
+                        Layout::from_size_align(8, 8).unwrap(),
+                    );
+                }
+            }
+            assert_eq!(
+                allocator.0.lock().segmenter_list.overhead(),
+                SegmentMetadata::SIZE
+            );
+
+            // Try to allocate entire memory to ensure we successfully deallocated everything
+            let mem = unsafe {
+                allocator
+                    .allocate(Layout::from_size_align(SIZE - SegmentMetadata::SIZE, 16).unwrap())
+                    .unwrap()
+                    .as_ptr()
+                    .as_mut()
+                    .unwrap()
+            };
+            mem.fill(0);
+            assert_eq!(mem.as_ptr().align_offset(16), 0);
+            assert_eq!(mem.len(), SIZE - SegmentMetadata::SIZE);
+
+            unsafe {
+                allocator.deallocate(
+                    NonNull::new(mem.as_ptr().cast_mut()).unwrap(),
+                    Layout::from_size_align(8, 8).unwrap(),
+                );
+            }
+
+            // Test a weird, nonstandard small size and alignment
+            let mem = unsafe {
+                {
+                    allocator
+                        .allocate(Layout::from_size_align(1, 2).unwrap())
+                        .unwrap()
+                        .as_ptr()
+                        .as_mut()
+                        .unwrap()
+                }
+            };
+            assert_eq!(mem.len(), SegmentMetadata::SIZE);
+            assert_eq!(mem.as_ptr().align_offset(SegmentMetadata::SIZE), 0);
+        }
+    }
+
+    #[test]
+    fn ll_allocator_policies() {
+        const SIZE: usize = 256 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        for policy in [
+            FitPolicy::FirstFit,
+            FitPolicy::LastFit,
+            FitPolicy::BestFit,
+            FitPolicy::AddressOrderedBestFit,
+            FitPolicy::NextFit,
+        ] {
+            let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+                unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+            allocator.set_fit_policy(policy);
+            assert_eq!(allocator.fit_policy(), policy);
+
+            // Interleave allocations and frees so the searches have several holes to choose from
+            let mut allocs = Vec::new();
+            let mut rng = thread_rng();
+            for _ in 0..2000 {
+                if !allocs.is_empty() && rng.gen_bool(0.4) {
+                    let idx = rng.gen_range(0..allocs.len());
+                    let (ptr, layout) = allocs.swap_remove(idx);
+                    unsafe { allocator.deallocate(ptr, layout) };
+                    continue;
+                }
+
+                let size = rng.gen_range(1..=512);
+                let align = 2usize.pow(rng.gen_range(0..=9));
+                let layout = Layout::from_size_align(size, align).unwrap();
+                if let Ok(mut res) = allocator.allocate(layout) {
+                    let mem = unsafe { res.as_mut() };
+                    mem.fill(0xAB);
+                    assert_eq!(mem.as_ptr().align_offset(align), 0);
+                    assert!(mem.len() >= size);
+                    allocs.push((res.cast(), layout));
+                }
+            }
+
+            for (ptr, layout) in allocs {
+                unsafe { allocator.deallocate(ptr, layout) };
+            }
+            assert_eq!(
+                allocator.0.lock().segmenter_list.overhead(),
+                SegmentMetadata::SIZE
+            );
+        }
+
+        // The first fit policy should always fill the region from the bottom
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        allocator.set_fit_policy(FitPolicy::FirstFit);
+        let first = allocator.allocate(Layout::new::<u64>()).unwrap();
+        assert_eq!(first.cast::<u8>().as_ptr(), unsafe {
+            mem.add(SegmentMetadata::SIZE)
+        });
+    }
+
+    #[test]
+    fn ll_allocator_preserve_wilderness() {
+        const SIZE: usize = 16 * 1024;
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        allocator.set_fit_policy(FitPolicy::FirstFit);
+        allocator.set_preserve_wilderness(true);
+
+        // Leave a hole at the bottom, below the wilderness
+        let large = Layout::new::<[u8; 1024]>();
+        let a = allocator.allocate(large).unwrap();
+        let b = allocator.allocate(Layout::new::<u64>()).unwrap();
+        unsafe { allocator.deallocate(a.cast(), large) };
+        let wilderness =
+            || allocator.with_segmenter(|segmenter| segmenter.wilderness().unwrap().size());
+        let before = wilderness();
+
+        // Even with a last fit policy, requests go into the hole as long as they fit
+        allocator.set_fit_policy(FitPolicy::LastFit);
+        let small = Layout::new::<[u8; 256]>();
+        let c = allocator.allocate(small).unwrap();
+        assert!(c.cast::<u8>() < b.cast());
+        assert_eq!(wilderness(), before);
+
+        // Only what fits nowhere else is carved out of the wilderness
+        let d = allocator.allocate(large).unwrap();
+        assert!(d.cast::<u8>() > b.cast());
+        assert!(wilderness() < before);
+
+        unsafe {
+            allocator.deallocate(b.cast(), Layout::new::<u64>());
+            allocator.deallocate(c.cast(), small);
+            allocator.deallocate(d.cast(), large);
+        }
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn ll_allocator_dual_ended() {
+        const SIZE: usize = 64 * 1024;
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+        // Each end is filled from the outside in, without splitting off more than one segment
+        let layout = Layout::new::<[u8; 1000]>();
+        let low = allocator.allocate_low(layout).unwrap();
+        let high = allocator.allocate_high(layout).unwrap();
+        assert_eq!(low.cast::<u8>().as_ptr(), unsafe {
+            mem.add(SegmentMetadata::SIZE)
+        });
+        assert_eq!(
+            high.cast::<u8>().as_ptr() as usize + high.len(),
+            mem as usize + SIZE
+        );
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), 3);
+
+        let aligned = Layout::from_size_align(100, 256).unwrap();
+        let high_aligned = allocator.allocate_high(aligned).unwrap();
+        assert_eq!(high_aligned.cast::<u8>().as_ptr().align_offset(256), 0);
+        assert!(high_aligned.cast::<u8>() < high.cast());
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), 4);
+
+        // Mixing both ends with the regular interface keeps the segment list sound
+        let mut allocs = vec![(low, layout), (high, layout), (high_aligned, aligned)];
+        let mut rng = thread_rng();
+        for _ in 0..2000 {
+            if !allocs.is_empty() && rng.gen_bool(0.4) {
+                let (ptr, layout) = allocs.swap_remove(rng.gen_range(0..allocs.len()));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+                continue;
+            }
+
+            let layout =
+                Layout::from_size_align(rng.gen_range(1..=512), 1 << rng.gen_range(0..=8)).unwrap();
+            let result = match rng.gen_range(0..3) {
+                0 => allocator.allocate_low(layout),
+                1 => allocator.allocate_high(layout),
+                _ => allocator.allocate(layout),
+            };
+            if let Ok(ptr) = result {
+                assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(layout.align()), 0);
+                allocs.push((ptr, layout));
+            }
+            assert_eq!(allocator.0.lock().segmenter_list.check_integrity(), Ok(()));
+        }
+
+        for (ptr, layout) in allocs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), 1);
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn ll_allocator_lifetime_hints() {
+        const SIZE: usize = 64 * 1024;
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+        // Interleaved allocations of both lifetimes end up at opposite ends
+        let layout = Layout::new::<[u8; 500]>();
+        let mut short = Vec::new();
+        let mut long = Vec::new();
+        for _ in 0..16 {
+            short.push(
+                allocator
+                    .allocate_with_hint(layout, LifetimeHint::Short)
+                    .unwrap(),
+            );
+            long.push(
+                allocator
+                    .allocate_with_hint(layout, LifetimeHint::Long)
+                    .unwrap(),
+            );
+        }
+        let highest_short = short.iter().map(|ptr| ptr.cast::<u8>()).max().unwrap();
+        assert!(long.iter().all(|ptr| ptr.cast::<u8>() > highest_short));
+
+        // So freeing the short lived ones leaves a single hole
+        for ptr in short {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.0.lock().segmenter_list.num_free_segments(), 1);
+        let unknown = allocator
+            .allocate_with_hint(layout, LifetimeHint::Unknown)
+            .unwrap();
+
+        for ptr in long.into_iter().chain([unknown]) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), 1);
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn ll_allocator_random_placement() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+        // Fragment the heap into many free segments
+        let layout = Layout::from_size_align(200, 16).unwrap();
+        let allocs: Vec<_> = (0..64)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        for ptr in allocs.iter().step_by(2) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        let placements = |placement: RandomPlacement| {
+            allocator.set_random_placement(Some(placement));
+            let ptrs: Vec<_> = (0..16)
+                .map(|_| allocator.allocate(layout).unwrap())
+                .collect();
+            for ptr in &ptrs {
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+            ptrs
+        };
+
+        // The same seed gives the same layout, different seeds different ones
+        for randomize_offset in [false, true] {
+            let seeded = |seed| RandomPlacement {
+                seed,
+                randomize_offset,
+            };
+            assert_eq!(placements(seeded(1)), placements(seeded(1)));
+            assert_ne!(placements(seeded(1)), placements(seeded(2)));
+        }
+
+        allocator.set_random_placement(None);
+        for ptr in allocs.iter().skip(1).step_by(2) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), 1);
+    }
+
+    #[test]
+    fn ll_allocator_reserve() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+
+        // Reserved blocks are split off up front, and handed out without splitting anything else
+        let layout = Layout::from_size_align(200, 64).unwrap();
+        allocator.reserve(layout, 8).unwrap();
+        assert_eq!(allocator.reserved(layout), 8);
+        let segments = allocator.0.lock().segmenter_list.num_segments();
+        let ptrs: Vec<_> = (0..8)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        assert!(ptrs
+            .iter()
+            .all(|ptr| ptr.cast::<u8>().align_offset(64) == 0 && ptr.len() >= 200));
+        assert_eq!(allocator.0.lock().segmenter_list.num_segments(), segments);
+        assert_eq!(allocator.reserved(layout), 0);
+        assert_eq!(allocator.stats().live_allocations, 8);
+
+        // Freed blocks refill the reserve, but only up to its size
+        let extra = allocator.allocate(layout).unwrap();
+        for ptr in ptrs.iter().chain([&extra]) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.reserved(layout), 8);
+        assert_eq!(allocator.stats().live_allocations, 0);
+
+        // Only so many layouts, and only those with segments of their own
+        for size in [300, 400, 500] {
+            let other = Layout::from_size_align(size, 16).unwrap();
+            allocator.reserve(other, 1).unwrap();
+        }
+        assert!(allocator.reserve(Layout::new::<[u8; 600]>(), 1).is_err());
+        assert!(allocator
+            .reserve(Layout::new::<[u8; 32 * 1024]>(), 1)
+            .is_err());
+
+        allocator.release_reserve(layout);
+        assert_eq!(allocator.reserved(layout), 0);
+        assert!(allocator.snapshot().is_empty());
+        assert!(allocator.is_drained());
+    }
+
+    #[test]
+    fn ll_allocator_cache_stats() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        allocator.set_quick_lists_enabled(true);
+        let layout = Layout::from_size_align(40, 8).unwrap();
+
+        let first = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(first.cast(), layout) };
+        let again = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(again.cast(), layout) };
+        // Too large to be cached at all
+        let large = Layout::new::<[u8; 1024]>();
+        let ptr = allocator.allocate(large).unwrap();
+        unsafe { allocator.deallocate(ptr.cast(), large) };
+        allocator.flush_quick_lists();
+        let expected = CacheStats {
+            hits: 1,
+            misses: 1,
+            refills: 2,
+            flushes: 1,
+        };
+        assert_eq!(allocator.stats().quick_lists, expected);
+
+        allocator.set_quick_lists_enabled(false);
+        let reserved = Layout::from_size_align(200, 16).unwrap();
+        allocator.reserve(reserved, 2).unwrap();
+        let ptrs: Vec<_> = (0..3)
+            .map(|_| allocator.allocate(reserved).unwrap())
+            .collect();
+        for ptr in ptrs {
+            unsafe { allocator.deallocate(ptr.cast(), reserved) };
+        }
+        allocator.release_reserve(reserved);
+        let expected = CacheStats {
+            hits: 2,
+            misses: 1,
+            refills: 4,
+            flushes: 2,
+        };
+        assert_eq!(allocator.stats().reserves, expected);
+        assert!(allocator.is_drained());
+    }
+
+    #[test]
+    fn ll_allocator_quick_lists() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        allocator.set_quick_lists_enabled(true);
+        let layout = Layout::from_size_align(40, 8).unwrap();
+        let overhead = || allocator.0.lock().segmenter_list.overhead();
+
+        // A freed block is handed straight back out for the next request of the same size
+        let first = allocator.allocate(layout).unwrap();
+        let _guard = allocator.allocate(layout).unwrap();
+        let overhead_before = overhead();
+        unsafe { allocator.deallocate(first.cast(), layout) };
+        assert_eq!(overhead(), overhead_before);
+        let again = allocator.allocate(layout).unwrap();
+        assert_eq!(again.cast::<u8>(), first.cast::<u8>());
+        assert_eq!(again.len(), first.len());
+
+        // Other sizes and over-aligned requests don't come from the quick list
+        unsafe { allocator.deallocate(again.cast(), layout) };
+        let bigger = allocator
+            .allocate(Layout::from_size_align(64, 8).unwrap())
+            .unwrap();
+        assert_ne!(bigger.cast::<u8>(), first.cast::<u8>());
+        let aligned = allocator
+            .allocate(Layout::from_size_align(40, 4096).unwrap())
+            .unwrap();
+        assert_ne!(aligned.cast::<u8>(), first.cast::<u8>());
+        assert_eq!(aligned.cast::<u8>().as_ptr().align_offset(4096), 0);
+
+        // Flushing returns the parked block to the segment list
+        let first_segment = unsafe { first.cast::<SegmentMetadata>().as_ptr().sub(1).as_ref() };
+        assert!(first_segment.unwrap().in_use());
+        allocator.flush_quick_lists();
+        assert!(allocator.0.lock().quick_lists.is_empty());
+        assert!(!first_segment.unwrap().in_use());
+
+        // When the heap runs out, parked blocks are flushed before giving up
+        let mut allocs = Vec::new();
+        while let Ok(ptr) = allocator.allocate(layout) {
+            allocs.push(ptr);
+        }
+        for ptr in allocs.drain(..) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        let large = Layout::from_size_align(SIZE / 2, 16).unwrap();
+        let res = allocator.allocate(large).unwrap();
+        unsafe { allocator.deallocate(res.cast(), large) };
+
+        allocator.set_quick_lists_enabled(false);
+        assert!(allocator.0.lock().quick_lists.is_empty());
+    }
+
+    #[test]
+    fn ll_allocator_deferred_coalescing() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        allocator.set_deferred_coalescing(true);
+        let layout = Layout::from_size_align(1000, 16).unwrap();
+        let num_segments = || allocator.0.lock().segmenter_list.num_segments();
+
+        // Freeing leaves the segments in place
+        let allocs: Vec<_> = (0..8)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        for ptr in &allocs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(num_segments(), 9);
+        assert_eq!(allocator.0.lock().segmenter_list.num_used_segments(), 0);
+        allocator.coalesce_all();
+        assert_eq!(num_segments(), 1);
+
+        // A failing fit search coalesces before giving up
+        let mut allocs = Vec::new();
+        while let Ok(ptr) = allocator.allocate(layout) {
+            allocs.push(ptr);
+        }
+        for ptr in allocs.drain(..) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert!(num_segments() > 1);
+        let large = Layout::from_size_align(SIZE / 2, 16).unwrap();
+        let res = allocator.allocate(large).unwrap();
+        unsafe { allocator.deallocate(res.cast(), large) };
+
+        allocator.set_deferred_coalescing(false);
+        assert_eq!(num_segments(), 1);
+    }
+
+    #[test]
+    fn ll_allocator_bucket_index() {
+        use crate::{memory_segmenter::BucketIndex, testing::TestHeap};
+
+        let heap: TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16, NoSource, BucketIndex>> =
+            TestHeap::new(64 * 1024);
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..3000 {
+            if i == 1000 {
+                heap.set_quick_lists_enabled(true);
+            } else if i == 2000 {
+                heap.set_deferred_coalescing(true);
+            }
+
+            if rng.gen_bool(0.55) {
+                let align = 1 << rng.gen_range(3..8);
+                let layout = Layout::from_size_align(rng.gen_range(1..2048), align).unwrap();
+                if let Ok(mut ptr) = heap.allocate(layout) {
+                    assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(align), 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { heap.deallocate(ptr.cast(), layout) };
+            }
+        }
+        heap.with_segmenter(|segmenter| assert_eq!(segmenter.check_integrity(), Ok(())));
+
+        for (ptr, layout, _) in live {
+            unsafe { heap.deallocate(ptr.cast(), layout) };
+        }
+        // The whole heap is found again once everything is merged
+        heap.coalesce_all();
+        heap.flush_quick_lists();
+        let everything = Layout::from_size_align(heap.largest_free_block(), 16).unwrap();
+        assert!(everything.size() > 60 * 1024);
+        let ptr = heap.allocate(everything).unwrap();
+        unsafe { heap.deallocate(ptr.cast(), everything) };
+    }
+
+    #[test]
+    fn ll_allocator_small_bins() {
+        const SIZE: usize = 256 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) }.with_small_bins();
+        let segments = || allocator.0.lock().segmenter_list.num_used_segments();
+
+        // Small objects are packed into a slab without per-object metadata
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let a = allocator.allocate(small).unwrap();
+        let b = allocator.allocate(small).unwrap();
+        assert_eq!(a.len(), 32);
+        assert_eq!(
+            unsafe { b.cast::<u8>().offset_from(a.cast::<u8>()) }.abs(),
+            32
+        );
+        assert_eq!(segments(), 1);
+
+        // Large or over-aligned requests go through the segment list
+        let large = allocator
+            .allocate(Layout::from_size_align(512, 8).unwrap())
+            .unwrap();
+        let aligned = allocator
+            .allocate(Layout::from_size_align(16, 64).unwrap())
+            .unwrap();
+        assert_eq!(aligned.cast::<u8>().as_ptr().align_offset(64), 0);
+        assert_eq!(segments(), 3);
+        unsafe {
+            allocator.deallocate(large.cast(), Layout::from_size_align(512, 8).unwrap());
+            allocator.deallocate(aligned.cast(), Layout::from_size_align(16, 64).unwrap());
+            allocator.deallocate(a.cast(), small);
+            allocator.deallocate(b.cast(), small);
+        }
+
+        // Churn every size class, including filling several slabs per class
+        let mut allocs = Vec::new();
+        let mut rng = thread_rng();
+        for _ in 0..20000 {
+            if !allocs.is_empty() && rng.gen_bool(0.45) {
+                let idx = rng.gen_range(0..allocs.len());
+                let (ptr, layout, val): (NonNull<[u8]>, Layout, u8) = allocs.swap_remove(idx);
+                let mem = unsafe { ptr.as_ref() };
+                assert!(mem[..layout.size()].iter().all(|x| *x == val));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+                continue;
+            }
+
+            let layout = Layout::from_size_align(rng.gen_range(0..=160), 8).unwrap();
+            if let Ok(mut ptr) = allocator.allocate(layout) {
+                let val = rng.gen();
+                unsafe { ptr.as_mut() }.fill(val);
+                assert!(ptr.len() >= layout.size());
+                allocs.push((ptr, layout, val));
+            }
+        }
+        for (ptr, layout, _) in allocs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+
+        // Once emptied, the slabs are reclaimed when the heap can't satisfy a request otherwise
+        let everything = Layout::from_size_align(SIZE - SegmentMetadata::SIZE, 16).unwrap();
+        let res = allocator.allocate(everything).unwrap();
+        unsafe { allocator.deallocate(res.cast(), everything) };
+        assert_eq!(segments(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn ll_allocator_small_bins_late() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        let _ = allocator.allocate(Layout::new::<u64>()).unwrap();
+        let _ = allocator.with_small_bins();
+    }
+
+    #[test]
+    fn ll_allocator_huge() {
+        use crate::memory_source::SystemSource;
+
+        const SIZE: usize = 256 * 1024;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::from_source(SystemSource::new(), SIZE).unwrap();
+        let (start, end) = {
+            let internal = allocator.0.lock();
+            let (region, layout) = internal.source_region.unwrap();
+            (region.as_ptr(), unsafe {
+                region.as_ptr().add(layout.size())
+            })
+        };
+        let in_heap = |ptr: NonNull<[u8]>| {
+            let ptr = ptr.cast::<u8>().as_ptr();
+            ptr >= start && ptr < end
+        };
+
+        // Huge allocations don't touch the heap at all
+        let huge = Layout::from_size_align(DEFAULT_HUGE_THRESHOLD, 16).unwrap();
+        let mut res = allocator.allocate(huge).unwrap();
+        assert!(!in_heap(res));
+        assert!(res.len() >= DEFAULT_HUGE_THRESHOLD);
+        unsafe { res.as_mut() }.fill(0);
+        assert_eq!(allocator.0.lock().segmenter_list.num_used_segments(), 0);
+
+        let small = Layout::from_size_align(DEFAULT_HUGE_THRESHOLD - 1, 16).unwrap();
+        let res_small = allocator.allocate(small).unwrap();
+        assert!(in_heap(res_small));
+
+        // Even if they are larger than the heap itself
+        let larger = Layout::from_size_align(SIZE * 4, 4096).unwrap();
+        let res_larger = allocator.allocate(larger).unwrap();
+        assert_eq!(res_larger.cast::<u8>().as_ptr().align_offset(4096), 0);
+
+        unsafe {
+            allocator.deallocate(res.cast(), huge);
+            allocator.deallocate(res_small.cast(), small);
+            allocator.deallocate(res_larger.cast(), larger);
+        }
+
+        // The threshold is configurable
+        let allocator = allocator.with_huge_threshold(1024);
+        let res = allocator.allocate(Layout::new::<[u8; 1024]>()).unwrap();
+        assert!(!in_heap(res));
+        unsafe { allocator.deallocate(res.cast(), Layout::new::<[u8; 1024]>()) };
+    }
+
+    #[test]
+    fn ll_allocator_huge_free_with_usable_size() {
+        use crate::memory_source::SystemSource;
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::from_source(SystemSource::new(), 1 << 20).unwrap();
+
+        // A heap block just below the threshold whose usable size reaches it is still freed to
+        // the heap
+        let layout = Layout::from_size_align(DEFAULT_HUGE_THRESHOLD - 8, 16).unwrap();
+        let res = allocator.allocate(layout).unwrap();
+        assert!(allocator.contains(res.cast().as_ptr()));
+        assert!(res.len() >= DEFAULT_HUGE_THRESHOLD);
+        let usable = Layout::from_size_align(res.len(), 16).unwrap();
+        unsafe { allocator.deallocate(res.cast(), usable) };
+        assert_eq!(allocator.stats().live_allocations, 0);
+        assert_eq!(allocator.stats().used_bytes, 0);
+        assert_eq!(allocator.0.lock().segmenter_list.num_used_segments(), 0);
+    }
+
+    #[cfg(feature = "zero-on-free")]
+    #[test]
+    fn ll_allocator_zero_on_free() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) }.with_small_bins();
+        allocator.set_quick_lists_enabled(true);
+
+        // Whatever ends up reusing or absorbing the block, the secret is gone. Only the first word
+        // may be reused, for free list links
+        for size in [8, 40, 1000] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let _guard = allocator.allocate(layout).unwrap();
+            let mut block = allocator.allocate(layout).unwrap();
+            let block = unsafe { block.as_mut() };
+            block.fill(0xA5);
+            unsafe { allocator.deallocate(NonNull::from(&mut *block).cast(), layout) };
+            assert!(block[size_of::<usize>()..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn ll_allocator_from_static() {
+        let region =
+            alloc::boxed::Box::leak(alloc::vec![MaybeUninit::uninit(); 4099].into_boxed_slice());
+        let unaligned = &mut region[1..];
+        let start = unaligned.as_ptr() as usize;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            LinkedListAlloc::from_static(unaligned);
+
+        let internal = allocator.0.lock();
+        let head = internal.segmenter_list.iter().next().unwrap();
+        assert_eq!(head.addr() as usize, start.next_multiple_of(16));
+        assert!(internal.segmenter_list.size() <= 4098);
+        assert!(internal.segmenter_list.size() >= 4098 - 30);
+        assert!(internal.segmenter_list.size().is_multiple_of(16));
+    }
+
+    #[test]
+    fn ll_allocator_with_capacity() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(5000, 256);
+        {
+            let internal = allocator.0.lock();
+            let head = internal.segmenter_list.iter().next().unwrap();
+            assert_eq!(head.addr().align_offset(256), 0);
+            assert!(internal.segmenter_list.size() >= 5000);
+        }
+
+        // Even large requests are served from the owned region
+        let layout = Layout::from_size_align(4096, 16).unwrap();
+        let largest = allocator.largest_free_block();
+        assert!(largest >= 5000 - SegmentMetadata::SIZE);
+        let ptr = allocator.allocate(layout).unwrap();
+        assert!(allocator.largest_free_block() < largest - 4096);
+        assert_eq!(allocator.0.lock().segmenter_list.num_used_segments(), 1);
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn ll_allocator_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LinkedListAlloc<parking_lot::RawMutex>>();
+        assert_send_sync::<LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>>();
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(64 * 1024, 16);
+        allocator.set_quick_lists_enabled(true);
+        std::thread::scope(|scope| {
+            for thread in 0..4u8 {
+                let allocator = &allocator;
+                scope.spawn(move || {
+                    let mut rng = thread_rng();
+                    for _ in 0..200 {
+                        let layout = Layout::from_size_align(rng.gen_range(1..512), 16).unwrap();
+                        let Ok(mut ptr) = allocator.allocate(layout) else {
+                            continue;
+                        };
+                        // Nobody else may touch the allocation while we hold it
+                        unsafe { ptr.as_mut() }.fill(thread);
+                        std::thread::yield_now();
+                        assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == thread));
+                        unsafe { allocator.deallocate(ptr.cast(), layout) };
+                    }
+                });
+            }
+        });
+
+        // Moving the allocator to another thread moves its heap along
+        std::thread::spawn(move || {
+            let layout = Layout::new::<u64>();
+            let ptr = allocator.allocate(layout).unwrap();
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn ll_allocator_report() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16).with_name(c"main");
+        let layout = Layout::new::<[u8; 1000]>();
+        let a = allocator.allocate(layout).unwrap();
+        let b = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(a.cast(), layout) };
+
+        let mut report = String::new();
+        allocator.report(&mut report).unwrap();
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next(),
+            Some("heap \"main\": 4096 bytes in 3 segments")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("  used: 1008 bytes in 1 allocations, peak 2016 bytes in 2 allocations")
+        );
+        let free = format!(
+            "  free: {} bytes in 2 segments, largest {} bytes",
+            allocator.free_bytes(),
+            allocator.largest_free_block()
+        );
+        assert_eq!(lines.next(), Some(free.as_str()));
+        assert_eq!(lines.next(), Some("  failed allocations: 0"));
+        #[cfg(feature = "tagging")]
+        assert_eq!(lines.next(), Some("  tag 0: 1008 bytes in 1 allocations"));
+        assert_eq!(lines.next(), None);
+
+        // A heap locked by a panicking operation is only reported as such
+        let internal = allocator.0.lock();
+        report.clear();
+        allocator.report(&mut report).unwrap();
+        assert_eq!(report, "heap: locked, no report available\n");
+        drop(internal);
+
+        unsafe { allocator.deallocate(b.cast(), layout) };
+    }
+
+    #[cfg(feature = "tagging")]
+    #[test]
+    fn ll_allocator_tagging() {
+        const AUDIO: u8 = 1;
+        const RENDERING: u8 = 2;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        allocator.set_quick_lists_enabled(true);
+
+        let untagged = Layout::new::<[u8; 32]>();
+        let audio = Layout::new::<[u8; 1024]>();
+        let rendering = Layout::new::<[u8; 512]>();
+        let a = allocator.allocate(untagged).unwrap();
+        let b = allocator.allocate_tagged(audio, AUDIO).unwrap();
+        allocator.set_current_tag(RENDERING);
+        let c = allocator.allocate(rendering).unwrap();
+        let d = allocator.allocate(rendering).unwrap();
+        allocator.set_current_tag(0);
+
+        let usage = allocator.usage_by_tag();
+        assert_eq!(usage[0], 32);
+        assert_eq!(usage[AUDIO as usize], 1024);
+        assert_eq!(usage[RENDERING as usize], 1024);
+
+        // Blocks reused from a quick list take on the new tag
+        unsafe { allocator.deallocate(a.cast(), untagged) };
+        let a = allocator.allocate_tagged(untagged, AUDIO).unwrap();
+        unsafe {
+            allocator.deallocate(c.cast(), rendering);
+            allocator.deallocate(d.cast(), rendering);
+        }
+        let usage = allocator.usage_by_tag();
+        assert_eq!(usage[0], 0);
+        assert_eq!(usage[AUDIO as usize], 1024 + 32);
+        assert_eq!(usage[RENDERING as usize], 0);
+
+        unsafe {
+            allocator.deallocate(a.cast(), untagged);
+            allocator.deallocate(b.cast(), audio);
+        }
+    }
+
+    #[cfg(feature = "user-data")]
+    #[test]
+    fn ll_allocator_user_data() {
+        const OWNER: usize = 0xC0FFEE;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16).with_small_bins();
+
+        let layout = Layout::new::<[u8; 512]>();
+        let a = allocator.allocate_with_user_data(layout, OWNER).unwrap();
+        let b = allocator.allocate(layout).unwrap();
+        assert_eq!(
+            unsafe { allocator.user_data(a.cast(), layout) },
+            Some(OWNER)
+        );
+        assert_eq!(unsafe { allocator.user_data(b.cast(), layout) }, Some(0));
+
+        // The user data moves along with the allocation
+        let grown = Layout::new::<[u8; 2048]>();
+        let a = unsafe { allocator.grow(a.cast(), layout, grown) }.unwrap();
+        assert_eq!(unsafe { allocator.user_data(a.cast(), grown) }, Some(OWNER));
+
+        // Small bin slots have no segment to keep it in
+        let small = Layout::new::<u64>();
+        let c = allocator.allocate_with_user_data(small, OWNER).unwrap();
+        assert_eq!(unsafe { allocator.user_data(c.cast(), small) }, None);
+
+        unsafe {
+            allocator.deallocate(a.cast(), grown);
+            allocator.deallocate(b.cast(), layout);
+            allocator.deallocate(c.cast(), small);
+        }
+    }
+
+    #[cfg(feature = "tagging")]
+    #[test]
+    fn ll_allocator_tag_budgets() {
+        const AUDIO: u8 = 1;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        allocator.set_tag_budget(AUDIO, Some(1024));
+
+        let layout = Layout::new::<[u8; 512]>();
+        let a = allocator.allocate_tagged(layout, AUDIO).unwrap();
+        let b = allocator.allocate_tagged(layout, AUDIO).unwrap();
+        // The budget is exhausted, but other tags don't care
+        assert!(allocator.allocate_tagged(layout, AUDIO).is_err());
+        assert_eq!(allocator.usage_by_tag()[AUDIO as usize], 1024);
+        let c = allocator.allocate(layout).unwrap();
+
+        // The handler may let allocations through anyway
+        allocator.set_budget_handler(Some(|tag, usage, _| tag == AUDIO && usage <= 2048));
+        let d = allocator.allocate_tagged(layout, AUDIO).unwrap();
+        allocator.set_tag_budget(AUDIO, None);
+        allocator.set_budget_handler(None);
+
+        for ptr in [a, b, c, d] {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.usage_by_tag()[AUDIO as usize], 0);
+    }
+
+    #[cfg(feature = "tagging")]
+    #[test]
+    fn ll_allocator_tag_budgets_cached() {
+        const AUDIO: u8 = 1;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        allocator.set_quick_lists_enabled(true);
+        allocator.set_tag_budget(AUDIO, Some(16));
+        let layout = Layout::from_size_align(40, 8).unwrap();
+
+        // A cached block the budget rejects stays cached, and counts as neither a hit nor a refill
+        let a = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(a.cast(), layout) };
+        let before = allocator.stats().quick_lists;
+        assert!(allocator.allocate_tagged(layout, AUDIO).is_err());
+        assert_eq!(allocator.stats().quick_lists, before);
+        assert_eq!(allocator.usage_by_tag()[AUDIO as usize], 0);
+        let b = allocator.allocate(layout).unwrap();
+        assert_eq!(b.cast::<u8>(), a.cast::<u8>());
+        assert_eq!(allocator.stats().quick_lists.hits, before.hits + 1);
+        unsafe { allocator.deallocate(b.cast(), layout) };
+
+        // The same goes for reserves
+        allocator.set_quick_lists_enabled(false);
+        allocator.reserve(layout, 1).unwrap();
+        let before = allocator.stats().reserves;
+        assert!(allocator.allocate_tagged(layout, AUDIO).is_err());
+        assert_eq!(allocator.stats().reserves, before);
+        assert_eq!(allocator.reserved(layout), 1);
+        assert_eq!(allocator.usage_by_tag()[AUDIO as usize], 0);
+    }
+
+    #[test]
+    fn ll_allocator_peaks() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::from_source(SystemSource::new(), 16 * 1024)
+                .unwrap()
+                .with_huge_threshold(8192)
+                .with_small_bins();
+        allocator.set_quick_lists_enabled(true);
+
+        let tiny = Layout::new::<[u8; 16]>();
+        let small = Layout::new::<[u8; 200]>();
+        let huge = Layout::new::<[u8; 10000]>();
+        let a = allocator.allocate(tiny).unwrap();
+        let b = allocator.allocate(small).unwrap();
+        let c = allocator.allocate(huge).unwrap();
+        let used = a.len() + b.len() + c.len();
+        let stats = allocator.stats();
+        assert_eq!(stats.used_bytes, used);
+        assert_eq!(stats.live_allocations, 3);
+        assert_eq!(stats.peak_used_bytes, used);
+        assert_eq!(allocator.used_bytes(), used);
+        let free = allocator.free_bytes();
+        assert!(free <= 16 * 1024 - b.len());
 
-#[derive(Debug)]
-struct LinkedListAllocImpl {
-    segmenter_list: MemorySegmenter,
-}
+        unsafe {
+            allocator.deallocate(c.cast(), huge);
+            allocator.deallocate(b.cast(), Layout::new::<[u8; 190]>());
+        }
+        let stats = allocator.stats();
+        assert_eq!(stats.used_bytes, a.len());
+        assert_eq!(stats.peak_used_bytes, used);
+        assert_eq!(stats.peak_live_allocations, 3);
+        allocator.flush_quick_lists();
+        assert!(allocator.free_bytes() > free);
 
-#[derive(Debug)]
-pub struct LinkedListAlloc<R: lock_api::RawMutex>(lock_api::Mutex<R, LinkedListAllocImpl>);
+        allocator.reset_peaks();
+        let stats = allocator.stats();
+        assert_eq!(stats.peak_used_bytes, a.len());
+        assert_eq!(stats.peak_live_allocations, 1);
+        unsafe { allocator.deallocate(a.cast(), tiny) };
+        assert_eq!(allocator.stats().used_bytes, 0);
 
-unsafe impl<R: lock_api::RawMutex> Send for LinkedListAlloc<R> {}
+        // Named heaps carry their name in their statistics
+        assert_eq!(allocator.stats().name, None);
+        allocator.set_name(c"frame scratch");
+        assert_eq!(allocator.stats().name, Some(c"frame scratch"));
+        assert_eq!(allocator.name(), Some(c"frame scratch"));
+    }
 
-impl<R: lock_api::RawMutex> LinkedListAlloc<R> {
-    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
-        let internal = LinkedListAllocImpl {
-            segmenter_list: unsafe { MemorySegmenter::new(start, end) },
-        };
+    #[test]
+    fn ll_allocator_heap_walk() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(64 * 1024, 16).with_small_bins();
+        allocator.set_quick_lists_enabled(true);
 
-        LinkedListAlloc(lock_api::Mutex::new(internal))
+        let layouts = [
+            Layout::new::<[u8; 16]>(),
+            Layout::new::<[u8; 16]>(),
+            Layout::new::<[u8; 300]>(),
+            Layout::new::<[u8; 64]>(),
+            Layout::new::<[u8; 1000]>(),
+        ];
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .map(|&layout| allocator.allocate(layout).unwrap())
+            .collect();
+        // Neither cached blocks nor free slots are live
+        let cached = allocator.allocate(Layout::new::<[u8; 64]>()).unwrap();
+        let freed = allocator.allocate(Layout::new::<[u8; 16]>()).unwrap();
+        unsafe {
+            allocator.deallocate(cached.cast(), Layout::new::<[u8; 64]>());
+            allocator.deallocate(freed.cast(), Layout::new::<[u8; 16]>());
+        }
+
+        let mut live = Vec::new();
+        allocator.for_each_allocation(|ptr, size, _| live.push((ptr.as_ptr(), size)));
+        live.sort();
+        let mut expected: Vec<_> = ptrs
+            .iter()
+            .map(|ptr| (ptr.cast::<u8>().as_ptr(), ptr.len()))
+            .collect();
+        expected.sort();
+        assert_eq!(live, expected);
+
+        for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        let mut count = 0;
+        allocator.for_each_allocation(|_, _, _| count += 1);
+        assert_eq!(count, 0);
     }
-}
 
-unsafe impl<R: lock_api::RawMutex> Allocator for LinkedListAlloc<R> {
-    fn allocate(&self, layout: Layout) -> Result<core::ptr::NonNull<[u8]>, AllocError> {
-        let mut internal = self.0.lock();
+    #[test]
+    fn ll_allocator_snapshots() {
+        use crate::snapshot;
 
-        let real_align = layout.align().max(SegmentMetadata::SIZE);
-        // Round size request to nearest SIZE byte boundary
-        let real_layout_size = layout.size().next_multiple_of(SegmentMetadata::SIZE);
-        let subsegment_size = real_layout_size + SegmentMetadata::SIZE;
-        let mut valid_segment_ptr = None;
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16).with_small_bins();
+        let small = Layout::new::<[u8; 16]>();
+        let large = Layout::new::<[u8; 256]>();
 
-        for entry in internal.segmenter_list.iter() {
-            if entry.size_allocable() < real_layout_size {
-                continue;
-            }
+        let kept = allocator.allocate(large).unwrap();
+        let freed = allocator.allocate(large).unwrap();
+        let before = allocator.snapshot();
+        assert_eq!(before.len(), 2);
+        assert!(before.is_sorted_by_key(|entry| entry.addr));
 
-            if internal
-                .segmenter_list
-                .calculate_alloc_ptr_with_required_align(entry, subsegment_size, real_align)
-                .is_err()
-            {
-                continue;
-            }
+        unsafe { allocator.deallocate(freed.cast(), large) };
+        let leaked = allocator.allocate(small).unwrap();
+        let after = allocator.snapshot();
+        // The freed block went away, and the small object showed up
+        let addr = |ptr: NonNull<[u8]>| ptr.cast::<u8>().as_ptr() as usize;
+        let mut changes: Vec<_> = snapshot::diff(&before, &after)
+            .map(|change| match change {
+                snapshot::HeapChange::Appeared(entry) => (true, entry.addr),
+                snapshot::HeapChange::Disappeared(entry) => (false, entry.addr),
+                snapshot::HeapChange::Changed { .. } => panic!("Unexpected change!"),
+            })
+            .collect();
+        changes.sort();
+        assert_eq!(changes, [(false, addr(freed)), (true, addr(leaked))]);
+
+        // Without the standard library, snapshots are captured into a buffer
+        let mut buf = [HeapEntry {
+            addr: 0,
+            size: 0,
+            tag: None,
+        }; 1];
+        assert_eq!(allocator.snapshot_into(&mut buf), 2);
+        assert_eq!(buf[0], after[0]);
+
+        unsafe {
+            allocator.deallocate(kept.cast(), large);
+            allocator.deallocate(leaked.cast(), small);
+        }
+    }
+
+    #[cfg(feature = "tagging")]
+    #[test]
+    fn ll_allocator_heap_walk_tags() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        let layout = Layout::new::<[u8; 64]>();
+        let a = allocator.allocate_tagged(layout, 7).unwrap();
+        let b = allocator.allocate(layout).unwrap();
 
-            // Found a valid segment to split
-            valid_segment_ptr = Some(entry.addr());
+        let mut tags = Vec::new();
+        allocator.for_each_allocation(|ptr, _, tag| tags.push((ptr.as_ptr(), tag)));
+        tags.sort();
+        let mut expected = vec![
+            (a.cast::<u8>().as_ptr(), Some(7)),
+            (b.cast::<u8>().as_ptr(), Some(0)),
+        ];
+        expected.sort();
+        assert_eq!(tags, expected);
+        unsafe {
+            allocator.deallocate(a.cast(), layout);
+            allocator.deallocate(b.cast(), layout);
         }
+    }
 
-        if let Some(valid_segment_ptr) = valid_segment_ptr {
-            let candidate = unsafe {
-                internal.segmenter_list.create_used_segment(
-                    valid_segment_ptr.cast_mut().as_mut().unwrap(),
-                    subsegment_size,
-                    real_align,
-                )
-            };
+    #[test]
+    fn ll_allocator_bulk() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        let layout = Layout::new::<[u8; 256]>();
+
+        let mut ptrs = Vec::new();
+        assert_eq!(allocator.allocate_many(layout, 8, |ptr| ptrs.push(ptr)), 8);
+        assert_eq!(ptrs.len(), 8);
+        assert_eq!(allocator.stats().live_allocations, 8);
+        unsafe { allocator.deallocate_many(layout, ptrs.drain(..).map(NonNull::cast)) };
+        allocator.with_segmenter(|segmenter| {
+            assert_eq!(segmenter.num_segments(), 1);
+            assert_eq!(segmenter.check_integrity(), Ok(()));
+        });
 
-            if let Ok(new_segment) = candidate {
-                let user_ptr = unsafe { new_segment.as_mut() }.unwrap().alloc_start_ptr();
-                let user_slice =
-                    unsafe { from_raw_parts_mut(user_ptr, real_layout_size) } as *mut [u8];
+        // Stops at the first failure
+        let n = allocator.allocate_many(layout, 1000, |ptr| ptrs.push(ptr));
+        assert!(n < 1000);
+        assert_eq!(ptrs.len(), n);
+        assert_eq!(allocator.stats().failed_allocations, 1);
+        unsafe { allocator.deallocate_many(layout, ptrs.into_iter().map(NonNull::cast)) };
+        assert!(allocator.is_drained());
+    }
+
+    #[test]
+    fn ll_allocator_counters() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        let small = Layout::new::<[u8; 32]>();
+        let large = Layout::new::<[u8; 512]>();
+
+        let ptr = allocator.allocate(small).unwrap().cast::<u8>();
+        unsafe { ptr.as_ptr().write_bytes(0xAB, small.size()) };
+        let ptr = unsafe { allocator.grow_zeroed(ptr, small, large) }.unwrap();
+        let bytes = unsafe { ptr.as_ref() };
+        assert!(bytes[..32].iter().all(|&byte| byte == 0xAB));
+        assert!(bytes[32..].iter().all(|&byte| byte == 0));
+        let ptr = unsafe { allocator.shrink(ptr.cast(), large, small) }.unwrap();
+        assert!(allocator.allocate(Layout::new::<[u8; 8192]>()).is_err());
+        assert!(unsafe { allocator.grow(ptr.cast(), small, Layout::new::<[u8; 8192]>()) }.is_err());
+        unsafe { allocator.deallocate(ptr.cast(), small) };
 
-                Ok(NonNull::new(user_slice).unwrap())
-            } else {
-                Err(AllocError)
+        let stats = allocator.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.deallocations, 1);
+        assert_eq!(stats.failed_allocations, 2);
+        assert_eq!((stats.grows, stats.shrinks), (1, 1));
+        assert_eq!(stats.live_allocations, 0);
+        assert_eq!(stats.used_bytes, 0);
+    }
+
+    #[test]
+    fn ll_allocator_hooks() {
+        // Enforces a limit on the live bytes, and counts the calls
+        #[derive(Default)]
+        struct Quota {
+            limit: usize,
+            calls: [usize; 4],
+            used_bytes: usize,
+        }
+        impl AllocHooks for Quota {
+            fn may_allocate(&mut self, layout: Layout, stats: &AllocStats) -> bool {
+                stats.used_bytes + layout.size() <= self.limit
             }
-        } else {
-            Err(AllocError)
+            fn on_alloc(&mut self, _: Layout, _: NonNull<[u8]>, stats: &AllocStats) {
+                self.calls[0] += 1;
+                self.used_bytes = stats.used_bytes;
+            }
+            fn on_dealloc(&mut self, ptr: NonNull<u8>, _: Layout, _: &AllocStats) {
+                // The contents are still intact
+                assert_eq!(unsafe { ptr.read() }, 0xAB);
+                self.calls[1] += 1;
+            }
+            fn on_realloc(
+                &mut self,
+                _: NonNull<u8>,
+                _: Layout,
+                _: Layout,
+                _: NonNull<[u8]>,
+                stats: &AllocStats,
+            ) {
+                self.calls[2] += 1;
+                self.used_bytes = stats.used_bytes;
+            }
+            fn on_fail(&mut self, _: Layout, _: &AllocStats) {
+                self.calls[3] += 1;
+            }
+        }
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource, LinearIndex, _> =
+            LinkedListAlloc::with_capacity(4096, 16).with_hooks(Quota {
+                limit: 1000,
+                ..Default::default()
+            });
+        let small = Layout::new::<[u8; 128]>();
+        let large = Layout::new::<[u8; 768]>();
+
+        // The stats passed to the hooks include the operation
+        let ptr = allocator.allocate(small).unwrap();
+        assert_eq!(
+            allocator.with_hooks_mut(|hooks| hooks.used_bytes),
+            ptr.len()
+        );
+        let ptr = unsafe { allocator.grow(ptr.cast(), small, large) }.unwrap();
+        assert_eq!(
+            allocator.with_hooks_mut(|hooks| hooks.used_bytes),
+            ptr.len()
+        );
+
+        // Refused requests fail like any other, though the heap has room for them
+        assert!(allocator.allocate(large).is_err());
+        assert!(allocator.allocate(Layout::new::<[u8; 1024]>()).is_err());
+        assert_eq!(allocator.stats().failed_allocations, 2);
+
+        unsafe {
+            ptr.cast::<u8>().write(0xAB);
+            allocator.deallocate(ptr.cast(), large);
         }
+        assert!(allocator.allocate(Layout::new::<[u8; 1024]>()).is_err());
+        allocator.with_hooks_mut(|hooks| hooks.limit = 2048);
+        let ptr = allocator.allocate(Layout::new::<[u8; 1024]>()).unwrap();
+        unsafe {
+            ptr.cast::<u8>().write(0xAB);
+            allocator.deallocate(ptr.cast(), Layout::new::<[u8; 1024]>());
+        }
+        assert_eq!(allocator.with_hooks_mut(|hooks| hooks.calls), [2, 2, 1, 3]);
     }
 
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
-        let mut internal = self.0.lock();
+    #[test]
+    fn ll_allocator_leak_check() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
 
-        // Get segment start
-        let segment_start_ptr = (ptr.as_ptr() as *mut SegmentMetadata).sub(1);
-        internal
-            .segmenter_list
-            .delete_used_segment(segment_start_ptr)
-            .expect("Failed to free data!");
+        static LEAKED: AtomicUsize = AtomicUsize::new(0);
+        fn count_leak(_: *const u8, size: usize) {
+            LEAKED.fetch_add(size, Ordering::Relaxed);
+        }
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        allocator.set_leak_policy(LeakPolicy::Report(count_leak));
+        allocator.set_quick_lists_enabled(true);
+        let layout = Layout::new::<[u8; 48]>();
+        let _ = allocator.allocate(layout).unwrap();
+        // Blocks parked on a quick list don't count
+        let freed = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(freed.cast(), layout) };
+        drop(allocator);
+        assert_eq!(LEAKED.load(Ordering::Relaxed), 48);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    extern crate alloc;
-    use core::mem::size_of;
+    #[test]
+    #[should_panic]
+    fn ll_allocator_leak_panic() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        allocator.set_leak_policy(LeakPolicy::Panic);
+        let _ = allocator.allocate(Layout::new::<u64>()).unwrap();
+    }
 
-    use rand::{thread_rng, Rng};
+    #[cfg(feature = "pointer-mangling")]
+    #[test]
+    fn ll_allocator_link_secret() {
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        // Kept until the heap is acquired on first use
+        allocator.set_link_secret(0x5eed_0000);
+        let boxed = Box::new_in([1u8; 64], &allocator);
+        let key = allocator.0.lock().segmenter_list.link_key();
+        assert_eq!(key, LinkKey::new(0x5eed_0000));
 
-    use super::*;
+        allocator.set_link_secret(0xbeef_0000);
+        drop(boxed);
+        let internal = allocator.0.lock();
+        assert_eq!(
+            internal.segmenter_list.link_key(),
+            LinkKey::new(0xbeef_0000)
+        );
+        assert_eq!(internal.segmenter_list.check_integrity(), Ok(()));
+    }
 
+    // Tag accounting can't survive a double free
+    #[cfg(not(feature = "tagging"))]
     #[test]
-    fn ll_allocator_tests() {
-        const MIB: usize = 1048576;
-        const SIZE: usize = 2 * MIB;
+    fn ll_allocator_free_error_policy() {
+        use core::sync::atomic::{AtomicPtr, Ordering};
+
+        static FAILED: AtomicPtr<u8> = AtomicPtr::new(null_mut());
+        fn record_failure(ptr: *const u8) {
+            FAILED.store(ptr.cast_mut(), Ordering::Relaxed);
+        }
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        allocator.set_quick_lists_enabled(false);
+        allocator.set_leak_policy(LeakPolicy::Ignore);
+        let layout = Layout::new::<[u8; 48]>();
+        let ptrs: Vec<_> = (0..3)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        let double_free = |ptr: NonNull<[u8]>| unsafe {
+            allocator.deallocate(ptr.cast(), layout);
+            allocator.deallocate(ptr.cast(), layout);
+        };
+
+        allocator.set_free_error_policy(FreeErrorPolicy::Handler(record_failure));
+        double_free(ptrs[0]);
+        assert_eq!(FAILED.load(Ordering::Relaxed), ptrs[0].cast().as_ptr());
+        assert_eq!(allocator.stats().failed_frees, 1);
+
+        // Panicking is the default
+        allocator.set_free_error_policy(FreeErrorPolicy::default());
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| double_free(ptrs[1])));
+        assert!(result.is_err());
+        assert_eq!(allocator.stats().failed_frees, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn ll_allocator_from_static_too_small() {
+        let region =
+            alloc::boxed::Box::leak(alloc::vec![MaybeUninit::uninit(); 20].into_boxed_slice());
+        let unaligned = &mut region[1..];
+        let _: LinkedListAlloc<parking_lot::RawMutex> = LinkedListAlloc::from_static(unaligned);
+    }
+
+    #[test]
+    fn ll_allocator_init() {
+        const SIZE: usize = 4096;
+        static HEAP: LinkedListAlloc<parking_lot::RawMutex> = LinkedListAlloc::empty();
+
+        let layout = Layout::new::<u64>();
+        assert!(HEAP.allocate(layout).is_err());
+
         let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        unsafe { HEAP.init(mem, mem.add(SIZE)) };
+        let ptr = HEAP.allocate(layout).unwrap();
+        unsafe { HEAP.deallocate(ptr.cast(), layout) };
+        assert_eq!(HEAP.0.lock().segmenter_list.size(), SIZE);
+    }
 
-        {
-            let allocator: LinkedListAlloc<parking_lot::RawMutex> =
-                unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
-            // Attempt to allocate larger than we can hold
-            let res = allocator.allocate(Layout::from_size_align(SIZE, 16).unwrap());
-            assert_eq!(res.is_err(), true);
+    mod static_heap {
+        use crate::allocators::linked_list_allocator::LinkedListAlloc;
 
-            // Attempt to allocate exactly as much as we can hold
-            let res = unsafe {
-                allocator
-                    .allocate(Layout::from_size_align(SIZE - SegmentMetadata::SIZE, 16).unwrap())
-                    .unwrap()
-                    .as_mut()
-            };
-            res.fill(0);
-            assert_eq!(res.as_ptr().align_offset(16), 0);
-            assert_eq!(res.len(), SIZE - SegmentMetadata::SIZE);
+        crate::static_heap! {
+            pub static HEAP: LinkedListAlloc<parking_lot::RawMutex> = 4100;
         }
+    }
 
-        {
-            let allocator: LinkedListAlloc<parking_lot::RawMutex> =
-                unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+    #[test]
+    fn ll_allocator_static_heap() {
+        use static_heap::{init, HEAP};
 
-            let mut allocs = Vec::new();
+        let layout = Layout::new::<u64>();
+        assert!(HEAP.allocate(layout).is_err());
 
-            // Allocate randomly until we no longer can:
-            let mut rng = thread_rng();
-            loop {
-                let mut random_size: usize = rng.gen_range(8..=1024);
-                random_size = random_size.next_multiple_of(SegmentMetadata::SIZE);
-                let random_alignment: usize = 2usize.pow(rng.gen_range(3..=10));
+        init();
+        let ptr = HEAP.allocate(layout).unwrap();
+        unsafe { HEAP.deallocate(ptr.cast(), layout) };
+        assert_eq!(HEAP.0.lock().segmenter_list.size(), 4096);
 
-                let res = unsafe {
-                    allocator
-                        .allocate(Layout::from_size_align(random_size, random_alignment).unwrap())
-                };
+        // A second call panics without touching the heap
+        assert!(std::panic::catch_unwind(init).is_err());
+        assert_eq!(HEAP.0.lock().segmenter_list.num_segments(), 1);
+    }
 
-                if res.is_err() {
-                    break;
-                }
+    // Stands in for the symbols of a linker script
+    #[cfg(target_os = "linux")]
+    core::arch::global_asm!(
+        ".pushsection .bss",
+        ".balign 16",
+        ".globl __lantern_test_heap_start",
+        "__lantern_test_heap_start:",
+        ".space 4096",
+        ".globl __lantern_test_heap_end",
+        "__lantern_test_heap_end:",
+        ".popsection",
+    );
 
-                let mem = unsafe { res.unwrap().as_mut() };
-                mem.fill(0);
-                assert_eq!(mem.as_ptr().align_offset(random_alignment), 0);
-                assert_eq!(mem.len(), random_size);
-                allocs.push(mem.as_ptr());
+    #[cfg(target_os = "linux")]
+    mod linker_heap {
+        use crate::allocators::linked_list_allocator::LinkedListAlloc;
 
-                // Metadata should be immediately before the ptr...
-                let metadata = mem.as_mut_ptr() as *mut SegmentMetadata;
-                let metadata = unsafe { metadata.sub(1) };
-                let metadata_mut = unsafe { metadata.as_mut().unwrap() };
-                assert_eq!(metadata_mut.size_allocable(), random_size);
-            }
+        crate::linker_heap! {
+            pub static HEAP: LinkedListAlloc<parking_lot::RawMutex> =
+                __lantern_test_heap_start..__lantern_test_heap_end;
+        }
+    }
 
-            // If we didnt allocate at least this many times, something very likely went wrong...
-            assert!(allocs.len() > 1000);
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ll_allocator_linker_heap() {
+        use linker_heap::{init, HEAP};
 
-            // Deallocate in a random order
-            while allocs.len() > 0 {
-                let idx = rng.gen_range(0..allocs.len());
-                let ptr = allocs.swap_remove(idx);
+        let layout = Layout::new::<[u8; 1024]>();
+        assert!(HEAP.allocate(layout).is_err());
 
-                unsafe {
-                    allocator.deallocate(
-                        NonNull::new(ptr.cast_mut()).unwrap(),
-                        Layout::from_size_align(8, 8).unwrap(),
-                    );
-                }
-            }
-            assert_eq!(
-                allocator.0.lock().segmenter_list.overhead(),
-                SegmentMetadata::SIZE
-            );
+        unsafe { init() };
+        let ptr = HEAP.allocate(layout).unwrap();
+        unsafe { HEAP.deallocate(ptr.cast(), layout) };
+        assert_eq!(HEAP.0.lock().segmenter_list.size(), 4096);
+        assert!(std::panic::catch_unwind(|| unsafe { init() }).is_err());
 
-            // Try to allocate entire memory to ensure we successfully deallocated everything
-            let mem = unsafe {
-                allocator
-                    .allocate(Layout::from_size_align(SIZE - SegmentMetadata::SIZE, 16).unwrap())
-                    .unwrap()
-                    .as_ptr()
-                    .as_mut()
-                    .unwrap()
-            };
-            mem.fill(0);
-            assert_eq!(mem.as_ptr().align_offset(16), 0);
-            assert_eq!(mem.len(), SIZE - SegmentMetadata::SIZE);
+        // Bounds out of order or misaligned are refused
+        let heap: LinkedListAlloc<parking_lot::RawMutex> = LinkedListAlloc::empty();
+        let mut region = [0u128; 4];
+        let start = region.as_mut_ptr().cast::<u8>();
+        let end = unsafe { start.add(64) };
+        let init = |start, end| {
+            let heap = std::panic::AssertUnwindSafe(&heap);
+            std::panic::catch_unwind(|| unsafe { heap.init_from_linker(start, end) })
+        };
+        assert!(init(end, start).is_err());
+        assert!(init(start, unsafe { end.sub(1) }).is_err());
+        assert_eq!(heap.0.lock().segmenter_list.num_segments(), 0);
+    }
 
-            unsafe {
-                allocator.deallocate(
-                    NonNull::new(mem.as_ptr().cast_mut()).unwrap(),
-                    Layout::from_size_align(8, 8).unwrap(),
-                );
-            }
+    #[test]
+    fn ll_allocator_lazy_source() {
+        use crate::memory_source::SystemSource;
 
-            // Test a weird, nonstandard small size and alignment
-            let mem = unsafe {
-                {
-                    allocator
-                        .allocate(Layout::from_size_align(1, 2).unwrap())
-                        .unwrap()
-                        .as_ptr()
-                        .as_mut()
-                        .unwrap()
-                }
-            };
-            assert_eq!(mem.len(), SegmentMetadata::SIZE);
-            assert_eq!(mem.as_ptr().align_offset(SegmentMetadata::SIZE), 0);
+        static HEAP: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::from_source_lazy(SystemSource::new(), 64 * 1024);
+        assert!(HEAP.0.lock().source_region.is_none());
+
+        let layout = Layout::new::<[u64; 16]>();
+        let ptr = unsafe { GlobalAlloc::alloc(&HEAP, layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(HEAP.0.lock().segmenter_list.size(), 64 * 1024);
+        let huge = Layout::from_size_align(DEFAULT_HUGE_THRESHOLD, 16).unwrap();
+        let huge_ptr = unsafe { GlobalAlloc::alloc(&HEAP, huge) };
+        assert!(!huge_ptr.is_null());
+        assert!(unsafe { GlobalAlloc::alloc(&HEAP, Layout::new::<[u8; 100_000]>()) }.is_null());
+
+        unsafe {
+            GlobalAlloc::dealloc(&HEAP, ptr, layout);
+            GlobalAlloc::dealloc(&HEAP, huge_ptr, huge);
         }
+        assert_eq!(HEAP.0.lock().segmenter_list.num_used_segments(), 0);
     }
 
     #[test]
@@ -250,4 +3595,209 @@ mod tests {
             assert_eq!(*boxed_val, i);
         }
     }
+
+    #[test]
+    fn ll_allocator_region() {
+        const SIZE: usize = 4096;
+        let empty: LinkedListAlloc<parking_lot::RawMutex> = LinkedListAlloc::empty();
+        assert!(empty.region().is_empty());
+
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        assert_eq!(allocator.region(), mem..unsafe { mem.add(SIZE) });
+
+        let boxed = Box::new_in(7u64, &allocator);
+        let ptr: *const u64 = &*boxed;
+        assert!(allocator.contains(ptr.cast()));
+        assert!(!allocator.contains(unsafe { mem.add(SIZE) }));
+        assert!(!empty.contains(ptr.cast()));
+    }
+
+    #[test]
+    fn ll_allocator_name() {
+        // Records the name of the heap each event came from
+        #[derive(Default)]
+        struct Names(Vec<Option<&'static CStr>>);
+        impl AllocHooks for Names {
+            fn on_alloc(&mut self, _: Layout, _: NonNull<[u8]>, stats: &AllocStats) {
+                self.0.push(stats.name);
+            }
+            fn on_dealloc(&mut self, _: NonNull<u8>, _: Layout, stats: &AllocStats) {
+                self.0.push(stats.name);
+            }
+        }
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource, LinearIndex, _> =
+            LinkedListAlloc::with_capacity(4096, 16)
+                .with_name(c"assets")
+                .with_hooks(Names::default());
+        assert_eq!(allocator.name(), Some(c"assets"));
+
+        let layout = Layout::new::<u64>();
+        let a = allocator.allocate(layout).unwrap();
+        // Renaming a heap in use applies to the events after it
+        allocator.set_name(c"network");
+        unsafe { allocator.deallocate(a.cast(), layout) };
+        assert_eq!(allocator.stats().name, Some(c"network"));
+        assert_eq!(
+            allocator.0.lock().hooks.0,
+            [Some(c"assets"), Some(c"network")]
+        );
+    }
+
+    #[test]
+    fn ll_allocator_largest_free_block() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        assert_eq!(allocator.largest_free_block(), SIZE - SegmentMetadata::SIZE);
+
+        let small = Layout::from_size_align(1024, 16).unwrap();
+        let a = allocator.allocate(small).unwrap();
+        let largest = allocator.largest_free_block();
+        assert_eq!(largest, SIZE - a.len() - 2 * SegmentMetadata::SIZE);
+
+        // Anything larger can't fit, while the largest block itself does
+        let too_large = Layout::from_size_align(largest + 1, 16).unwrap();
+        assert!(allocator.allocate(too_large).is_err());
+        let b = allocator
+            .allocate(Layout::from_size_align(largest, 16).unwrap())
+            .unwrap();
+        assert_eq!(allocator.largest_free_block(), 0);
+
+        unsafe {
+            allocator.deallocate(a.cast(), small);
+            allocator.deallocate(b.cast(), Layout::from_size_align(largest, 16).unwrap());
+        }
+        assert_eq!(allocator.largest_free_block(), SIZE - SegmentMetadata::SIZE);
+    }
+
+    #[test]
+    fn ll_allocator_free_and_used_bytes() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+        assert_eq!((allocator.used_bytes(), allocator.free_bytes()), (0, SIZE));
+
+        let layouts = [100, 256, 1000].map(|size| Layout::from_size_align(size, 16).unwrap());
+        let blocks = layouts.map(|layout| allocator.allocate(layout).unwrap());
+        let usable: usize = blocks.iter().map(|block| block.len()).sum();
+        assert_eq!(allocator.used_bytes(), usable);
+        assert_eq!(
+            allocator.free_bytes(),
+            SIZE - usable - blocks.len() * SegmentMetadata::SIZE
+        );
+
+        // Both are kept up to date without walking the list, so they must agree with a walk
+        unsafe { allocator.deallocate(blocks[1].cast(), layouts[1]) };
+        assert_eq!(allocator.used_bytes(), usable - blocks[1].len());
+        {
+            let internal = allocator.0.lock();
+            let heap = &internal.segmenter_list;
+            let walked: usize = heap.iter_free().map(|segment| segment.size()).sum();
+            assert_eq!(heap.free_bytes(), walked);
+            assert_eq!(heap.check_integrity(), Ok(()));
+        }
+
+        unsafe {
+            allocator.deallocate(blocks[0].cast(), layouts[0]);
+            allocator.deallocate(blocks[2].cast(), layouts[2]);
+        }
+        assert_eq!((allocator.used_bytes(), allocator.free_bytes()), (0, SIZE));
+    }
+
+    #[cfg(not(any(feature = "requested-size", feature = "tagging", feature = "user-data")))]
+    #[test]
+    fn ll_allocator_usable_slice() {
+        const SIZE: usize = 256;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 8> =
+            unsafe { LinkedListAlloc::with_granularity(mem, mem.add(SIZE)) };
+
+        // The 8 bytes left over can't hold a segment of their own, so they come with the block
+        let layout = Layout::from_size_align(SIZE - SegmentMetadata::SIZE - 8, 8).unwrap();
+        let block = allocator.allocate(layout).unwrap();
+        assert_eq!(block.len(), SIZE - SegmentMetadata::SIZE);
+        assert_eq!(allocator.stats().used_bytes, block.len());
+        unsafe { block.cast::<u8>().as_ptr().write_bytes(0xF, block.len()) };
+
+        // Any size between the requested and the returned one may free the block
+        unsafe {
+            allocator.deallocate(
+                block.cast(),
+                Layout::from_size_align(block.len(), 8).unwrap(),
+            )
+        };
+        assert_eq!(allocator.stats().live_allocations, 0);
+        assert_eq!(allocator.largest_free_block(), SIZE - SegmentMetadata::SIZE);
+    }
+
+    #[cfg(not(any(feature = "requested-size", feature = "tagging", feature = "user-data")))]
+    #[test]
+    fn ll_allocator_granularity() {
+        const MIB: usize = 1048576;
+        const SIZE: usize = MIB;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let allocator: LinkedListAlloc<parking_lot::RawMutex, 8> =
+            unsafe { LinkedListAlloc::with_granularity(mem, mem.add(SIZE)) };
+
+        // A small request should only be padded to the next 8 byte boundary
+        let res = allocator
+            .allocate(Layout::from_size_align(20, 8).unwrap())
+            .unwrap();
+        assert_eq!(res.len(), 24);
+        assert_eq!(res.as_ptr().cast::<u8>().align_offset(8), 0);
+
+        // Slack too small to become its own segment is handed out as part of the allocation
+        let small_mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(64, 16).unwrap()) };
+        let small: LinkedListAlloc<parking_lot::RawMutex, 8> =
+            unsafe { LinkedListAlloc::with_granularity(small_mem, small_mem.add(64)) };
+        let slack = small
+            .allocate(Layout::from_size_align(40, 8).unwrap())
+            .unwrap();
+        assert_eq!(slack.len(), 64 - SegmentMetadata::SIZE);
+
+        let mut allocs = Vec::new();
+        let mut rng = thread_rng();
+        loop {
+            let random_size: usize = rng.gen_range(1..=256);
+            let random_alignment: usize = 2usize.pow(rng.gen_range(0..=8));
+
+            let Ok(mut res) =
+                allocator.allocate(Layout::from_size_align(random_size, random_alignment).unwrap())
+            else {
+                break;
+            };
+
+            let mem = unsafe { res.as_mut() };
+            mem.fill(0);
+            assert_eq!(mem.as_ptr().align_offset(random_alignment), 0);
+            assert!(mem.len() >= random_size);
+            assert!(mem.len() < random_size + 8 + SegmentMetadata::SIZE);
+            allocs.push(mem.as_mut_ptr());
+        }
+
+        while !allocs.is_empty() {
+            let idx = rng.gen_range(0..allocs.len());
+            let ptr = allocs.swap_remove(idx);
+
+            unsafe {
+                allocator.deallocate(
+                    NonNull::new(ptr).unwrap(),
+                    Layout::from_size_align(8, 8).unwrap(),
+                );
+            }
+        }
+        unsafe {
+            allocator.deallocate(res.cast(), Layout::from_size_align(20, 8).unwrap());
+        }
+        assert_eq!(
+            allocator.0.lock().segmenter_list.overhead(),
+            SegmentMetadata::SIZE
+        );
+    }
 }
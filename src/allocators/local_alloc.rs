@@ -0,0 +1,81 @@
+use core::cell::Cell;
+
+use crate::{
+    allocators::linked_list_allocator::LinkedListAlloc,
+    hooks::NoHooks,
+    memory_segmenter::{LinearIndex, DEFAULT_GRANULARITY},
+    memory_source::NoSource,
+};
+
+/// A lock that is no lock at all, only a flag catching reentrant use like a
+/// [`RefCell`](core::cell::RefCell) does. It is not `Sync`, so an allocator using it can't be
+/// shared between threads, and doesn't pay for atomics on every operation either.
+pub struct LocalLock {
+    locked: Cell<bool>,
+}
+
+unsafe impl lock_api::RawMutex for LocalLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = LocalLock {
+        locked: Cell::new(false),
+    };
+
+    type GuardMarker = lock_api::GuardNoSend;
+
+    fn lock(&self) {
+        // Nobody else can release the lock, so waiting would never end
+        assert!(self.try_lock(), "The heap lock is already held!");
+    }
+
+    fn try_lock(&self) -> bool {
+        !self.locked.replace(true)
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.set(false);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.get()
+    }
+}
+
+/// A [`LinkedListAlloc`] without a lock, for single core systems that never allocate from
+/// interrupt handlers. Being `!Sync`, it can't be the global allocator, but serves collections
+/// through the [`Allocator`](core::alloc::Allocator) API:
+///
+/// ```ignore
+/// let heap = LocalLinkedListAlloc::from_static(&mut REGION);
+/// let mut samples = Vec::new_in(&heap);
+/// ```
+pub type LocalLinkedListAlloc<
+    const GRANULE: usize = DEFAULT_GRANULARITY,
+    S = NoSource,
+    I = LinearIndex,
+    H = NoHooks,
+> = LinkedListAlloc<LocalLock, GRANULE, S, I, H>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory_source::SystemSource;
+
+    #[test]
+    fn local_alloc() {
+        let heap: LocalLinkedListAlloc<16, SystemSource> = LinkedListAlloc::with_capacity(4096, 16);
+
+        let mut values = Vec::new_in(&heap);
+        values.extend(0..64u32);
+        assert_eq!(values.iter().sum::<u32>(), 63 * 32);
+        assert!(heap.used_bytes() >= 64 * 4);
+        drop(values);
+        assert_eq!(heap.used_bytes(), 0);
+
+        // Locking again from within an operation panics rather than corrupting the heap
+        let lock = <LocalLock as lock_api::RawMutex>::INIT;
+        assert!(lock_api::RawMutex::try_lock(&lock));
+        assert!(!lock_api::RawMutex::try_lock(&lock));
+        unsafe { lock_api::RawMutex::unlock(&lock) };
+        assert!(!lock_api::RawMutex::is_locked(&lock));
+    }
+}
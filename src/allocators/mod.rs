@@ -1 +1,37 @@
+pub mod arena;
+pub mod async_alloc;
+pub mod bitmapped_block_alloc;
+#[cfg(any(feature = "std", test))]
+pub mod blocking_alloc;
+pub mod bootstrap_alloc;
+pub mod bucketizer_alloc;
+pub mod buddy_alloc;
+pub mod bump_alloc;
+pub mod cache_aligned_alloc;
+pub mod cascade_alloc;
+pub mod freelist_alloc;
+pub mod generational_alloc;
+pub mod guard_alloc;
+pub mod heap_registry;
+pub mod hoard_alloc;
+pub mod hybrid_alloc;
 pub mod linked_list_allocator;
+pub mod local_alloc;
+pub mod numa_alloc;
+pub mod paged_alloc;
+mod quick_lists;
+mod reserves;
+pub mod router_alloc;
+pub mod shadow_alloc;
+pub mod sharded_alloc;
+pub mod slob_alloc;
+mod small_bins;
+pub mod stack_fallback_alloc;
+pub mod static_pool;
+pub mod tiered_alloc;
+pub mod timed_alloc;
+pub mod trace_alloc;
+pub mod verified_alloc;
+pub mod vmalloc;
+pub mod window_alloc;
+pub mod zoned_frame_alloc;
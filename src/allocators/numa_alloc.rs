@@ -0,0 +1,221 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::Range,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::cascade_alloc::FromRegion;
+
+/// Identifies a NUMA node, as in the firmware's memory affinity tables.
+pub type NodeId = u32;
+
+/// Usage statistics of one node of a [`NumaAlloc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeStats {
+    /// The requested bytes of all live allocations on the node.
+    pub used_bytes: usize,
+    pub live_allocations: usize,
+    /// The number of allocations served by the node, although another one was preferred.
+    pub fallback_allocations: usize,
+}
+
+struct Node<A> {
+    id: NodeId,
+    range: Range<usize>,
+    heap: A,
+    used_bytes: AtomicUsize,
+    live_allocations: AtomicUsize,
+    fallback_allocations: AtomicUsize,
+}
+
+/// An allocator keeping a sub-heap of type `A` for each of up to `NODES` NUMA nodes, over the
+/// memory local to the node. Allocations are served by a preferred node if it has room, and by
+/// the other nodes in the order they were given otherwise, so memory stays close to the CPU using
+/// it for as long as possible. Deallocations are routed to the node owning the memory.
+///
+/// Through [`Allocator`] and [`GlobalAlloc`], the node returned by the node hint is preferred,
+/// which usually looks up the node of the current CPU:
+///
+/// ```ignore
+/// let heap = unsafe { NumaAlloc::<LinkedListAlloc<RawSpinlock>, 4>::new(&srat_ranges) }
+///     .with_node_hint(|| PER_CPU.node());
+/// ```
+pub struct NumaAlloc<A: Allocator + FromRegion, const NODES: usize> {
+    nodes: [Option<Node<A>>; NODES],
+    hint: Option<fn() -> NodeId>,
+}
+
+impl<A: Allocator + FromRegion, const NODES: usize> NumaAlloc<A, NODES> {
+    /// Creates an allocator with a sub-heap for every node id and memory range, e.g. from the SRAT
+    /// or the memory map. The first node is preferred until a node hint is set.
+    ///
+    /// # Safety
+    /// See [`FromRegion::from_region`], for every range.
+    ///
+    /// # Panics
+    /// Panics if there are more than `NODES` ranges, or a node id is given twice.
+    pub unsafe fn new(nodes: &[(NodeId, *mut u8, *mut u8)]) -> Self {
+        assert!(nodes.len() <= NODES, "Too many NUMA nodes!");
+        let mut slots = [const { None }; NODES];
+        for (slot, &(id, start, end)) in slots.iter_mut().zip(nodes) {
+            assert!(
+                nodes.iter().filter(|node| node.0 == id).count() == 1,
+                "NUMA node {id} was given twice!"
+            );
+            *slot = Some(Node {
+                id,
+                range: start as usize..end as usize,
+                heap: A::from_region(start, end),
+                used_bytes: AtomicUsize::new(0),
+                live_allocations: AtomicUsize::new(0),
+                fallback_allocations: AtomicUsize::new(0),
+            });
+        }
+
+        NumaAlloc {
+            nodes: slots,
+            hint: None,
+        }
+    }
+
+    /// Prefers the node returned by `hint` for allocations through [`Allocator`] and
+    /// [`GlobalAlloc`].
+    pub fn with_node_hint(mut self, hint: fn() -> NodeId) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// Allocates memory for `layout`, on `node` if it has room, and on another node otherwise.
+    pub fn allocate_on(&self, layout: Layout, node: NodeId) -> Result<NonNull<[u8]>, AllocError> {
+        let preferred = self.node(node);
+        let fallbacks = self.nodes().filter(|other| other.id != node);
+        for candidate in preferred.into_iter().chain(fallbacks) {
+            if let Ok(ptr) = candidate.heap.allocate(layout) {
+                candidate
+                    .used_bytes
+                    .fetch_add(layout.size(), Ordering::Relaxed);
+                candidate.live_allocations.fetch_add(1, Ordering::Relaxed);
+                if candidate.id != node {
+                    candidate
+                        .fallback_allocations
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(ptr);
+            }
+        }
+        Err(AllocError)
+    }
+
+    /// The node the memory at `ptr` is local to, if any.
+    pub fn node_of(&self, ptr: NonNull<u8>) -> Option<NodeId> {
+        self.owner(ptr).map(|node| node.id)
+    }
+
+    /// The statistics of `node`, if there is such a node.
+    pub fn node_stats(&self, node: NodeId) -> Option<NodeStats> {
+        self.node(node).map(|node| NodeStats {
+            used_bytes: node.used_bytes.load(Ordering::Relaxed),
+            live_allocations: node.live_allocations.load(Ordering::Relaxed),
+            fallback_allocations: node.fallback_allocations.load(Ordering::Relaxed),
+        })
+    }
+
+    /// The sub-heap of `node`, e.g. to query allocator specific statistics.
+    pub fn heap(&self, node: NodeId) -> Option<&A> {
+        self.node(node).map(|node| &node.heap)
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = &Node<A>> {
+        self.nodes.iter().flatten()
+    }
+
+    fn node(&self, id: NodeId) -> Option<&Node<A>> {
+        self.nodes().find(|node| node.id == id)
+    }
+
+    fn owner(&self, ptr: NonNull<u8>) -> Option<&Node<A>> {
+        self.nodes()
+            .find(|node| node.range.contains(&(ptr.as_ptr() as usize)))
+    }
+
+    fn preferred(&self) -> NodeId {
+        match self.hint {
+            Some(hint) => hint(),
+            None => self.nodes().next().map_or(0, |node| node.id),
+        }
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const NODES: usize> Allocator for NumaAlloc<A, NODES> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_on(layout, self.preferred())
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let node = self.owner(ptr).expect("Freed memory no NUMA node owns!");
+        node.heap.deallocate(ptr, layout);
+        node.used_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        node.live_allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const NODES: usize> GlobalAlloc for NumaAlloc<A, NODES> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+
+    const SIZE: usize = 4096;
+
+    #[test]
+    fn numa_alloc() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let (a, b) = unsafe { (std::alloc::alloc(region), std::alloc::alloc(region)) };
+        let allocator: NumaAlloc<LinkedListAlloc<parking_lot::RawMutex>, 4> =
+            unsafe { NumaAlloc::new(&[(3, a, a.add(SIZE)), (7, b, b.add(SIZE))]) };
+
+        // Without a hint, the first node is preferred
+        let layout = Layout::new::<[u8; 1024]>();
+        let first = allocator.allocate(layout).unwrap();
+        assert_eq!(allocator.node_of(first.cast()), Some(3));
+        let local = allocator.allocate_on(layout, 7).unwrap();
+        assert_eq!(allocator.node_of(local.cast()), Some(7));
+
+        // A full node falls back to the others
+        let allocator = allocator.with_node_hint(|| 7);
+        let mut ptrs = vec![first, local];
+        while allocator.node_stats(3).unwrap().fallback_allocations == 0 {
+            ptrs.push(allocator.allocate(layout).unwrap());
+        }
+        assert_eq!(allocator.node_of(ptrs.last().unwrap().cast()), Some(3));
+        let stats = allocator.node_stats(3).unwrap();
+        assert_eq!(stats.fallback_allocations, 1);
+        assert_eq!(stats.live_allocations, 2);
+        assert_eq!(stats.used_bytes, 2048);
+        assert!(allocator.node_stats(5).is_none());
+
+        for ptr in ptrs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        for node in [3, 7] {
+            assert_eq!(allocator.node_stats(node).unwrap().live_allocations, 0);
+        }
+        unsafe {
+            std::alloc::dealloc(a, region);
+            std::alloc::dealloc(b, region);
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::{Cell, UnsafeCell},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+use super::{bitmapped_block_alloc::BlockMap, linked_list_allocator::CacheStats};
+
+/// Size (and alignment) of the pages the region is divided into.
+pub const PAGE_SIZE: usize = 64 * 1024;
+/// Number of size classes. Class `i` serves requests of up to `MIN_SIZE << i` bytes, so the
+/// largest class is an eighth of a page. Larger requests get whole pages.
+pub const CLASSES: usize = 10;
+const MIN_SIZE: usize = 16;
+
+/// A free block, linked into one of the free lists of its page.
+struct Block {
+    next: *mut Block,
+}
+
+/// Header at the start of every page of small blocks. The blocks follow it.
+struct Page {
+    // Id of the local heap allocating from this page, or zero if it was abandoned
+    owner: AtomicUsize,
+    // Blocks to allocate from. Only touched by the owner
+    free: *mut Block,
+    // Blocks freed by the owner, which become the free list once it runs out
+    local_free: *mut Block,
+    // Blocks freed by anyone else, collected by the owner once the local lists run out
+    thread_free: AtomicPtr<Block>,
+    // Blocks handed out and not yet collected again
+    used: usize,
+    // Links of the owner's pages of the same class, or of the abandoned pages of that class
+    next: *mut Page,
+    prev: *mut Page,
+}
+
+impl Page {
+    /// Moves the blocks freed since the free list ran out onto the free list. Returns whether
+    /// there are any free blocks now.
+    fn collect(&mut self) -> bool {
+        if self.free.is_null() {
+            self.free = self.local_free;
+            self.local_free = null_mut();
+        }
+
+        let mut remote = self.thread_free.swap(null_mut(), Ordering::Acquire);
+        while let Some(block) = unsafe { remote.as_mut() } {
+            remote = block.next;
+            block.next = self.free;
+            self.free = block;
+            self.used -= 1;
+        }
+
+        !self.free.is_null()
+    }
+}
+
+struct PagePool<const WORDS: usize> {
+    pages: BlockMap<WORDS>,
+    // Pages whose heap was dropped while they still had blocks in use, by class
+    abandoned: [*mut Page; CLASSES],
+}
+
+// SAFETY: The pool owns its region exclusively, so it may be moved to another thread
+unsafe impl<const WORDS: usize> Send for PagePool<WORDS> {}
+
+impl<const WORDS: usize> PagePool<WORDS> {
+    fn allocate_pages(&mut self, layout: Layout) -> Option<*mut u8> {
+        self.pages.allocate::<PAGE_SIZE>(layout)
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by [`PagePool::allocate_pages`] for the same `layout`.
+    unsafe fn deallocate_pages(&mut self, ptr: *mut u8, layout: Layout) {
+        self.pages.deallocate::<PAGE_SIZE>(ptr, layout);
+    }
+}
+
+/// An allocator in the style of mimalloc. The region is divided into pages of [`PAGE_SIZE`]
+/// bytes, and every page serves a single size class, for a single [`LocalHeap`]. Each thread
+/// allocates through a local heap of its own, which takes no lock and touches no shared state
+/// unless it runs out of pages, and keeps the blocks of each class close together.
+///
+/// Every page keeps its free blocks on separate lists: one to allocate from, one the owner frees
+/// onto, and one any other thread frees onto with a single atomic operation. The latter two are
+/// only collected once the first runs out, which keeps allocation and local frees to a few
+/// instructions, and remote frees from ever waiting for the owner.
+///
+/// Requests larger than the largest class get a run of whole pages of their own from the shared
+/// pool, which is tracked in a bitmap of `WORDS` words, so the region holds at most
+/// `WORDS * usize::BITS` pages.
+pub struct PagedAlloc<R: lock_api::RawMutex, const WORDS: usize = 16> {
+    pool: lock_api::Mutex<R, PagePool<WORDS>>,
+    next_heap: AtomicUsize,
+}
+
+impl<R: lock_api::RawMutex, const WORDS: usize> PagedAlloc<R, WORDS> {
+    /// Creates an allocator managing the memory between `start` and `end`. Pages start at the
+    /// first page aligned address.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        let base = start.add(start.align_offset(PAGE_SIZE));
+        PagedAlloc {
+            pool: lock_api::Mutex::new(PagePool {
+                pages: BlockMap::new::<PAGE_SIZE>(base, end),
+                abandoned: [null_mut(); CLASSES],
+            }),
+            next_heap: AtomicUsize::new(1),
+        }
+    }
+
+    /// Creates a heap to allocate from on the current thread.
+    pub fn local_heap(&self) -> LocalHeap<'_, R, WORDS> {
+        LocalHeap {
+            alloc: self,
+            id: self.next_heap.fetch_add(1, Ordering::Relaxed),
+            pages: UnsafeCell::new([null_mut(); CLASSES]),
+            stats: Cell::new(CacheStats::new()),
+        }
+    }
+
+    /// The number of pages not in use by any heap.
+    pub fn free_pages(&self) -> usize {
+        self.pool.lock().pages.free_blocks()
+    }
+
+    /// Frees memory allocated by any local heap of this allocator, from any thread. Prefer
+    /// [`LocalHeap`]'s `deallocate` on the thread that allocated it, which avoids the atomic
+    /// operation.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by a local heap of this allocator for the same `layout`, and
+    /// not freed since.
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if class_for(layout).is_none() {
+            self.pool.lock().deallocate_pages(ptr.as_ptr(), layout);
+            return;
+        }
+
+        let page = page_of(ptr.as_ptr());
+        let block = ptr.as_ptr().cast::<Block>();
+        let thread_free = &(*page).thread_free;
+        let mut head = thread_free.load(Ordering::Relaxed);
+        loop {
+            block.write(Block { next: head });
+            match thread_free.compare_exchange_weak(
+                head,
+                block,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+fn class_for(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(MIN_SIZE);
+    let class = size.next_power_of_two().trailing_zeros() - MIN_SIZE.trailing_zeros();
+    (class < CLASSES as u32).then_some(class as usize)
+}
+
+const fn block_size(class: usize) -> usize {
+    MIN_SIZE << class
+}
+
+fn page_of(ptr: *mut u8) -> *mut Page {
+    ptr.map_addr(|addr| addr & !(PAGE_SIZE - 1)).cast()
+}
+
+/// A thread's view of a [`PagedAlloc`], holding the pages it allocates small blocks from. Create
+/// one per thread with [`PagedAlloc::local_heap`].
+///
+/// When a local heap is dropped, its empty pages are returned to the pool, and pages still in use
+/// are abandoned to be adopted by the next heap that runs out of pages of their class.
+pub struct LocalHeap<'a, R: lock_api::RawMutex, const WORDS: usize = 16> {
+    alloc: &'a PagedAlloc<R, WORDS>,
+    id: usize,
+    // The pages of every class, the one to allocate from first
+    pages: UnsafeCell<[*mut Page; CLASSES]>,
+    stats: Cell<CacheStats>,
+}
+
+impl<R: lock_api::RawMutex, const WORDS: usize> LocalHeap<'_, R, WORDS> {
+    fn allocate_small(&self, class: usize) -> Option<*mut u8> {
+        let head = unsafe { (*self.pages.get())[class] };
+        let mut page_ptr = head;
+        loop {
+            let Some(page) = (unsafe { page_ptr.as_mut() }) else {
+                self.count(|stats| stats.misses += 1);
+                page_ptr = self.new_page(class)?;
+                self.count(|stats| stats.refills += 1);
+                break;
+            };
+            if !page.free.is_null() || page.collect() {
+                self.count(|stats| stats.hits += 1);
+                break;
+            }
+            page_ptr = page.next;
+        }
+
+        // Allocate from the page with free blocks first from now on
+        if page_ptr != head {
+            self.unlink(class, page_ptr);
+            self.push_front(class, page_ptr);
+        }
+        let page = unsafe { &mut *page_ptr };
+        let block = page.free;
+        page.free = unsafe { (*block).next };
+        page.used += 1;
+        Some(block.cast())
+    }
+
+    /// # Safety
+    /// `ptr` must be a block of a page owned by this heap, and in use.
+    unsafe fn deallocate_small(&self, ptr: *mut u8, class: usize) {
+        let page_ptr = page_of(ptr);
+        let page = &mut *page_ptr;
+        let block = ptr.cast::<Block>();
+        block.write(Block {
+            next: page.local_free,
+        });
+        page.local_free = block;
+        page.used -= 1;
+
+        // Keep the page allocated from first, so a single block being allocated and freed
+        // repeatedly doesn't take and return a page every time
+        if page.used == 0 && (*self.pages.get())[class] != page_ptr {
+            self.count(|stats| stats.flushes += 1);
+            self.unlink(class, page_ptr);
+            self.alloc
+                .pool
+                .lock()
+                .deallocate_pages(page_ptr.cast(), Self::page_layout());
+        }
+    }
+
+    /// Takes a page for `class`, adopting an abandoned one if there is any.
+    fn new_page(&self, class: usize) -> Option<*mut Page> {
+        let mut pool = self.alloc.pool.lock();
+        let page = pool.abandoned[class];
+        if let Some(abandoned) = unsafe { page.as_mut() } {
+            pool.abandoned[class] = abandoned.next;
+            drop(pool);
+            abandoned.owner.store(self.id, Ordering::Relaxed);
+            abandoned.collect();
+            self.push_front(class, page);
+            return Some(page);
+        }
+        let base = pool.allocate_pages(Self::page_layout())?;
+        drop(pool);
+
+        // Blocks are aligned to their size, so the first one comes after a whole block or more
+        let size = block_size(class);
+        let first = size_of::<Page>().next_multiple_of(size);
+        let capacity = (PAGE_SIZE - first) / size;
+        let mut free = null_mut();
+        for i in (0..capacity).rev() {
+            unsafe {
+                let block = base.add(first + i * size).cast::<Block>();
+                block.write(Block { next: free });
+                free = block;
+            }
+        }
+
+        let page = base.cast::<Page>();
+        unsafe {
+            page.write(Page {
+                owner: AtomicUsize::new(self.id),
+                free,
+                local_free: null_mut(),
+                thread_free: AtomicPtr::new(null_mut()),
+                used: 0,
+                next: null_mut(),
+                prev: null_mut(),
+            })
+        };
+        self.push_front(class, page);
+        Some(page)
+    }
+
+    fn page_layout() -> Layout {
+        Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+
+    fn push_front(&self, class: usize, page_ptr: *mut Page) {
+        let pages = unsafe { &mut *self.pages.get() };
+        let page = unsafe { &mut *page_ptr };
+        page.prev = null_mut();
+        page.next = pages[class];
+        if let Some(next) = unsafe { page.next.as_mut() } {
+            next.prev = page_ptr;
+        }
+        pages[class] = page_ptr;
+    }
+
+    fn unlink(&self, class: usize, page_ptr: *mut Page) {
+        let pages = unsafe { &mut *self.pages.get() };
+        let page = unsafe { &mut *page_ptr };
+        if let Some(prev) = unsafe { page.prev.as_mut() } {
+            prev.next = page.next;
+        } else {
+            pages[class] = page.next;
+        }
+        if let Some(next) = unsafe { page.next.as_mut() } {
+            next.prev = page.prev;
+        }
+        page.next = null_mut();
+        page.prev = null_mut();
+    }
+
+    /// Collects the blocks freed by other threads on all pages, and returns the pages that are
+    /// empty now to the pool, except the first of each class.
+    pub fn collect(&self) {
+        for class in 0..CLASSES {
+            let mut page_ptr = unsafe { (*self.pages.get())[class] };
+            while let Some(page) = unsafe { page_ptr.as_mut() } {
+                let next = page.next;
+                page.collect();
+                if page.used == 0 && !page.prev.is_null() {
+                    self.count(|stats| stats.flushes += 1);
+                    self.unlink(class, page_ptr);
+                    unsafe {
+                        self.alloc
+                            .pool
+                            .lock()
+                            .deallocate_pages(page_ptr.cast(), Self::page_layout())
+                    };
+                }
+                page_ptr = next;
+            }
+        }
+    }
+
+    /// How well the heap does at serving small blocks from the pages it holds. Hits are
+    /// allocations served by the pages held, and misses allocations that took a page from the
+    /// shared pool. Refills and flushes count the pages taken from and returned to
+    /// the pool.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    fn count(&self, f: impl FnOnce(&mut CacheStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// The number of blocks in use on the pages of this heap, including those freed by other
+    /// threads but not collected yet.
+    pub fn used_blocks(&self) -> usize {
+        let pages = unsafe { &*self.pages.get() };
+        let mut used = 0;
+        for &head in pages {
+            let mut page = head;
+            while let Some(current) = unsafe { page.as_ref() } {
+                used += current.used;
+                page = current.next;
+            }
+        }
+        used
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const WORDS: usize> Allocator for LocalHeap<'_, R, WORDS> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (ptr, size) = match class_for(layout) {
+            Some(class) => (self.allocate_small(class), block_size(class)),
+            None => (
+                self.alloc.pool.lock().allocate_pages(layout),
+                layout.size().next_multiple_of(PAGE_SIZE),
+            ),
+        };
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr.ok_or(AllocError)?).unwrap(),
+            size,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match class_for(layout) {
+            Some(class) if (*page_of(ptr.as_ptr())).owner.load(Ordering::Relaxed) == self.id => {
+                self.deallocate_small(ptr.as_ptr(), class)
+            }
+            _ => self.alloc.deallocate(ptr, layout),
+        }
+    }
+}
+
+impl<R: lock_api::RawMutex, const WORDS: usize> Drop for LocalHeap<'_, R, WORDS> {
+    fn drop(&mut self) {
+        let pages = self.pages.get_mut();
+        let mut pool = self.alloc.pool.lock();
+        for (class, head) in pages.iter_mut().enumerate() {
+            let mut page_ptr = *head;
+            while let Some(page) = unsafe { page_ptr.as_mut() } {
+                let next = page.next;
+                page.collect();
+                if page.used == 0 {
+                    unsafe { pool.deallocate_pages(page_ptr.cast(), Self::page_layout()) };
+                } else {
+                    page.owner.store(0, Ordering::Relaxed);
+                    page.next = pool.abandoned[class];
+                    page.prev = null_mut();
+                    pool.abandoned[class] = page_ptr;
+                }
+                page_ptr = next;
+            }
+            *head = null_mut();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 32 * PAGE_SIZE;
+
+    fn region() -> (*mut u8, Layout) {
+        let layout = Layout::from_size_align(SIZE, PAGE_SIZE).unwrap();
+        (unsafe { std::alloc::alloc(layout) }, layout)
+    }
+
+    #[test]
+    fn paged_alloc() {
+        let (mem, region) = region();
+        let allocator: PagedAlloc<parking_lot::RawMutex, 1> =
+            unsafe { PagedAlloc::new(mem, mem.add(SIZE)) };
+        let heap = allocator.local_heap();
+
+        // Blocks of one class share a page, other classes get a page of their own
+        let small = Layout::new::<[u8; 24]>();
+        let a = heap.allocate(small).unwrap();
+        let b = heap.allocate(small).unwrap();
+        assert_eq!(a.len(), 32);
+        assert_eq!(page_of(a.cast().as_ptr()), page_of(b.cast().as_ptr()));
+        let c = heap.allocate(Layout::new::<[u8; 100]>()).unwrap();
+        assert_ne!(page_of(a.cast().as_ptr()), page_of(c.cast().as_ptr()));
+        let large = Layout::new::<[u8; 100_000]>();
+        let d = heap.allocate(large).unwrap();
+        assert_eq!(d.len(), 2 * PAGE_SIZE);
+        assert_eq!(allocator.free_pages(), 32 - 4);
+
+        // Every class took a page from the pool, and the second small block came from the page
+        let stats = heap.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.refills), (1, 2, 2));
+
+        // Freed blocks are reused once the free list runs out
+        unsafe { heap.deallocate(a.cast(), small) };
+        assert_eq!(heap.used_blocks(), 2);
+        unsafe {
+            heap.deallocate(b.cast(), small);
+            heap.deallocate(c.cast(), Layout::new::<[u8; 100]>());
+            heap.deallocate(d.cast(), large);
+        }
+        assert_eq!(allocator.free_pages(), 32 - 2);
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..4000 {
+            if rng.gen_bool(0.55) {
+                let size = if rng.gen_bool(0.9) {
+                    rng.gen_range(1..8192)
+                } else {
+                    rng.gen_range(8192..3 * PAGE_SIZE)
+                };
+                let align = 1 << rng.gen_range(0..7);
+                let layout = Layout::from_size_align(size, align).unwrap();
+                if let Ok(mut ptr) = heap.allocate(layout) {
+                    assert_eq!(ptr.cast::<u8>().as_ptr().align_offset(align), 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { heap.deallocate(ptr.cast(), layout) };
+            }
+        }
+        for (ptr, layout, _) in live {
+            unsafe { heap.deallocate(ptr.cast(), layout) };
+        }
+
+        assert_eq!(heap.used_blocks(), 0);
+        drop(heap);
+        assert_eq!(allocator.free_pages(), 32);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn paged_alloc_remote_free() {
+        let (mem, region) = region();
+        let allocator: PagedAlloc<parking_lot::RawMutex, 1> =
+            unsafe { PagedAlloc::new(mem, mem.add(SIZE)) };
+        let layout = Layout::new::<[u8; 64]>();
+        let capacity = (PAGE_SIZE - size_of::<Page>().next_multiple_of(64)) / 64;
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel::<usize>();
+            let allocator = &allocator;
+            scope.spawn(move || {
+                for addr in receiver {
+                    let ptr = NonNull::new(addr as *mut u8).unwrap();
+                    unsafe { allocator.deallocate(ptr, layout) };
+                }
+            });
+
+            // Blocks freed by the other thread are collected once the page runs out, so the
+            // heap never needs a second page
+            let heap = allocator.local_heap();
+            for _ in 0..4 * capacity {
+                let ptr = heap.allocate(layout).unwrap();
+                sender.send(ptr.cast::<u8>().as_ptr() as usize).unwrap();
+                while heap.used_blocks() == capacity {
+                    heap.collect();
+                }
+            }
+            drop(sender);
+            assert_eq!(allocator.free_pages(), 31);
+        });
+
+        // If the heap was dropped with blocks still on their way back, its page was abandoned and
+        // is adopted by the next heap. Either way, that heap only needs a single page too
+        let heap = allocator.local_heap();
+        let ptr = heap.allocate(layout).unwrap();
+        assert_eq!(allocator.free_pages(), 31);
+        unsafe { heap.deallocate(ptr.cast(), layout) };
+        heap.collect();
+        assert_eq!(heap.used_blocks(), 0);
+        drop(heap);
+        assert_eq!(allocator.free_pages(), 32);
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
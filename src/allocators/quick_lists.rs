@@ -0,0 +1,89 @@
+use core::{mem::size_of, ptr::null_mut};
+
+/// Number of size classes with a quick list. Class `i` holds blocks with exactly
+/// `(i + 1) * GRANULE` usable bytes.
+pub const QUICK_LIST_CLASSES: usize = 8;
+/// Maximum number of blocks kept on a single quick list, so a burst of frees can't hoard memory.
+pub const QUICK_LIST_DEPTH: usize = 32;
+
+/// Singly linked lists of recently freed blocks, one per small size class.
+///
+/// Blocks on a quick list are still marked as used by the segmenter; the first word of their
+/// payload links to the next block of the same class.
+#[derive(Debug)]
+pub(crate) struct QuickLists<const GRANULE: usize> {
+    heads: [*mut u8; QUICK_LIST_CLASSES],
+    lens: [usize; QUICK_LIST_CLASSES],
+}
+
+impl<const GRANULE: usize> QuickLists<GRANULE> {
+    pub const fn new() -> Self {
+        QuickLists {
+            heads: [null_mut(); QUICK_LIST_CLASSES],
+            lens: [0; QUICK_LIST_CLASSES],
+        }
+    }
+
+    fn class_of(usable_size: usize) -> Option<usize> {
+        // Every block needs room to store the link to the next block
+        if usable_size < size_of::<*mut u8>() || !usable_size.is_multiple_of(GRANULE) {
+            return None;
+        }
+
+        let class = usable_size / GRANULE - 1;
+        (class < QUICK_LIST_CLASSES).then_some(class)
+    }
+
+    /// Whether blocks with `usable_size` bytes belong to a size class, and are cached at all.
+    pub fn caches(usable_size: usize) -> bool {
+        Self::class_of(usable_size).is_some()
+    }
+
+    /// Stashes a freed block with `usable_size` bytes. Returns false if the block doesn't belong
+    /// to a size class or its list is full, in which case it must be freed normally.
+    ///
+    /// # Safety
+    /// `ptr` must be the alloc ptr of a used segment with exactly `usable_size` usable bytes that is
+    /// no longer referenced by anyone else.
+    pub unsafe fn push(&mut self, ptr: *mut u8, usable_size: usize) -> bool {
+        let Some(class) = Self::class_of(usable_size) else {
+            return false;
+        };
+        if self.lens[class] == QUICK_LIST_DEPTH {
+            return false;
+        }
+
+        ptr.cast::<*mut u8>().write(self.heads[class]);
+        self.heads[class] = ptr;
+        self.lens[class] += 1;
+        true
+    }
+
+    /// Takes a block with exactly `usable_size` bytes whose alloc ptr satisfies `align`, if the
+    /// most recently freed block of that class does.
+    pub fn pop(&mut self, usable_size: usize, align: usize) -> Option<*mut u8> {
+        let class = Self::class_of(usable_size)?;
+        let head = self.heads[class];
+        if head.is_null() || head.align_offset(align) != 0 {
+            return None;
+        }
+
+        self.heads[class] = unsafe { head.cast::<*mut u8>().read() };
+        self.lens[class] -= 1;
+        Some(head)
+    }
+
+    /// Takes any block, from any class.
+    pub fn pop_any(&mut self) -> Option<*mut u8> {
+        let class = self.lens.iter().position(|&len| len != 0)?;
+        let head = self.heads[class];
+
+        self.heads[class] = unsafe { head.cast::<*mut u8>().read() };
+        self.lens[class] -= 1;
+        Some(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lens.iter().all(|&len| len == 0)
+    }
+}
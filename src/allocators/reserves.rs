@@ -0,0 +1,161 @@
+use core::{mem::size_of, ptr::null_mut};
+
+/// The most distinct layouts memory can be reserved for at once, see
+/// [`LinkedListAlloc::reserve`](super::linked_list_allocator::LinkedListAlloc::reserve).
+pub const MAX_RESERVES: usize = 4;
+
+/// The blocks set aside for one layout.
+#[derive(Debug, Clone, Copy)]
+struct Reserve {
+    usable_size: usize,
+    align: usize,
+    head: *mut u8,
+    len: usize,
+    // The number of blocks the reserve is refilled up to by deallocations
+    target: usize,
+}
+
+/// Singly linked lists of blocks split off ahead of time for particular layouts, so allocating
+/// them later is a pop rather than a search.
+///
+/// Like on the quick lists, blocks in a reserve are still marked as used by the segmenter, and the
+/// first word of their payload links to the next block of the same reserve.
+#[derive(Debug)]
+pub(crate) struct Reserves {
+    reserves: [Option<Reserve>; MAX_RESERVES],
+}
+
+impl Reserves {
+    pub const fn new() -> Self {
+        Reserves {
+            reserves: [None; MAX_RESERVES],
+        }
+    }
+
+    /// Whether blocks with `usable_size` bytes can be kept in a reserve at all.
+    pub fn fits(usable_size: usize) -> bool {
+        usable_size >= size_of::<*mut u8>()
+    }
+
+    fn find(&mut self, usable_size: usize, align: usize) -> Option<&mut Reserve> {
+        self.reserves
+            .iter_mut()
+            .flatten()
+            .find(|reserve| reserve.usable_size == usable_size && reserve.align == align)
+    }
+
+    /// Creates the reserve for blocks of `usable_size` bytes aligned to `align`, unless it exists
+    /// already. Fails if all reserves are taken by other layouts.
+    pub fn insert(&mut self, usable_size: usize, align: usize) -> Result<(), ()> {
+        if self.find(usable_size, align).is_some() {
+            return Ok(());
+        }
+
+        let slot = self
+            .reserves
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(())?;
+        *slot = Some(Reserve {
+            usable_size,
+            align,
+            head: null_mut(),
+            len: 0,
+            target: 0,
+        });
+        Ok(())
+    }
+
+    /// Adds a freshly split block to its reserve, which must exist, raising the number of blocks
+    /// the reserve holds on to.
+    ///
+    /// # Safety
+    /// `ptr` must be the alloc ptr of a used segment with at least `usable_size` usable bytes that
+    /// is no longer referenced by anyone else.
+    pub unsafe fn add(&mut self, ptr: *mut u8, usable_size: usize, align: usize) {
+        let reserve = self.find(usable_size, align).unwrap();
+        ptr.cast::<*mut u8>().write(reserve.head);
+        reserve.head = ptr;
+        reserve.len += 1;
+        reserve.target += 1;
+    }
+
+    /// Takes a block with exactly `usable_size` bytes whose alloc ptr satisfies `align`, if there
+    /// is a reserve for it with blocks left.
+    pub fn pop(&mut self, usable_size: usize, align: usize) -> Option<*mut u8> {
+        let reserve = self.reserves.iter_mut().flatten().find(|reserve| {
+            reserve.usable_size == usable_size && reserve.align >= align && reserve.len != 0
+        })?;
+
+        let head = reserve.head;
+        reserve.head = unsafe { head.cast::<*mut u8>().read() };
+        reserve.len -= 1;
+        Some(head)
+    }
+
+    /// Whether there is a reserve [`Reserves::pop`] would take a block for `usable_size` bytes
+    /// aligned to `align` from, if it had blocks left.
+    pub fn covers(&self, usable_size: usize, align: usize) -> bool {
+        self.reserves
+            .iter()
+            .flatten()
+            .any(|reserve| reserve.usable_size == usable_size && reserve.align >= align)
+    }
+
+    /// Puts a freed block back into its reserve. Returns false if there is no reserve for it, or
+    /// the reserve is full already, in which case it must be freed normally.
+    ///
+    /// # Safety
+    /// See [`Reserves::add`].
+    pub unsafe fn push(&mut self, ptr: *mut u8, usable_size: usize) -> bool {
+        let reserve = self.reserves.iter_mut().flatten().find(|reserve| {
+            reserve.usable_size == usable_size
+                && ptr.align_offset(reserve.align) == 0
+                && reserve.len < reserve.target
+        });
+        let Some(reserve) = reserve else {
+            return false;
+        };
+
+        ptr.cast::<*mut u8>().write(reserve.head);
+        reserve.head = ptr;
+        reserve.len += 1;
+        true
+    }
+
+    /// The number of blocks left in the reserve for `usable_size` bytes aligned to `align`.
+    pub fn available(&self, usable_size: usize, align: usize) -> usize {
+        self.reserves
+            .iter()
+            .flatten()
+            .find(|reserve| reserve.usable_size == usable_size && reserve.align == align)
+            .map_or(0, |reserve| reserve.len)
+    }
+
+    /// Removes the reserve for `usable_size` bytes aligned to `align`, passing each of its blocks to
+    /// `release`.
+    pub fn remove(&mut self, usable_size: usize, align: usize, release: impl FnMut(*mut u8)) {
+        let slot = self.reserves.iter_mut().find(|slot| {
+            slot.is_some_and(|reserve| reserve.usable_size == usable_size && reserve.align == align)
+        });
+        if let Some(reserve) = slot.and_then(Option::take) {
+            Self::drain(reserve, release);
+        }
+    }
+
+    /// Removes every reserve, passing each of their blocks to `release`.
+    pub fn clear(&mut self, mut release: impl FnMut(*mut u8)) {
+        for reserve in self.reserves.iter_mut().filter_map(Option::take) {
+            Self::drain(reserve, &mut release);
+        }
+    }
+
+    fn drain(reserve: Reserve, mut release: impl FnMut(*mut u8)) {
+        let mut block = reserve.head;
+        while !block.is_null() {
+            let next = unsafe { block.cast::<*mut u8>().read() };
+            release(block);
+            block = next;
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::{
+    buddy_alloc::{BuddyAlloc, SplitScheme},
+    bump_alloc::BumpAlloc,
+    hybrid_alloc::HybridAlloc,
+    linked_list_allocator::LinkedListAlloc,
+    slob_alloc::SlobAlloc,
+};
+use crate::{hooks::AllocHooks, memory_segmenter::FreeIndex, memory_source::MemorySource};
+
+/// An allocator a [`RouterAlloc`] can route to, which can tell the memory it hands out apart from
+/// that of the other allocators, so blocks are freed into the allocator that served them.
+pub trait RouteTarget: Allocator {
+    /// Whether `ptr` lies in memory the allocator hands out.
+    fn contains(&self, ptr: *const u8) -> bool;
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource, I: FreeIndex, H: AllocHooks>
+    RouteTarget for LinkedListAlloc<R, GRANULE, S, I, H>
+{
+    fn contains(&self, ptr: *const u8) -> bool {
+        LinkedListAlloc::contains(self, ptr)
+    }
+}
+
+impl<R: lock_api::RawMutex, const MIN_BLOCK: usize, S: SplitScheme> RouteTarget
+    for BuddyAlloc<R, MIN_BLOCK, S>
+{
+    fn contains(&self, ptr: *const u8) -> bool {
+        BuddyAlloc::contains(self, ptr)
+    }
+}
+
+impl<R: lock_api::RawMutex> RouteTarget for HybridAlloc<R> {
+    fn contains(&self, ptr: *const u8) -> bool {
+        HybridAlloc::contains(self, ptr)
+    }
+}
+
+impl<R: lock_api::RawMutex> RouteTarget for SlobAlloc<R> {
+    fn contains(&self, ptr: *const u8) -> bool {
+        SlobAlloc::contains(self, ptr)
+    }
+}
+
+impl<R: lock_api::RawMutex> RouteTarget for BumpAlloc<R> {
+    fn contains(&self, ptr: *const u8) -> bool {
+        BumpAlloc::contains(self, ptr)
+    }
+}
+
+/// A rule of a [`RouterAlloc`], sending requests whose layout and tag it matches to one of the
+/// router's allocators. A rule built with [`Rule::to`] matches every request, and each condition
+/// added narrows it down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    target: usize,
+    min_size: usize,
+    max_size: usize,
+    min_align: usize,
+    tag: Option<u8>,
+}
+
+impl Rule {
+    /// A rule sending requests to the allocator at index `target`.
+    pub const fn to(target: usize) -> Self {
+        Rule {
+            target,
+            min_size: 0,
+            max_size: usize::MAX,
+            min_align: 1,
+            tag: None,
+        }
+    }
+
+    pub const fn size_at_least(mut self, size: usize) -> Self {
+        self.min_size = size;
+        self
+    }
+
+    pub const fn size_at_most(mut self, size: usize) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    pub const fn align_at_least(mut self, align: usize) -> Self {
+        self.min_align = align;
+        self
+    }
+
+    /// Only matches requests tagged with `tag`, see [`RouterAlloc::allocate_tagged`].
+    pub const fn tagged(mut self, tag: u8) -> Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    pub const fn target(&self) -> usize {
+        self.target
+    }
+
+    pub const fn matches(&self, layout: Layout, tag: u8) -> bool {
+        let tag_matches = match self.tag {
+            Some(rule_tag) => rule_tag == tag,
+            None => true,
+        };
+
+        layout.size() >= self.min_size
+            && layout.size() <= self.max_size
+            && layout.align() >= self.min_align
+            && tag_matches
+    }
+}
+
+/// Routes every request to one of `N` allocators of any type, by the first of its rules matching
+/// the layout and tag of the request. Requests no rule matches go to the fallback, which starts
+/// out as the last allocator. Rules are evaluated in place, so routing never allocates.
+///
+/// Deallocations go to the allocator whose memory contains the block, whatever the tag or size it
+/// is freed with, or to the fallback if none does. Allocators handing out memory they can't
+/// recognize, like the huge allocations a [`LinkedListAlloc`] gets from its source, must therefore
+/// be the fallback. Resizes that change the allocator a block is routed to move it from one
+/// allocator to the other.
+///
+/// ```ignore
+/// let router = RouterAlloc::new(
+///     [&PAGES, &SLAB, &HEAP],
+///     [Rule::to(0).align_at_least(4096), Rule::to(1).size_at_most(256)],
+/// );
+/// ```
+pub struct RouterAlloc<'a, const N: usize, const RULES: usize> {
+    allocators: [&'a (dyn RouteTarget + Sync); N],
+    rules: [Rule; RULES],
+    fallback: usize,
+    current_tag: AtomicU8,
+}
+
+impl<'a, const N: usize, const RULES: usize> RouterAlloc<'a, N, RULES> {
+    /// # Panics
+    /// Panics if there are no allocators, or a rule targets one that doesn't exist.
+    pub const fn new(allocators: [&'a (dyn RouteTarget + Sync); N], rules: [Rule; RULES]) -> Self {
+        assert!(N > 0, "There must be at least one allocator!");
+        let mut rule = 0;
+        while rule < RULES {
+            assert!(
+                rules[rule].target < N,
+                "A rule targets an allocator that doesn't exist!"
+            );
+            rule += 1;
+        }
+
+        RouterAlloc {
+            allocators,
+            rules,
+            fallback: N - 1,
+            current_tag: AtomicU8::new(0),
+        }
+    }
+
+    /// Sends requests no rule matches to the allocator at index `fallback`.
+    ///
+    /// # Panics
+    /// Panics if the allocator doesn't exist.
+    pub const fn with_fallback(mut self, fallback: usize) -> Self {
+        assert!(fallback < N, "The fallback allocator doesn't exist!");
+        self.fallback = fallback;
+        self
+    }
+
+    /// The index of the allocator requests for `layout` tagged with `tag` are routed to.
+    pub fn route(&self, layout: Layout, tag: u8) -> usize {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(layout, tag))
+            .map_or(self.fallback, Rule::target)
+    }
+
+    /// The index of the allocator whose memory contains `ptr`, or of the fallback if none does.
+    pub fn owner(&self, ptr: NonNull<u8>) -> usize {
+        self.allocators
+            .iter()
+            .position(|allocator| allocator.contains(ptr.as_ptr()))
+            .unwrap_or(self.fallback)
+    }
+
+    /// The allocator at `index`, e.g. to query allocator specific statistics.
+    pub fn allocator(&self, index: usize) -> Option<&'a (dyn RouteTarget + Sync)> {
+        self.allocators.get(index).copied()
+    }
+
+    /// Sets the tag of every request made through [`Allocator`] or [`GlobalAlloc`] from now on.
+    /// Starts out as 0.
+    pub fn set_current_tag(&self, tag: u8) {
+        self.current_tag.store(tag, Ordering::Relaxed);
+    }
+
+    pub fn current_tag(&self) -> u8 {
+        self.current_tag.load(Ordering::Relaxed)
+    }
+
+    /// Allocates memory for `layout` like [`Allocator::allocate`], routed as if tagged with `tag`.
+    pub fn allocate_tagged(&self, layout: Layout, tag: u8) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.allocators[self.route(layout, tag)].allocate(layout)?;
+        // Any spare room the allocator hands out could be freed with a size routed elsewhere
+        Ok(NonNull::slice_from_raw_parts(ptr.cast(), layout.size()))
+    }
+
+    /// Resizes the block at `ptr` within the allocator that owns it if the new layout is routed
+    /// there too, or moves it to the allocator the new layout is routed to.
+    ///
+    /// # Safety
+    /// See [`Allocator::grow`].
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let source = self.owner(ptr);
+        let target = self.route(new_layout, self.current_tag());
+        let old = self.allocators[source];
+        let new = if source == target {
+            if new_layout.size() >= old_layout.size() {
+                old.grow(ptr, old_layout, new_layout)?
+            } else {
+                old.shrink(ptr, old_layout, new_layout)?
+            }
+        } else {
+            let new = self.allocators[target].allocate(new_layout)?;
+            ptr.as_ptr().copy_to_nonoverlapping(
+                new.cast().as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+            old.deallocate(ptr, old_layout);
+            new
+        };
+
+        Ok(NonNull::slice_from_raw_parts(new.cast(), new_layout.size()))
+    }
+}
+
+unsafe impl<const N: usize, const RULES: usize> Allocator for RouterAlloc<'_, N, RULES> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_tagged(layout, self.current_tag())
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.allocators[self.owner(ptr)].deallocate(ptr, layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+unsafe impl<const N: usize, const RULES: usize> GlobalAlloc for RouterAlloc<'_, N, RULES> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    #[test]
+    fn router_alloc() {
+        let pages = Heap::with_capacity(64 * 1024, 4096);
+        let slab = Heap::with_capacity(16 * 1024, 16);
+        let heap = Heap::with_capacity(64 * 1024, 16);
+        let scratch = Heap::with_capacity(16 * 1024, 16);
+        let router = RouterAlloc::new(
+            [&pages, &slab, &heap, &scratch],
+            [
+                Rule::to(0).align_at_least(4096),
+                Rule::to(3).tagged(7),
+                Rule::to(1).size_at_most(256),
+            ],
+        )
+        .with_fallback(2);
+
+        let page = Layout::from_size_align(100, 4096).unwrap();
+        let small = Layout::new::<[u8; 64]>();
+        let large = Layout::new::<[u8; 1024]>();
+        assert_eq!(router.route(page, 7), 0);
+        assert_eq!(router.route(small, 7), 3);
+        assert_eq!(router.route(small, 0), 1);
+        assert_eq!(router.route(large, 0), 2);
+
+        let a = router.allocate(page).unwrap().cast::<u8>();
+        let b = router.allocate(small).unwrap().cast::<u8>();
+        let c = router.allocate_tagged(small, 7).unwrap().cast::<u8>();
+        assert!(pages.contains(a.as_ptr()));
+        assert!(slab.contains(b.as_ptr()));
+        assert!(scratch.contains(c.as_ptr()));
+
+        // Resizes stay within an allocator, unless they cross a rule boundary
+        let b = unsafe { router.grow(b, small, Layout::new::<[u8; 128]>()) }.unwrap();
+        assert!(slab.contains(b.cast().as_ptr()));
+        unsafe { b.cast::<u8>().as_ptr().write_bytes(0xB, 128) };
+        let b = unsafe { router.grow(b.cast(), Layout::new::<[u8; 128]>(), large) }.unwrap();
+        assert!(heap.contains(b.cast().as_ptr()));
+        assert_eq!(b.len(), 1024);
+        assert!(unsafe { b.cast::<[u8; 128]>().as_ref() }
+            .iter()
+            .all(|&byte| byte == 0xB));
+        assert_eq!(slab.stats().live_allocations, 0);
+        let b = unsafe { router.shrink(b.cast(), large, small) }.unwrap();
+        assert!(slab.contains(b.cast().as_ptr()));
+
+        // Blocks come back at their requested size, so freeing them with it routes them back
+        let edge = Layout::new::<[u8; 250]>();
+        let d = router.allocate(edge).unwrap();
+        assert_eq!(d.len(), 250);
+
+        unsafe {
+            router.deallocate(a, page);
+            router.deallocate(b.cast(), small);
+            router.deallocate(c, small);
+            router.deallocate(d.cast(), Layout::from_size_align(d.len(), 1).unwrap());
+        }
+        for heap in [&pages, &slab, &heap, &scratch] {
+            assert_eq!(heap.stats().live_allocations, 0);
+        }
+    }
+
+    #[test]
+    fn router_alloc_tag_change() {
+        let slab = Heap::with_capacity(16 * 1024, 16);
+        let scratch = Heap::with_capacity(16 * 1024, 16);
+        let router = RouterAlloc::new([&slab, &scratch], [Rule::to(1).tagged(7)]).with_fallback(0);
+
+        let small = Layout::new::<[u8; 64]>();
+        router.set_current_tag(7);
+        let a = router.allocate(small).unwrap().cast::<u8>();
+        let b = router.allocate(small).unwrap().cast::<u8>();
+        assert!(scratch.contains(a.as_ptr()));
+        assert_eq!(router.owner(a), 1);
+
+        // Blocks are freed and resized within the allocator that served them, whatever the tag
+        router.set_current_tag(0);
+        unsafe { router.deallocate(a, small) };
+        assert_eq!(scratch.stats().live_allocations, 1);
+        assert_eq!(slab.stats().deallocations, 0);
+        let b = unsafe { router.grow(b, small, Layout::new::<[u8; 128]>()) }.unwrap();
+        assert!(slab.contains(b.cast().as_ptr()));
+        assert_eq!(scratch.stats().live_allocations, 0);
+
+        unsafe { router.deallocate(b.cast(), Layout::new::<[u8; 128]>()) };
+        assert_eq!(slab.stats().live_allocations, 0);
+        assert_eq!(scratch.stats().deallocations, 2);
+    }
+}
@@ -0,0 +1,199 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+use crate::{
+    memory_segmenter::DEFAULT_GRANULARITY,
+    shadow_map::{ShadowMap, ShadowState},
+};
+
+struct ShadowAllocImpl<'a, const GRANULE: usize, const QUARANTINE: usize> {
+    map: ShadowMap<'a, GRANULE>,
+    // Ring buffer of freed allocations held back from reuse, oldest first
+    quarantine: [Option<(NonNull<u8>, Layout)>; QUARANTINE],
+    oldest: usize,
+}
+
+// SAFETY: The quarantined pointers are only handed back to the wrapped allocator, never
+// dereferenced, so they don't tie the state to a thread. The lock around it takes care of `Sync`.
+unsafe impl<const GRANULE: usize, const QUARANTINE: usize> Send
+    for ShadowAllocImpl<'_, GRANULE, QUARANTINE>
+{
+}
+
+/// Wraps an allocator managing the region covered by a [`ShadowMap`], keeping the map up to date
+/// on every allocation and deallocation.
+///
+/// Freed allocations are quarantined: the most recent `QUARANTINE` of them are only handed back to
+/// the wrapped allocator once newer ones push them out, so that use after free can be detected
+/// with [`ShadowAlloc::is_accessible`] for a while. Freeing anything but a live allocation panics,
+/// which catches double frees and wild pointers.
+pub struct ShadowAlloc<
+    'a,
+    A: Allocator,
+    R: lock_api::RawMutex,
+    const GRANULE: usize = DEFAULT_GRANULARITY,
+    const QUARANTINE: usize = 16,
+> {
+    inner: A,
+    shadow: lock_api::Mutex<R, ShadowAllocImpl<'a, GRANULE, QUARANTINE>>,
+}
+
+impl<'a, A: Allocator, R: lock_api::RawMutex, const GRANULE: usize, const QUARANTINE: usize>
+    ShadowAlloc<'a, A, R, GRANULE, QUARANTINE>
+{
+    /// Wraps `inner`, which must not have any live allocations within the region of `map`.
+    pub fn new(inner: A, map: ShadowMap<'a, GRANULE>) -> Self {
+        ShadowAlloc {
+            inner,
+            shadow: lock_api::Mutex::new(ShadowAllocImpl {
+                map,
+                quarantine: [None; QUARANTINE],
+                oldest: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The state of the granule `ptr` points into, or `None` if it isn't shadowed.
+    pub fn state(&self, ptr: *const u8) -> Option<ShadowState> {
+        self.shadow.lock().map.state(ptr)
+    }
+
+    /// Whether all `len` bytes at `ptr` lie in live allocations.
+    pub fn is_accessible(&self, ptr: *const u8, len: usize) -> bool {
+        self.shadow.lock().map.is_accessible(ptr, len)
+    }
+
+    /// Asserts that all `len` bytes at `ptr` lie in live allocations, in debug builds only.
+    pub fn debug_check_access(&self, ptr: *const u8, len: usize) {
+        debug_assert!(
+            self.is_accessible(ptr, len),
+            "Invalid access of {len} bytes at {ptr:?}!"
+        );
+    }
+
+    /// Hands every quarantined allocation back to the wrapped allocator.
+    pub fn flush_quarantine(&self) {
+        let mut shadow = self.shadow.lock();
+        for i in 0..QUARANTINE {
+            if let Some((ptr, layout)) = shadow.quarantine[i].take() {
+                shadow.map.release_quarantined(ptr.as_ptr());
+                unsafe { self.inner.deallocate(ptr, layout) };
+            }
+        }
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex, const GRANULE: usize, const QUARANTINE: usize>
+    Allocator for ShadowAlloc<'_, A, R, GRANULE, QUARANTINE>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate(layout)?;
+        self.shadow
+            .lock()
+            .map
+            .mark_allocated(ptr.cast::<u8>().as_ptr(), ptr.len());
+
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut shadow = self.shadow.lock();
+        if !shadow.map.contains(ptr.as_ptr()) {
+            drop(shadow);
+            self.inner.deallocate(ptr, layout);
+            return;
+        }
+
+        let state = if QUARANTINE == 0 {
+            ShadowState::Free
+        } else {
+            ShadowState::Quarantined
+        };
+        assert!(
+            shadow.map.mark_freed(ptr.as_ptr(), state),
+            "Freed {ptr:?}, which is not a live allocation!"
+        );
+        if QUARANTINE == 0 {
+            drop(shadow);
+            self.inner.deallocate(ptr, layout);
+            return;
+        }
+
+        // Push out the oldest quarantined allocation to make room
+        let oldest = shadow.oldest;
+        let evicted = shadow.quarantine[oldest].replace((ptr, layout));
+        shadow.oldest = (oldest + 1) % QUARANTINE;
+        if let Some((evicted, evicted_layout)) = evicted {
+            shadow.map.release_quarantined(evicted.as_ptr());
+            drop(shadow);
+            self.inner.deallocate(evicted, evicted_layout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    const SIZE: usize = 4096;
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex>>;
+
+    #[test]
+    fn shadow_alloc() {
+        let heap = Heap::new(SIZE);
+        let mut storage = [0; ShadowMap::<16>::storage_size(SIZE)];
+        let map = ShadowMap::new(heap.start(), heap.end(), &mut storage).unwrap();
+        let allocator: ShadowAlloc<_, parking_lot::RawMutex, 16, 2> = ShadowAlloc::new(&*heap, map);
+
+        let layout = Layout::new::<[u8; 100]>();
+        let first = allocator.allocate(layout).unwrap().cast::<u8>().as_ptr();
+        assert_eq!(allocator.state(first), Some(ShadowState::AllocStart));
+        assert!(allocator.is_accessible(first, 100));
+        allocator.debug_check_access(first, 100);
+        // The allocation's own metadata lies in front of it
+        assert!(!allocator.is_accessible(first.wrapping_sub(1), 1));
+
+        // Freed allocations stay quarantined until pushed out
+        unsafe { allocator.deallocate(NonNull::new(first).unwrap(), layout) };
+        assert_eq!(allocator.state(first), Some(ShadowState::Quarantined));
+        assert!(!allocator.is_accessible(first, 1));
+        let others: Vec<_> = (0..2)
+            .map(|_| allocator.allocate(layout).unwrap())
+            .collect();
+        assert!(others.iter().all(|ptr| ptr.cast::<u8>().as_ptr() != first));
+        for ptr in others {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(allocator.state(first), Some(ShadowState::Free));
+
+        allocator.flush_quarantine();
+        let all = Layout::new::<[u8; 4000]>();
+        let ptr = allocator.allocate(all).unwrap();
+        unsafe { allocator.deallocate(ptr.cast(), all) };
+        allocator.flush_quarantine();
+    }
+
+    #[test]
+    #[should_panic]
+    fn shadow_alloc_double_free() {
+        let heap = Heap::new(SIZE);
+        let mut storage = [0; ShadowMap::<16>::storage_size(SIZE)];
+        let map = ShadowMap::new(heap.start(), heap.end(), &mut storage).unwrap();
+        let allocator: ShadowAlloc<_, parking_lot::RawMutex> = ShadowAlloc::new(&*heap, map);
+
+        let layout = Layout::new::<u64>();
+        let ptr = allocator.allocate(layout).unwrap();
+        unsafe {
+            allocator.deallocate(ptr.cast(), layout);
+            allocator.deallocate(ptr.cast(), layout);
+        }
+    }
+}
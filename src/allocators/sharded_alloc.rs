@@ -0,0 +1,167 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use super::cascade_alloc::FromRegion;
+
+/// Shards start at multiples of this, so no two shards share a cache line.
+pub const SHARD_ALIGN: usize = 64;
+
+/// Requests up to this size share the smallest size class.
+const MIN_CLASS_SIZE: usize = 16;
+
+/// Splits a region into `SHARDS` equal parts, each managed by a sub-heap of type `A` with a lock
+/// of its own, so threads allocating different sizes don't wait for each other. Requests are
+/// routed to a shard by their power of two size class, and to the other shards in turn once that
+/// one is full. Deallocations are routed to the shard owning the memory.
+///
+/// Each shard only sees part of the region, so a single allocation can't be larger than a shard.
+///
+/// ```ignore
+/// let heap = unsafe { ShardedAlloc::<LinkedListAlloc<RawSpinlock>, 8>::new(start, end) };
+/// ```
+pub struct ShardedAlloc<A: Allocator + FromRegion, const SHARDS: usize = 4> {
+    shards: [A; SHARDS],
+    start: usize,
+    shard_size: usize,
+}
+
+impl<A: Allocator + FromRegion, const SHARDS: usize> ShardedAlloc<A, SHARDS> {
+    /// Creates an allocator over the memory between `start` and `end`. The start is aligned up to
+    /// [`SHARD_ALIGN`], and the size of each shard rounded down to a multiple of it.
+    ///
+    /// # Safety
+    /// See [`FromRegion::from_region`].
+    ///
+    /// # Panics
+    /// Panics if `SHARDS` is zero, or the region is too small to give every shard some memory.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        const { assert!(SHARDS > 0, "There must be at least one shard!") };
+        let start = start.wrapping_add(start.align_offset(SHARD_ALIGN));
+        let size = (end as usize).saturating_sub(start as usize);
+        let shard_size = size / SHARDS / SHARD_ALIGN * SHARD_ALIGN;
+        assert!(shard_size > 0, "The region is too small to be sharded!");
+
+        ShardedAlloc {
+            shards: core::array::from_fn(|shard| {
+                let shard_start = start.add(shard * shard_size);
+                A::from_region(shard_start, shard_start.add(shard_size))
+            }),
+            start: start as usize,
+            shard_size,
+        }
+    }
+
+    /// The shard requests for `layout` are served by first.
+    pub fn shard_for(&self, layout: Layout) -> usize {
+        let class = layout
+            .size()
+            .max(MIN_CLASS_SIZE)
+            .next_power_of_two()
+            .trailing_zeros()
+            - MIN_CLASS_SIZE.trailing_zeros();
+        class as usize % SHARDS
+    }
+
+    /// The shard owning the memory at `ptr`, if any.
+    pub fn shard_of(&self, ptr: NonNull<u8>) -> Option<usize> {
+        let offset = (ptr.as_ptr() as usize).checked_sub(self.start)?;
+        let shard = offset / self.shard_size;
+        (shard < SHARDS).then_some(shard)
+    }
+
+    /// The sub-heap of the shard at `index`, e.g. to query allocator specific statistics.
+    pub fn shard(&self, index: usize) -> Option<&A> {
+        self.shards.get(index)
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const SHARDS: usize> Allocator for ShardedAlloc<A, SHARDS> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let first = self.shard_for(layout);
+        (0..SHARDS)
+            .map(|offset| &self.shards[(first + offset) % SHARDS])
+            .find_map(|shard| shard.allocate(layout).ok())
+            .ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let shard = self.shard_of(ptr).expect("Freed memory no shard owns!");
+        self.shards[shard].deallocate(ptr, layout);
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const SHARDS: usize> GlobalAlloc
+    for ShardedAlloc<A, SHARDS>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+
+    const SIZE: usize = 64 * 1024;
+
+    #[test]
+    fn sharded_alloc() {
+        let region = Layout::from_size_align(SIZE, SHARD_ALIGN).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        let allocator: ShardedAlloc<LinkedListAlloc<parking_lot::RawMutex>, 4> =
+            unsafe { ShardedAlloc::new(mem, mem.add(SIZE)) };
+
+        // Neighbouring size classes go to different shards
+        let small = Layout::new::<[u8; 32]>();
+        let large = Layout::new::<[u8; 64]>();
+        let a = allocator.allocate(small).unwrap();
+        let b = allocator.allocate(large).unwrap();
+        assert_eq!(allocator.shard_of(a.cast()), Some(1));
+        assert_eq!(allocator.shard_of(b.cast()), Some(2));
+        unsafe {
+            allocator.deallocate(a.cast(), small);
+            allocator.deallocate(b.cast(), large);
+        }
+
+        // Threads allocating different sizes use their own shards
+        std::thread::scope(|scope| {
+            for size in [16, 32, 64, 128] {
+                let allocator = &allocator;
+                scope.spawn(move || {
+                    let layout = Layout::from_size_align(size, 8).unwrap();
+                    for _ in 0..64 {
+                        let ptrs: Vec<_> = (0..64)
+                            .map(|_| allocator.allocate(layout).unwrap())
+                            .collect();
+                        for ptr in ptrs {
+                            unsafe { allocator.deallocate(ptr.cast(), layout) };
+                        }
+                    }
+                });
+            }
+        });
+
+        // Requests spill over into the other shards once theirs is full
+        let huge = Layout::new::<[u8; SIZE / 16]>();
+        let ptrs: Vec<_> = (0..12).map(|_| allocator.allocate(huge).unwrap()).collect();
+        assert!(allocator.allocate(huge).is_err());
+        for ptr in ptrs {
+            unsafe { allocator.deallocate(ptr.cast(), huge) };
+        }
+
+        for shard in 0..4 {
+            assert_eq!(allocator.shard(shard).unwrap().used_bytes(), 0);
+        }
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
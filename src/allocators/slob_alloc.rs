@@ -0,0 +1,310 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::Range,
+    ptr::{null_mut, NonNull},
+};
+
+/// Memory is managed in units of this many bytes, the size of a block header.
+const UNIT: usize = 4;
+/// A free block needs a second unit for the link to the next free block.
+const MIN_BLOCK_UNITS: u32 = 2;
+/// Terminates the free list.
+const NONE: u32 = u32::MAX;
+
+struct SlobHeap {
+    start: *mut u32,
+    units: u32,
+    // Offset of the first free block, in units
+    free: u32,
+}
+
+// SAFETY: The heap owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for SlobHeap {}
+
+impl SlobHeap {
+    /// Every block starts with a header holding its size in units. Free blocks link to the next
+    /// free block, in address order, with their second unit.
+    fn size(&self, block: u32) -> u32 {
+        unsafe { self.start.add(block as usize).read() }
+    }
+
+    fn set_size(&mut self, block: u32, size: u32) {
+        unsafe { self.start.add(block as usize).write(size) };
+    }
+
+    fn next(&self, block: u32) -> u32 {
+        unsafe { self.start.add(block as usize + 1).read() }
+    }
+
+    fn set_next(&mut self, block: u32, next: u32) {
+        unsafe { self.start.add(block as usize + 1).write(next) };
+    }
+
+    fn set_link(&mut self, prev: u32, next: u32) {
+        if prev == NONE {
+            self.free = next;
+        } else {
+            self.set_next(prev, next);
+        }
+    }
+
+    fn payload(&self, block: u32) -> *mut u8 {
+        unsafe { self.start.add(block as usize + 1) }.cast()
+    }
+
+    fn allocate(&mut self, layout: Layout) -> Option<*mut u8> {
+        let align = layout.align().max(UNIT);
+        let need = (layout.size().div_ceil(UNIT) + 1).max(MIN_BLOCK_UNITS as usize);
+        let need = u32::try_from(need).ok()?;
+
+        let (mut prev, mut block) = (NONE, self.free);
+        while block != NONE {
+            let size = self.size(block);
+            // Units in front of the aligned payload, which stay behind as a free block of their own
+            let mut lead = self.payload(block).align_offset(align) / UNIT;
+            while lead != 0 && lead < MIN_BLOCK_UNITS as usize {
+                lead += align / UNIT;
+            }
+            let lead = lead as u32;
+
+            if lead.checked_add(need).is_some_and(|end| end <= size) {
+                return Some(self.split(prev, block, lead, need));
+            }
+            (prev, block) = (block, self.next(block));
+        }
+        None
+    }
+
+    /// Carves a used block of `need` units out of the free `block`, `lead` units in.
+    fn split(&mut self, prev: u32, block: u32, lead: u32, mut need: u32) -> *mut u8 {
+        let size = self.size(block);
+        let next = self.next(block);
+        let used = block + lead;
+
+        // A remainder too small to be linked is absorbed into the used block
+        let mut trailing = size - lead - need;
+        if trailing < MIN_BLOCK_UNITS {
+            need += trailing;
+            trailing = 0;
+        }
+        let after = if trailing != 0 {
+            let tail = used + need;
+            self.set_size(tail, trailing);
+            self.set_next(tail, next);
+            tail
+        } else {
+            next
+        };
+
+        if lead != 0 {
+            self.set_size(block, lead);
+            self.set_next(block, after);
+        } else {
+            self.set_link(prev, after);
+        }
+        self.set_size(used, need);
+        self.payload(used)
+    }
+
+    /// Returns the block of `ptr` to the free list, merging it with adjacent free blocks.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`SlobHeap::allocate`] and not freed since.
+    unsafe fn deallocate(&mut self, ptr: *mut u8) {
+        let block = (ptr.cast::<u32>().offset_from(self.start) - 1) as u32;
+        let mut size = self.size(block);
+
+        // Find the free blocks on either side
+        let (mut prev, mut next) = (NONE, self.free);
+        while next != NONE && next < block {
+            (prev, next) = (next, self.next(next));
+        }
+
+        if next != NONE && block + size == next {
+            size += self.size(next);
+            next = self.next(next);
+        }
+        if prev != NONE && prev + self.size(prev) == block {
+            self.set_size(prev, self.size(prev) + size);
+            self.set_next(prev, next);
+        } else {
+            self.set_size(block, size);
+            self.set_next(block, next);
+            self.set_link(prev, block);
+        }
+    }
+
+    fn free_units(&self) -> u32 {
+        let mut total = 0;
+        let mut block = self.free;
+        while block != NONE {
+            total += self.size(block);
+            block = self.next(block);
+        }
+        total
+    }
+}
+
+/// A minimal overhead allocator for heaps of a few KiB, such as on microcontrollers with little
+/// RAM, where the 16 byte segment header of a
+/// [`LinkedListAlloc`](super::linked_list_allocator::LinkedListAlloc) is too costly.
+///
+/// Every allocation carries a single 4 byte header and is rounded up to a multiple of 4 bytes. The
+/// free blocks are kept on one address ordered list, which is searched first fit and merged on
+/// every free, so allocation is linear in the number of free blocks. That is fine for the small
+/// heaps this is meant for, but makes it a poor choice for large ones.
+pub struct SlobAlloc<R: lock_api::RawMutex> {
+    heap: lock_api::Mutex<R, SlobHeap>,
+}
+
+impl<R: lock_api::RawMutex> SlobAlloc<R> {
+    /// Creates an allocator managing the memory between `start` and `end` as a single free block.
+    /// Bytes in front of the first 4 byte aligned address and after the last whole unit are left
+    /// unused.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the allocator.
+    ///
+    /// # Panics
+    /// Panics if the region is too small to hold a single block.
+    pub unsafe fn new(start: *mut u8, end: *mut u8) -> Self {
+        let aligned = start.add(start.align_offset(UNIT));
+        let units = (end as usize).saturating_sub(aligned as usize) / UNIT;
+        let units = u32::try_from(units).unwrap_or(NONE - 1);
+        assert!(units >= MIN_BLOCK_UNITS, "The heap region is too small!");
+
+        let mut heap = SlobHeap {
+            start: aligned.cast(),
+            units,
+            free: 0,
+        };
+        heap.set_size(0, units);
+        heap.set_next(0, NONE);
+
+        SlobAlloc {
+            heap: lock_api::Mutex::new(heap),
+        }
+    }
+
+    /// The bytes of all free blocks, including their headers. Walks the free list.
+    pub fn free_bytes(&self) -> usize {
+        self.heap.lock().free_units() as usize * UNIT
+    }
+
+    /// The size of the managed region, in bytes.
+    pub fn size(&self) -> usize {
+        self.heap.lock().units as usize * UNIT
+    }
+
+    /// The managed region.
+    pub fn region(&self) -> Range<*mut u8> {
+        let heap = self.heap.lock();
+        let start = heap.start.cast::<u8>();
+        start..start.wrapping_add(heap.units as usize * UNIT)
+    }
+
+    /// Whether `ptr` lies in the managed region.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.region().contains(&ptr.cast_mut())
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> Allocator for SlobAlloc<R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.heap.lock().allocate(layout).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).unwrap(),
+            layout.size(),
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+        self.heap.lock().deallocate(ptr.as_ptr());
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> GlobalAlloc for SlobAlloc<R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.lock().allocate(layout).unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
+        self.heap.lock().deallocate(ptr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+
+    const SIZE: usize = 4096;
+
+    fn region() -> *mut u8 {
+        unsafe { std::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) }
+    }
+
+    #[test]
+    fn slob_alloc() {
+        let mem = region();
+        let allocator: SlobAlloc<parking_lot::RawMutex> =
+            unsafe { SlobAlloc::new(mem, mem.add(SIZE)) };
+        assert_eq!(allocator.size(), SIZE);
+
+        // Tiny allocations only cost a single header
+        let tiny = Layout::new::<u32>();
+        let a = allocator.allocate(tiny).unwrap().cast::<u8>();
+        let b = allocator.allocate(tiny).unwrap().cast::<u8>();
+        assert_eq!(b.as_ptr() as usize - a.as_ptr() as usize, 2 * UNIT);
+        assert_eq!(allocator.free_bytes(), SIZE - 4 * UNIT);
+
+        // Overaligned requests leave the padding in front free
+        let aligned = Layout::from_size_align(32, 64).unwrap();
+        let c = allocator.allocate(aligned).unwrap().cast::<u8>();
+        assert!(c.as_ptr().align_offset(64) == 0);
+        assert!(allocator.allocate(Layout::new::<[u8; SIZE]>()).is_err());
+
+        unsafe {
+            allocator.deallocate(b, tiny);
+            allocator.deallocate(a, tiny);
+            allocator.deallocate(c, aligned);
+        }
+        assert_eq!(allocator.free_bytes(), SIZE);
+        unsafe { std::alloc::dealloc(mem, Layout::from_size_align(SIZE, 16).unwrap()) };
+    }
+
+    #[test]
+    fn slob_alloc_random() {
+        let mem = region();
+        let allocator: SlobAlloc<parking_lot::RawMutex> =
+            unsafe { SlobAlloc::new(mem, mem.add(SIZE)) };
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for i in 0..2000 {
+            if rng.gen_bool(0.55) {
+                let align = 1 << rng.gen_range(0..5);
+                let layout = Layout::from_size_align(rng.gen_range(1..128), align).unwrap();
+                if let Ok(mut ptr) = allocator.allocate(layout) {
+                    assert!(ptr.cast::<u8>().as_ptr().align_offset(align) == 0);
+                    unsafe { ptr.as_mut() }.fill(i as u8);
+                    live.push((ptr, layout, i as u8));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout, fill) = live.swap_remove(rng.gen_range(0..live.len()));
+                assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == fill));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+
+        for (ptr, layout, _) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        // Everything merged back into a single block
+        assert_eq!(allocator.free_bytes(), SIZE);
+        assert_eq!(allocator.heap.lock().size(0), (SIZE / UNIT) as u32);
+        unsafe { std::alloc::dealloc(mem, Layout::from_size_align(SIZE, 16).unwrap()) };
+    }
+}
@@ -0,0 +1,215 @@
+use core::{alloc::Layout, mem::size_of, ptr::null_mut};
+
+use crate::memory_segmenter::{FitPolicy, FreeIndex, MemorySegmenter, SegmentMetadata};
+
+/// Number of small object size classes. Class `i` serves requests of up to `(i + 1) * GRANULE`
+/// bytes.
+pub const SMALL_BIN_CLASSES: usize = 8;
+/// Size (and alignment) of the slabs small objects are carved from.
+pub const SLAB_SIZE: usize = 4096;
+/// Upper bound on the number of slots in a slab, reached with the smallest possible granularity.
+const MAX_SLOTS: usize = SLAB_SIZE / SegmentMetadata::MIN_GRANULARITY;
+
+/// Header at the start of every slab. The slots follow it.
+struct Slab {
+    next: *mut Slab,
+    prev: *mut Slab,
+    free_slots: *mut u8,
+    used: usize,
+    capacity: usize,
+    slot_size: usize,
+}
+
+/// Slab style bins serving small requests without any per-allocation metadata.
+///
+/// Every slab is a single used segment of the segmenter, and serves one size class. Only slabs
+/// with at least one free slot are kept on the list of their class.
+#[derive(Debug)]
+pub(crate) struct SmallBins<const GRANULE: usize> {
+    partial: [*mut Slab; SMALL_BIN_CLASSES],
+}
+
+impl<const GRANULE: usize> SmallBins<GRANULE> {
+    const FIRST_SLOT_OFFSET: usize = size_of::<Slab>().next_multiple_of(GRANULE);
+
+    pub const fn new() -> Self {
+        SmallBins {
+            partial: [null_mut(); SMALL_BIN_CLASSES],
+        }
+    }
+
+    /// The size class serving `layout`, if it is small enough to be binned. Since the class is
+    /// derived from the layout alone, deallocation finds its way back to the same bin.
+    pub fn class_for(layout: Layout) -> Option<usize> {
+        if layout.align() > GRANULE || layout.size() > SMALL_BIN_CLASSES * GRANULE {
+            return None;
+        }
+
+        Some(layout.size().max(1).div_ceil(GRANULE) - 1)
+    }
+
+    pub const fn slot_size(class: usize) -> usize {
+        (class + 1) * GRANULE
+    }
+
+    /// The layout of the segment of every slab.
+    pub fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    pub fn allocate<I: FreeIndex>(
+        &mut self,
+        segmenter: &mut MemorySegmenter<GRANULE, I>,
+        policy: FitPolicy,
+        class: usize,
+    ) -> Option<*mut u8> {
+        if self.partial[class].is_null() {
+            let slab = Self::create_slab(segmenter, policy, class)?;
+            self.push_front(class, slab);
+        }
+
+        let slab = unsafe { self.partial[class].as_mut() }.unwrap();
+        let slot = slab.free_slots;
+        slab.free_slots = unsafe { slot.cast::<*mut u8>().read() };
+        slab.used += 1;
+
+        if slab.free_slots.is_null() {
+            self.unlink(class, slab);
+        }
+
+        Some(slot)
+    }
+
+    /// # Safety
+    /// `slot` must have been handed out by [`SmallBins::allocate`] for the same `class`.
+    pub unsafe fn deallocate<I: FreeIndex>(
+        &mut self,
+        segmenter: &mut MemorySegmenter<GRANULE, I>,
+        class: usize,
+        slot: *mut u8,
+    ) {
+        let slab_ptr = slot.map_addr(|addr| addr & !(SLAB_SIZE - 1)) as *mut Slab;
+        let slab = slab_ptr.as_mut().unwrap();
+
+        let was_full = slab.used == slab.capacity;
+        slot.cast::<*mut u8>().write(slab.free_slots);
+        slab.free_slots = slot;
+        slab.used -= 1;
+
+        if was_full {
+            self.push_front(class, slab_ptr);
+        }
+
+        // Return empty slabs, but keep the last one of each class around so a single object being
+        // allocated and freed repeatedly doesn't create and destroy a slab every time
+        if slab.used == 0 && !(slab.next.is_null() && slab.prev.is_null()) {
+            self.unlink(class, slab_ptr);
+            segmenter
+                .cursor_at(SegmentMetadata::from_alloc_ptr(slab_ptr.cast()))
+                .try_coalesce()
+                .expect("Failed to free slab!");
+        }
+    }
+
+    /// Returns every slab that doesn't have any live objects to the segmenter.
+    pub fn release_empty<I: FreeIndex>(&mut self, segmenter: &mut MemorySegmenter<GRANULE, I>) {
+        for class in 0..SMALL_BIN_CLASSES {
+            let mut slab_ptr = self.partial[class];
+            while let Some(slab) = unsafe { slab_ptr.as_mut() } {
+                let next = slab.next;
+                if slab.used == 0 {
+                    self.unlink(class, slab_ptr);
+                    unsafe {
+                        segmenter
+                            .cursor_at(SegmentMetadata::from_alloc_ptr(slab_ptr.cast()))
+                            .try_coalesce()
+                            .expect("Failed to free slab!");
+                    }
+                }
+                slab_ptr = next;
+            }
+        }
+    }
+
+    /// Calls `f` with every slot of the slab at `base` that is handed out, and the slot size.
+    ///
+    /// # Safety
+    /// `base` must be the alloc ptr of a slab created by these bins, i.e. of a container segment.
+    pub unsafe fn for_each_used_slot(base: *mut u8, mut f: impl FnMut(*mut u8, usize)) {
+        let slab = (base as *const Slab).as_ref().unwrap();
+        let slot_at = |i: usize| base.add(Self::FIRST_SLOT_OFFSET + i * slab.slot_size);
+
+        let mut free = [false; MAX_SLOTS];
+        let mut slot = slab.free_slots;
+        while !slot.is_null() {
+            free[(slot as usize - slot_at(0) as usize) / slab.slot_size] = true;
+            slot = slot.cast::<*mut u8>().read();
+        }
+        for i in (0..slab.capacity).filter(|&i| !free[i]) {
+            f(slot_at(i), slab.slot_size);
+        }
+    }
+
+    fn create_slab<I: FreeIndex>(
+        segmenter: &mut MemorySegmenter<GRANULE, I>,
+        policy: FitPolicy,
+        class: usize,
+    ) -> Option<*mut Slab> {
+        let layout = Self::slab_layout();
+        let fit = segmenter.find_fit(layout, policy)?;
+        let mut cursor = unsafe { segmenter.cursor_at(fit.segment) };
+        cursor.split_at(layout.size(), layout.align()).ok()?;
+        let base = cursor.current().alloc_start_ptr();
+        unsafe { (*SegmentMetadata::from_alloc_ptr(base)).set_container(true) };
+
+        // Thread every slot onto the free list, lowest address first
+        let slot_size = Self::slot_size(class);
+        let capacity = (SLAB_SIZE - Self::FIRST_SLOT_OFFSET) / slot_size;
+        let mut free_slots = null_mut();
+        for i in (0..capacity).rev() {
+            unsafe {
+                let slot = base.add(Self::FIRST_SLOT_OFFSET + i * slot_size);
+                slot.cast::<*mut u8>().write(free_slots);
+                free_slots = slot;
+            }
+        }
+
+        let slab = base as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                next: null_mut(),
+                prev: null_mut(),
+                free_slots,
+                used: 0,
+                capacity,
+                slot_size,
+            })
+        };
+
+        Some(slab)
+    }
+
+    fn push_front(&mut self, class: usize, slab_ptr: *mut Slab) {
+        let slab = unsafe { slab_ptr.as_mut() }.unwrap();
+        slab.prev = null_mut();
+        slab.next = self.partial[class];
+        if let Some(next) = unsafe { slab.next.as_mut() } {
+            next.prev = slab_ptr;
+        }
+        self.partial[class] = slab_ptr;
+    }
+
+    fn unlink(&mut self, class: usize, slab_ptr: *mut Slab) {
+        let slab = unsafe { slab_ptr.as_mut() }.unwrap();
+        if let Some(prev) = unsafe { slab.prev.as_mut() } {
+            prev.next = slab.next;
+        } else {
+            self.partial[class] = slab.next;
+        }
+        if let Some(next) = unsafe { slab.next.as_mut() } {
+            next.prev = slab.prev;
+        }
+        slab.next = null_mut();
+        slab.prev = null_mut();
+    }
+}
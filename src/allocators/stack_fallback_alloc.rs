@@ -0,0 +1,142 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::{Cell, UnsafeCell},
+    mem::MaybeUninit,
+    ptr::NonNull,
+};
+
+/// Serves allocations from an inline buffer of `N` bytes, bump style, and from the inner
+/// allocator once the buffer is exhausted. Deallocations are routed by address: memory in the
+/// buffer is only reclaimed when it was the most recent allocation there, or on
+/// [`StackFallbackAlloc::reset`], and everything else goes to the inner allocator.
+///
+/// Meant for temporary workspaces that are small most of the time, but occasionally large:
+///
+/// ```ignore
+/// let workspace = StackFallbackAlloc::<1024, _>::new(&heap);
+/// let mut scratch = Vec::new_in(&workspace);
+/// ```
+///
+/// Since the buffer is part of the value, moving it would invalidate its allocations, so only
+/// references to it implement [`Allocator`]. It isn't `Sync`, so it can only be used on one
+/// thread.
+pub struct StackFallbackAlloc<const N: usize, A: Allocator> {
+    buffer: UnsafeCell<[MaybeUninit<u8>; N]>,
+    // Offset of the free part of the buffer
+    next: Cell<usize>,
+    inner: A,
+}
+
+impl<const N: usize, A: Allocator> StackFallbackAlloc<N, A> {
+    pub const fn new(inner: A) -> Self {
+        StackFallbackAlloc {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            next: Cell::new(0),
+            inner,
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Whether `ptr` lies in the inline buffer.
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        let start = self.buffer.get() as usize;
+        (start..start + N).contains(&(ptr.as_ptr() as usize))
+    }
+
+    /// The bytes of the inline buffer in use, including padding.
+    pub fn used_bytes(&self) -> usize {
+        self.next.get()
+    }
+
+    /// Frees everything allocated from the inline buffer at once. Allocations from the inner
+    /// allocator are unaffected.
+    pub fn reset(&mut self) {
+        self.next.set(0);
+    }
+
+    fn allocate_inline(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let start = self.buffer.get().cast::<u8>();
+        let next = self.next.get();
+        let offset = next + unsafe { start.add(next) }.align_offset(layout.align());
+        let end = offset.checked_add(layout.size())?;
+        if end > N {
+            return None;
+        }
+
+        self.next.set(end);
+        NonNull::new(unsafe { start.add(offset) })
+    }
+}
+
+unsafe impl<const N: usize, A: Allocator> Allocator for &StackFallbackAlloc<N, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self.allocate_inline(layout) {
+            Some(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
+            None => self.inner.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if !self.owns(ptr) {
+            self.inner.deallocate(ptr, layout);
+            return;
+        }
+
+        // Only the most recent allocation in the buffer can be given back
+        let offset = ptr.as_ptr() as usize - self.buffer.get() as usize;
+        if offset + layout.size() == self.next.get() {
+            self.next.set(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn stack_fallback_alloc() {
+        let heap = Heap::new(16 * 1024);
+        let mut workspace = StackFallbackAlloc::<256, _>::new(&*heap);
+        let free = heap.free_bytes();
+
+        // Small requests are served inline, larger ones by the inner allocator
+        let alloc = &workspace;
+        let a = alloc.allocate(Layout::new::<[u64; 8]>()).unwrap();
+        assert!(workspace.owns(a.cast()));
+        assert_eq!(heap.free_bytes(), free);
+        let b = alloc.allocate(Layout::new::<[u8; 1024]>()).unwrap();
+        assert!(!workspace.owns(b.cast()));
+        assert!(heap.free_bytes() < free);
+        unsafe { alloc.deallocate(b.cast(), Layout::new::<[u8; 1024]>()) };
+        assert_eq!(heap.free_bytes(), free);
+
+        // Once the buffer is exhausted, the inner allocator takes over, and freeing the last
+        // inline allocation makes room again
+        let c = alloc.allocate(Layout::new::<[u8; 192]>()).unwrap();
+        assert!(workspace.owns(c.cast()));
+        let d = alloc.allocate(Layout::new::<[u8; 16]>()).unwrap();
+        assert!(!workspace.owns(d.cast()));
+        unsafe {
+            alloc.deallocate(c.cast(), Layout::new::<[u8; 192]>());
+            alloc.deallocate(d.cast(), Layout::new::<[u8; 16]>());
+        }
+        assert_eq!(workspace.used_bytes(), 64);
+
+        // Collections grow out of the buffer into the inner allocator
+        let mut list = Vec::new_in(&workspace);
+        list.extend(0..100u32);
+        assert!(!workspace.owns(NonNull::from(&list[0]).cast()));
+        assert_eq!(list.iter().sum::<u32>(), 4950);
+        drop(list);
+
+        workspace.reset();
+        assert_eq!(workspace.used_bytes(), 0);
+    }
+}
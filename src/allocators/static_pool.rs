@@ -0,0 +1,116 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::UnsafeCell,
+    mem::{align_of, size_of, MaybeUninit},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A single block of a [`StaticPoolAlloc`].
+#[repr(C, align(16))]
+struct Block<const BLOCK: usize>([MaybeUninit<u8>; BLOCK]);
+
+/// A pool of `N` blocks of `BLOCK` bytes each, aligned to 16 bytes, whose storage is part of the
+/// pool itself. Since it needs no initialization it can be declared as a `static`:
+///
+/// ```ignore
+/// static PACKETS: StaticPoolAlloc<64, 32> = StaticPoolAlloc::new();
+/// ```
+///
+/// The pool is lock-free: blocks are claimed by a compare-and-swap scan over their occupancy flags,
+/// and released with a single atomic operation, so it may be used from interrupt handlers.
+/// Requests that don't fit a block fail.
+pub struct StaticPoolAlloc<const BLOCK: usize, const N: usize> {
+    blocks: UnsafeCell<[Block<BLOCK>; N]>,
+    used: [AtomicBool; N],
+}
+
+// Every block is only ever accessed by whoever claimed it
+unsafe impl<const BLOCK: usize, const N: usize> Sync for StaticPoolAlloc<BLOCK, N> {}
+
+impl<const BLOCK: usize, const N: usize> StaticPoolAlloc<BLOCK, N> {
+    pub const fn new() -> Self {
+        StaticPoolAlloc {
+            blocks: UnsafeCell::new([const { Block([MaybeUninit::uninit(); BLOCK]) }; N]),
+            used: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// The number of blocks currently handed out. Only a snapshot if the pool is used concurrently.
+    pub fn used_blocks(&self) -> usize {
+        self.used
+            .iter()
+            .filter(|used| used.load(Ordering::Relaxed))
+            .count()
+    }
+
+    fn block_ptr(&self, index: usize) -> *mut u8 {
+        unsafe { self.blocks.get().cast::<Block<BLOCK>>().add(index).cast() }
+    }
+}
+
+impl<const BLOCK: usize, const N: usize> Default for StaticPoolAlloc<BLOCK, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const BLOCK: usize, const N: usize> Allocator for StaticPoolAlloc<BLOCK, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() > BLOCK || layout.align() > align_of::<Block<BLOCK>>() {
+            return Err(AllocError);
+        }
+
+        let index = self
+            .used
+            .iter()
+            .position(|used| {
+                used.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .ok_or(AllocError)?;
+
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(self.block_ptr(index)).unwrap(),
+            BLOCK,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _: Layout) {
+        let index =
+            (ptr.as_ptr() as usize - self.block_ptr(0) as usize) / size_of::<Block<BLOCK>>();
+        self.used[index].store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_pool_alloc() {
+        static POOL: StaticPoolAlloc<24, 4> = StaticPoolAlloc::new();
+
+        assert!(POOL.allocate(Layout::new::<[u8; 25]>()).is_err());
+        assert!(POOL
+            .allocate(Layout::from_size_align(8, 32).unwrap())
+            .is_err());
+
+        let layout = Layout::new::<[u64; 3]>();
+        let blocks: Vec<_> = (0..4).map(|_| POOL.allocate(layout).unwrap()).collect();
+        assert!(POOL.allocate(layout).is_err());
+        assert_eq!(POOL.used_blocks(), 4);
+        for block in &blocks {
+            assert_eq!(block.len(), 24);
+            assert_eq!(block.cast::<u8>().as_ptr().align_offset(16), 0);
+        }
+
+        // Freed blocks are handed out again
+        unsafe { POOL.deallocate(blocks[2].cast(), layout) };
+        assert_eq!(POOL.allocate(layout).unwrap(), blocks[2]);
+        for block in blocks {
+            unsafe { POOL.deallocate(block.cast(), layout) };
+        }
+        assert_eq!(POOL.used_blocks(), 0);
+    }
+}
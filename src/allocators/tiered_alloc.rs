@@ -0,0 +1,314 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::{Range, RangeInclusive},
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::cascade_alloc::FromRegion;
+
+/// The maximum number of placement rules of a [`TieredAlloc`].
+pub const MAX_RULES: usize = 8;
+
+/// Sends matching requests to a particular bank of a [`TieredAlloc`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlacementRule {
+    /// The request sizes the rule applies to.
+    pub sizes: RangeInclusive<usize>,
+    /// The smallest request alignment the rule applies to.
+    pub min_align: usize,
+    /// The tag the rule applies to, or `None` for requests with any tag or none at all.
+    pub tag: Option<u8>,
+    /// The index of the bank matching requests are served by.
+    pub bank: usize,
+    /// Whether the other banks may serve matching requests once the bank is full, in the order of
+    /// their priority.
+    pub fallback: bool,
+}
+
+impl PlacementRule {
+    /// A rule sending requests of `sizes` to `bank`, falling back to the other banks.
+    pub const fn sizes(sizes: RangeInclusive<usize>, bank: usize) -> Self {
+        PlacementRule {
+            sizes,
+            min_align: 1,
+            tag: None,
+            bank,
+            fallback: true,
+        }
+    }
+
+    /// A rule sending requests tagged with `tag` to `bank`, falling back to the other banks.
+    pub const fn tagged(tag: u8, bank: usize) -> Self {
+        PlacementRule {
+            sizes: 0..=usize::MAX,
+            min_align: 1,
+            tag: Some(tag),
+            bank,
+            fallback: true,
+        }
+    }
+
+    pub fn with_min_align(mut self, min_align: usize) -> Self {
+        self.min_align = min_align;
+        self
+    }
+
+    /// Fails matching requests once the bank is full, rather than serving them elsewhere.
+    pub fn without_fallback(mut self) -> Self {
+        self.fallback = false;
+        self
+    }
+
+    fn matches(&self, layout: Layout, tag: Option<u8>) -> bool {
+        self.sizes.contains(&layout.size())
+            && layout.align() >= self.min_align
+            && (self.tag.is_none() || self.tag == tag)
+    }
+}
+
+/// Usage statistics of one bank of a [`TieredAlloc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BankStats {
+    pub name: &'static str,
+    /// The requested bytes of all live allocations in the bank.
+    pub used_bytes: usize,
+    pub live_allocations: usize,
+    /// The number of allocations served by the bank, although a rule sent them to another one.
+    pub fallback_allocations: usize,
+}
+
+struct Bank<A> {
+    name: &'static str,
+    range: Range<usize>,
+    heap: A,
+    used_bytes: AtomicUsize,
+    live_allocations: AtomicUsize,
+    fallback_allocations: AtomicUsize,
+}
+
+/// An allocator over up to `BANKS` banks of RAM with different speeds, like the tightly coupled
+/// or core coupled memory of a microcontroller next to external SDRAM, each managed by a sub-heap
+/// of type `A`. Banks are given in the order of their priority, and requests are served by the
+/// first bank with room, so the fast memory is used up before the slow memory.
+///
+/// [`PlacementRule`]s override that order for the requests they match, by size, alignment, or the
+/// tag passed to [`TieredAlloc::allocate_tagged`]. The first matching rule applies. Deallocations
+/// are routed to the bank owning the memory.
+///
+/// ```ignore
+/// let heap = unsafe {
+///     TieredAlloc::<LinkedListAlloc<CriticalSectionLock>, 2>::new(&[
+///         ("dtcm", DTCM_START, DTCM_END),
+///         ("sdram", SDRAM_START, SDRAM_END),
+///     ])
+/// }
+/// .with_rule(PlacementRule::tagged(DMA, 1).without_fallback())
+/// // Large buffers would crowd out everything else from the DTCM
+/// .with_rule(PlacementRule::sizes(4096..=usize::MAX, 1));
+/// ```
+pub struct TieredAlloc<A: Allocator + FromRegion, const BANKS: usize> {
+    banks: [Option<Bank<A>>; BANKS],
+    rules: [Option<PlacementRule>; MAX_RULES],
+}
+
+impl<A: Allocator + FromRegion, const BANKS: usize> TieredAlloc<A, BANKS> {
+    /// Creates an allocator with a sub-heap for every named region, in the order of their
+    /// priority.
+    ///
+    /// # Safety
+    /// See [`FromRegion::from_region`], for every region.
+    ///
+    /// # Panics
+    /// Panics if there are more than `BANKS` regions.
+    pub unsafe fn new(banks: &[(&'static str, *mut u8, *mut u8)]) -> Self {
+        assert!(banks.len() <= BANKS, "Too many memory banks!");
+        let mut slots = [const { None }; BANKS];
+        for (slot, &(name, start, end)) in slots.iter_mut().zip(banks) {
+            *slot = Some(Bank {
+                name,
+                range: start as usize..end as usize,
+                heap: A::from_region(start, end),
+                used_bytes: AtomicUsize::new(0),
+                live_allocations: AtomicUsize::new(0),
+                fallback_allocations: AtomicUsize::new(0),
+            });
+        }
+
+        TieredAlloc {
+            banks: slots,
+            rules: [const { None }; MAX_RULES],
+        }
+    }
+
+    /// Adds `rule`, after the rules added before.
+    ///
+    /// # Panics
+    /// Panics if there are [`MAX_RULES`] rules already, or the rule names a bank that doesn't
+    /// exist.
+    pub fn with_rule(mut self, rule: PlacementRule) -> Self {
+        assert!(
+            self.bank(rule.bank).is_some(),
+            "The rule names bank {}, which doesn't exist!",
+            rule.bank
+        );
+        let slot = self.rules.iter_mut().find(|slot| slot.is_none());
+        *slot.expect("Too many placement rules!") = Some(rule);
+        self
+    }
+
+    /// Allocates memory for `layout`, placing it according to the rules matching `tag`.
+    pub fn allocate_tagged(&self, layout: Layout, tag: u8) -> Result<NonNull<[u8]>, AllocError> {
+        self.place(layout, Some(tag))
+    }
+
+    /// The index of the bank owning the memory at `ptr`, if any.
+    pub fn bank_of(&self, ptr: NonNull<u8>) -> Option<usize> {
+        self.owner(ptr).map(|(index, _)| index)
+    }
+
+    /// The statistics of the bank at `index`, if there is such a bank.
+    pub fn bank_stats(&self, index: usize) -> Option<BankStats> {
+        self.bank(index).map(|bank| BankStats {
+            name: bank.name,
+            used_bytes: bank.used_bytes.load(Ordering::Relaxed),
+            live_allocations: bank.live_allocations.load(Ordering::Relaxed),
+            fallback_allocations: bank.fallback_allocations.load(Ordering::Relaxed),
+        })
+    }
+
+    /// The sub-heap of the bank at `index`, e.g. to query allocator specific statistics.
+    pub fn heap(&self, index: usize) -> Option<&A> {
+        self.bank(index).map(|bank| &bank.heap)
+    }
+
+    fn place(&self, layout: Layout, tag: Option<u8>) -> Result<NonNull<[u8]>, AllocError> {
+        let rule = self
+            .rules
+            .iter()
+            .flatten()
+            .find(|rule| rule.matches(layout, tag));
+        let preferred = rule.map(|rule| rule.bank);
+        let fallback = rule.is_none_or(|rule| rule.fallback);
+        let fallbacks = self
+            .banks()
+            .filter(|&(index, _)| fallback && Some(index) != preferred);
+        let preferred = preferred.and_then(|index| Some((index, self.bank(index)?)));
+
+        for (index, bank) in preferred.into_iter().chain(fallbacks) {
+            if let Ok(ptr) = bank.heap.allocate(layout) {
+                bank.used_bytes.fetch_add(layout.size(), Ordering::Relaxed);
+                bank.live_allocations.fetch_add(1, Ordering::Relaxed);
+                if preferred.is_some_and(|(preferred, _)| preferred != index) {
+                    bank.fallback_allocations.fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(ptr);
+            }
+        }
+        Err(AllocError)
+    }
+
+    fn banks(&self) -> impl Iterator<Item = (usize, &Bank<A>)> {
+        self.banks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bank)| Some((index, bank.as_ref()?)))
+    }
+
+    fn bank(&self, index: usize) -> Option<&Bank<A>> {
+        self.banks.get(index)?.as_ref()
+    }
+
+    fn owner(&self, ptr: NonNull<u8>) -> Option<(usize, &Bank<A>)> {
+        self.banks()
+            .find(|(_, bank)| bank.range.contains(&(ptr.as_ptr() as usize)))
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const BANKS: usize> Allocator for TieredAlloc<A, BANKS> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.place(layout, None)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let (_, bank) = self.owner(ptr).expect("Freed memory no bank owns!");
+        bank.heap.deallocate(ptr, layout);
+        bank.used_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        bank.live_allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: Allocator + FromRegion, const BANKS: usize> GlobalAlloc for TieredAlloc<A, BANKS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+
+    const SIZE: usize = 4096;
+    const DMA: u8 = 1;
+
+    #[test]
+    fn tiered_alloc() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let (fast, slow) = unsafe { (std::alloc::alloc(region), std::alloc::alloc(region)) };
+        let allocator: TieredAlloc<LinkedListAlloc<parking_lot::RawMutex>, 2> = unsafe {
+            TieredAlloc::new(&[
+                ("fast", fast, fast.add(SIZE)),
+                ("slow", slow, slow.add(SIZE)),
+            ])
+        }
+        .with_rule(PlacementRule::tagged(DMA, 1).without_fallback())
+        .with_rule(PlacementRule::sizes(1024..=usize::MAX, 1));
+
+        // Small requests go to the fast bank first, large ones to the slow bank
+        let small = Layout::new::<[u8; 256]>();
+        let large = Layout::new::<[u8; 1024]>();
+        let mut ptrs = vec![
+            (allocator.allocate(small).unwrap(), small),
+            (allocator.allocate(large).unwrap(), large),
+        ];
+        assert_eq!(allocator.bank_of(ptrs[0].0.cast()), Some(0));
+        assert_eq!(allocator.bank_of(ptrs[1].0.cast()), Some(1));
+
+        // Untagged requests may spill over, DMA buffers may not
+        let tagged = allocator.allocate_tagged(small, DMA).unwrap();
+        assert_eq!(allocator.bank_of(tagged.cast()), Some(1));
+        ptrs.push((tagged, small));
+        while let Ok(ptr) = allocator.allocate_tagged(large, DMA) {
+            ptrs.push((ptr, large));
+        }
+        assert_eq!(allocator.bank_stats(0).unwrap().live_allocations, 1);
+        let spilled = allocator.allocate(large).unwrap();
+        assert_eq!(allocator.bank_of(spilled.cast()), Some(0));
+        ptrs.push((spilled, large));
+
+        let stats = allocator.bank_stats(0).unwrap();
+        assert_eq!(stats.name, "fast");
+        assert_eq!(stats.fallback_allocations, 1);
+        assert_eq!(stats.used_bytes, 256 + 1024);
+        assert!(allocator.bank_stats(2).is_none());
+
+        for (ptr, layout) in ptrs {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+        for bank in 0..2 {
+            assert_eq!(allocator.bank_stats(bank).unwrap().live_allocations, 0);
+        }
+        unsafe {
+            std::alloc::dealloc(fast, region);
+            std::alloc::dealloc(slow, region);
+        }
+    }
+}
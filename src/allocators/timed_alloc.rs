@@ -0,0 +1,310 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+/// The number of buckets of a [`LatencyStats`] histogram.
+pub const LATENCY_BUCKETS: usize = 32;
+
+/// A monotonic clock, counting ticks of any length. Any `Fn() -> u64` is a clock, e.g. one
+/// reading the cycle counter of a microcontroller:
+///
+/// ```ignore
+/// let heap = TimedAlloc::<_, RawSpinlock, _>::new(&HEAP, || DWT::cycle_count() as u64);
+/// ```
+///
+/// Clocks are read around every operation, so they must not allocate from the allocator they time.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// Counts nanoseconds since its creation.
+#[cfg(any(feature = "std", test))]
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    start: std::time::Instant,
+}
+
+#[cfg(any(feature = "std", test))]
+impl StdClock {
+    pub fn new() -> Self {
+        StdClock {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl Clock for StdClock {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
+
+/// The latencies of one kind of operation, in clock ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: u64,
+    /// The shortest latency, or `u64::MAX` before the first operation.
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+    /// The number of operations by their latency. Bucket 0 counts operations taking no ticks at
+    /// all, and bucket `i` those taking from `2^(i - 1)` up to `2^i - 1` ticks. The last bucket
+    /// also counts everything longer.
+    pub histogram: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyStats {
+    pub const fn new() -> Self {
+        LatencyStats {
+            count: 0,
+            min: u64::MAX,
+            max: 0,
+            total: 0,
+            histogram: [0; LATENCY_BUCKETS],
+        }
+    }
+
+    /// The bucket of the histogram counting operations taking `ticks`.
+    pub fn bucket_of(ticks: u64) -> usize {
+        ((u64::BITS - ticks.leading_zeros()) as usize).min(LATENCY_BUCKETS - 1)
+    }
+
+    /// The mean latency, if there were any operations.
+    pub fn mean(&self) -> Option<u64> {
+        self.total.checked_div(self.count)
+    }
+
+    /// An upper bound for the latency of `percent` percent of the operations, from the
+    /// histogram. Never more than the longest latency.
+    pub fn percentile(&self, percent: u8) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let wanted = (u128::from(self.count) * u128::from(percent.min(100))).div_ceil(100) as u64;
+        let mut seen = 0;
+        let bucket = self
+            .histogram
+            .iter()
+            .position(|&count| {
+                seen += count;
+                seen >= wanted.max(1)
+            })
+            .unwrap_or(LATENCY_BUCKETS - 1);
+        let bound = match bucket {
+            0 => 0,
+            bucket => (1 << bucket) - 1,
+        };
+        Some(self.max.min(bound))
+    }
+
+    fn record(&mut self, ticks: u64) {
+        self.count += 1;
+        self.min = self.min.min(ticks);
+        self.max = self.max.max(ticks);
+        self.total = self.total.saturating_add(ticks);
+        self.histogram[Self::bucket_of(ticks)] += 1;
+    }
+}
+
+impl Default for LatencyStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The latencies of all operations of a [`TimedAlloc`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latencies {
+    /// Allocations, including failed ones.
+    pub allocate: LatencyStats,
+    pub deallocate: LatencyStats,
+    /// Growing and shrinking allocations.
+    pub resize: LatencyStats,
+}
+
+/// Wraps an allocator to measure how long each operation on it takes, with a [`Clock`] of the
+/// user's choice. Real-time code needs to know the worst case rather than the average, so besides
+/// the mean, the shortest and longest latency and a histogram of all of them are kept.
+///
+/// Only the inner operation is timed. Recording it takes the lock of the statistics afterwards,
+/// which briefly serializes concurrent operations.
+pub struct TimedAlloc<A: Allocator, R: lock_api::RawMutex, C: Clock> {
+    inner: A,
+    clock: C,
+    latencies: lock_api::Mutex<R, Latencies>,
+}
+
+impl<A: Allocator, R: lock_api::RawMutex, C: Clock> TimedAlloc<A, R, C> {
+    pub const fn new(inner: A, clock: C) -> Self {
+        TimedAlloc {
+            inner,
+            clock,
+            latencies: lock_api::Mutex::new(Latencies {
+                allocate: LatencyStats::new(),
+                deallocate: LatencyStats::new(),
+                resize: LatencyStats::new(),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn latencies(&self) -> Latencies {
+        *self.latencies.lock()
+    }
+
+    /// Forgets all latencies recorded so far, e.g. once a system has warmed up.
+    pub fn reset(&self) {
+        *self.latencies.lock() = Latencies::default();
+    }
+
+    fn timed<T>(
+        &self,
+        stats: fn(&mut Latencies) -> &mut LatencyStats,
+        op: impl FnOnce() -> T,
+    ) -> T {
+        let start = self.clock.now();
+        let result = op();
+        let ticks = self.clock.now().saturating_sub(start);
+
+        stats(&mut self.latencies.lock()).record(ticks);
+        result
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex, C: Clock> Allocator for TimedAlloc<A, R, C> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.timed(
+            |latencies| &mut latencies.allocate,
+            || self.inner.allocate(layout),
+        )
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.timed(
+            |latencies| &mut latencies.allocate,
+            || self.inner.allocate_zeroed(layout),
+        )
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.timed(
+            |latencies| &mut latencies.deallocate,
+            || self.inner.deallocate(ptr, layout),
+        )
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.timed(
+            |latencies| &mut latencies.resize,
+            || self.inner.grow(ptr, old_layout, new_layout),
+        )
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.timed(
+            |latencies| &mut latencies.resize,
+            || self.inner.grow_zeroed(ptr, old_layout, new_layout),
+        )
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.timed(
+            |latencies| &mut latencies.resize,
+            || self.inner.shrink(ptr, old_layout, new_layout),
+        )
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex, C: Clock> GlobalAlloc for TimedAlloc<A, R, C> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+    use core::cell::Cell;
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn timed_alloc() {
+        let heap = Heap::new(4096);
+
+        // A fake clock advancing by 5 ticks per reading, so every operation takes 5 ticks
+        let ticks = Cell::new(0);
+        let clock = || {
+            ticks.set(ticks.get() + 5);
+            ticks.get()
+        };
+        let allocator: TimedAlloc<_, parking_lot::RawMutex, _> = TimedAlloc::new(&*heap, clock);
+        let layout = Layout::new::<[u8; 64]>();
+        let ptr = allocator.allocate(layout).unwrap();
+        unsafe { allocator.deallocate(ptr.cast(), layout) };
+        assert!(allocator.allocate(Layout::new::<[u8; 8192]>()).is_err());
+
+        let latencies = allocator.latencies();
+        assert_eq!(latencies.allocate.count, 2);
+        assert_eq!(latencies.deallocate.count, 1);
+        assert_eq!(latencies.allocate.min, latencies.allocate.max);
+        assert_eq!(latencies.allocate.mean(), Some(5));
+        assert_eq!(latencies.allocate.histogram[LatencyStats::bucket_of(5)], 2);
+        assert_eq!(latencies.allocate.percentile(99), Some(5));
+        assert_eq!(latencies.resize.mean(), None);
+
+        let mut stats = LatencyStats::new();
+        for ticks in [0, 1, 3, 900, 5000] {
+            stats.record(ticks);
+        }
+        assert_eq!(stats.percentile(50), Some(3));
+        assert_eq!(stats.percentile(80), Some(1023));
+        assert_eq!(stats.percentile(100), Some(5000));
+        assert_eq!(LatencyStats::bucket_of(u64::MAX), LATENCY_BUCKETS - 1);
+
+        allocator.reset();
+        assert_eq!(allocator.latencies(), Latencies::default());
+        assert!(heap.is_drained());
+    }
+}
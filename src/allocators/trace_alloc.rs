@@ -0,0 +1,185 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ffi::CStr,
+    ptr::NonNull,
+};
+
+use crate::trace::{TraceEvent, TraceKind, TraceSink};
+
+struct Recorder<T: TraceSink> {
+    sink: T,
+    seq: u64,
+}
+
+/// Wraps an allocator to record every operation on it into a [`TraceSink`]. Offsets in the trace
+/// are relative to `base`, usually the start of the heap, so that traces of different runs can be
+/// compared.
+pub struct TracingAlloc<A: Allocator, R: lock_api::RawMutex, T: TraceSink> {
+    inner: A,
+    base: usize,
+    name: Option<&'static CStr>,
+    recorder: lock_api::Mutex<R, Recorder<T>>,
+}
+
+impl<A: Allocator, R: lock_api::RawMutex, T: TraceSink> TracingAlloc<A, R, T> {
+    pub fn new(inner: A, sink: T, base: *const u8) -> Self {
+        TracingAlloc {
+            inner,
+            base: base as usize,
+            name: None,
+            recorder: lock_api::Mutex::new(Recorder { sink, seq: 0 }),
+        }
+    }
+
+    /// Names the trace, to tell it apart from the traces of other heaps, e.g. when printing the
+    /// sinks of several heaps from a panic handler.
+    pub fn with_name(mut self, name: &'static CStr) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn name(&self) -> Option<&'static CStr> {
+        self.name
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Runs `f` on the sink while holding the lock, which keeps any new events from being
+    /// recorded in the meantime.
+    pub fn with_sink<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        f(&mut self.recorder.lock().sink)
+    }
+
+    /// The sink, without taking the lock. Meant for panic handlers, which can't wait for a lock
+    /// that may be held by the very operation that panicked.
+    ///
+    /// # Safety
+    /// No events may be recorded while the reference is alive.
+    pub unsafe fn sink_unlocked(&self) -> &T {
+        &(*self.recorder.data_ptr()).sink
+    }
+
+    fn offset_of(&self, ptr: NonNull<u8>) -> usize {
+        (ptr.as_ptr() as usize).wrapping_sub(self.base)
+    }
+
+    /// Runs `op` and records its outcome, with the lock held throughout so that events are
+    /// recorded in the order they took effect.
+    fn traced(
+        &self,
+        kind: TraceKind,
+        layout: Layout,
+        op: impl FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let mut recorder = self.recorder.lock();
+        let result = op();
+
+        let seq = recorder.seq;
+        recorder.seq += 1;
+        recorder.sink.record(TraceEvent {
+            seq,
+            kind,
+            size: layout.size(),
+            align: layout.align(),
+            offset: result.ok().map(|ptr| self.offset_of(ptr.cast())),
+        });
+
+        result
+    }
+}
+
+unsafe impl<A: Allocator, R: lock_api::RawMutex, T: TraceSink> Allocator for TracingAlloc<A, R, T> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.traced(TraceKind::Allocate, layout, || self.inner.allocate(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = self.traced(TraceKind::Deallocate, layout, || {
+            self.inner.deallocate(ptr, layout);
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        });
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_offset = self.offset_of(ptr);
+        self.traced(TraceKind::Grow { old_offset }, new_layout, || {
+            self.inner.grow(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_offset = self.offset_of(ptr);
+        self.traced(TraceKind::Grow { old_offset }, new_layout, || {
+            self.inner.grow_zeroed(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_offset = self.offset_of(ptr);
+        self.traced(TraceKind::Shrink { old_offset }, new_layout, || {
+            self.inner.shrink(ptr, old_layout, new_layout)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap, trace::TraceRing,
+    };
+
+    #[test]
+    fn tracing_alloc() {
+        let heap: TestHeap<LinkedListAlloc<parking_lot::RawMutex>> = TestHeap::new(4096);
+        let allocator: TracingAlloc<_, parking_lot::RawMutex, _> =
+            TracingAlloc::new(&*heap, TraceRing::<8>::new(), heap.start()).with_name(c"scratch");
+        assert_eq!(allocator.name(), Some(c"scratch"));
+
+        let small = Layout::new::<[u8; 32]>();
+        let large = Layout::new::<[u8; 256]>();
+        let ptr = allocator.allocate(small).unwrap().cast::<u8>();
+        let ptr = unsafe { allocator.grow(ptr, small, large) }
+            .unwrap()
+            .cast::<u8>();
+        assert!(allocator.allocate(Layout::new::<[u8; 8192]>()).is_err());
+        unsafe { allocator.deallocate(ptr, large) };
+
+        allocator.with_sink(|ring| {
+            let events: Vec<_> = ring.iter().copied().collect();
+            assert_eq!(events.len(), 4);
+            assert!(events.iter().map(|event| event.seq).eq(0..4));
+
+            let first = events[0].offset.unwrap();
+            assert!(first < 4096);
+            assert_eq!(events[0].kind, TraceKind::Allocate);
+            assert_eq!(events[1].kind, TraceKind::Grow { old_offset: first });
+            assert_eq!(events[1].size, 256);
+            assert_eq!(events[2].offset, None);
+            assert_eq!(events[3].kind, TraceKind::Deallocate);
+            assert_eq!(events[3].offset, events[1].offset);
+        });
+
+        // The events can be read even while the lock is held
+        let guard = allocator.recorder.lock();
+        assert_eq!(unsafe { allocator.sink_unlocked() }.iter().count(), 4);
+        drop(guard);
+    }
+}
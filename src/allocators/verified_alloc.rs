@@ -0,0 +1,226 @@
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+use crate::{
+    allocators::linked_list_allocator::LinkedListAlloc,
+    memory_segmenter::{IntegrityError, MemorySegmenter, DEFAULT_GRANULARITY},
+    memory_source::{MemorySource, NoSource},
+};
+
+/// A live allocation, as recorded in the side table of a [`VerifiedAlloc`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackedAllocation {
+    pub ptr: NonNull<u8>,
+    pub layout: Layout,
+}
+
+// SAFETY: A record of an allocation is never dereferenced, so it can be shared freely
+unsafe impl Send for TrackedAllocation {}
+unsafe impl Sync for TrackedAllocation {}
+
+/// The first disagreement a [`VerifiedAlloc`] found between the heap and its side table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Divergence {
+    /// The segment list itself is inconsistent.
+    Corrupt(IntegrityError),
+    /// A new allocation overlaps the live allocation at `live`.
+    Overlap { ptr: *const u8, live: *const u8 },
+    /// A live allocation lies within a free segment.
+    NotInUse(*const u8),
+    /// A pointer that isn't live according to the side table, or a layout that doesn't match the
+    /// one it was allocated with, was freed.
+    UnknownFree(*const u8),
+}
+
+/// A debugging wrapper around a [`LinkedListAlloc`] that mirrors every live allocation in an
+/// independent side table, and cross-checks the table against the segment list after every
+/// operation. The first divergence panics, right at the operation that caused it, instead of
+/// surfacing as corruption much later.
+///
+/// The side table lives in memory of its own, so damage to the heap can't reach it. Every
+/// operation walks the whole segment list and the whole table, so this is only meant for tracking
+/// down heap corruption.
+pub struct VerifiedAlloc<
+    'a,
+    R: lock_api::RawMutex,
+    const GRANULE: usize = DEFAULT_GRANULARITY,
+    S: MemorySource = NoSource,
+> {
+    inner: LinkedListAlloc<R, GRANULE, S>,
+    table: lock_api::Mutex<R, &'a mut [Option<TrackedAllocation>]>,
+}
+
+impl<'a, R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource>
+    VerifiedAlloc<'a, R, GRANULE, S>
+{
+    /// Wraps `inner`, which must not have any live allocations. At most `table.len()` allocations
+    /// can be live at once.
+    pub fn new(
+        inner: LinkedListAlloc<R, GRANULE, S>,
+        table: &'a mut [Option<TrackedAllocation>],
+    ) -> Self {
+        table.fill(None);
+
+        VerifiedAlloc {
+            inner,
+            table: lock_api::Mutex::new(table),
+        }
+    }
+
+    pub fn inner(&self) -> &LinkedListAlloc<R, GRANULE, S> {
+        &self.inner
+    }
+
+    /// Cross-checks the side table against the segment list.
+    pub fn verify(&self) -> Result<(), Divergence> {
+        let table = self.table.lock();
+        self.inner
+            .with_segmenter(|segmenter| Self::check(segmenter, &table))
+    }
+
+    fn check(
+        segmenter: &MemorySegmenter<GRANULE>,
+        table: &[Option<TrackedAllocation>],
+    ) -> Result<(), Divergence> {
+        segmenter.check_integrity().map_err(Divergence::Corrupt)?;
+
+        for allocation in table.iter().flatten() {
+            let ptr = allocation.ptr.as_ptr();
+            // Allocations outside of every segment were served by the memory source
+            let segment = segmenter.iter().find(|segment| {
+                (segment.addr() as *mut u8..segment.end_exclusive()).contains(&ptr)
+            });
+            if segment.is_some_and(|segment| !segment.in_use()) {
+                return Err(Divergence::NotInUse(ptr));
+            }
+        }
+        Ok(())
+    }
+
+    fn diverged(divergence: Divergence) -> ! {
+        panic!("The heap diverged from the side table: {divergence:?}");
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const GRANULE: usize, S: MemorySource> Allocator
+    for VerifiedAlloc<'_, R, GRANULE, S>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut table = self.table.lock();
+        let ptr = self.inner.allocate(layout)?;
+        let start = ptr.cast::<u8>().as_ptr();
+        let range = start..start.wrapping_add(layout.size().max(1));
+
+        let mut free_slot = None;
+        for (i, slot) in table.iter().enumerate() {
+            match slot {
+                Some(live) => {
+                    let live_start = live.ptr.as_ptr();
+                    let live_end = live_start.wrapping_add(live.layout.size().max(1));
+                    if range.start < live_end && live_start < range.end {
+                        Self::diverged(Divergence::Overlap {
+                            ptr: start,
+                            live: live_start,
+                        });
+                    }
+                }
+                None => free_slot = free_slot.or(Some(i)),
+            }
+        }
+        let Some(slot) = free_slot else {
+            unsafe { self.inner.deallocate(ptr.cast(), layout) };
+            panic!("The side table is full!");
+        };
+        table[slot] = Some(TrackedAllocation {
+            ptr: ptr.cast(),
+            layout,
+        });
+
+        if let Err(divergence) = self
+            .inner
+            .with_segmenter(|segmenter| Self::check(segmenter, &table))
+        {
+            Self::diverged(divergence);
+        }
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut table = self.table.lock();
+        let tracked = TrackedAllocation { ptr, layout };
+        let Some(slot) = table.iter().position(|slot| *slot == Some(tracked)) else {
+            Self::diverged(Divergence::UnknownFree(ptr.as_ptr()));
+        };
+        table[slot] = None;
+
+        self.inner.deallocate(ptr, layout);
+        if let Err(divergence) = self
+            .inner
+            .with_segmenter(|segmenter| Self::check(segmenter, &table))
+        {
+            Self::diverged(divergence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, Rng};
+
+    use super::*;
+    use crate::{memory_segmenter::SegmentMetadata, memory_source::SystemSource};
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    #[test]
+    fn verified_alloc() {
+        let mut table = [None; 64];
+        let allocator = VerifiedAlloc::new(Heap::with_capacity(16 * 1024, 16), &mut table);
+        allocator.inner().set_quick_lists_enabled(true);
+
+        let mut rng = thread_rng();
+        let mut live = Vec::new();
+        for _ in 0..1000 {
+            if live.len() < 64 && rng.gen_bool(0.6) {
+                let layout = Layout::from_size_align(rng.gen_range(1..256), 16).unwrap();
+                if let Ok(ptr) = allocator.allocate(layout) {
+                    live.push((ptr, layout));
+                }
+            } else if !live.is_empty() {
+                let (ptr, layout) = live.swap_remove(rng.gen_range(0..live.len()));
+                unsafe { allocator.deallocate(ptr.cast(), layout) };
+            }
+        }
+        assert_eq!(allocator.verify(), Ok(()));
+
+        for (ptr, layout) in live {
+            unsafe { allocator.deallocate(ptr.cast(), layout) };
+        }
+    }
+
+    #[test]
+    fn verified_alloc_corruption() {
+        let mut table = [None; 4];
+        let allocator = VerifiedAlloc::new(Heap::with_capacity(4096, 16), &mut table);
+        let layout = Layout::new::<[u8; 64]>();
+        let ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+
+        // Mark the allocation's segment as free behind the allocator's back
+        unsafe { (*SegmentMetadata::from_alloc_ptr(ptr.as_ptr())).set_in_use(false) };
+        assert_eq!(
+            allocator.verify(),
+            Err(Divergence::Corrupt(IntegrityError::CountMismatch))
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn verified_alloc_unknown_free() {
+        let mut table = [None; 4];
+        let allocator = VerifiedAlloc::new(Heap::with_capacity(4096, 16), &mut table);
+        let ptr = allocator.allocate(Layout::new::<[u8; 64]>()).unwrap();
+        unsafe { allocator.deallocate(ptr.cast(), Layout::new::<[u8; 32]>()) };
+    }
+}
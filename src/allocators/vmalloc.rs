@@ -0,0 +1,230 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{self, null_mut, NonNull},
+};
+
+use super::window_alloc::WindowAlloc;
+
+/// Wires pages of virtual memory to frames of physical memory, usually by editing the page tables.
+///
+/// # Safety
+/// Once [`PageMapper::map`] succeeds, the page at `virt` must be readable and writable, backed by
+/// `frame`, until it is unmapped again.
+pub unsafe trait PageMapper {
+    /// Maps the page at the virtual address `virt` to `frame`, as handed out by the frame
+    /// allocator.
+    #[allow(clippy::result_unit_err)]
+    fn map(&self, virt: usize, frame: NonNull<u8>) -> Result<(), ()>;
+
+    /// Unmaps the page at `virt`, flushing it from the TLBs, and returns the frame it was mapped to.
+    fn unmap(&self, virt: usize) -> NonNull<u8>;
+}
+
+/// Serves large requests the way `vmalloc` does: with frames gathered one by one from `F`, which
+/// needn't be contiguous, mapped by `M` into a contiguous range of virtual addresses reserved from
+/// a [`WindowAlloc`]. Memory that is too fragmented for a contiguous allocation can still be used
+/// this way, at the cost of a mapping per page.
+///
+/// Every allocation is rounded up to whole pages of `PAGE` bytes, and followed by an unmapped
+/// guard page, so running off its end faults instead of corrupting the next one.
+///
+/// ```ignore
+/// let vmalloc = VmallocAlloc::new(&FRAMES, vmalloc_windows, PageTables);
+/// let table = Vec::<Entry, _>::with_capacity_in(1 << 20, &vmalloc);
+/// ```
+pub struct VmallocAlloc<
+    F: Allocator,
+    M: PageMapper,
+    R: lock_api::RawMutex,
+    const PAGE: usize = 4096,
+> {
+    frames: F,
+    vma: WindowAlloc<R, PAGE>,
+    mapper: M,
+}
+
+impl<F: Allocator, M: PageMapper, R: lock_api::RawMutex, const PAGE: usize>
+    VmallocAlloc<F, M, R, PAGE>
+{
+    const FRAME: Layout = match Layout::from_size_align(PAGE, PAGE) {
+        Ok(layout) => layout,
+        Err(_) => panic!("The page size must be a power of two!"),
+    };
+
+    /// Creates an allocator taking frames from `frames`, and virtual addresses from `vma`.
+    pub fn new(frames: F, vma: WindowAlloc<R, PAGE>, mapper: M) -> Self {
+        VmallocAlloc {
+            frames,
+            vma,
+            mapper,
+        }
+    }
+
+    pub fn frames(&self) -> &F {
+        &self.frames
+    }
+
+    pub fn mapper(&self) -> &M {
+        &self.mapper
+    }
+
+    /// Unmaps the first `pages` pages from `start`, and frees their frames.
+    fn unmap_pages(&self, start: usize, pages: usize) {
+        for page in 0..pages {
+            let frame = self.mapper.unmap(start + page * PAGE);
+            unsafe { self.frames.deallocate(frame, Self::FRAME) };
+        }
+    }
+
+    /// The number of pages mapped for `layout`.
+    fn pages_for(layout: Layout) -> usize {
+        layout.size().max(1).div_ceil(PAGE)
+    }
+}
+
+unsafe impl<F: Allocator, M: PageMapper, R: lock_api::RawMutex, const PAGE: usize> Allocator
+    for VmallocAlloc<F, M, R, PAGE>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let pages = Self::pages_for(layout);
+        let size = pages.checked_add(1).ok_or(AllocError)? * PAGE;
+        let window = self.vma.reserve(size, layout.align().max(PAGE))?;
+
+        for page in 0..pages {
+            let virt = window.start + page * PAGE;
+            let mapped = match self.frames.allocate(Self::FRAME) {
+                Ok(frame) => self
+                    .mapper
+                    .map(virt, frame.cast())
+                    .map_err(|_| unsafe { self.frames.deallocate(frame.cast(), Self::FRAME) }),
+                Err(_) => Err(()),
+            };
+            if mapped.is_err() {
+                self.unmap_pages(window.start, page);
+                self.vma.release(window.start).unwrap();
+                return Err(AllocError);
+            }
+        }
+
+        let ptr = ptr::with_exposed_provenance_mut(window.start);
+        Ok(NonNull::slice_from_raw_parts(
+            NonNull::new(ptr).ok_or(AllocError)?,
+            pages * PAGE,
+        ))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let start = ptr.as_ptr().addr();
+        self.unmap_pages(start, Self::pages_for(layout));
+        self.vma.release(start).unwrap();
+    }
+}
+
+unsafe impl<F: Allocator, M: PageMapper, R: lock_api::RawMutex, const PAGE: usize> GlobalAlloc
+    for VmallocAlloc<F, M, R, PAGE>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::MaybeUninit;
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    const PAGE: usize = 4096;
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    /// Records the mappings, while the virtual range is ordinary memory.
+    #[derive(Default)]
+    struct FakeMapper {
+        pages: Mutex<BTreeMap<usize, NonNull<u8>>>,
+        fail_after: Option<usize>,
+    }
+
+    unsafe impl PageMapper for FakeMapper {
+        fn map(&self, virt: usize, frame: NonNull<u8>) -> Result<(), ()> {
+            let mut pages = self.pages.lock().unwrap();
+            if self.fail_after == Some(pages.len()) {
+                return Err(());
+            }
+            assert!(pages.insert(virt, frame).is_none());
+            Ok(())
+        }
+
+        fn unmap(&self, virt: usize) -> NonNull<u8> {
+            self.pages.lock().unwrap().remove(&virt).unwrap()
+        }
+    }
+
+    fn vma(virt: &mut [u8]) -> WindowAlloc<parking_lot::RawMutex, PAGE> {
+        let range = virt.as_mut_ptr_range();
+        let (start, end) = (
+            range.start.expose_provenance(),
+            range.end.expose_provenance(),
+        );
+        let metadata = vec![MaybeUninit::uninit(); 1024].leak();
+        WindowAlloc::from_static(start..end, metadata)
+    }
+
+    #[test]
+    fn vmalloc() {
+        let frames = Heap::new(64 * 1024);
+        let layout = Layout::from_size_align(64 * PAGE, PAGE).unwrap();
+        let virt = unsafe { std::alloc::alloc(layout) };
+        let virt_range = unsafe { core::slice::from_raw_parts_mut(virt, layout.size()) };
+
+        let vmalloc = VmallocAlloc::new(&*frames, vma(virt_range), FakeMapper::default());
+        let request = Layout::from_size_align(3 * PAGE + 1, 8).unwrap();
+        let mut block = vmalloc.allocate(request).unwrap();
+        assert_eq!(block.len(), 4 * PAGE);
+        unsafe { block.as_mut() }.fill(0xAB);
+        {
+            let pages = vmalloc.mapper().pages.lock().unwrap();
+            assert_eq!(pages.len(), 4);
+            assert!(pages
+                .keys()
+                .zip(pages.keys().skip(1))
+                .all(|(a, b)| b - a == PAGE));
+        }
+
+        // The next allocation starts after the guard page
+        let next = vmalloc.allocate(Layout::new::<u8>()).unwrap();
+        assert_eq!(
+            next.cast::<u8>().as_ptr().addr(),
+            block.cast::<u8>().as_ptr().addr() + 5 * PAGE
+        );
+        unsafe {
+            vmalloc.deallocate(block.cast(), request);
+            vmalloc.deallocate(next.cast(), Layout::new::<u8>());
+        }
+        assert!(vmalloc.mapper().pages.lock().unwrap().is_empty());
+        drop(vmalloc);
+        assert!(frames.is_drained());
+
+        // A failure halfway through gives back everything mapped so far
+        let mapper = FakeMapper {
+            fail_after: Some(2),
+            ..Default::default()
+        };
+        let vmalloc = VmallocAlloc::new(&*frames, vma(virt_range), mapper);
+        assert!(vmalloc.allocate(request).is_err());
+        assert!(vmalloc.mapper().pages.lock().unwrap().is_empty());
+        drop(vmalloc);
+        assert!(frames.is_drained());
+
+        unsafe { std::alloc::dealloc(virt, layout) };
+    }
+}
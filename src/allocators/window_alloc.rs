@@ -0,0 +1,177 @@
+use core::{
+    alloc::{AllocError, Layout},
+    mem::{align_of, MaybeUninit},
+    ops::Range,
+    ptr,
+};
+
+use crate::memory_segmenter::{
+    FitPolicy, OutOfBandSegmenter, SegmentDescriptor, DEFAULT_GRANULARITY,
+};
+
+struct WindowState<const GRANULE: usize> {
+    segmenter: OutOfBandSegmenter<GRANULE>,
+}
+
+// SAFETY: The segmenter owns its metadata region exclusively, and never accesses the addresses it
+// manages, so it may be moved to another thread
+unsafe impl<const GRANULE: usize> Send for WindowState<GRANULE> {}
+
+/// Hands out windows of a range of device addresses, like the MMIO space behind a PCI host bridge
+/// for BARs, or the IO port space. The range isn't memory the allocator may touch, so it keeps its
+/// bookkeeping in a separate metadata region with an [`OutOfBandSegmenter`], and only deals in
+/// addresses rather than pointers. For the same reason, it doesn't implement
+/// [`Allocator`](core::alloc::Allocator).
+///
+/// Each window takes a descriptor of the metadata region, as does each gap between them, which
+/// bounds how many windows can be reserved at once. Window sizes are rounded up to `GRANULE`:
+///
+/// ```ignore
+/// let windows: WindowAlloc<RawSpinlock, 16> =
+///     WindowAlloc::from_static(0xE000_0000..0xF000_0000, &mut METADATA);
+/// // A BAR is aligned to its size
+/// let bar = windows.reserve(0x4000, 0x4000)?;
+/// ```
+pub struct WindowAlloc<R: lock_api::RawMutex, const GRANULE: usize = DEFAULT_GRANULARITY> {
+    state: lock_api::Mutex<R, WindowState<GRANULE>>,
+    range: Range<usize>,
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize> WindowAlloc<R, GRANULE> {
+    /// Creates an allocator handing out windows of `range`, keeping its bookkeeping in the memory
+    /// between `metadata_start` and `metadata_end`.
+    ///
+    /// # Safety
+    /// The metadata region must be valid for reads and writes, and must not be used by anything
+    /// else for the lifetime of the allocator.
+    ///
+    /// # Panics
+    /// Panics if the bounds of `range` aren't multiples of `GRANULE`.
+    pub unsafe fn new(range: Range<usize>, metadata_start: *mut u8, metadata_end: *mut u8) -> Self {
+        assert!(
+            range.start.is_multiple_of(GRANULE) && range.end.is_multiple_of(GRANULE),
+            "The address range isn't aligned to the granularity!"
+        );
+        // The segmenter never dereferences the pointers to the managed range
+        let segmenter = OutOfBandSegmenter::new(
+            ptr::without_provenance_mut(range.start),
+            ptr::without_provenance_mut(range.end),
+            metadata_start,
+            metadata_end,
+        );
+
+        WindowAlloc {
+            state: lock_api::Mutex::new(WindowState { segmenter }),
+            range,
+        }
+    }
+
+    /// Like [`WindowAlloc::new`], but keeping the bookkeeping in `metadata`, which is borrowed
+    /// exclusively for `'static`, so this is safe.
+    pub fn from_static(range: Range<usize>, metadata: &'static mut [MaybeUninit<u8>]) -> Self {
+        let metadata = metadata.as_mut_ptr_range();
+        unsafe { Self::new(range, metadata.start.cast(), metadata.end.cast()) }
+    }
+
+    /// The number of bytes of metadata needed to reserve up to `windows` windows at once, in the
+    /// worst case of every window being surrounded by gaps, and of a misaligned metadata region.
+    pub const fn metadata_size_for(windows: usize) -> usize {
+        OutOfBandSegmenter::<GRANULE>::metadata_size_for(2 * windows + 1)
+            + align_of::<SegmentDescriptor>()
+            - 1
+    }
+
+    /// Reserves the lowest window of `size` bytes aligned to `align` that is free, and returns its
+    /// addresses. Fails if `align` isn't a power of two, or there is no such window left, or no
+    /// descriptor for it.
+    pub fn reserve(&self, size: usize, align: usize) -> Result<Range<usize>, AllocError> {
+        let layout = Layout::from_size_align(size, align).map_err(|_| AllocError)?;
+        let mut state = self.state.lock();
+        let start = state
+            .segmenter
+            .allocate(layout, FitPolicy::FirstFit)
+            .map_err(|_| AllocError)?;
+        let size = state.segmenter.segment_of(start).unwrap().size();
+
+        Ok(start.addr()..start.addr() + size)
+    }
+
+    /// Releases the window starting at `start`, and returns its size. Fails if no window starts
+    /// there.
+    #[allow(clippy::result_unit_err)]
+    pub fn release(&self, start: usize) -> Result<usize, ()> {
+        self.state
+            .lock()
+            .segmenter
+            .deallocate(ptr::without_provenance_mut(start))
+    }
+
+    /// The window `addr` lies in, if it is reserved.
+    pub fn window_of(&self, addr: usize) -> Option<Range<usize>> {
+        let state = self.state.lock();
+        let segments = state.segmenter.segments();
+        let index = segments.partition_point(|segment| segment.start().addr() <= addr);
+        let segment = segments[..index].last()?;
+        let window = segment.start().addr()..segment.end_exclusive().addr();
+
+        (segment.in_use() && window.contains(&addr)).then_some(window)
+    }
+
+    /// The addresses handed out by the allocator.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.state.lock().segmenter.used_bytes()
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.state.lock().segmenter.free_bytes()
+    }
+
+    /// The number of windows currently reserved.
+    pub fn num_windows(&self) -> usize {
+        self.state.lock().segmenter.num_used_segments()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_alloc() {
+        const BASE: usize = 0xE000_0000;
+        let metadata =
+            vec![MaybeUninit::uninit(); WindowAlloc::<parking_lot::RawMutex>::metadata_size_for(4)];
+        let windows: WindowAlloc<parking_lot::RawMutex> =
+            WindowAlloc::from_static(BASE..BASE + 0x10000, metadata.leak());
+
+        // Windows are aligned, and sizes rounded up to the granularity
+        let small = windows.reserve(10, 4).unwrap();
+        assert_eq!(small, BASE..BASE + 16);
+        let bar = windows.reserve(0x4000, 0x4000).unwrap();
+        assert_eq!(bar, BASE + 0x4000..BASE + 0x8000);
+        assert_eq!(windows.window_of(BASE + 0x5000), Some(bar.clone()));
+        assert_eq!(windows.window_of(BASE + 0x100), None);
+        assert_eq!(windows.num_windows(), 2);
+        assert_eq!(windows.used_bytes(), 0x4010);
+
+        // Too large, and out of descriptors
+        assert!(windows.reserve(0x10000, 16).is_err());
+        let mut more = Vec::new();
+        while let Ok(window) = windows.reserve(16, 0x1000) {
+            more.push(window);
+        }
+        assert!(windows.num_windows() >= 4);
+        assert!(windows.free_bytes() > 0);
+
+        for window in more.into_iter().chain([small, bar]) {
+            assert_eq!(windows.release(window.start), Ok(window.len()));
+        }
+        assert_eq!(windows.release(BASE), Err(()));
+        assert_eq!(windows.free_bytes(), 0x10000);
+        assert_eq!(windows.reserve(0x10000, 0x10000), Ok(windows.range()));
+    }
+}
@@ -0,0 +1,294 @@
+use core::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ops::BitOr,
+    ptr::{null_mut, NonNull},
+};
+
+use super::buddy_alloc::BuddyHeap;
+
+/// The most regions of memory each zone can be made up of.
+pub const MAX_REGIONS: usize = 8;
+
+/// A range of physical memory that devices may be restricted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Memory below [`ZoneLimits::dma`], for legacy devices.
+    Dma,
+    /// Memory below [`ZoneLimits::dma32`], for devices with 32 bit addressing.
+    Dma32,
+    Normal,
+}
+
+impl Zone {
+    /// The zones from the lowest to the highest.
+    pub const ALL: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+}
+
+/// Where the zones end, as physical addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneLimits {
+    pub dma: usize,
+    pub dma32: usize,
+}
+
+impl ZoneLimits {
+    /// The limits of the PC: 16 MiB for ISA DMA, and 4 GiB for 32 bit devices.
+    pub const PC: Self = ZoneLimits {
+        dma: 16 << 20,
+        dma32: 0xFFFF_FFFF_usize.saturating_add(1),
+    };
+
+    /// The zone the physical address `addr` lies in.
+    pub fn zone_of(&self, addr: usize) -> Zone {
+        if addr < self.dma {
+            Zone::Dma
+        } else if addr < self.dma32 {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+}
+
+/// The set of zones an allocation may be served from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneFlags(u8);
+
+impl ZoneFlags {
+    pub const DMA: Self = ZoneFlags(1 << Zone::Dma as u8);
+    pub const DMA32: Self = ZoneFlags(1 << Zone::Dma32 as u8);
+    pub const NORMAL: Self = ZoneFlags(1 << Zone::Normal as u8);
+    /// Every zone, as for allocations without constraints.
+    pub const ANY: Self = ZoneFlags(Self::DMA.0 | Self::DMA32.0 | Self::NORMAL.0);
+
+    pub const fn contains(self, zone: Zone) -> bool {
+        self.0 & 1 << zone as u8 != 0
+    }
+}
+
+impl BitOr for ZoneFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        ZoneFlags(self.0 | rhs.0)
+    }
+}
+
+struct Zones<const FRAME: usize> {
+    heaps: [[Option<BuddyHeap<FRAME>>; MAX_REGIONS]; 3],
+}
+
+impl<const FRAME: usize> Zones<FRAME> {
+    fn heaps(&self, zone: Zone) -> impl Iterator<Item = &BuddyHeap<FRAME>> {
+        self.heaps[zone as usize].iter().flatten()
+    }
+}
+
+/// A physical memory manager handing out power of two runs of `FRAME` byte frames, split into the
+/// [`Zone`]s devices may be restricted to. Every zone keeps buddy free lists of its own, and each
+/// request names the zones it can accept, with [`ZoneFlags`]. They are tried from the highest to
+/// the lowest, so the scarce low memory is only used up once nothing else will do.
+///
+/// The manager accesses memory through pointers, at a fixed `phys_offset` above its physical
+/// address, e.g. 0 for identity mapped memory, or the base of a direct map of physical memory.
+/// Like [`BuddyAlloc`](super::buddy_alloc::BuddyAlloc), every region reserves one byte per frame
+/// at its start to track free blocks.
+pub struct ZonedFrameAlloc<R: lock_api::RawMutex, const FRAME: usize = 4096> {
+    zones: lock_api::Mutex<R, Zones<FRAME>>,
+    phys_offset: usize,
+    limits: ZoneLimits,
+}
+
+impl<R: lock_api::RawMutex, const FRAME: usize> ZonedFrameAlloc<R, FRAME> {
+    /// Creates a manager without any memory, see [`ZonedFrameAlloc::add_region`].
+    pub const fn new(phys_offset: usize, limits: ZoneLimits) -> Self {
+        ZonedFrameAlloc {
+            zones: lock_api::Mutex::new(Zones {
+                heaps: [const { [const { None }; MAX_REGIONS] }; 3],
+            }),
+            phys_offset,
+            limits,
+        }
+    }
+
+    /// Hands the memory between `start` and `end` to the manager, splitting it at the zone limits.
+    /// Returns `Err` if a zone it overlaps already has [`MAX_REGIONS`] regions, in which case none
+    /// of it is used.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for
+    /// the lifetime of the manager.
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn add_region(&self, start: *mut u8, end: *mut u8) -> Result<(), ()> {
+        let mut zones = self.zones.lock();
+        let mut pieces = [(start, start); 3];
+        for (zone, piece) in Zone::ALL.into_iter().zip(&mut pieces) {
+            let (low, high) = match zone {
+                Zone::Dma => (0, self.limits.dma),
+                Zone::Dma32 => (self.limits.dma, self.limits.dma32),
+                Zone::Normal => (self.limits.dma32, usize::MAX),
+            };
+            let piece_start = self.phys_addr(start).max(low);
+            let piece_end = self.phys_addr(end).min(high);
+            if piece_start >= piece_end {
+                continue;
+            }
+            if zones.heaps[zone as usize].iter().all(Option::is_some) {
+                return Err(());
+            }
+            let base = self.phys_addr(start);
+            *piece = (start.add(piece_start - base), start.add(piece_end - base));
+        }
+
+        for (zone, (start, end)) in Zone::ALL.into_iter().zip(pieces) {
+            if start != end {
+                let slot = zones.heaps[zone as usize]
+                    .iter_mut()
+                    .find(|heap| heap.is_none());
+                *slot.unwrap() = Some(BuddyHeap::new(start, end, FRAME));
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates frames for `layout` from the highest zone in `zones` that can serve it. Requests
+    /// are rounded up to a power of two number of frames, and aligned to their size.
+    pub fn allocate_in(
+        &self,
+        layout: Layout,
+        zones: ZoneFlags,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let order = BuddyHeap::<FRAME>::order_for(layout).ok_or(AllocError)?;
+        let mut state = self.zones.lock();
+        for zone in Zone::ALL
+            .into_iter()
+            .rev()
+            .filter(|&zone| zones.contains(zone))
+        {
+            for heap in state.heaps[zone as usize].iter_mut().flatten() {
+                if layout.align() > heap.base_align() {
+                    continue;
+                }
+                if let Some(ptr) = heap.allocate(order) {
+                    return Ok(NonNull::slice_from_raw_parts(
+                        NonNull::new(ptr).unwrap(),
+                        BuddyHeap::<FRAME>::block_size(order),
+                    ));
+                }
+            }
+        }
+        Err(AllocError)
+    }
+
+    /// The zone the frames at `ptr` belong to.
+    pub fn zone_of(&self, ptr: NonNull<u8>) -> Zone {
+        self.limits.zone_of(self.phys_addr(ptr.as_ptr()))
+    }
+
+    /// The physical address of the memory at `ptr`.
+    pub fn phys_addr(&self, ptr: *const u8) -> usize {
+        (ptr as usize).wrapping_sub(self.phys_offset)
+    }
+
+    /// The bytes of all free frames of `zone`.
+    pub fn free_bytes(&self, zone: Zone) -> usize {
+        self.zones
+            .lock()
+            .heaps(zone)
+            .map(BuddyHeap::free_bytes)
+            .sum()
+    }
+
+    /// The bytes of all frames of `zone`, not counting the state bytes.
+    pub fn size(&self, zone: Zone) -> usize {
+        self.zones.lock().heaps(zone).map(BuddyHeap::size).sum()
+    }
+}
+
+/// Allocations through [`Allocator`] and [`GlobalAlloc`] accept any zone.
+unsafe impl<R: lock_api::RawMutex, const FRAME: usize> Allocator for ZonedFrameAlloc<R, FRAME> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_in(layout, ZoneFlags::ANY)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let order = BuddyHeap::<FRAME>::order_for(layout).unwrap();
+        let zone = self.zone_of(ptr);
+        let mut state = self.zones.lock();
+        let heap = state.heaps[zone as usize]
+            .iter_mut()
+            .flatten()
+            .find(|heap| heap.contains(ptr.as_ptr()))
+            .expect("Freed frames the manager doesn't own!");
+        heap.deallocate(ptr.as_ptr(), order);
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex, const FRAME: usize> GlobalAlloc for ZonedFrameAlloc<R, FRAME> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr.cast().as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: usize = 4096;
+    const SIZE: usize = 64 * FRAME;
+
+    #[test]
+    fn zoned_frame_alloc() {
+        let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+        let mem = unsafe { std::alloc::alloc(region) };
+        // Pretend the region starts at physical address 0, with zone limits scaled down to it
+        let limits = ZoneLimits {
+            dma: 16 * FRAME,
+            dma32: 32 * FRAME,
+        };
+        let pmm: ZonedFrameAlloc<parking_lot::RawMutex> =
+            ZonedFrameAlloc::new(mem as usize, limits);
+        unsafe { pmm.add_region(mem, mem.add(SIZE)) }.unwrap();
+        for zone in Zone::ALL {
+            assert!(pmm.size(zone) > 0);
+            assert_eq!(pmm.free_bytes(zone), pmm.size(zone));
+        }
+        assert_eq!(pmm.phys_addr(mem), 0);
+
+        // Unconstrained requests come from the highest zone first
+        let frame = Layout::from_size_align(FRAME, FRAME).unwrap();
+        let normal = pmm.allocate(frame).unwrap();
+        assert_eq!(pmm.zone_of(normal.cast()), Zone::Normal);
+        let low = pmm
+            .allocate_in(frame, ZoneFlags::DMA | ZoneFlags::DMA32)
+            .unwrap();
+        assert_eq!(pmm.zone_of(low.cast()), Zone::Dma32);
+        let dma = pmm.allocate_in(frame, ZoneFlags::DMA).unwrap();
+        assert_eq!(pmm.zone_of(dma.cast()), Zone::Dma);
+
+        // Once a zone runs out, requests fall back to lower ones they accept
+        let mut filled = Vec::new();
+        while let Ok(ptr) = pmm.allocate_in(frame, ZoneFlags::NORMAL) {
+            filled.push(ptr);
+        }
+        assert_eq!(pmm.free_bytes(Zone::Normal), 0);
+        let fallback = pmm.allocate(frame).unwrap();
+        assert_eq!(pmm.zone_of(fallback.cast()), Zone::Dma32);
+
+        filled.extend([normal, low, dma, fallback]);
+        for ptr in filled {
+            unsafe { pmm.deallocate(ptr.cast(), frame) };
+        }
+        for zone in Zone::ALL {
+            assert_eq!(pmm.free_bytes(zone), pmm.size(zone));
+        }
+        unsafe { std::alloc::dealloc(mem, region) };
+    }
+}
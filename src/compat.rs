@@ -0,0 +1,203 @@
+//! A stand-in for the `Heap` and `LockedHeap` types of the
+//! [`linked_list_allocator`](https://docs.rs/linked_list_allocator) crate, backed by a
+//! [`MemorySegmenter`], so code written against that crate can switch by changing its imports:
+//!
+//! ```ignore
+//! use allocators::compat::LockedHeap;
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: LockedHeap<RawSpinlock> = LockedHeap::empty();
+//!
+//! unsafe { ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE) };
+//! ```
+//!
+//! Only the core of the API is provided. Heaps can't be extended, and sizes are reported in terms
+//! of the segmenter, so [`Heap::used`] includes the segment metadata.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+    ptr::{null_mut, NonNull},
+};
+
+use crate::memory_segmenter::{FitPolicy, MemorySegmenter, SegmentMetadata, DEFAULT_GRANULARITY};
+
+/// A heap over a single region, handing out memory first fit.
+pub struct Heap {
+    segmenter: MemorySegmenter,
+    bottom: *mut u8,
+    size: usize,
+}
+
+// SAFETY: The heap owns its region exclusively, so it may be moved to another thread
+unsafe impl Send for Heap {}
+
+impl Heap {
+    /// Creates a heap without memory, for which every allocation fails until it is initialized.
+    pub const fn empty() -> Self {
+        Heap {
+            segmenter: MemorySegmenter::empty(),
+            bottom: null_mut(),
+            size: 0,
+        }
+    }
+
+    /// Hands the `heap_size` bytes from `heap_bottom` to the heap. Bounds that aren't aligned to
+    /// the granularity are trimmed.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, and must not be used by anything else for the
+    /// lifetime of the heap. Allocations made before the call must not be used anymore.
+    ///
+    /// # Panics
+    /// Panics if the region is too small to hold a single segment.
+    pub unsafe fn init(&mut self, heap_bottom: *mut u8, heap_size: usize) {
+        let start = heap_bottom.wrapping_add(heap_bottom.align_offset(DEFAULT_GRANULARITY));
+        let end = heap_bottom.wrapping_add(heap_size);
+        let end = end.wrapping_sub(end.addr() % DEFAULT_GRANULARITY);
+        assert!(
+            end.addr().saturating_sub(start.addr()) >= SegmentMetadata::SIZE,
+            "The heap region is too small!"
+        );
+
+        self.segmenter = MemorySegmenter::new(start, end);
+        self.bottom = heap_bottom;
+        self.size = heap_size;
+    }
+
+    /// Creates a heap over the `heap_size` bytes from `heap_bottom`.
+    ///
+    /// # Safety
+    /// See [`Heap::init`].
+    pub unsafe fn new(heap_bottom: *mut u8, heap_size: usize) -> Self {
+        let mut heap = Self::empty();
+        heap.init(heap_bottom, heap_size);
+        heap
+    }
+
+    /// Allocates memory for `layout` from the lowest segment that fits it.
+    #[allow(clippy::result_unit_err)]
+    pub fn allocate_first_fit(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        let fit = self
+            .segmenter
+            .find_fit(layout, FitPolicy::FirstFit)
+            .ok_or(())?;
+        let segment = unsafe { self.segmenter.create_used_segment_at(fit, layout.size()) }?;
+
+        NonNull::new(unsafe { (*segment).alloc_start_ptr() }).ok_or(())
+    }
+
+    /// Frees memory returned by [`Heap::allocate_first_fit`].
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate_first_fit` of this heap with `layout`, and not
+    /// been freed since.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, _layout: Layout) {
+        let segment = SegmentMetadata::from_alloc_ptr(ptr.as_ptr());
+        self.segmenter.delete_used_segment(segment).unwrap();
+    }
+
+    /// The start of the region the heap was initialized with.
+    pub fn bottom(&self) -> *mut u8 {
+        self.bottom
+    }
+
+    /// The size of the region the heap was initialized with.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The end of the region the heap was initialized with.
+    pub fn top(&self) -> *mut u8 {
+        self.bottom.wrapping_add(self.size)
+    }
+
+    pub fn used(&self) -> usize {
+        self.segmenter.used_bytes()
+    }
+
+    pub fn free(&self) -> usize {
+        self.segmenter.free_bytes()
+    }
+}
+
+/// A [`Heap`] behind a lock, usable as the global allocator.
+pub struct LockedHeap<R: lock_api::RawMutex>(lock_api::Mutex<R, Heap>);
+
+impl<R: lock_api::RawMutex> LockedHeap<R> {
+    pub const fn empty() -> Self {
+        LockedHeap(lock_api::Mutex::new(Heap::empty()))
+    }
+
+    /// Creates a locked heap over the `heap_size` bytes from `heap_bottom`.
+    ///
+    /// # Safety
+    /// See [`Heap::init`].
+    pub unsafe fn new(heap_bottom: *mut u8, heap_size: usize) -> Self {
+        LockedHeap(lock_api::Mutex::new(Heap::new(heap_bottom, heap_size)))
+    }
+}
+
+impl<R: lock_api::RawMutex> Deref for LockedHeap<R> {
+    type Target = lock_api::Mutex<R, Heap>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+unsafe impl<R: lock_api::RawMutex> GlobalAlloc for LockedHeap<R> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.lock().allocate_first_fit(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compat_heap() {
+        let region = Layout::from_size_align(4096, 16).unwrap();
+        let bottom = unsafe { std::alloc::alloc(region) };
+
+        let heap: LockedHeap<parking_lot::RawMutex> = LockedHeap::empty();
+        let layout = Layout::new::<[u64; 4]>();
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+        // A misaligned start is trimmed
+        unsafe { heap.lock().init(bottom.add(1), 4095) };
+        assert_eq!(heap.lock().top(), unsafe { bottom.add(4096) });
+
+        let ptrs: Vec<_> = (0..4).map(|_| unsafe { heap.alloc(layout) }).collect();
+        assert!(ptrs.iter().all(|ptr| ptr.align_offset(8) == 0));
+        // First fit hands out the lowest addresses
+        assert!(ptrs.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(heap.lock().used() > 4 * layout.size());
+
+        let mut heap = heap.0.into_inner();
+        let large = Layout::from_size_align(1024, 64).unwrap();
+        let aligned = heap.allocate_first_fit(large).unwrap();
+        assert!(aligned.align_offset(64) == 0);
+        assert!(heap
+            .allocate_first_fit(Layout::new::<[u8; 4096]>())
+            .is_err());
+
+        unsafe {
+            heap.deallocate(aligned, large);
+            for ptr in ptrs {
+                heap.deallocate(NonNull::new(ptr).unwrap(), layout);
+            }
+        }
+        assert_eq!(heap.used(), 0);
+        assert_eq!(heap.free(), 4096 - 16);
+
+        unsafe { std::alloc::dealloc(bottom, region) };
+    }
+}
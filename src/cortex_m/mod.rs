@@ -0,0 +1,130 @@
+//! A ready-made heap for Cortex-M microcontrollers.
+//!
+//! The heap spans the memory between the `__sheap` and `__eheap` symbols, which the linker script
+//! has to provide, and is guarded by a critical section, so it is safe to use from interrupt
+//! handlers, and on multi-core parts or under an RTOS with a fitting `critical-section`
+//! implementation. To use it, enable the `cortex-m` feature, declare the heap, and call [`init`]
+//! once at the top of `main`, before anything allocates:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: CortexMHeap = CortexMHeap::empty();
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!     unsafe { allocators::cortex_m::init(&HEAP) };
+//!     let v = alloc::vec![1, 2, 3];
+//!     // ...
+//! }
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use critical_section::RestoreState;
+
+use crate::allocators::linked_list_allocator::LinkedListAlloc;
+
+/// A lock holding a critical section while it is held, so neither an interrupt handler nor
+/// another core can observe the heap halfway through an operation. The critical section is
+/// provided by whatever `critical-section` implementation the program links in.
+///
+/// Critical sections must be released in the reverse order they were acquired in, so critical
+/// sections entered while the lock is held must be left before it is released.
+pub struct CriticalSectionLock {
+    locked: AtomicBool,
+    // The state to release the critical section with, only accessed while holding it
+    restore: UnsafeCell<RestoreState>,
+}
+
+// SAFETY: The restore state is only accessed by whoever holds the lock
+unsafe impl Sync for CriticalSectionLock {}
+
+unsafe impl lock_api::RawMutex for CriticalSectionLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = CriticalSectionLock {
+        locked: AtomicBool::new(false),
+        restore: UnsafeCell::new(RestoreState::invalid()),
+    };
+
+    type GuardMarker = lock_api::GuardNoSend;
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let restore = unsafe { critical_section::acquire() };
+        if self.locked.swap(true, Ordering::Acquire) {
+            unsafe { critical_section::release(restore) };
+            return false;
+        }
+        unsafe { self.restore.get().write(restore) };
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        let restore = self.restore.get().read();
+        self.locked.store(false, Ordering::Release);
+        critical_section::release(restore);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// A heap guarded by a [`CriticalSectionLock`], to be declared as a static and set up with
+/// [`init`]. It fails every allocation until then.
+pub type CortexMHeap = LinkedListAlloc<CriticalSectionLock>;
+
+extern "C" {
+    static mut __sheap: u8;
+    static mut __eheap: u8;
+}
+
+/// Hands the memory between the `__sheap` and `__eheap` linker symbols to `heap`. The bounds are
+/// shrunk to the granularity if necessary.
+///
+/// # Safety
+/// The linker script must reserve the memory between the symbols for the heap, and nothing else may
+/// use it.
+///
+/// # Panics
+/// Panics if called more than once.
+pub unsafe fn init(heap: &CortexMHeap) {
+    heap.init(&raw mut __sheap, &raw mut __eheap);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::*;
+
+    #[test]
+    fn critical_section_lock() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { std::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let heap = CortexMHeap::empty();
+        unsafe { heap.init(mem, mem.add(SIZE)) };
+
+        let layout = Layout::new::<u64>();
+        let a = heap.allocate(layout).unwrap();
+        unsafe { heap.deallocate(a.cast(), layout) };
+
+        // The lock can't be taken again while it is held, and is free once released
+        let lock = <CriticalSectionLock as lock_api::RawMutex>::INIT;
+        let mutex = lock_api::Mutex::<CriticalSectionLock, _>::const_new(lock, 0);
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert!(!mutex.is_locked());
+        *mutex.lock() += 1;
+        assert_eq!(mutex.into_inner(), 1);
+    }
+}
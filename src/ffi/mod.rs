@@ -0,0 +1,333 @@
+use core::{
+    alloc::{Allocator, Layout},
+    ffi::{c_int, c_void},
+    mem::size_of,
+    ptr::{null_mut, NonNull},
+};
+
+/// The alignment C guarantees for every `malloc`, suitable for any fundamental type.
+pub const MIN_ALIGN: usize = 16;
+
+/// The `errno` values `posix_memalign` reports, as defined by Linux.
+pub const EINVAL: c_int = 22;
+pub const ENOMEM: c_int = 12;
+
+/// Stored right in front of every pointer handed to C, since `free` and `realloc` don't receive the
+/// layout the Rust side needs.
+struct Header {
+    usable_size: usize,
+    align: usize,
+}
+
+impl Header {
+    /// Space reserved in front of the user pointer. It has to keep the user pointer aligned, so it
+    /// is a whole multiple of the alignment.
+    const fn offset_for(align: usize) -> usize {
+        size_of::<Header>().next_multiple_of(align)
+    }
+
+    /// # Safety
+    /// `ptr` must have been returned by one of the allocation functions of this module.
+    unsafe fn of(ptr: *mut c_void) -> *mut Header {
+        ptr.cast::<Header>().sub(1)
+    }
+
+    fn layout(&self) -> Layout {
+        let offset = Self::offset_for(self.align);
+        Layout::from_size_align(offset + self.usable_size, self.align).unwrap()
+    }
+
+    fn base(&self, ptr: *mut c_void) -> NonNull<u8> {
+        NonNull::new(ptr.cast::<u8>().wrapping_sub(Self::offset_for(self.align))).unwrap()
+    }
+}
+
+fn layout_for(size: usize, align: usize) -> Option<Layout> {
+    let align = align.max(MIN_ALIGN);
+    let size = Header::offset_for(align).checked_add(size)?;
+    Layout::from_size_align(size, align).ok()
+}
+
+/// Records the header of a fresh block, returning the pointer to give to C.
+unsafe fn finish(block: NonNull<[u8]>, align: usize) -> *mut c_void {
+    let offset = Header::offset_for(align);
+    let ptr = block.cast::<u8>().as_ptr().add(offset).cast::<c_void>();
+    Header::of(ptr).write(Header {
+        usable_size: block.len() - offset,
+        align,
+    });
+    ptr
+}
+
+/// `aligned_alloc` over `allocator`. Returns null if `align` is not a power of two. Reallocating
+/// the block keeps its alignment.
+pub fn aligned_alloc<A: Allocator>(allocator: &A, align: usize, size: usize) -> *mut c_void {
+    let Some(layout) = layout_for(size, align) else {
+        return null_mut();
+    };
+
+    match allocator.allocate(layout) {
+        Ok(block) => unsafe { finish(block, layout.align()) },
+        Err(_) => null_mut(),
+    }
+}
+
+/// `malloc` over `allocator`.
+pub fn malloc<A: Allocator>(allocator: &A, size: usize) -> *mut c_void {
+    aligned_alloc(allocator, MIN_ALIGN, size)
+}
+
+/// `memalign` over `allocator`, the obsolete spelling of [`aligned_alloc`].
+pub fn memalign<A: Allocator>(allocator: &A, align: usize, size: usize) -> *mut c_void {
+    aligned_alloc(allocator, align, size)
+}
+
+/// `posix_memalign` over `allocator`. Unlike [`aligned_alloc`], `align` must also be a multiple of
+/// the pointer size.
+///
+/// # Safety
+/// `out` must be valid for writes.
+pub unsafe fn posix_memalign<A: Allocator>(
+    allocator: &A,
+    out: *mut *mut c_void,
+    align: usize,
+    size: usize,
+) -> c_int {
+    if !align.is_power_of_two() || !align.is_multiple_of(size_of::<*mut c_void>()) {
+        return EINVAL;
+    }
+
+    let ptr = aligned_alloc(allocator, align, size);
+    if ptr.is_null() {
+        return ENOMEM;
+    }
+    out.write(ptr);
+    0
+}
+
+/// `calloc` over `allocator`.
+pub fn calloc<A: Allocator>(allocator: &A, count: usize, size: usize) -> *mut c_void {
+    let Some(layout) = count
+        .checked_mul(size)
+        .and_then(|size| layout_for(size, MIN_ALIGN))
+    else {
+        return null_mut();
+    };
+
+    match allocator.allocate_zeroed(layout) {
+        Ok(block) => unsafe { finish(block, layout.align()) },
+        Err(_) => null_mut(),
+    }
+}
+
+/// `free` over `allocator`.
+///
+/// # Safety
+/// `ptr` must be null, or have been returned by one of the allocation functions of this module for
+/// the same allocator and not been freed since.
+pub unsafe fn free<A: Allocator>(allocator: &A, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let header = Header::of(ptr).read();
+
+    allocator.deallocate(header.base(ptr), header.layout());
+}
+
+/// `malloc_usable_size`: how many bytes the block can hold, which may exceed what was requested.
+/// Returns 0 for null.
+///
+/// # Safety
+/// See [`free`].
+pub unsafe fn malloc_usable_size(ptr: *mut c_void) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+
+    Header::of(ptr).read().usable_size
+}
+
+/// `realloc` over `allocator`. Requests that still fit the usable size of the block are served in
+/// place, everything else goes through [`Allocator::grow`] or [`Allocator::shrink`], which keep the
+/// contents. A `size` of 0 frees the block.
+///
+/// # Safety
+/// See [`free`].
+pub unsafe fn realloc<A: Allocator>(allocator: &A, ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc(allocator, size);
+    }
+    if size == 0 {
+        free(allocator, ptr);
+        return null_mut();
+    }
+
+    let header = Header::of(ptr).read();
+    let old_layout = header.layout();
+    let Some(new_layout) = layout_for(size, header.align) else {
+        return null_mut();
+    };
+
+    let block = if size > header.usable_size {
+        allocator.grow(header.base(ptr), old_layout, new_layout)
+    } else if size < header.usable_size / 2 {
+        // Only give memory back if it is worth moving the block for
+        allocator.shrink(header.base(ptr), old_layout, new_layout)
+    } else {
+        return ptr;
+    };
+
+    match block {
+        Ok(block) => finish(block, header.align),
+        // The original block is left untouched, as C requires
+        Err(_) => null_mut(),
+    }
+}
+
+/// Exports the C allocation functions (`malloc`, `calloc`, `realloc`, `free`, the aligned variants
+/// and `malloc_usable_size`), serving them from
+/// the given allocator, so that linked in C code shares the Rust heap. The argument must be an
+/// expression evaluating to a `&'static` reference to an [`Allocator`], for example a reference to
+/// a static heap.
+///
+/// Blocks carry a small header recording their layout, so pointers from these functions must never
+/// be passed to the allocator directly, and vice versa.
+#[macro_export]
+macro_rules! export_c_allocator {
+    ($allocator:expr) => {
+        #[no_mangle]
+        pub extern "C" fn malloc(size: usize) -> *mut core::ffi::c_void {
+            $crate::ffi::malloc($allocator, size)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn calloc(count: usize, size: usize) -> *mut core::ffi::c_void {
+            $crate::ffi::calloc($allocator, count, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn realloc(
+            ptr: *mut core::ffi::c_void,
+            size: usize,
+        ) -> *mut core::ffi::c_void {
+            $crate::ffi::realloc($allocator, ptr, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn free(ptr: *mut core::ffi::c_void) {
+            $crate::ffi::free($allocator, ptr)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn aligned_alloc(align: usize, size: usize) -> *mut core::ffi::c_void {
+            $crate::ffi::aligned_alloc($allocator, align, size)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn memalign(align: usize, size: usize) -> *mut core::ffi::c_void {
+            $crate::ffi::memalign($allocator, align, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn posix_memalign(
+            out: *mut *mut core::ffi::c_void,
+            align: usize,
+            size: usize,
+        ) -> core::ffi::c_int {
+            $crate::ffi::posix_memalign($allocator, out, align, size)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn malloc_usable_size(ptr: *mut core::ffi::c_void) -> usize {
+            $crate::ffi::malloc_usable_size(ptr)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+
+    #[test]
+    fn c_allocation() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+        let ptr = malloc(&allocator, 100);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.align_offset(MIN_ALIGN), 0);
+        unsafe { ptr.cast::<u8>().write_bytes(0xAB, 100) };
+        assert!(malloc(&allocator, SIZE).is_null());
+        assert!(calloc(&allocator, usize::MAX, 2).is_null());
+
+        let zeroed = calloc(&allocator, 10, 10).cast::<u8>();
+        assert!((0..100).all(|i| unsafe { zeroed.add(i).read() } == 0));
+
+        unsafe {
+            // Growing keeps the contents
+            let ptr = realloc(&allocator, ptr, 1000).cast::<u8>();
+            assert!((0..100).all(|i| ptr.add(i).read() == 0xAB));
+            // Slight shrinking happens in place
+            let same = realloc(&allocator, ptr.cast(), 900);
+            assert_eq!(same, ptr.cast());
+            let small = realloc(&allocator, same, 10).cast::<u8>();
+            assert!((0..10).all(|i| small.add(i).read() == 0xAB));
+            assert!(realloc(&allocator, small.cast(), SIZE).is_null());
+
+            free(&allocator, null_mut());
+            free(&allocator, zeroed.cast());
+            assert!(realloc(&allocator, small.cast(), 0).is_null());
+            let fresh = realloc(&allocator, null_mut(), 10);
+            assert!(!fresh.is_null());
+            free(&allocator, fresh);
+        }
+
+        // Everything was given back
+        let all = malloc(&allocator, SIZE - 1024);
+        assert!(!all.is_null());
+        unsafe { free(&allocator, all) };
+    }
+
+    #[test]
+    fn c_aligned_allocation() {
+        const SIZE: usize = 64 * 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let allocator: LinkedListAlloc<parking_lot::RawMutex> =
+            unsafe { LinkedListAlloc::new(mem, mem.add(SIZE)) };
+
+        let ptr = aligned_alloc(&allocator, 256, 100);
+        assert_eq!(ptr.align_offset(256), 0);
+        assert!(unsafe { malloc_usable_size(ptr) } >= 100);
+        assert!(aligned_alloc(&allocator, 48, 100).is_null());
+        assert_eq!(unsafe { malloc_usable_size(null_mut()) }, 0);
+
+        // Reallocation keeps the alignment
+        let grown = unsafe { realloc(&allocator, ptr, 5000) };
+        assert_eq!(grown.align_offset(256), 0);
+        assert!(unsafe { malloc_usable_size(grown) } >= 5000);
+
+        let mut out = null_mut();
+        unsafe {
+            assert_eq!(posix_memalign(&allocator, &mut out, 4, 10), EINVAL);
+            assert_eq!(posix_memalign(&allocator, &mut out, 24, 10), EINVAL);
+            assert_eq!(posix_memalign(&allocator, &mut out, 4096, SIZE), ENOMEM);
+            assert!(out.is_null());
+            assert_eq!(posix_memalign(&allocator, &mut out, 4096, 10), 0);
+        }
+        assert_eq!(out.align_offset(4096), 0);
+
+        let small = memalign(&allocator, 1, 1);
+        assert_eq!(small.align_offset(MIN_ALIGN), 0);
+
+        unsafe {
+            free(&allocator, grown);
+            free(&allocator, out);
+            free(&allocator, small);
+        }
+    }
+}
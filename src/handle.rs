@@ -0,0 +1,167 @@
+//! An owned handle to a heap, for collections that would otherwise borrow the allocator and carry
+//! its lifetime into every struct holding them:
+//!
+//! ```ignore
+//! struct Mixer {
+//!     voices: Vec<Voice, HeapHandle<LinkedListAlloc<RawSpinlock>>>,
+//!     effects: Vec<Effect, HeapHandle<LinkedListAlloc<RawSpinlock>>>,
+//! }
+//!
+//! let heap = HeapHandle::from_static(&AUDIO_HEAP);
+//! let mixer = Mixer {
+//!     voices: Vec::new_in(heap.clone()),
+//!     effects: Vec::new_in(heap),
+//! };
+//! ```
+//!
+//! Heaps in statics are shared by reference. With `std`, heaps created at runtime can be shared by
+//! reference count instead, and are dropped with their last handle.
+
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ops::Deref,
+    ptr::NonNull,
+};
+
+#[cfg(any(feature = "std", test))]
+use std::sync::Arc;
+
+/// A cloneable handle forwarding to a shared heap of type `A`.
+pub struct HeapHandle<A: 'static> {
+    heap: Shared<A>,
+}
+
+enum Shared<A: 'static> {
+    Static(&'static A),
+    #[cfg(any(feature = "std", test))]
+    Counted(Arc<A>),
+}
+
+impl<A> HeapHandle<A> {
+    /// Creates a handle to a heap that lives forever.
+    pub const fn from_static(heap: &'static A) -> Self {
+        HeapHandle {
+            heap: Shared::Static(heap),
+        }
+    }
+
+    /// Creates a handle owning `heap`, which is dropped along with the last handle to it.
+    #[cfg(any(feature = "std", test))]
+    pub fn new(heap: A) -> Self {
+        Arc::new(heap).into()
+    }
+
+    /// Whether both handles forward to the same heap.
+    pub fn same_heap(&self, other: &Self) -> bool {
+        core::ptr::eq(&**self, &**other)
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<A> From<Arc<A>> for HeapHandle<A> {
+    fn from(heap: Arc<A>) -> Self {
+        HeapHandle {
+            heap: Shared::Counted(heap),
+        }
+    }
+}
+
+impl<A> Clone for HeapHandle<A> {
+    fn clone(&self) -> Self {
+        let heap = match &self.heap {
+            Shared::Static(heap) => Shared::Static(*heap),
+            #[cfg(any(feature = "std", test))]
+            Shared::Counted(heap) => Shared::Counted(heap.clone()),
+        };
+        HeapHandle { heap }
+    }
+}
+
+impl<A> Deref for HeapHandle<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        match &self.heap {
+            Shared::Static(heap) => heap,
+            #[cfg(any(feature = "std", test))]
+            Shared::Counted(heap) => heap,
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for HeapHandle<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).shrink(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    struct Collections {
+        numbers: Vec<u64, HeapHandle<Heap>>,
+        names: Vec<&'static str, HeapHandle<Heap>>,
+    }
+
+    #[test]
+    fn heap_handle() {
+        let handle = HeapHandle::new(Heap::with_capacity(4096, 16));
+        let mut collections = Collections {
+            numbers: Vec::new_in(handle.clone()),
+            names: Vec::new_in(handle.clone()),
+        };
+        collections.numbers.extend(0..32);
+        collections.names.push("handle");
+        assert!(handle.same_heap(collections.numbers.allocator()));
+        assert_eq!(handle.stats().live_allocations, 2);
+        drop(collections);
+        assert_eq!(handle.used_bytes(), 0);
+
+        let heap: &'static Heap = Box::leak(Box::new(Heap::with_capacity(4096, 16)));
+        let handle = HeapHandle::from_static(heap);
+        let numbers = Vec::<u8, _>::with_capacity_in(100, handle.clone());
+        assert!(!handle.same_heap(&HeapHandle::new(Heap::with_capacity(4096, 16))));
+        assert_eq!(heap.stats().live_allocations, 1);
+        drop(numbers);
+        assert_eq!(heap.used_bytes(), 0);
+    }
+}
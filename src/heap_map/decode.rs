@@ -0,0 +1,248 @@
+use std::{string::String, vec::Vec};
+
+use super::{MAGIC, VERSION};
+
+/// A segment of a decoded [`HeapMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapSegment {
+    /// The offset of the segment from the start of the heap.
+    pub offset: usize,
+    /// The size of the segment, including its metadata.
+    pub size: usize,
+    pub in_use: bool,
+}
+
+/// A map of the segments of a heap, as decoded from a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeapMap {
+    pub seq: u32,
+    /// The size of the heap.
+    pub size: usize,
+    /// The segments, in address order.
+    pub segments: Vec<MapSegment>,
+}
+
+// Why a frame couldn't be decoded
+enum Error {
+    Incomplete,
+    Invalid,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        let byte = *self.bytes.get(self.pos).ok_or(Error::Incomplete)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f)
+                .checked_shl(shift)
+                .filter(|bits| bits >> shift == u64::from(byte & 0x7f))
+                .ok_or(Error::Invalid)?;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Invalid)
+    }
+
+    fn usize(&mut self) -> Result<usize, Error> {
+        self.number()?.try_into().map_err(|_| Error::Invalid)
+    }
+}
+
+impl HeapMap {
+    /// Reads a frame written by [`encode`](super::encode) from the front of `bytes`. Returns the
+    /// map and the number of bytes the frame took, or `None` if `bytes` doesn't start with a
+    /// complete, valid frame.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        Self::parse(bytes).ok()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut reader = Reader { bytes, pos: 0 };
+        for expected in MAGIC {
+            if reader.byte()? != expected {
+                return Err(Error::Invalid);
+            }
+        }
+        if reader.byte()? != VERSION {
+            return Err(Error::Invalid);
+        }
+        let seq = reader.number()?.try_into().map_err(|_| Error::Invalid)?;
+        let size = reader.usize()?;
+        let count = reader.usize()?;
+        // Every segment takes at least a byte of the heap, which rules out most garbled counts
+        // before waiting for segments that will never arrive
+        if count > size {
+            return Err(Error::Invalid);
+        }
+
+        let mut segments = Vec::with_capacity(count.min(bytes.len()));
+        let mut offset = 0usize;
+        for _ in 0..count {
+            let word = reader.number()?;
+            let segment_size = usize::try_from(word >> 1).map_err(|_| Error::Invalid)?;
+            segments.push(MapSegment {
+                offset,
+                size: segment_size,
+                in_use: word & 1 != 0,
+            });
+            offset = offset
+                .checked_add(segment_size)
+                .filter(|&end| end <= size)
+                .ok_or(Error::Invalid)?;
+        }
+
+        let checksum = bytes[MAGIC.len()..reader.pos]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if reader.byte()? != checksum {
+            return Err(Error::Invalid);
+        }
+
+        Ok((
+            HeapMap {
+                seq,
+                size,
+                segments,
+            },
+            reader.pos,
+        ))
+    }
+
+    /// The bytes of all segments in use, including their metadata.
+    pub fn used_bytes(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| segment.in_use)
+            .map(|segment| segment.size)
+            .sum()
+    }
+
+    /// The bytes of all free segments.
+    pub fn free_bytes(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| !segment.in_use)
+            .map(|segment| segment.size)
+            .sum()
+    }
+
+    pub fn largest_free(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| !segment.in_use)
+            .map(|segment| segment.size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The share of the free memory outside of the largest free segment, from 0 for a heap whose
+    /// free memory is all in one piece towards 1 for one where it is scattered in small pieces.
+    pub fn fragmentation(&self) -> f64 {
+        match self.free_bytes() {
+            0 => 0.0,
+            free => 1.0 - self.largest_free() as f64 / free as f64,
+        }
+    }
+
+    /// Draws the heap as a bar of `width` characters, each covering an equal part of the heap:
+    /// `#` for parts entirely in use, `.` for entirely free parts and `:` for parts with both.
+    pub fn render(&self, width: usize) -> String {
+        if self.size == 0 {
+            return String::new();
+        }
+        let mut used = std::vec![0usize; width];
+        let mut covered = std::vec![0usize; width];
+
+        for segment in &self.segments {
+            let mut offset = segment.offset;
+            let end = segment.offset + segment.size;
+            while offset < end {
+                let column = offset * width / self.size;
+                // The first offset of the next column
+                let next = ((column + 1) * self.size).div_ceil(width).min(end);
+                covered[column] += next - offset;
+                if segment.in_use {
+                    used[column] += next - offset;
+                }
+                offset = next;
+            }
+        }
+
+        used.iter()
+            .zip(&covered)
+            .map(|(&used, &covered)| match used {
+                0 => '.',
+                used if used == covered => '#',
+                _ => ':',
+            })
+            .collect()
+    }
+}
+
+/// Picks the frames out of the bytes received from a stream, which may have been joined at any
+/// point and may drop or garble bytes. Whatever doesn't belong to a valid frame is skipped.
+#[derive(Debug, Default)]
+pub struct MapDecoder {
+    buf: Vec<u8>,
+    skipped: usize,
+}
+
+impl MapDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds bytes received from the stream.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete frame, skipping anything in front of it.
+    pub fn next_map(&mut self) -> Option<HeapMap> {
+        loop {
+            let Some(start) = self
+                .buf
+                .windows(MAGIC.len())
+                .position(|bytes| bytes == MAGIC)
+            else {
+                // Keep a partial magic at the end, which the next bytes may complete
+                let keep = usize::from(self.buf.last() == Some(&MAGIC[0]));
+                self.skip(self.buf.len() - keep);
+                return None;
+            };
+            self.skip(start);
+
+            match HeapMap::parse(&self.buf) {
+                Ok((map, len)) => {
+                    self.buf.drain(..len);
+                    return Some(map);
+                }
+                Err(Error::Incomplete) => return None,
+                // Look for the next frame right after the bad magic
+                Err(Error::Invalid) => self.skip(1),
+            }
+        }
+    }
+
+    /// The number of bytes skipped so far, because they didn't belong to a valid frame.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.buf.drain(..len);
+        self.skipped += len;
+    }
+}
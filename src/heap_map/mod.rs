@@ -0,0 +1,258 @@
+//! Compact binary maps of the segments of a heap, for watching fragmentation evolve live on a
+//! host while the target runs. Maps are written to a [`MapSink`] such as an RTT channel or a
+//! UART, either on demand or every few calls of [`HeapMapStream::tick`]:
+//!
+//! ```ignore
+//! static STREAM: Mutex<RefCell<HeapMapStream<RttChannel>>> = ...;
+//!
+//! #[interrupt]
+//! fn TIM2() {
+//!     // Every 10th tick, i.e. at 10 Hz with a 100 Hz timer
+//!     STREAM.borrow(cs).borrow_mut().tick(&HEAP);
+//! }
+//! ```
+//!
+//! Every map is a self-contained frame, so a host can join a stream at any point, and skips frames
+//! that were garbled or cut short. With `std`, [`MapDecoder`] picks the frames out of the raw
+//! bytes received.
+//!
+//! A frame starts with the two bytes of [`MAGIC`] and the format [`VERSION`], followed by the
+//! sequence number of the map, the size of the heap and the number of segments, and then the size
+//! of every segment in address order, shifted left by one with the lowest bit set for segments in
+//! use. All numbers are LEB128 encoded, so small segments take a single byte. A checksum byte, the
+//! wrapping sum of every byte after the magic, ends the frame.
+
+#[cfg(any(feature = "std", test))]
+mod decode;
+#[cfg(any(feature = "std", test))]
+pub use decode::{HeapMap, MapDecoder, MapSegment};
+
+/// Starts every frame.
+pub const MAGIC: [u8; 2] = [0xa5, 0x4d];
+
+/// The version of the frame format.
+pub const VERSION: u8 = 1;
+
+/// Where maps are written to, e.g. an RTT channel or a UART. Heaps are locked while their map is
+/// written, so sinks must not allocate from the heap they are given the map of.
+pub trait MapSink {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Appends every frame.
+#[cfg(any(feature = "std", test))]
+impl MapSink for std::vec::Vec<u8> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+impl<T: MapSink + ?Sized> MapSink for &mut T {
+    fn write(&mut self, bytes: &[u8]) {
+        (**self).write(bytes);
+    }
+}
+
+/// A heap that can write a map of its segments.
+pub trait MappableHeap {
+    /// Writes a frame with a map of the heap to `sink`, numbered `seq`. Rather than waiting for a
+    /// heap that is locked, e.g. when called from an interrupt, nothing is written and `false`
+    /// returned.
+    fn write_map(&self, seq: u32, sink: &mut dyn MapSink) -> bool;
+}
+
+/// Writes a frame for a heap of `size` bytes to `sink`, with the size and whether it is in use of
+/// each of its segments, in address order.
+pub fn encode(
+    sink: &mut dyn MapSink,
+    seq: u32,
+    size: usize,
+    segments: impl ExactSizeIterator<Item = (usize, bool)>,
+) {
+    sink.write(&MAGIC);
+    let mut frame = FrameWriter { sink, checksum: 0 };
+    frame.write(&[VERSION]);
+    frame.number(u64::from(seq));
+    frame.number(size as u64);
+    frame.number(segments.len() as u64);
+    for (size, in_use) in segments {
+        frame.number((size as u64) << 1 | u64::from(in_use));
+    }
+    let checksum = frame.checksum;
+    frame.sink.write(&[checksum]);
+}
+
+struct FrameWriter<'a> {
+    sink: &'a mut dyn MapSink,
+    checksum: u8,
+}
+
+impl FrameWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.checksum = bytes
+            .iter()
+            .fold(self.checksum, |sum, byte| sum.wrapping_add(*byte));
+        self.sink.write(bytes);
+    }
+
+    fn number(&mut self, mut value: u64) {
+        let mut buf = [0; 10];
+        let mut len = 0;
+        loop {
+            buf[len] = value as u8 & 0x7f;
+            value >>= 7;
+            if value == 0 {
+                break;
+            }
+            buf[len] |= 0x80;
+            len += 1;
+        }
+        self.write(&buf[..=len]);
+    }
+}
+
+/// Writes numbered maps of a heap to a sink, on demand or periodically.
+#[derive(Debug)]
+pub struct HeapMapStream<W: MapSink> {
+    sink: W,
+    seq: u32,
+    every: u32,
+    ticks: u32,
+}
+
+impl<W: MapSink> HeapMapStream<W> {
+    /// Creates a stream writing to `sink`, which emits a map on every `every`th call of
+    /// [`HeapMapStream::tick`].
+    pub const fn new(sink: W, every: u32) -> Self {
+        HeapMapStream {
+            sink,
+            seq: 0,
+            every,
+            ticks: 0,
+        }
+    }
+
+    /// Writes a map of `heap` right away. Returns false if the heap was locked, see
+    /// [`MappableHeap::write_map`].
+    pub fn emit(&mut self, heap: &(impl MappableHeap + ?Sized)) -> bool {
+        let written = heap.write_map(self.seq, &mut self.sink);
+        if written {
+            self.seq = self.seq.wrapping_add(1);
+        }
+        written
+    }
+
+    /// Counts a tick, and writes a map of `heap` if it was the `every`th since the last map. A map
+    /// that couldn't be written because the heap was locked is retried on the next tick. Returns
+    /// whether a map was written.
+    pub fn tick(&mut self, heap: &(impl MappableHeap + ?Sized)) -> bool {
+        self.ticks = self.ticks.saturating_add(1);
+        if self.ticks < self.every || !self.emit(heap) {
+            return false;
+        }
+        self.ticks = 0;
+        true
+    }
+
+    /// The number of maps written so far, which is also the sequence number of the next one.
+    pub fn maps_written(&self) -> u32 {
+        self.seq
+    }
+
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    pub fn into_sink(self) -> W {
+        self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+    use core::alloc::{Allocator, Layout};
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    #[test]
+    fn heap_map_encoding() {
+        let segments = [
+            (64, true),
+            (300, false),
+            (4096, true),
+            ((1 << 40) + 16, false),
+        ];
+        let size = segments.iter().map(|(size, _)| size).sum();
+        let mut bytes = Vec::new();
+        encode(&mut bytes, 7, size, segments.into_iter());
+
+        let (map, len) = HeapMap::decode(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!((map.seq, map.size), (7, size));
+        assert_eq!(map.segments[2].offset, 364);
+        assert!(map
+            .segments
+            .iter()
+            .map(|segment| (segment.size, segment.in_use))
+            .eq(segments));
+
+        // Truncated and garbled frames are rejected
+        assert!(HeapMap::decode(&bytes[..len - 1]).is_none());
+        let mut garbled = bytes.clone();
+        garbled[5] ^= 1;
+        assert!(HeapMap::decode(&garbled).is_none());
+    }
+
+    #[test]
+    fn heap_map_stream() {
+        let heap = Heap::with_capacity(4096, 16);
+        heap.set_quick_lists_enabled(false);
+        let mut stream = HeapMapStream::new(Vec::new(), 2);
+        let layout = Layout::new::<[u8; 256]>();
+        let ptrs: Vec<_> = (0..4).map(|_| heap.allocate(layout).unwrap()).collect();
+        unsafe { heap.deallocate(ptrs[1].cast(), layout) };
+
+        // Only every second tick emits a map
+        assert!(!stream.tick(&heap));
+        assert!(stream.tick(&heap));
+        assert!(stream.emit(&heap));
+        assert_eq!(stream.maps_written(), 2);
+
+        // A decoder joining mid-stream skips to the first complete frame, across pushes
+        let mut bytes = vec![0x4d, 0xa5, 0xa5, 1, 2];
+        bytes.extend_from_slice(stream.sink());
+        let mut decoder = MapDecoder::new();
+        let (first, second) = bytes.split_at(bytes.len() - 3);
+        decoder.push(first);
+        let map = decoder.next_map().unwrap();
+        assert!(decoder.next_map().is_none());
+        decoder.push(second);
+        assert_eq!(decoder.next_map().unwrap().seq, 1);
+        assert_eq!(decoder.skipped(), 5);
+
+        assert_eq!(map.seq, 0);
+        let region = heap.region();
+        assert_eq!(map.size, region.end as usize - region.start as usize);
+        assert_eq!(map.free_bytes(), heap.free_bytes());
+        assert_eq!(map.segments.iter().filter(|s| s.in_use).count(), 3);
+        assert!(map.fragmentation() > 0.0);
+        assert_eq!(map.render(16).len(), 16);
+        // 256 bytes per column, with the freed segment and the following ones straddling columns
+        assert_eq!(map.render(16), "#::#:...........");
+
+        // Locked heaps are skipped
+        heap.with_hooks_mut(|_| assert!(!stream.emit(&heap)));
+
+        for (index, ptr) in ptrs.into_iter().enumerate() {
+            if index != 1 {
+                unsafe { heap.deallocate(ptr.cast(), layout) };
+            }
+        }
+    }
+}
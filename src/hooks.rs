@@ -0,0 +1,58 @@
+//! Callbacks around the operations of an allocator.
+//!
+//! Tracing, quotas, telemetry and sanitizers all need to see every operation, but each of them
+//! wrapping the allocator in a whole allocator of its own stacks up locks and indirection. Instead,
+//! a [`LinkedListAlloc`](crate::allocators::linked_list_allocator::LinkedListAlloc) takes an
+//! [`AllocHooks`] implementation, which is called right where the operations take effect:
+//!
+//! ```ignore
+//! let heap = LinkedListAlloc::<RawSpinlock>::from_static(region).with_hooks(Quota::new(64 * 1024));
+//! ```
+//!
+//! Hooks run with the allocator locked, so they see the operations in the order they took effect,
+//! along with statistics consistent with them. They must not use the allocator themselves, see
+//! [`ReentrancyCheck`](crate::reentrancy::ReentrancyCheck) for catching hooks that do.
+
+use core::{alloc::Layout, ptr::NonNull};
+
+use crate::allocators::linked_list_allocator::AllocStats;
+
+/// Callbacks invoked by an allocator on every operation. Every method does nothing by default, so
+/// implementations only override the ones they are interested in.
+///
+/// Stats passed to the hooks already account for the operation, except for
+/// [`AllocHooks::on_dealloc`], which is called before the memory is freed.
+pub trait AllocHooks {
+    /// Called before an allocation or resize to `layout` is attempted. Returning `false` fails the
+    /// request, as if the allocator were out of memory, e.g. to enforce a quota.
+    fn may_allocate(&mut self, _layout: Layout, _stats: &AllocStats) -> bool {
+        true
+    }
+
+    /// Called after `block` was allocated for `layout`.
+    fn on_alloc(&mut self, _layout: Layout, _block: NonNull<[u8]>, _stats: &AllocStats) {}
+
+    /// Called before the allocation at `ptr` is freed, while its contents are still intact.
+    fn on_dealloc(&mut self, _ptr: NonNull<u8>, _layout: Layout, _stats: &AllocStats) {}
+
+    /// Called after the allocation at `old_ptr` was moved to `block` and resized to `new_layout`.
+    fn on_realloc(
+        &mut self,
+        _old_ptr: NonNull<u8>,
+        _old_layout: Layout,
+        _new_layout: Layout,
+        _block: NonNull<[u8]>,
+        _stats: &AllocStats,
+    ) {
+    }
+
+    /// Called after an allocation or resize to `layout` failed, including when
+    /// [`AllocHooks::may_allocate`] refused it.
+    fn on_fail(&mut self, _layout: Layout, _stats: &AllocStats) {}
+}
+
+/// Hooks doing nothing, the default of allocators taking hooks. All calls compile away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoHooks;
+
+impl AllocHooks for NoHooks {}
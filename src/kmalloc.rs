@@ -0,0 +1,185 @@
+//! A `kmalloc`/`kfree` front-end, for code that just needs some bytes and would rather not carry a
+//! [`Layout`] around to free them again:
+//!
+//! ```ignore
+//! let kmalloc = Kmalloc::<_, RawSpinlock>::new(&HEAP, 64);
+//! let buf = kmalloc.kmalloc(len, KmallocFlags::ZEROED).ok_or(ENOMEM)?;
+//! ...
+//! unsafe { kmalloc.kfree(buf.as_ptr()) };
+//! ```
+//!
+//! Requests are served from a [`FreelistAlloc`] cache per power of two size class, and larger ones
+//! straight from the heap. Like with [`ffi`](crate::ffi), the size is stored in a header in front
+//! of every block, so each allocation costs [`KMALLOC_ALIGN`] extra bytes.
+
+use core::{
+    alloc::{Allocator, Layout},
+    ops::BitOr,
+    ptr::NonNull,
+};
+
+use crate::{allocators::freelist_alloc::FreelistAlloc, shrinker::Shrinker};
+
+/// The alignment of every allocation, which is also the size of the header in front of it.
+pub const KMALLOC_ALIGN: usize = 16;
+/// The number of size classes. Class `i` holds blocks of `(2 * KMALLOC_ALIGN) << i` bytes, header
+/// included, so the largest class serves requests of up to 8 KiB minus the header.
+pub const KMALLOC_CLASSES: usize = 9;
+
+/// Modifiers of a [`Kmalloc::kmalloc`] request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KmallocFlags(u8);
+
+impl KmallocFlags {
+    pub const NONE: Self = KmallocFlags(0);
+    /// Zeroes the memory handed out.
+    pub const ZEROED: Self = KmallocFlags(1);
+
+    pub const fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+impl BitOr for KmallocFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        KmallocFlags(self.0 | rhs.0)
+    }
+}
+
+/// The `kmalloc` front-end over `heap`, see the [module documentation](self).
+pub struct Kmalloc<'a, A: Allocator, R: lock_api::RawMutex> {
+    caches: [FreelistAlloc<&'a A, R>; KMALLOC_CLASSES],
+    heap: &'a A,
+}
+
+impl<'a, A: Allocator, R: lock_api::RawMutex> Kmalloc<'a, A, R> {
+    /// Creates the front-end, caching up to `capacity` freed blocks of each size class.
+    pub fn new(heap: &'a A, capacity: usize) -> Self {
+        Kmalloc {
+            caches: core::array::from_fn(|class| {
+                let size = Self::class_size(class);
+                FreelistAlloc::new(heap, size..=size, KMALLOC_ALIGN, capacity)
+            }),
+            heap,
+        }
+    }
+
+    /// Allocates at least `size` bytes aligned to [`KMALLOC_ALIGN`], or returns `None` if the heap
+    /// is exhausted.
+    pub fn kmalloc(&self, size: usize, flags: KmallocFlags) -> Option<NonNull<u8>> {
+        let total = size.checked_add(KMALLOC_ALIGN)?;
+        let class = Self::class_of(total);
+        let block_size = class.map_or(total, Self::class_size);
+        let layout = Layout::from_size_align(block_size, KMALLOC_ALIGN).ok()?;
+        let block = match class {
+            Some(class) => self.caches[class].allocate(layout),
+            None => self.heap.allocate(layout),
+        }
+        .ok()?;
+
+        let block = block.cast::<u8>();
+        unsafe {
+            block.cast::<usize>().write(block_size);
+            let ptr = block.add(KMALLOC_ALIGN);
+            if flags.contains(KmallocFlags::ZEROED) {
+                ptr.write_bytes(0, block_size - KMALLOC_ALIGN);
+            }
+            Some(ptr)
+        }
+    }
+
+    /// Frees memory returned by [`Kmalloc::kmalloc`]. Null pointers are ignored.
+    ///
+    /// # Safety
+    /// `ptr` must be null or have been returned by `kmalloc` of this front-end, and not been freed
+    /// since.
+    pub unsafe fn kfree(&self, ptr: *mut u8) {
+        let Some(ptr) = NonNull::new(ptr) else {
+            return;
+        };
+        let block = ptr.sub(KMALLOC_ALIGN);
+        let block_size = block.cast::<usize>().read();
+        let layout = Layout::from_size_align_unchecked(block_size, KMALLOC_ALIGN);
+        match Self::class_of(block_size) {
+            Some(class) => self.caches[class].deallocate(block, layout),
+            None => self.heap.deallocate(block, layout),
+        }
+    }
+
+    /// The number of bytes usable at `ptr`, which may be more than were requested.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `kmalloc` of this front-end, and not been freed since.
+    pub unsafe fn ksize(&self, ptr: NonNull<u8>) -> usize {
+        ptr.sub(KMALLOC_ALIGN).cast::<usize>().read() - KMALLOC_ALIGN
+    }
+
+    /// The cache of the size class `class`, e.g. for its statistics.
+    pub fn cache(&self, class: usize) -> &FreelistAlloc<&'a A, R> {
+        &self.caches[class]
+    }
+
+    const fn class_size(class: usize) -> usize {
+        (2 * KMALLOC_ALIGN) << class
+    }
+
+    /// The smallest size class holding blocks of `size` bytes, if any does.
+    fn class_of(size: usize) -> Option<usize> {
+        let class = size
+            .max(2 * KMALLOC_ALIGN)
+            .next_power_of_two()
+            .trailing_zeros()
+            - (2 * KMALLOC_ALIGN).trailing_zeros();
+        (size <= Self::class_size(KMALLOC_CLASSES - 1)).then_some(class as usize)
+    }
+}
+
+/// Shrinking flushes the caches of every size class.
+impl<A: Allocator, R: lock_api::RawMutex> Shrinker for Kmalloc<'_, A, R>
+where
+    Self: Sync,
+{
+    fn shrink(&self) -> usize {
+        self.caches.iter().map(FreelistAlloc::flush).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, testing::TestHeap};
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex, 16>>;
+
+    #[test]
+    fn kmalloc() {
+        let heap = Heap::new(64 * 1024);
+        let kmalloc: Kmalloc<_, parking_lot::RawMutex> = Kmalloc::new(&*heap, 4);
+
+        // Small requests are rounded up to their class, large ones only to the alignment
+        let small = kmalloc.kmalloc(16, KmallocFlags::NONE).unwrap();
+        assert_eq!(unsafe { kmalloc.ksize(small) }, 32 - KMALLOC_ALIGN);
+        let large = kmalloc.kmalloc(10_000, KmallocFlags::ZEROED).unwrap();
+        assert_eq!(unsafe { kmalloc.ksize(large) }, 10_000);
+        assert!(large.align_offset(KMALLOC_ALIGN) == 0);
+        let zeroed = unsafe { core::slice::from_raw_parts(large.as_ptr(), 10_000) };
+        assert!(zeroed.iter().all(|&byte| byte == 0));
+
+        // Freed blocks are cached for their class
+        unsafe { kmalloc.kfree(small.as_ptr()) };
+        assert_eq!(kmalloc.cache(0).cached(), 1);
+        let again = kmalloc.kmalloc(1, KmallocFlags::ZEROED).unwrap();
+        assert_eq!(again, small);
+        assert_eq!(unsafe { again.read() }, 0);
+
+        unsafe {
+            kmalloc.kfree(again.as_ptr());
+            kmalloc.kfree(large.as_ptr());
+            kmalloc.kfree(core::ptr::null_mut());
+        }
+        assert_eq!(kmalloc.shrink(), 32);
+        assert!(heap.is_drained());
+    }
+}
@@ -3,4 +3,31 @@
 #![feature(int_roundings)]
 
 pub mod allocators;
+pub mod compat;
+#[cfg(feature = "cortex-m")]
+pub mod cortex_m;
+#[cfg(any(feature = "ffi", test))]
+pub mod ffi;
+pub mod handle;
+#[cfg(any(feature = "heap-map", test))]
+pub mod heap_map;
+pub mod hooks;
+pub mod kmalloc;
 pub mod memory_segmenter;
+pub mod memory_source;
+pub mod migrate;
+#[cfg(any(feature = "std", test))]
+pub mod pprof;
+#[cfg(any(feature = "std", test))]
+pub mod prometheus;
+pub mod reentrancy;
+pub mod shadow_map;
+pub mod shrinker;
+pub mod snapshot;
+#[cfg(any(feature = "std", test))]
+pub mod svg;
+#[cfg(any(feature = "testing", test))]
+pub mod testing;
+pub mod trace;
+#[cfg(feature = "tracy")]
+pub mod tracy;
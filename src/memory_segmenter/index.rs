@@ -0,0 +1,192 @@
+use core::{mem::size_of, ptr::null_mut};
+
+use super::{LinkKey, SegmentFit, SegmentMetadata};
+
+/// Keeps track of the free segments of a [`MemorySegmenter`](super::MemorySegmenter), so fit
+/// searches don't have to walk the whole segment list.
+///
+/// The segmenter inserts every segment that becomes free, and removes free segments before they
+/// are used, resized or merged away, so a segment always has the same size when it is removed as
+/// when it was inserted. Resized free segments are inserted again afterwards.
+pub trait FreeIndex {
+    const EMPTY: Self;
+    /// Whether fit searches go through [`FreeIndex::search`]. Otherwise the segment list is walked
+    /// according to the fit policy, and the index is never updated.
+    const SEARCHES: bool;
+
+    /// # Safety
+    /// `segment` must be a valid free segment that isn't in the index yet.
+    unsafe fn insert(&mut self, segment: *mut SegmentMetadata);
+
+    /// # Safety
+    /// `segment` must have been inserted, and not have been resized since.
+    unsafe fn remove(&mut self, segment: *mut SegmentMetadata);
+
+    /// Returns the first fit `fit` finds among the indexed segments of at least `size` bytes, in
+    /// whatever order suits the index.
+    fn search(
+        &self,
+        size: usize,
+        fit: impl FnMut(&SegmentMetadata) -> Option<SegmentFit>,
+    ) -> Option<SegmentFit>;
+
+    /// Mangles any links the index stores in the free segments with `key` from now on, see
+    /// [`LinkKey`]. Called with the key of the segmenter before any segment is inserted, and again
+    /// whenever it changes.
+    ///
+    /// # Safety
+    /// The indexed segments must still be valid.
+    unsafe fn rekey(&mut self, _key: LinkKey) {}
+}
+
+/// No index at all: every search walks the segment list, honouring the fit policy. Costs nothing
+/// to maintain, which suits small heaps with few segments.
+#[derive(Debug, Default)]
+pub struct LinearIndex;
+
+impl FreeIndex for LinearIndex {
+    const EMPTY: Self = LinearIndex;
+    const SEARCHES: bool = false;
+
+    unsafe fn insert(&mut self, _: *mut SegmentMetadata) {}
+
+    unsafe fn remove(&mut self, _: *mut SegmentMetadata) {}
+
+    fn search(
+        &self,
+        _: usize,
+        _: impl FnMut(&SegmentMetadata) -> Option<SegmentFit>,
+    ) -> Option<SegmentFit> {
+        None
+    }
+}
+
+/// Links of a free segment on its bucket, stored in the segment's payload, mangled with the key of
+/// the index.
+struct BucketLinks {
+    next: *mut SegmentMetadata,
+    prev: *mut SegmentMetadata,
+}
+
+/// Segregated lists of free segments, one per power of two of segment sizes. A search starts at
+/// the bucket of the requested size and takes the first segment that fits, which approximates a
+/// best fit in time proportional to the number of buckets rather than segments. The fit policy is
+/// ignored.
+///
+/// Free segments too small to hold the links in their payload aren't indexed. They can't serve
+/// anything but zero sized requests anyway, and are reused once merged with a neighbour.
+#[derive(Debug)]
+pub struct BucketIndex {
+    heads: [*mut SegmentMetadata; Self::BUCKETS],
+    key: LinkKey,
+}
+
+impl BucketIndex {
+    const BUCKETS: usize = usize::BITS as usize;
+
+    fn bucket(size: usize) -> usize {
+        size.max(1).ilog2() as usize
+    }
+
+    fn links(segment: *mut SegmentMetadata) -> *mut BucketLinks {
+        unsafe { &*segment }.alloc_start_ptr().cast()
+    }
+
+    fn is_indexed(segment: *mut SegmentMetadata) -> bool {
+        unsafe { &*segment }.size_allocable() >= size_of::<BucketLinks>()
+    }
+
+    /// The demangled links of an indexed segment.
+    unsafe fn read_links(&self, segment: *mut SegmentMetadata) -> BucketLinks {
+        let links = Self::links(segment).read();
+        BucketLinks {
+            next: self.key.demangle(links.next),
+            prev: self.key.demangle(links.prev),
+        }
+    }
+
+    unsafe fn set_next(&self, segment: *mut SegmentMetadata, next: *mut SegmentMetadata) {
+        (*Self::links(segment)).next = self.key.mangle(next);
+    }
+
+    unsafe fn set_prev(&self, segment: *mut SegmentMetadata, prev: *mut SegmentMetadata) {
+        (*Self::links(segment)).prev = self.key.mangle(prev);
+    }
+}
+
+impl Default for BucketIndex {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl FreeIndex for BucketIndex {
+    const EMPTY: Self = BucketIndex {
+        heads: [null_mut(); Self::BUCKETS],
+        key: LinkKey::NONE,
+    };
+    const SEARCHES: bool = true;
+
+    unsafe fn insert(&mut self, segment: *mut SegmentMetadata) {
+        if !Self::is_indexed(segment) {
+            return;
+        }
+
+        let bucket = Self::bucket((*segment).size());
+        let head = self.heads[bucket];
+        self.set_next(segment, head);
+        self.set_prev(segment, null_mut());
+        if !head.is_null() {
+            self.set_prev(head, segment);
+        }
+        self.heads[bucket] = segment;
+    }
+
+    unsafe fn remove(&mut self, segment: *mut SegmentMetadata) {
+        if !Self::is_indexed(segment) {
+            return;
+        }
+
+        let links = self.read_links(segment);
+        if links.prev.is_null() {
+            self.heads[Self::bucket((*segment).size())] = links.next;
+        } else {
+            self.set_next(links.prev, links.next);
+        }
+        if !links.next.is_null() {
+            self.set_prev(links.next, links.prev);
+        }
+    }
+
+    fn search(
+        &self,
+        size: usize,
+        mut fit: impl FnMut(&SegmentMetadata) -> Option<SegmentFit>,
+    ) -> Option<SegmentFit> {
+        for &head in &self.heads[Self::bucket(size)..] {
+            let mut segment = head;
+            while let Some(current) = unsafe { segment.as_ref() } {
+                if let Some(fit) = fit(current) {
+                    return Some(fit);
+                }
+                segment = unsafe { self.read_links(segment) }.next;
+            }
+        }
+        None
+    }
+
+    unsafe fn rekey(&mut self, key: LinkKey) {
+        for &head in &self.heads {
+            let mut segment = head;
+            while !segment.is_null() {
+                let links = self.read_links(segment);
+                Self::links(segment).write(BucketLinks {
+                    next: key.mangle(links.next),
+                    prev: key.mangle(links.prev),
+                });
+                segment = links.next;
+            }
+        }
+        self.key = key;
+    }
+}
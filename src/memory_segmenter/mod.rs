@@ -1,42 +1,551 @@
 use bit_field::BitField;
-use core::{fmt::Debug, marker::PhantomData, mem::size_of, ptr::null_mut};
-
-pub struct MemorySegmenter {
+use core::{
+    alloc::Layout, fmt::Debug, marker::PhantomData, mem::size_of, ops::Range, ptr::null_mut,
+};
+
+mod index;
+mod out_of_band;
+pub use index::{BucketIndex, FreeIndex, LinearIndex};
+pub use out_of_band::{OutOfBandSegmenter, SegmentDescriptor};
+
+/// The granularity used when none is specified. Every segment size is a multiple of this, so
+/// it is also the amount of padding a tiny allocation may have to pay for.
+pub const DEFAULT_GRANULARITY: usize = 2 * size_of::<usize>();
+
+/// Manages a region of memory as a list of adjacent segments, each either free or in use. `I`
+/// decides how free segments are found, see [`FreeIndex`].
+///
+/// Segment sizes are multiples of `GRANULE`, which must be a power of two from
+/// [`SegmentMetadata::MIN_GRANULARITY`] up to [`SegmentMetadata::SIZE`]. The low bits of a size
+/// hold flags, and the padding in front of an aligned allocation is only ever a multiple of the
+/// metadata size, so a coarser granularity couldn't be kept. On 64 bit targets without the
+/// features adding metadata fields, that leaves 8 and 16.
+pub struct MemorySegmenter<const GRANULE: usize = DEFAULT_GRANULARITY, I: FreeIndex = LinearIndex> {
     head: *mut SegmentMetadata,
+    tail: *mut SegmentMetadata,
+    // Where the previous allocation was made, for next fit searches
+    rover: *mut SegmentMetadata,
     start: *mut u8,
     end_exclusive: *mut u8,
     num_nodes: usize,
+    num_used: usize,
+    // The total size of the used segments, including their metadata
+    used_bytes: usize,
+    index: I,
+    key: LinkKey,
 }
 
+/// Iterates the segments of a [`MemorySegmenter`] as [`SegmentView`]s.
 pub struct MemorySegmenterIter<'a> {
-    curr_segment: *mut SegmentMetadata,
+    front: *mut SegmentMetadata,
+    back: *mut SegmentMetadata,
+    remaining: usize,
+    base: *mut u8,
+    key: LinkKey,
     phantom: PhantomData<&'a SegmentMetadata>,
 }
 
+pub struct MemorySegmenterIterMut<'a> {
+    front: *mut SegmentMetadata,
+    back: *mut SegmentMetadata,
+    remaining: usize,
+    key: LinkKey,
+    phantom: PhantomData<&'a mut SegmentMetadata>,
+}
+
+/// Iterates only the segments whose in use flag matches `in_use`.
+pub struct MemorySegmenterFilterIter<'a> {
+    inner: MemorySegmenterIter<'a>,
+    in_use: bool,
+    remaining: usize,
+}
+
+/// A read only view of a segment, borrowed from its [`MemorySegmenter`] for as long as the
+/// iterator that yielded it.
+#[derive(Clone, Copy)]
+pub struct SegmentView<'a> {
+    segment: &'a SegmentMetadata,
+    base: *mut u8,
+}
+
+/// A cursor over the segments of a [`MemorySegmenter`], which can safely split and coalesce the
+/// segment it points at while maintaining the segment list invariants.
+pub struct SegmentCursor<'a, const GRANULE: usize = DEFAULT_GRANULARITY, I: FreeIndex = LinearIndex>
+{
+    segmenter: &'a mut MemorySegmenter<GRANULE, I>,
+    current: *mut SegmentMetadata,
+}
+
+/// Decides which free segment [`MemorySegmenter::find_fit`] picks when several could satisfy a
+/// request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitPolicy {
+    /// The lowest addressed segment that fits.
+    FirstFit,
+    /// The highest addressed segment that fits.
+    #[default]
+    LastFit,
+    /// The smallest segment that fits, preferring lower addresses among equal sizes.
+    BestFit,
+    /// The segment the request fits most snugly, i.e. that leaves the least space behind the
+    /// allocation once alignment is taken into account, preferring lower addresses among equally
+    /// good fits. Stops at the first exact fit. Keeping allocations packed towards the bottom of
+    /// the region this way is known to reduce fragmentation in the long run.
+    AddressOrderedBestFit,
+    /// The first segment that fits, resuming the search where the previous allocation was made and
+    /// wrapping around. This keeps scans short when many small allocations are made.
+    NextFit,
+}
+
+/// Decides where in a free segment the used sub-segment is carved out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// At the lowest suitably aligned address. Splitting may leave a free segment in front for the
+    /// alignment padding, and one behind for the rest.
+    #[default]
+    Bottom,
+    /// At the highest suitably aligned address, extending to the end of the free segment. Only the
+    /// free segment itself shrinks, so at most one new segment is created, and none behind it has
+    /// to be updated. Falls back to [`Placement::Bottom`] where the gap in front would be too small
+    /// to hold its own metadata.
+    Top,
+}
+
+/// A free segment chosen to satisfy a request, together with everything required to split it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFit {
+    pub segment: *mut SegmentMetadata,
+    /// Where the user allocation will start once the segment is split.
+    pub alloc_ptr: *mut u8,
+    /// The size of the used sub-segment, including its metadata.
+    pub subsegment_size: usize,
+    /// The alignment the alloc ptr must satisfy.
+    pub align: usize,
+}
+
+/// The first inconsistency [`MemorySegmenter::check_integrity`] found in the segment list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// The back link of the segment doesn't point at the segment in front of it.
+    BrokenLink(*const SegmentMetadata),
+    /// The size of the segment is too small, not a multiple of the granularity, or reaches past
+    /// the end of the region.
+    BadSize(*const SegmentMetadata),
+    /// The segments don't start at the start of the region, or the last one doesn't end at its end.
+    BadBounds,
+    /// The segment counters don't match the list.
+    CountMismatch,
+}
+
+/// The secret a segmenter mangles the links stored in its memory with: the back link of every
+/// segment header, and the links of its [`FreeIndex`]. With the `pointer-mangling` feature, links
+/// are XORed with the secret, so an overflow into a header can't forge a link that survives being
+/// followed without knowing it. Without the feature, links are stored as they are and keys are
+/// empty.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LinkKey {
+    #[cfg(feature = "pointer-mangling")]
+    secret: usize,
+}
+
+impl LinkKey {
+    /// Stores links as they are.
+    pub const NONE: Self = Self::new(0);
+
+    #[cfg_attr(not(feature = "pointer-mangling"), allow(unused_variables))]
+    pub const fn new(secret: usize) -> Self {
+        LinkKey {
+            #[cfg(feature = "pointer-mangling")]
+            secret,
+        }
+    }
+
+    /// The key of a segmenter created over the region at `start` without a key of its own, mixed
+    /// from the addresses of the region and of this crate. It differs between heaps and, with
+    /// address space layout randomization, between runs, but anyone who knows both addresses can
+    /// work it out. Heaps exposed to attackers should get a key from a real source of entropy.
+    pub fn for_region(start: *mut u8) -> Self {
+        static ANCHOR: u8 = 0;
+        let anchor = (&ANCHOR as *const u8).addr();
+        Self::new(
+            (start.addr() ^ anchor.rotate_left(usize::BITS / 2))
+                .wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize),
+        )
+    }
+
+    pub fn mangle<T>(self, ptr: *mut T) -> *mut T {
+        #[cfg(feature = "pointer-mangling")]
+        return ptr.map_addr(|addr| addr ^ self.secret);
+        #[cfg(not(feature = "pointer-mangling"))]
+        ptr
+    }
+
+    pub fn demangle<T>(self, ptr: *mut T) -> *mut T {
+        // XORing twice restores the pointer
+        self.mangle(ptr)
+    }
+}
+
+// Keys are meant to be secret
+impl Debug for LinkKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("LinkKey(..)")
+    }
+}
+
+// With the requested size, a tag or user data recorded the metadata no longer fits in two words,
+// so it is padded to keep alloc ptrs aligned to the default granularity
+#[cfg_attr(
+    any(feature = "requested-size", feature = "tagging", feature = "user-data"),
+    repr(align(16))
+)]
 pub struct SegmentMetadata {
     prev: *mut SegmentMetadata,
     size: usize,
+    #[cfg(feature = "requested-size")]
+    requested_size: usize,
+    #[cfg(feature = "tagging")]
+    tag: u8,
+    #[cfg(feature = "user-data")]
+    user_data: usize,
 }
 
 impl MemorySegmenter {
+    /// Creates a segmenter with the default granularity managing the memory between `start` and
+    /// `end_exclusive` as a single free segment.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
     pub unsafe fn new(start: *mut u8, end_exclusive: *mut u8) -> Self {
+        Self::with_granularity(start, end_exclusive)
+    }
+}
+
+impl<const GRANULE: usize, I: FreeIndex> MemorySegmenter<GRANULE, I> {
+    /// Creates a segmenter that doesn't manage any memory, for which every search fails. Useful as
+    /// a placeholder until the real region is known.
+    pub const fn empty() -> Self {
+        MemorySegmenter {
+            head: null_mut(),
+            tail: null_mut(),
+            rover: null_mut(),
+            start: null_mut(),
+            end_exclusive: null_mut(),
+            num_nodes: 0,
+            num_used: 0,
+            used_bytes: 0,
+            index: I::EMPTY,
+            key: LinkKey::NONE,
+        }
+    }
+
+    /// Creates a segmenter managing the memory between `start` and `end_exclusive` as a single
+    /// free segment, with every segment size quantized to `GRANULE` bytes.
+    ///
+    /// # Safety
+    /// The region must be valid for reads and writes, must not be used by anything else for the
+    /// lifetime of the segmenter, `start` must be suitably aligned for [`SegmentMetadata`], and
+    /// the region size must be a multiple of `GRANULE`.
+    ///
+    /// # Panics
+    /// Panics if `end_exclusive` lies below `start`, i.e. if the region wraps around the end of the
+    /// address space.
+    pub unsafe fn with_granularity(start: *mut u8, end_exclusive: *mut u8) -> Self {
+        Self::with_key(start, end_exclusive, LinkKey::for_region(start))
+    }
+
+    /// Like [`MemorySegmenter::with_granularity`], mangling links with `key`.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
+    pub unsafe fn with_key(start: *mut u8, end_exclusive: *mut u8, key: LinkKey) -> Self {
+        const {
+            assert!(
+                GRANULE.is_power_of_two(),
+                "Granularity must be a power of two!"
+            );
+            // The low bits of the size field are reserved for flags, and every segment header must
+            // land on an address that is suitably aligned for SegmentMetadata
+            assert!(
+                GRANULE >= SegmentMetadata::MIN_GRANULARITY
+                    && GRANULE >= core::mem::align_of::<SegmentMetadata>(),
+                "Granularity is too small for the segment metadata layout!"
+            );
+            // Alignment padding is always at least SegmentMetadata::SIZE bytes, so a coarser
+            // granularity could not be maintained
+            assert!(
+                GRANULE <= SegmentMetadata::SIZE,
+                "Granularity may not exceed the size of the segment metadata!"
+            );
+        }
+
+        // Every segment end must be representable, so the region may reach up to, but not past,
+        // the top of the address space
+        assert!(
+            end_exclusive as usize >= start as usize,
+            "The region wraps around the end of the address space!"
+        );
         let head = start as *mut SegmentMetadata;
 
-        let this = MemorySegmenter {
+        let mut this = MemorySegmenter {
             head,
+            tail: head,
+            rover: head,
             start,
             end_exclusive,
             num_nodes: 1,
+            num_used: 0,
+            used_bytes: 0,
+            index: I::EMPTY,
+            key,
         };
 
-        MemorySegmenter::write_metadata(
+        Self::write_metadata(
             head,
-            SegmentMetadata::new(null_mut(), this.size(), false, false),
+            SegmentMetadata::new(null_mut(), this.size(), false, false, key),
         );
+        this.index.rekey(key);
+        this.index.insert(head);
 
         this
     }
 
+    pub fn link_key(&self) -> LinkKey {
+        self.key
+    }
+
+    /// Mangles every link stored in the region with `key` instead, e.g. one drawn from a source of
+    /// entropy once it becomes available.
+    pub fn rekey(&mut self, key: LinkKey) {
+        let mut prev = null_mut();
+        let mut current = self.head;
+        while let Some(segment) = unsafe { current.as_mut() } {
+            segment.set_prev(prev, key);
+            prev = current;
+            current = segment.next().unwrap_or(null_mut());
+        }
+        unsafe { self.index.rekey(key) };
+        self.key = key;
+    }
+
+    /// Grows the region at its end by `additional` bytes, which are added to the free segment at
+    /// the top, or become one if the last segment is in use.
+    ///
+    /// # Safety
+    /// The `additional` bytes following the region must be valid for reads and writes, and must
+    /// not be used by anything else for the lifetime of the segmenter.
+    ///
+    /// # Panics
+    /// Panics if the segmenter doesn't manage any memory yet, or if `additional` isn't a multiple
+    /// of `GRANULE` able to hold a segment.
+    pub unsafe fn extend(&mut self, additional: usize) {
+        assert!(
+            !self.tail.is_null(),
+            "The segmenter has no region to extend!"
+        );
+        assert!(
+            additional.is_multiple_of(GRANULE) && additional >= SegmentMetadata::SIZE,
+            "The region can't be extended by {additional} bytes!"
+        );
+
+        let tail = Self::read_metadata(self.tail);
+        if tail.in_use() {
+            let segment = self.end_exclusive as *mut SegmentMetadata;
+            Self::write_metadata(
+                segment,
+                SegmentMetadata::new(self.tail, additional, false, false, self.key),
+            );
+            tail.set_next_exists(true);
+            self.tail = segment;
+            self.num_nodes += 1;
+        } else {
+            self.index.remove(self.tail);
+            tail.set_size(tail.size() + additional);
+        }
+        self.index.insert(self.tail);
+        self.end_exclusive = self.end_exclusive.add(additional);
+    }
+
+    /// The size of the sub-segment (including metadata) needed to serve `size` bytes.
+    pub const fn subsegment_size_for(size: usize) -> usize {
+        (size + SegmentMetadata::SIZE).next_multiple_of(GRANULE)
+    }
+
+    /// The alignment the alloc ptr of a sub-segment must satisfy to serve a request aligned to
+    /// `align`.
+    pub const fn alloc_align_for(align: usize) -> usize {
+        if align > GRANULE {
+            align
+        } else {
+            GRANULE
+        }
+    }
+
+    /// Searches for a free segment able to serve `layout`, choosing between candidates according to
+    /// `policy`.
+    pub fn find_fit(&self, layout: Layout, policy: FitPolicy) -> Option<SegmentFit> {
+        self.find_placed_fit(layout, policy, Placement::Bottom)
+    }
+
+    /// Like [`MemorySegmenter::find_fit`], but places the used sub-segment within the chosen
+    /// segment according to `placement`.
+    pub fn find_placed_fit(
+        &self,
+        layout: Layout,
+        policy: FitPolicy,
+        placement: Placement,
+    ) -> Option<SegmentFit> {
+        self.search(layout, policy, placement, null_mut())
+    }
+
+    /// Like [`MemorySegmenter::find_placed_fit`], but only splits the wilderness, the free segment
+    /// at the top of the region, if no other segment fits. This keeps a large contiguous block
+    /// available for big requests, and for trimming the region back.
+    pub fn find_fit_sparing_wilderness(
+        &self,
+        layout: Layout,
+        policy: FitPolicy,
+        placement: Placement,
+    ) -> Option<SegmentFit> {
+        let Some(wilderness) = self.wilderness() else {
+            return self.find_placed_fit(layout, policy, placement);
+        };
+
+        let wilderness_ptr = wilderness.addr().cast_mut();
+        self.search(layout, policy, placement, wilderness_ptr)
+            .or_else(|| {
+                let subsegment_size = Self::subsegment_size_for(layout.size());
+                let align = Self::alloc_align_for(layout.align());
+                (wilderness.size() >= subsegment_size)
+                    .then(|| self.place(wilderness, subsegment_size, align, placement))
+                    .flatten()
+            })
+    }
+
+    /// The free segment at the top of the region, if the last segment is free.
+    pub fn wilderness(&self) -> Option<&SegmentMetadata> {
+        unsafe { self.tail.as_ref() }.filter(|segment| !segment.in_use())
+    }
+
+    /// Searches the free segments other than `skipped` for a fit, see
+    /// [`MemorySegmenter::find_placed_fit`].
+    fn search(
+        &self,
+        layout: Layout,
+        policy: FitPolicy,
+        placement: Placement,
+        skipped: *mut SegmentMetadata,
+    ) -> Option<SegmentFit> {
+        let subsegment_size = Self::subsegment_size_for(layout.size());
+        let align = Self::alloc_align_for(layout.align());
+
+        let fit = |segment: &SegmentMetadata| {
+            if segment.size() < subsegment_size || segment.addr() == skipped {
+                return None;
+            }
+
+            self.place(segment, subsegment_size, align, placement)
+        };
+
+        if I::SEARCHES {
+            return self.index.search(subsegment_size, fit);
+        }
+        match policy {
+            FitPolicy::FirstFit => self.free_segments().find_map(fit),
+            FitPolicy::LastFit => self.free_segments().rev().find_map(fit),
+            FitPolicy::BestFit => self
+                .free_segments()
+                .filter_map(fit)
+                .min_by_key(|fit| unsafe { fit.segment.as_ref() }.unwrap().size()),
+            FitPolicy::AddressOrderedBestFit => {
+                let mut best: Option<(usize, SegmentFit)> = None;
+                for fit in self.free_segments().filter_map(fit) {
+                    let segment = unsafe { fit.segment.as_ref() }.unwrap();
+                    let used_end =
+                        fit.alloc_ptr as usize - SegmentMetadata::SIZE + fit.subsegment_size;
+                    let rest = segment.end_exclusive() as usize - used_end;
+                    // Candidates come in address order, so only strictly better ones replace the
+                    // best so far
+                    if best.is_none_or(|(best_rest, _)| rest < best_rest) {
+                        best = Some((rest, fit));
+                        if rest == 0 {
+                            break;
+                        }
+                    }
+                }
+                best.map(|(_, fit)| fit)
+            }
+            FitPolicy::NextFit => {
+                let rover = unsafe { self.rover.as_ref() }?;
+                let from_rover = core::iter::successors(Some(rover), |segment| {
+                    segment.next().map(|next| unsafe { next.as_ref() }.unwrap())
+                });
+                let before_rover = self
+                    .iter()
+                    .map(|view| view.segment)
+                    .take_while(|segment| segment.addr() != rover.addr());
+
+                from_rover
+                    .chain(before_rover)
+                    .filter(|segment| !segment.in_use())
+                    .find_map(fit)
+            }
+        }
+    }
+
+    /// Picks one of the free segments able to serve `layout` uniformly at random, drawing random
+    /// numbers from `random`. With `randomize_offset`, the alloc ptr is also moved to a random
+    /// aligned position within the segment, rather than as close to its start as possible.
+    pub fn find_random_fit(
+        &self,
+        layout: Layout,
+        mut random: impl FnMut() -> u64,
+        randomize_offset: bool,
+    ) -> Option<SegmentFit> {
+        let subsegment_size = Self::subsegment_size_for(layout.size());
+        let align = Self::alloc_align_for(layout.align());
+
+        // Reservoir sampling, so the free segments only have to be walked once
+        let mut chosen = None;
+        let fits = self.free_segments().filter_map(|segment| {
+            if segment.size() < subsegment_size {
+                return None;
+            }
+            self.calculate_alloc_ptr_with_required_align(segment, subsegment_size, align)
+                .ok()
+                .map(|alloc_ptr| (segment, alloc_ptr))
+        });
+        for (i, candidate) in fits.enumerate() {
+            if random().is_multiple_of(i as u64 + 1) {
+                chosen = Some(candidate);
+            }
+        }
+        let (segment, mut alloc_ptr) = chosen?;
+
+        if randomize_offset {
+            let used_end = alloc_ptr as usize - SegmentMetadata::SIZE + subsegment_size;
+            let slack = segment.end_exclusive() as usize - used_end;
+            let mut shift = (random() % (slack / align + 1) as u64) as usize * align;
+            // A segment that was used from its very start needs room for the metadata of the free
+            // segment left in front
+            if alloc_ptr == segment.alloc_start_ptr() && shift < SegmentMetadata::SIZE {
+                shift = SegmentMetadata::SIZE.next_multiple_of(align);
+                if shift > slack {
+                    shift = 0;
+                }
+            }
+            alloc_ptr = alloc_ptr.wrapping_add(shift);
+        }
+
+        Some(SegmentFit {
+            segment: segment.addr().cast_mut(),
+            alloc_ptr,
+            subsegment_size,
+            align,
+        })
+    }
+
+    #[allow(clippy::result_unit_err)]
     pub fn calculate_alloc_ptr_with_required_align(
         &self,
         segment: &SegmentMetadata,
@@ -52,56 +561,197 @@ impl MemorySegmenter {
             // We also want all sizes to be a multiple of SegmentMetadata::SIZE, to avoid scenarios a small
             // segment to small to fit metadata
             let alloc_bytes = segment.alloc_start_ptr();
-            let alloc_bytes = unsafe {
-                alloc_bytes.add(
-                    alloc_bytes
-                        .align_offset(required_align)
-                        .max(SegmentMetadata::SIZE),
-                )
-            };
-            let new_segment_bytes =
-                unsafe { (alloc_bytes as *mut SegmentMetadata).sub(1) } as *mut u8;
+            let mut align_offset = alloc_bytes.align_offset(required_align);
+            if align_offset < SegmentMetadata::SIZE {
+                // Skip ahead by whole multiples of the alignment until there is room
+                align_offset = (SegmentMetadata::SIZE - align_offset)
+                    .checked_next_multiple_of(required_align)
+                    .and_then(|skip| skip.checked_add(align_offset))
+                    .ok_or(())?;
+            }
+            // The arithmetic is done on addresses, so a huge alignment near the top of the address
+            // space fails the request rather than wrapping around
+            let new_segment_addr = (alloc_bytes as usize)
+                .checked_add(align_offset - SegmentMetadata::SIZE)
+                .ok_or(())?;
             // After applying the proper alignment, it's possible we end up
             // with not enough space to satisfy the request
-            if unsafe { new_segment_bytes.add(subsegment_size) } > segment.end_exclusive() {
-                return Err(());
-            } else {
-                Ok(alloc_bytes)
+            match new_segment_addr.checked_add(subsegment_size) {
+                Some(end) if end <= segment.end_exclusive() as usize => {
+                    Ok(alloc_bytes.wrapping_add(align_offset))
+                }
+                _ => Err(()),
             }
         }
     }
 
+    /// Places a used sub-segment of `subsegment_size` bytes, whose alloc ptr satisfies `align`, in
+    /// `segment` according to `placement`.
+    fn place(
+        &self,
+        segment: &SegmentMetadata,
+        subsegment_size: usize,
+        align: usize,
+        placement: Placement,
+    ) -> Option<SegmentFit> {
+        let top = match placement {
+            Placement::Top => Self::top_alloc_ptr(segment, subsegment_size, align),
+            Placement::Bottom => None,
+        };
+        let (alloc_ptr, subsegment_size) = match top {
+            Some(alloc_ptr) => {
+                // The used sub-segment takes everything up to the end of the free segment
+                let end = segment.end_exclusive() as usize;
+                (
+                    alloc_ptr,
+                    end - (alloc_ptr as usize - SegmentMetadata::SIZE),
+                )
+            }
+            None => (
+                self.calculate_alloc_ptr_with_required_align(segment, subsegment_size, align)
+                    .ok()?,
+                subsegment_size,
+            ),
+        };
+
+        Some(SegmentFit {
+            segment: segment.addr().cast_mut(),
+            alloc_ptr,
+            subsegment_size,
+            align,
+        })
+    }
+
+    /// The highest alloc ptr aligned to `align` in `segment` that leaves room for a used
+    /// sub-segment of `subsegment_size` bytes behind it, and for the metadata of the free segment
+    /// in front of it. `None` if there is no such ptr.
+    fn top_alloc_ptr(
+        segment: &SegmentMetadata,
+        subsegment_size: usize,
+        align: usize,
+    ) -> Option<*mut u8> {
+        let end = segment.end_exclusive() as usize;
+        let alloc_ptr = (end.checked_sub(subsegment_size)? + SegmentMetadata::SIZE) & !(align - 1);
+        let lead = alloc_ptr.checked_sub(segment.alloc_start_ptr() as usize)?;
+        if lead != 0 && (lead < SegmentMetadata::SIZE || !lead.is_multiple_of(GRANULE)) {
+            return None;
+        }
+
+        Some(segment.alloc_start_ptr().wrapping_add(lead))
+    }
+
+    /// Carves a used sub-segment able to hold `size` bytes out of the free `segment`, such that its
+    /// alloc ptr satisfies `align`. The size is rounded up to the granularity and the metadata is
+    /// accounted for internally, so any size and power of two alignment may be requested.
+    ///
+    /// # Safety
+    /// `segment` must point to a valid segment belonging to this segmenter.
+    #[allow(clippy::result_unit_err)]
     pub unsafe fn create_used_segment(
         &mut self,
         segment: *mut SegmentMetadata,
-        subsegment_size: usize,
-        required_align: usize, // alignment of the ALLOC ptr, not the segment
+        size: usize,
+        align: usize, // alignment of the ALLOC ptr, not the segment
     ) -> Result<*mut SegmentMetadata, ()> {
-        let required_alloc_ptr = self.calculate_alloc_ptr_with_required_align(
-            segment.as_ref().unwrap(),
-            subsegment_size,
-            required_align,
-        )?;
+        self.create_placed_used_segment(segment, size, align, Placement::Bottom)
+    }
 
-        let segment_bytes = segment as *mut u8;
-        let segment_mut = segment.as_mut().unwrap();
+    /// Like [`MemorySegmenter::create_used_segment`], but places the used sub-segment according to
+    /// `placement`.
+    ///
+    /// # Safety
+    /// `segment` must point to a valid segment belonging to this segmenter.
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn create_placed_used_segment(
+        &mut self,
+        segment: *mut SegmentMetadata,
+        size: usize,
+        align: usize,
+        placement: Placement,
+    ) -> Result<*mut SegmentMetadata, ()> {
+        if !align.is_power_of_two() || size > isize::MAX as usize - SegmentMetadata::SIZE {
+            return Err(());
+        }
+        let subsegment_size = Self::subsegment_size_for(size);
+        let required_align = Self::alloc_align_for(align);
+
+        let segment_ref = segment.as_ref().unwrap();
+        if segment_ref.size() < subsegment_size {
+            return Err(());
+        }
+        let fit = self
+            .place(segment_ref, subsegment_size, required_align, placement)
+            .ok_or(())?;
+        self.create_used_segment_at(fit, size)
+    }
+
+    /// Carves the used sub-segment described by `fit` out of its free segment, serving `size`
+    /// bytes. The alloc ptr may lie anywhere in the segment, as long as it is aligned and leaves
+    /// room for the metadata of any free segment in front of it.
+    ///
+    /// # Safety
+    /// `fit.segment` must point to a valid segment belonging to this segmenter.
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn create_used_segment_at(
+        &mut self,
+        fit: SegmentFit,
+        size: usize,
+    ) -> Result<*mut SegmentMetadata, ()> {
+        let segment_mut = fit.segment.as_mut().unwrap();
 
         if segment_mut.in_use()
-            || subsegment_size > segment_mut.size()
-            || subsegment_size % SegmentMetadata::SIZE != 0
+            || fit.alloc_ptr.align_offset(fit.align) != 0
+            || fit.subsegment_size < Self::subsegment_size_for(size)
         {
             return Err(());
         }
+        // Everything in front of the alloc ptr that isn't its metadata becomes a free segment
+        let lead = (fit.alloc_ptr as usize)
+            .checked_sub(segment_mut.alloc_start_ptr() as usize)
+            .ok_or(())?;
+        if lead != 0 && (lead < SegmentMetadata::SIZE || !lead.is_multiple_of(GRANULE)) {
+            return Err(());
+        }
+        if lead.checked_add(fit.subsegment_size).ok_or(())? > segment_mut.size() {
+            return Err(());
+        }
+        self.num_used += 1;
+        self.index.remove(fit.segment);
+
+        let used_segment = self.split_segment(fit.segment, fit.subsegment_size, fit.alloc_ptr);
+        self.used_bytes += used_segment.as_ref().unwrap().size();
+        used_segment.as_mut().unwrap().set_container(false);
+        #[cfg(feature = "requested-size")]
+        used_segment.as_mut().unwrap().set_requested_size(size);
+        #[cfg(feature = "tagging")]
+        used_segment.as_mut().unwrap().set_tag(0);
+        #[cfg(feature = "user-data")]
+        used_segment.as_mut().unwrap().set_user_data(0);
+
+        self.rover = used_segment;
+
+        Ok(used_segment)
+    }
+
+    unsafe fn split_segment(
+        &mut self,
+        segment: *mut SegmentMetadata,
+        subsegment_size: usize,
+        required_alloc_ptr: *mut u8,
+    ) -> *mut SegmentMetadata {
+        let segment_bytes = segment as *mut u8;
+        let segment_mut = segment.as_mut().unwrap();
 
         // Can we utilize this segment as is, without having to create a new segment
         // to represent the used subsegment?
         if required_alloc_ptr == segment_mut.alloc_start_ptr() {
             segment_mut.set_in_use(true);
 
-            // Did we use up the entire space of this segment?
-            if segment_mut.size() == subsegment_size {
+            // Did we use up the entire space of this segment? A remainder too small to hold its own
+            // metadata can't become a segment, so it is absorbed into this one instead
+            if segment_mut.size() - subsegment_size < SegmentMetadata::SIZE {
                 // The easiest possible case - we are already done!
-                return Ok(segment);
+                return segment;
             }
 
             // We are truncating this segment, and building a new free segment immediately after...
@@ -110,50 +760,64 @@ impl MemorySegmenter {
             segment_mut.set_size(subsegment_size);
             let next_free_ptr = segment_bytes.add(segment_mut.size()) as *mut SegmentMetadata;
             let next_free_size = old_size - subsegment_size;
-            MemorySegmenter::write_metadata(
+            Self::write_metadata(
                 next_free_ptr,
-                SegmentMetadata::new(segment, next_free_size, false, old_next_exists),
+                SegmentMetadata::new(segment, next_free_size, false, old_next_exists, self.key),
             );
             segment_mut.set_next_exists(true);
+            if !old_next_exists {
+                self.tail = next_free_ptr;
+            }
 
             // Fixup prevs
-            let next_free_mut = MemorySegmenter::read_metadata(next_free_ptr);
-            next_free_mut.set_prev(segment);
-            next_free_mut
-                .next()
-                .and_then(|x| x.as_mut())
-                .and_then(|x| Some(x.set_prev(next_free_ptr)));
+            let next_free_mut = Self::read_metadata(next_free_ptr);
+            next_free_mut.set_prev(segment, self.key);
+            if let Some(next) = next_free_mut.next().and_then(|x| x.as_mut()) {
+                next.set_prev(next_free_ptr, self.key);
+            }
 
             self.num_nodes += 1;
-            return Ok(segment);
+            self.index.insert(next_free_ptr);
+            return segment;
         }
 
         let new_segment_bytes = required_alloc_ptr.sub(SegmentMetadata::SIZE);
 
         let new_segment_metadata_ptr = new_segment_bytes as *mut SegmentMetadata;
-        MemorySegmenter::write_metadata(
+        Self::write_metadata(
             new_segment_metadata_ptr,
-            SegmentMetadata::new(segment, subsegment_size, true, false),
+            SegmentMetadata::new(segment, subsegment_size, true, false, self.key),
         );
         self.num_nodes += 1;
         let new_segment_mut = new_segment_metadata_ptr.as_mut().unwrap();
 
         // Do we need to construct a new trailing segment?
-        let trailing_segment = if segment_mut.end_exclusive() != new_segment_mut.end_exclusive() {
+        let trailing_size =
+            segment_mut.end_exclusive() as usize - new_segment_mut.end_exclusive() as usize;
+        let trailing_segment = if trailing_size >= SegmentMetadata::SIZE {
             // If not, we have to create a new trailing free segment
             let new_next_ptr = new_segment_mut.end_exclusive() as *mut SegmentMetadata;
             let new_next_size = segment_mut.end_exclusive() as usize - new_next_ptr as usize;
-            MemorySegmenter::write_metadata(
+            Self::write_metadata(
                 new_next_ptr,
-                SegmentMetadata::new(new_segment_metadata_ptr, new_next_size, false, false),
+                SegmentMetadata::new(
+                    new_segment_metadata_ptr,
+                    new_next_size,
+                    false,
+                    false,
+                    self.key,
+                ),
             );
-            let new_next_mut = MemorySegmenter::read_metadata(new_next_ptr);
+            let new_next_mut = Self::read_metadata(new_next_ptr);
             new_next_mut.set_next_exists(segment_mut.next_exists());
             new_segment_mut.set_next_exists(true);
 
             self.num_nodes += 1;
+            self.index.insert(new_next_ptr);
             new_next_ptr
         } else {
+            // Absorb any remainder too small to hold its own metadata
+            new_segment_mut.set_size(subsegment_size + trailing_size);
             new_segment_mut.set_next_exists(segment_mut.next_exists());
             new_segment_metadata_ptr
         };
@@ -161,16 +825,25 @@ impl MemorySegmenter {
         // Fix up prev's next if it exists
         if let Some(next) = segment_mut.next() {
             let next_mut = next.as_mut().unwrap();
-            next_mut.set_prev(trailing_segment);
+            next_mut.set_prev(trailing_segment, self.key);
+        } else {
+            self.tail = trailing_segment;
         }
 
         // Fixup the size of the prev node
         segment_mut.set_size(new_segment_bytes as usize - segment_mut.addr() as usize);
         segment_mut.set_next_exists(true);
+        self.index.insert(segment);
 
-        Ok(new_segment_metadata_ptr)
+        new_segment_metadata_ptr
     }
 
+    /// Marks a used segment as free again, coalescing it with its free neighbours. Returns the
+    /// resulting free segment.
+    ///
+    /// # Safety
+    /// `segment` must point to a valid segment belonging to this segmenter.
+    #[allow(clippy::result_unit_err)]
     pub unsafe fn delete_used_segment(
         &mut self,
         segment: *mut SegmentMetadata,
@@ -180,23 +853,35 @@ impl MemorySegmenter {
         if !segment_mut.in_use() {
             return Err(());
         }
+        // A corrupted or forged back link would have the coalescing below write wherever it points
+        let prev = segment_mut.prev(self.key);
+        if !prev.is_null()
+            && (!self.contains(prev.cast())
+                || !prev.is_aligned()
+                || (*prev).end_exclusive() != segment.cast())
+        {
+            return Err(());
+        }
+        self.num_used -= 1;
+        self.used_bytes -= segment_mut.size();
 
         // Handle the special case that this is the very first segment
-        if segment_mut.prev() == null_mut() {
+        let freed = if prev.is_null() {
             // Does it have a next?
             if let Some(next) = segment_mut.next() {
                 let next_mut = next.as_mut().unwrap();
                 // Can the next be coalesced?
                 if !next_mut.in_use() {
                     // Coalesce next_mut into segment_mut
+                    self.index.remove(next);
                     segment_mut.set_next_exists(next_mut.next_exists());
                     segment_mut.set_size(segment_mut.size() + next_mut.size());
                     self.num_nodes -= 1;
 
                     // Fix up the new next, if necessary
-                    segment_mut
-                        .next()
-                        .and_then(|x| Some(x.as_mut().unwrap().set_prev(segment)));
+                    if let Some(next) = segment_mut.next() {
+                        next.as_mut().unwrap().set_prev(segment, self.key);
+                    }
                 } else {
                     // No coalescing can be done....
                 }
@@ -204,117 +889,582 @@ impl MemorySegmenter {
                 // This is the only segment that exists...no coalescing needed
             }
             segment_mut.set_in_use(false);
-            Ok(segment)
+            segment
         }
         // Handle the special case that this is the very last segment
         else if !segment_mut.next_exists() {
-            let prev_mut = segment_mut.prev().as_mut().unwrap();
+            let prev_mut = prev.as_mut().unwrap();
             // Can prev be coalesced?
             if !prev_mut.in_use() {
                 // Coalesce prev into segment
+                self.index.remove(prev);
                 prev_mut.set_next_exists(segment_mut.next_exists());
                 prev_mut.set_size(prev_mut.size() + segment_mut.size());
                 self.num_nodes -= 1;
 
                 // Fixup new next, if necessary
-                prev_mut
-                    .next()
-                    .and_then(|x| Some(x.as_mut().unwrap().set_prev(prev_mut)));
+                if let Some(next) = prev_mut.next() {
+                    next.as_mut().unwrap().set_prev(prev_mut, self.key);
+                }
+
+                prev_mut.addr().cast_mut()
+            } else {
+                segment_mut.set_in_use(false);
+                segment
+            }
+        }
+        // The general case...this is a middle node
+        else {
+            let prev_mut = prev.as_mut().unwrap();
+            let next_mut = segment_mut.next().unwrap().as_mut().unwrap();
+
+            if !prev_mut.in_use() && !next_mut.in_use() {
+                // Coalesce prev with curr and next
+                self.index.remove(prev);
+                self.index.remove(next_mut);
+                prev_mut.set_next_exists(next_mut.next_exists());
+                prev_mut.set_size(prev_mut.size() + segment_mut.size() + next_mut.size());
+                self.num_nodes -= 2;
+
+                // Fixup new next, if necessary
+                if let Some(next) = prev_mut.next() {
+                    next.as_mut().unwrap().set_prev(prev_mut, self.key);
+                }
+
+                prev_mut.addr().cast_mut()
+            } else if !prev_mut.in_use() {
+                // coalesce curr with just prev
+                self.index.remove(prev);
+                prev_mut.set_next_exists(true);
+                prev_mut.set_size(prev_mut.size() + segment_mut.size());
+                self.num_nodes -= 1;
+
+                // Fixup new next
+                next_mut.set_prev(prev_mut.addr().cast_mut(), self.key);
+
+                prev_mut.addr().cast_mut()
+            } else if !next_mut.in_use() {
+                // coalesce curr with just next
+                self.index.remove(next_mut);
+                segment_mut.set_next_exists(next_mut.next_exists());
+                segment_mut.set_size(segment_mut.size() + next_mut.size());
+                self.num_nodes -= 1;
+
+                // Fixup new next, if necessary
+                if let Some(next) = segment_mut.next() {
+                    next.as_mut().unwrap().set_prev(segment_mut, self.key);
+                }
+
+                segment_mut.set_in_use(false);
+                segment
+            } else {
+                // No coalescing can be done at all...
+                segment_mut.set_in_use(false);
+                segment
+            }
+        };
+
+        self.index.insert(freed);
+        let freed_ref = freed.as_ref().unwrap();
+        if !freed_ref.next_exists() {
+            self.tail = freed;
+        }
+        // The rover may have pointed at a segment that was just coalesced away
+        let rover_bytes = self.rover as *mut u8;
+        if rover_bytes > freed as *mut u8 && rover_bytes < freed_ref.end_exclusive() {
+            self.rover = freed;
+        }
+        Ok(freed)
+    }
+
+    /// Marks a used segment as free without coalescing it with its neighbours, which is left to a
+    /// later [`MemorySegmenter::coalesce_all`]. Until then, free segments may be adjacent.
+    ///
+    /// # Safety
+    /// `segment` must point to a valid segment belonging to this segmenter.
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn release_used_segment(&mut self, segment: *mut SegmentMetadata) -> Result<(), ()> {
+        let segment_mut = segment.as_mut().unwrap();
+
+        if !segment_mut.in_use() {
+            return Err(());
+        }
+        self.num_used -= 1;
+        self.used_bytes -= segment_mut.size();
+        segment_mut.set_in_use(false);
+        self.index.insert(segment);
+
+        Ok(())
+    }
+
+    /// Coalesces every run of adjacent free segments into a single segment. Returns the number of
+    /// segments that were merged away.
+    pub fn coalesce_all(&mut self) -> usize {
+        let mut merged = 0;
+        let mut current = self.head;
+
+        while let Some(next) = unsafe { current.as_ref() }.and_then(|current| current.next()) {
+            let current_mut = unsafe { Self::read_metadata(current) };
+            let next_mut = unsafe { Self::read_metadata(next) };
+            if current_mut.in_use() || next_mut.in_use() {
+                current = next;
+                continue;
+            }
+
+            // Coalesce next into current, staying on current in case the one after is free too
+            unsafe {
+                self.index.remove(current);
+                self.index.remove(next);
+            }
+            current_mut.set_next_exists(next_mut.next_exists());
+            current_mut.set_size(current_mut.size() + next_mut.size());
+            match current_mut.next() {
+                Some(new_next) => {
+                    unsafe { Self::read_metadata(new_next) }.set_prev(current, self.key)
+                }
+                None => self.tail = current,
+            }
+            if self.rover == next {
+                self.rover = current;
+            }
+            unsafe { self.index.insert(current) };
+            merged += 1;
+        }
+
+        self.num_nodes -= merged;
+        merged
+    }
+
+    /// Walks the whole segment list, following the sizes rather than trusting the counters, and
+    /// checks that the segments tile the region and link back to each other.
+    pub fn check_integrity(&self) -> Result<(), IntegrityError> {
+        if self.num_nodes == 0 {
+            return Ok(());
+        }
+        if self.head as *mut u8 != self.start {
+            return Err(IntegrityError::BadBounds);
+        }
+
+        let (mut nodes, mut used, mut used_bytes) = (0, 0, 0);
+        let mut prev = null_mut();
+        let mut current = self.head;
+        loop {
+            let segment = unsafe { &*current };
+            if segment.prev(self.key) != prev {
+                return Err(IntegrityError::BrokenLink(current));
+            }
+            let size = segment.size();
+            if size < SegmentMetadata::SIZE
+                || !size.is_multiple_of(GRANULE)
+                || size > self.end_exclusive as usize - current as usize
+            {
+                return Err(IntegrityError::BadSize(current));
+            }
+
+            nodes += 1;
+            if segment.in_use() {
+                used += 1;
+                used_bytes += size;
+            }
+            match segment.next() {
+                Some(next) => (prev, current) = (current, next),
+                None => break,
             }
+        }
+
+        if unsafe { &*current }.end_exclusive() != self.end_exclusive || current != self.tail {
+            return Err(IntegrityError::BadBounds);
+        }
+        if nodes != self.num_nodes || used != self.num_used || used_bytes != self.used_bytes {
+            return Err(IntegrityError::CountMismatch);
+        }
+        Ok(())
+    }
+
+    /// The usable size of the largest free segment, or 0 if there is none. A request for at most
+    /// this many bytes with no more than `GRANULE` alignment is bound to fit.
+    pub fn largest_free_segment(&self) -> usize {
+        self.iter_free()
+            .map(|segment| segment.usable_size())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn overhead(&self) -> usize {
+        self.num_nodes * SegmentMetadata::SIZE
+    }
+
+    pub fn size(&self) -> usize {
+        self.end_exclusive as usize - self.start as usize
+    }
+
+    /// The memory managed by the segmenter, which is empty before it is initialized.
+    pub fn region(&self) -> Range<*mut u8> {
+        self.start..self.end_exclusive
+    }
+
+    /// Whether `ptr` lies in the memory managed by the segmenter.
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.region().contains(&ptr.cast_mut())
+    }
+
+    /// The total size of the used segments, including their metadata. Kept up to date as segments
+    /// are created and freed, so this doesn't walk the list.
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    /// The total size of the free segments, including their metadata.
+    pub fn free_bytes(&self) -> usize {
+        self.size() - self.used_bytes
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.num_nodes
+    }
+
+    pub fn num_used_segments(&self) -> usize {
+        self.num_used
+    }
+
+    pub fn num_free_segments(&self) -> usize {
+        self.num_nodes - self.num_used
+    }
+
+    /// Iterates all segments in address order. The iterator is double ended, so the segments can
+    /// also be walked from the top of the region down.
+    pub fn iter(&self) -> MemorySegmenterIter<'_> {
+        MemorySegmenterIter {
+            front: self.head,
+            back: self.tail,
+            remaining: self.num_nodes,
+            base: self.start,
+            key: self.key,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Like [`MemorySegmenter::iter`], but yields mutable references to the raw metadata, the
+    /// mutable counterpart of [`SegmentView::metadata`].
+    ///
+    /// # Safety
+    /// The segments must be left consistent with each other: their sizes must still tile the
+    /// region, and their flags and links must still describe the list. The segmenter writes
+    /// through the metadata, e.g. when splitting or coalescing segments, trusting it to be valid.
+    pub unsafe fn iter_mut(&mut self) -> MemorySegmenterIterMut<'_> {
+        MemorySegmenterIterMut {
+            front: self.head,
+            back: self.tail,
+            remaining: self.num_nodes,
+            key: self.key,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Iterates only the free segments, in address order.
+    pub fn iter_free(&self) -> MemorySegmenterFilterIter<'_> {
+        MemorySegmenterFilterIter {
+            inner: self.iter(),
+            in_use: false,
+            remaining: self.num_free_segments(),
+        }
+    }
+
+    /// Iterates only the used segments, in address order.
+    pub fn iter_used(&self) -> MemorySegmenterFilterIter<'_> {
+        MemorySegmenterFilterIter {
+            inner: self.iter(),
+            in_use: true,
+            remaining: self.num_used_segments(),
+        }
+    }
+
+    /// The metadata of the free segments, for the searches.
+    fn free_segments(&self) -> impl DoubleEndedIterator<Item = &SegmentMetadata> {
+        self.iter_free().map(|view| view.segment)
+    }
+
+    /// Returns a cursor pointing at the first segment.
+    pub fn cursor_front(&mut self) -> SegmentCursor<'_, GRANULE, I> {
+        SegmentCursor {
+            current: self.head,
+            segmenter: self,
+        }
+    }
+
+    /// Returns a cursor pointing at the last segment.
+    pub fn cursor_back(&mut self) -> SegmentCursor<'_, GRANULE, I> {
+        SegmentCursor {
+            current: self.tail,
+            segmenter: self,
+        }
+    }
+
+    /// Returns a cursor pointing at `segment`.
+    ///
+    /// # Safety
+    /// `segment` must point to a valid segment belonging to this segmenter.
+    pub unsafe fn cursor_at(
+        &mut self,
+        segment: *mut SegmentMetadata,
+    ) -> SegmentCursor<'_, GRANULE, I> {
+        SegmentCursor {
+            current: segment,
+            segmenter: self,
+        }
+    }
+
+    unsafe fn write_metadata(dest: *mut SegmentMetadata, src: SegmentMetadata) {
+        core::ptr::write(dest, src);
+    }
+
+    unsafe fn read_metadata(src: *mut SegmentMetadata) -> &'static mut SegmentMetadata {
+        src.as_mut().unwrap()
+    }
+}
+
+impl<const GRANULE: usize, I: FreeIndex> Debug for MemorySegmenter<GRANULE, I> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for segment in self.iter() {
+            write!(f, "{:?}", segment)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for MemorySegmenterIter<'a> {
+    type Item = SegmentView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let item = unsafe { self.front.as_ref() }?;
+
+        self.front = item.next().unwrap_or(null_mut());
+        self.remaining -= 1;
+        Some(SegmentView {
+            segment: item,
+            base: self.base,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for MemorySegmenterIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let item = unsafe { self.back.as_ref() }?;
+
+        self.back = item.prev(self.key);
+        self.remaining -= 1;
+        Some(SegmentView {
+            segment: item,
+            base: self.base,
+        })
+    }
+}
+
+impl ExactSizeIterator for MemorySegmenterIter<'_> {}
+
+impl<'a> Iterator for MemorySegmenterIterMut<'a> {
+    type Item = &'a mut SegmentMetadata;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let item = unsafe { self.front.as_mut() }?;
+
+        self.front = item.next().unwrap_or(null_mut());
+        self.remaining -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for MemorySegmenterIterMut<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let item = unsafe { self.back.as_mut() }?;
+
+        self.back = item.prev(self.key);
+        self.remaining -= 1;
+        Some(item)
+    }
+}
+
+impl ExactSizeIterator for MemorySegmenterIterMut<'_> {}
+
+impl<'a> Iterator for MemorySegmenterFilterIter<'a> {
+    type Item = SegmentView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // No need to walk the rest of the list once every matching segment has been seen
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let in_use = self.in_use;
+        let item = self.inner.find(|segment| segment.in_use() == in_use)?;
+        self.remaining -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for MemorySegmenterFilterIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let in_use = self.in_use;
+        let item = self.inner.rfind(|segment| segment.in_use() == in_use)?;
+        self.remaining -= 1;
+        Some(item)
+    }
+}
 
-            prev_mut.set_in_use(false);
-            Ok(prev_mut.addr().cast_mut())
-        }
-        // The general case...this is a middle node
-        else {
-            let prev_mut = segment_mut.prev().as_mut().unwrap();
-            let next_mut = segment_mut.next().unwrap().as_mut().unwrap();
+impl ExactSizeIterator for MemorySegmenterFilterIter<'_> {}
 
-            if !prev_mut.in_use() && !next_mut.in_use() {
-                // Coalesce prev with curr and next
-                prev_mut.set_next_exists(next_mut.next_exists());
-                prev_mut.set_size(prev_mut.size() + segment_mut.size() + next_mut.size());
-                self.num_nodes -= 2;
+impl<'a> SegmentView<'a> {
+    /// The address of the segment, i.e. of its metadata.
+    pub fn addr(&self) -> *const SegmentMetadata {
+        self.segment.addr()
+    }
 
-                // Fixup new next, if necessary
-                prev_mut
-                    .next()
-                    .and_then(|x| Some(x.as_mut().unwrap().set_prev(prev_mut)));
+    /// The offset of the segment from the start of the region.
+    pub fn offset(&self) -> usize {
+        self.segment.addr() as usize - self.base as usize
+    }
 
-                Ok(prev_mut.addr().cast_mut())
-            } else if !prev_mut.in_use() {
-                // coalesce curr with just prev
-                prev_mut.set_next_exists(true);
-                prev_mut.set_size(prev_mut.size() + segment_mut.size());
-                self.num_nodes -= 1;
+    /// The size of the segment, including its metadata.
+    pub fn size(&self) -> usize {
+        self.segment.size()
+    }
 
-                // Fixup new next
-                next_mut.set_prev(prev_mut.addr().cast_mut());
+    pub fn end_exclusive(&self) -> *mut u8 {
+        self.segment.end_exclusive()
+    }
 
-                Ok(prev_mut.addr().cast_mut())
-            } else if !next_mut.in_use() {
-                // coalesce curr with just next
-                segment_mut.set_next_exists(next_mut.next_exists());
-                segment_mut.set_size(segment_mut.size() + next_mut.size());
-                self.num_nodes -= 1;
+    pub fn in_use(&self) -> bool {
+        self.segment.in_use()
+    }
 
-                // Fixup new next, if necessary
-                segment_mut
-                    .next()
-                    .and_then(|x| Some(x.as_mut().unwrap().set_prev(segment_mut)));
+    /// Whether the segment holds a slab of smaller allocations rather than a single one.
+    pub fn is_container(&self) -> bool {
+        self.segment.is_container()
+    }
 
-                segment_mut.set_in_use(false);
-                Ok(segment)
-            } else {
-                // No coalescing can be done at all...
-                segment_mut.set_in_use(false);
-                Ok(segment)
-            }
-        }
+    /// The number of bytes the segment can hold, behind its metadata.
+    pub fn usable_size(&self) -> usize {
+        self.segment.size_allocable()
     }
 
-    pub fn overhead(&self) -> usize {
-        self.num_nodes * SegmentMetadata::SIZE
+    /// The bytes the segment can hold, starting at its alloc ptr.
+    pub fn usable_range(&self) -> Range<*mut u8> {
+        self.segment.alloc_start_ptr()..self.segment.end_exclusive()
     }
 
-    pub fn size(&self) -> usize {
-        self.end_exclusive as usize - self.start as usize
+    #[cfg(feature = "requested-size")]
+    pub fn requested_size(&self) -> usize {
+        self.segment.requested_size()
     }
 
-    pub fn iter(&self) -> MemorySegmenterIter {
-        MemorySegmenterIter {
-            curr_segment: self.head,
-            phantom: PhantomData,
-        }
+    #[cfg(feature = "tagging")]
+    pub fn tag(&self) -> u8 {
+        self.segment.tag()
     }
 
-    unsafe fn write_metadata(dest: *mut SegmentMetadata, src: SegmentMetadata) {
-        core::ptr::write(dest, src);
+    #[cfg(feature = "user-data")]
+    pub fn user_data(&self) -> usize {
+        self.segment.user_data()
     }
 
-    unsafe fn read_metadata(src: *mut SegmentMetadata) -> &'static mut SegmentMetadata {
-        src.as_mut().unwrap()
+    /// The raw metadata of the segment.
+    ///
+    /// # Safety
+    /// The metadata links to its neighbours with raw pointers, which are only valid as long as
+    /// the segment list isn't changed. Following them is up to the caller.
+    pub unsafe fn metadata(&self) -> &'a SegmentMetadata {
+        self.segment
     }
 }
 
-impl Debug for MemorySegmenter {
+impl Debug for SegmentView<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        for segment in self.iter() {
-            write!(f, "{:?}", segment)?;
+        self.segment.fmt(f)
+    }
+}
+
+impl<const GRANULE: usize, I: FreeIndex> SegmentCursor<'_, GRANULE, I> {
+    pub fn current(&self) -> &SegmentMetadata {
+        unsafe { self.current.as_ref() }.unwrap()
+    }
+
+    /// Moves to the next segment. Returns false, leaving the cursor in place, if this is the last
+    /// segment.
+    pub fn move_next(&mut self) -> bool {
+        match self.current().next() {
+            Some(next) => {
+                self.current = next;
+                true
+            }
+            None => false,
         }
+    }
 
-        Ok(())
+    /// Moves to the previous segment. Returns false, leaving the cursor in place, if this is the
+    /// first segment.
+    pub fn move_prev(&mut self) -> bool {
+        let prev = self.current().prev(self.segmenter.key);
+        if prev.is_null() {
+            false
+        } else {
+            self.current = prev;
+            true
+        }
     }
-}
 
-impl<'a> Iterator for MemorySegmenterIter<'a> {
-    type Item = &'a SegmentMetadata;
+    /// Splits a used sub-segment able to hold `size` bytes whose alloc ptr satisfies `align` off of
+    /// the current free segment, and moves the cursor to it.
+    #[allow(clippy::result_unit_err)]
+    pub fn split_at(&mut self, size: usize, align: usize) -> Result<(), ()> {
+        self.current = unsafe {
+            self.segmenter
+                .create_used_segment(self.current, size, align)?
+        };
+        Ok(())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let item = unsafe { self.curr_segment.as_ref() }?;
+    /// Frees the current used segment, coalescing it with any free neighbours, and moves the cursor
+    /// to the resulting free segment.
+    #[allow(clippy::result_unit_err)]
+    pub fn try_coalesce(&mut self) -> Result<(), ()> {
+        self.current = unsafe { self.segmenter.delete_used_segment(self.current)? };
+        Ok(())
+    }
 
-        self.curr_segment = item.next().unwrap_or(null_mut());
-        Some(item)
+    /// Marks the current used segment as free without coalescing it, see
+    /// [`MemorySegmenter::release_used_segment`]. The cursor stays in place.
+    #[allow(clippy::result_unit_err)]
+    pub fn release(&mut self) -> Result<(), ()> {
+        unsafe { self.segmenter.release_used_segment(self.current) }
     }
 }
 
@@ -323,10 +1473,15 @@ impl Debug for SegmentMetadata {
         write!(
             f,
             "[prev: {:?}, size: {}, used: {}]",
-            self.prev(),
+            // As stored, i.e. mangled with the pointer-mangling feature
+            self.prev,
             self.size(),
             self.in_use()
         )?;
+        #[cfg(feature = "requested-size")]
+        if self.in_use() {
+            write!(f, "(requested: {})", self.requested_size())?;
+        }
 
         if self.next_exists() {
             write!(f, " -> ")
@@ -338,11 +1493,29 @@ impl Debug for SegmentMetadata {
 
 impl SegmentMetadata {
     pub const SIZE: usize = size_of::<Self>();
+    /// The low 3 bits of the size field hold flags, so sizes must be a multiple of this
+    pub const MIN_GRANULARITY: usize = 1 << 3;
     const IN_USE_BIT: usize = 0;
     const NEXT_EXISTS_BIT: usize = 1;
-
-    pub fn new(prev: *mut SegmentMetadata, size: usize, in_use: bool, next_exists: bool) -> Self {
-        let mut this = SegmentMetadata { prev, size };
+    const CONTAINER_BIT: usize = 2;
+
+    pub fn new(
+        prev: *mut SegmentMetadata,
+        size: usize,
+        in_use: bool,
+        next_exists: bool,
+        key: LinkKey,
+    ) -> Self {
+        let mut this = SegmentMetadata {
+            prev: key.mangle(prev),
+            size,
+            #[cfg(feature = "requested-size")]
+            requested_size: 0,
+            #[cfg(feature = "tagging")]
+            tag: 0,
+            #[cfg(feature = "user-data")]
+            user_data: 0,
+        };
         this.set_in_use(in_use);
         this.set_next_exists(next_exists);
 
@@ -353,7 +1526,7 @@ impl SegmentMetadata {
         self as *const SegmentMetadata
     }
 
-    pub fn set_size(&mut self, size: usize) {
+    pub(crate) fn set_size(&mut self, size: usize) {
         if size.get_bits(0..3) != 0 {
             panic!("Size must be a multiple of 8!");
         }
@@ -364,15 +1537,53 @@ impl SegmentMetadata {
         self.size.get_bits(3..) << 3
     }
 
+    /// The exact size that was requested when this segment was created.
+    #[cfg(feature = "requested-size")]
+    pub fn requested_size(&self) -> usize {
+        self.requested_size
+    }
+
+    #[cfg(feature = "requested-size")]
+    pub fn set_requested_size(&mut self, requested_size: usize) {
+        self.requested_size = requested_size;
+    }
+
+    /// The user tag of the allocation in this segment, zero if it wasn't tagged.
+    #[cfg(feature = "tagging")]
+    pub fn tag(&self) -> u8 {
+        self.tag
+    }
+
+    #[cfg(feature = "tagging")]
+    pub fn set_tag(&mut self, tag: u8) {
+        self.tag = tag;
+    }
+
+    /// The word of user data attached to the allocation in this segment, zero if none was set.
+    #[cfg(feature = "user-data")]
+    pub fn user_data(&self) -> usize {
+        self.user_data
+    }
+
+    #[cfg(feature = "user-data")]
+    pub fn set_user_data(&mut self, user_data: usize) {
+        self.user_data = user_data;
+    }
+
     pub fn size_allocable(&self) -> usize {
         self.size() - Self::SIZE
     }
 
+    /// The metadata of the segment whose alloc ptr is `alloc_ptr`.
+    pub fn from_alloc_ptr(alloc_ptr: *mut u8) -> *mut SegmentMetadata {
+        (alloc_ptr as *mut SegmentMetadata).wrapping_sub(1)
+    }
+
     pub fn alloc_start_ptr(&self) -> *mut u8 {
         (unsafe { self.addr().add(1) }) as *mut u8
     }
 
-    pub fn set_in_use(&mut self, in_use: bool) {
+    pub(crate) fn set_in_use(&mut self, in_use: bool) {
         self.size.set_bit(Self::IN_USE_BIT, in_use);
     }
 
@@ -380,7 +1591,7 @@ impl SegmentMetadata {
         self.size.get_bit(Self::IN_USE_BIT)
     }
 
-    pub fn set_next_exists(&mut self, next_exists: bool) {
+    pub(crate) fn set_next_exists(&mut self, next_exists: bool) {
         self.size.set_bit(Self::NEXT_EXISTS_BIT, next_exists);
     }
 
@@ -388,21 +1599,43 @@ impl SegmentMetadata {
         self.size.get_bit(Self::NEXT_EXISTS_BIT)
     }
 
-    pub fn prev(&self) -> *mut SegmentMetadata {
-        self.prev
+    /// Marks a used segment as holding allocations of its own, carved out by the allocator, rather
+    /// than a single allocation. Cleared whenever a used segment is created.
+    pub(crate) fn set_container(&mut self, container: bool) {
+        self.size.set_bit(Self::CONTAINER_BIT, container);
     }
 
-    pub fn set_prev(&mut self, prev: *mut SegmentMetadata) {
-        self.prev = prev;
+    pub fn is_container(&self) -> bool {
+        self.size.get_bit(Self::CONTAINER_BIT)
     }
 
+    /// The segment in front of this one, or null for the first segment. `key` must be the
+    /// [`LinkKey`] of the segmenter, see [`MemorySegmenter::link_key`].
+    pub fn prev(&self, key: LinkKey) -> *mut SegmentMetadata {
+        key.demangle(self.prev)
+    }
+
+    pub(crate) fn set_prev(&mut self, prev: *mut SegmentMetadata, key: LinkKey) {
+        self.prev = key.mangle(prev);
+    }
+
+    /// The segment behind this one, if there is one. A corrupted size that would reach past the
+    /// end of the address space is taken as the end of the list.
     pub fn next(&self) -> Option<*mut SegmentMetadata> {
-        self.next_exists()
-            .then(|| unsafe { (self.addr() as *mut u8).add(self.size()) } as *mut SegmentMetadata)
+        if !self.next_exists() {
+            return None;
+        }
+        (self.addr() as usize)
+            .checked_add(self.size())
+            .map(|_| self.end_exclusive() as *mut SegmentMetadata)
     }
 
+    /// The end of the segment. Saturates at the top of the address space, which no segment of a
+    /// valid segmenter reaches past.
     pub fn end_exclusive(&self) -> *mut u8 {
-        unsafe { (self.addr() as *mut u8).add(self.size()) }
+        let addr = self.addr() as *mut u8;
+        let size = self.size().min(usize::MAX - addr as usize);
+        addr.wrapping_add(size)
     }
 }
 
@@ -411,9 +1644,14 @@ mod tests {
     extern crate alloc;
     use core::{alloc::Layout, ptr::null_mut};
 
+    use rand::Rng;
+
     use super::*;
 
+    // The exact segment sizes below assume the default two word header
+    #[cfg(not(any(feature = "requested-size", feature = "tagging", feature = "user-data")))]
     #[test]
+    #[allow(clippy::bool_assert_comparison, clippy::cmp_null)]
     fn segmenter() {
         const MIB: usize = 1048576;
         const SIZE: usize = 2 * MIB;
@@ -424,7 +1662,7 @@ mod tests {
         assert_eq!(segmenter.overhead(), SegmentMetadata::SIZE);
 
         let segment_too_big =
-            unsafe { segmenter.create_used_segment(segmenter.head, SIZE + 64, 16) };
+            unsafe { segmenter.create_used_segment(segmenter.head, SIZE + 48, 16) };
         assert_eq!(segment_too_big.is_err(), true);
         assert_eq!(unsafe { segmenter.head.as_mut().unwrap().size() }, SIZE);
 
@@ -432,32 +1670,32 @@ mod tests {
         // This tests case 1a (same alignment)
         let segment = unsafe {
             segmenter
-                .create_used_segment(segmenter.head, 128, 16)
+                .create_used_segment(segmenter.head, 128 - SegmentMetadata::SIZE, 16)
                 .unwrap()
                 .as_mut()
                 .unwrap()
         };
         assert_eq!(segment.in_use(), true);
         assert_eq!(segment.next_exists(), true);
-        assert_eq!(segment.prev(), null_mut());
+        assert_eq!(segment.prev(segmenter.link_key()), null_mut());
         assert_eq!(segment.size(), 128);
         assert_eq!(segment.alloc_start_ptr().align_offset(16), 0);
         assert_eq!(segmenter.overhead(), SegmentMetadata::SIZE * 2);
 
         // Try (and fail) to create a segment with a segment thats already in use
-        let in_use_error = unsafe { segmenter.create_used_segment(segment, 64, 16) };
+        let in_use_error = unsafe { segmenter.create_used_segment(segment, 48, 16) };
         assert_eq!(in_use_error.is_err(), true);
 
         // Now segment.next() is not on a 1mib boundary, we can test alignment errors
         // This allocation succeeds regarding size, but fails after applying alignment
         let segment_align_error =
-            unsafe { segmenter.create_used_segment(segment.next().unwrap(), MIB + 32, MIB) };
+            unsafe { segmenter.create_used_segment(segment.next().unwrap(), MIB + 16, MIB) };
         assert_eq!(segment_align_error.is_err(), true);
 
         // Perform a middle allocation
         let middle = unsafe {
             segmenter
-                .create_used_segment(segment.next().unwrap(), 1024, 4096)
+                .create_used_segment(segment.next().unwrap(), 1024 - SegmentMetadata::SIZE, 4096)
                 .unwrap()
                 .as_mut()
                 .unwrap()
@@ -469,7 +1707,7 @@ mod tests {
         // Another middle allocation, but allocating everything without leaving a trailing segment
         let middle2 = unsafe {
             segmenter
-                .create_used_segment(middle.next().unwrap(), MIB + 16, MIB)
+                .create_used_segment(middle.next().unwrap(), MIB, MIB)
                 .unwrap()
                 .as_mut()
                 .unwrap()
@@ -499,7 +1737,7 @@ mod tests {
         assert_eq!(head_mut.in_use(), false);
         assert_eq!(head_mut.size(), SIZE);
         assert_eq!(head_mut.next_exists(), false);
-        assert_eq!(head_mut.prev(), null_mut());
+        assert_eq!(head_mut.prev(segmenter.link_key()), null_mut());
 
         // Try to delete a free segment
         let res = unsafe { segmenter.delete_used_segment(segmenter.head) };
@@ -507,15 +1745,725 @@ mod tests {
     }
 
     #[test]
+    fn filtered_iterators() {
+        const SIZE: usize = 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter: MemorySegmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        let mut used = alloc::vec::Vec::new();
+        let mut next = segmenter.head;
+        for _ in 0..4 {
+            let segment = unsafe { segmenter.create_used_segment(next, 64, 16) }.unwrap();
+            used.push(segment.cast_const());
+            next = unsafe { (*segment).next() }.unwrap();
+        }
+        // Leaves the second segment and the rest of the region free
+        let freed = unsafe { segmenter.delete_used_segment(used.remove(1).cast_mut()) }.unwrap();
+
+        let used_addrs: alloc::vec::Vec<_> = segmenter.iter_used().map(|x| x.addr()).collect();
+        assert_eq!(used_addrs, used);
+        let free_addrs: alloc::vec::Vec<_> = segmenter.iter_free().map(|x| x.addr()).collect();
+        assert_eq!(free_addrs, [freed.cast_const(), next.cast_const()]);
+        assert!(segmenter.iter_used().all(|x| x.in_use()));
+        assert!(segmenter.iter_free().all(|x| !x.in_use()));
+
+        // The counts are known up front, and shrink as the iterators advance
+        let mut iter = segmenter.iter_used();
+        assert_eq!(iter.len(), 3);
+        iter.next();
+        assert_eq!(iter.len(), 2);
+        assert_eq!(segmenter.iter_free().len(), 2);
+    }
+
+    #[test]
+    fn segment_views() {
+        const SIZE: usize = 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter: MemorySegmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        let first = unsafe { segmenter.create_used_segment(segmenter.head, 64, 16) }.unwrap();
+        let rest = unsafe { (*first).next() }.unwrap();
+        let second = unsafe { segmenter.create_used_segment(rest, 128, 16) }.unwrap();
+        unsafe { segmenter.delete_used_segment(first) }.unwrap();
+
+        // A free segment, a used one, and the free tail
+        let views: alloc::vec::Vec<_> = segmenter.iter().collect();
+        assert_eq!(views.len(), 3);
+        assert_eq!(
+            views
+                .iter()
+                .map(|x| x.in_use())
+                .collect::<alloc::vec::Vec<_>>(),
+            [false, true, false]
+        );
+        assert_eq!(views[1].addr(), second.cast_const());
+        assert_eq!(views[1].usable_size(), 128);
+
+        // Views locate each segment within the region, and tile it
+        let mut offset = 0;
+        for view in &views {
+            assert_eq!(view.offset(), offset);
+            assert_eq!(view.addr() as usize, mem as usize + offset);
+            let usable = view.usable_range();
+            assert_eq!(
+                usable.start as usize - view.addr() as usize,
+                SegmentMetadata::SIZE
+            );
+            assert_eq!(usable.end, view.end_exclusive());
+            assert_eq!(
+                usable.end as usize - usable.start as usize,
+                view.usable_size()
+            );
+            assert!(!view.is_container());
+            offset += view.size();
+        }
+        assert_eq!(offset, segmenter.size());
+
+        // The tail ends the region
+        let tail = views.last().unwrap();
+        assert_eq!(tail.end_exclusive(), mem.wrapping_add(SIZE));
+        assert_eq!(
+            tail.usable_size(),
+            SIZE - tail.offset() - SegmentMetadata::SIZE
+        );
+        assert!(!unsafe { tail.metadata() }.next_exists());
+    }
+
+    #[test]
+    fn double_ended_iteration() {
+        const SIZE: usize = 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter: MemorySegmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        let mut next = segmenter.head;
+        for _ in 0..4 {
+            let segment = unsafe { segmenter.create_used_segment(next, 64, 16) }.unwrap();
+            next = unsafe { (*segment).next() }.unwrap();
+        }
+        assert_eq!(
+            segmenter.iter().next_back().unwrap().addr(),
+            next.cast_const()
+        );
+
+        // Walking from the tail visits the same segments as walking from the head
+        let forward: alloc::vec::Vec<_> = segmenter.iter().map(|x| x.addr()).collect();
+        let mut backward: alloc::vec::Vec<_> = segmenter.iter().rev().map(|x| x.addr()).collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+        let used: alloc::vec::Vec<_> = segmenter.iter_used().rev().map(|x| x.addr()).collect();
+        assert_eq!(used, [forward[3], forward[2], forward[1], forward[0]]);
+
+        // Both ends meet without handing out a segment twice
+        let mut both_ends = segmenter.iter();
+        assert_eq!(both_ends.len(), 5);
+        assert_eq!(both_ends.next().unwrap().addr(), forward[0]);
+        assert_eq!(both_ends.next_back().unwrap().addr(), forward[4]);
+        assert_eq!(both_ends.len(), 3);
+        assert_eq!(both_ends.next_back().unwrap().addr(), forward[3]);
+        assert_eq!(both_ends.next().unwrap().addr(), forward[1]);
+        assert_eq!(both_ends.next().unwrap().addr(), forward[2]);
+        assert!(both_ends.next().is_none() && both_ends.next_back().is_none());
+
+        // The same holds for mutable iteration
+        let mut iter = unsafe { segmenter.iter_mut() };
+        assert!(!iter.next_back().unwrap().in_use());
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.rev().filter(|x| x.in_use()).count(), 4);
+
+        // Once the last used segment is freed, the head is the tail again
+        for segment in forward[..4].iter().rev() {
+            unsafe { segmenter.delete_used_segment(segment.cast_mut()) }.unwrap();
+        }
+        assert_eq!(segmenter.tail, segmenter.head);
+        assert_eq!(segmenter.iter().rev().count(), 1);
+    }
+
+    #[test]
+    fn address_ordered_best_fit() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter: MemorySegmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        // Lay out free segments of 480, 240, 240 and 208 usable bytes separated by used segments
+        let mut cursor = segmenter.cursor_front();
+        for usable in [480, 16, 240, 16, 240, 16, 208, 16] {
+            cursor.split_at(usable, 16).unwrap();
+            cursor.move_next();
+        }
+        let mut cursor = segmenter.cursor_front();
+        for _ in 0..4 {
+            cursor.try_coalesce().unwrap();
+            cursor.move_next();
+            cursor.move_next();
+        }
+        let free: alloc::vec::Vec<_> = segmenter.iter_free().map(|x| x.addr()).collect();
+        let fit_of = |size| {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let fit = segmenter
+                .find_fit(layout, FitPolicy::AddressOrderedBestFit)
+                .unwrap();
+            free.iter()
+                .position(|&segment| segment == fit.segment.cast_const())
+                .unwrap()
+        };
+
+        // An exact fit wins even though looser fits come before it
+        assert_eq!(fit_of(200), 3);
+        // Of two equally good fits, the lower one wins
+        assert_eq!(fit_of(220), 1);
+        // Fits are ranked by the space they leave, so the largest segment only serves what nothing
+        // else can
+        assert_eq!(fit_of(300), 0);
+        assert_eq!(fit_of(600), 4);
+    }
+
+    #[test]
+    fn delete_last_segment() {
+        const SIZE: usize = 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter: MemorySegmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        let first = unsafe { segmenter.create_used_segment(segmenter.head, 128, 16) }.unwrap();
+        let rest = unsafe { (*first).next() }.unwrap();
+        let last =
+            unsafe { segmenter.create_used_segment(rest, (*rest).size_allocable(), 16) }.unwrap();
+        assert!(unsafe { !(*last).next_exists() });
+
+        // Freeing the last segment while the one in front of it is in use frees only the last one
+        let freed = unsafe { segmenter.delete_used_segment(last) }.unwrap();
+        assert_eq!(freed, last);
+        unsafe {
+            assert!((*first).in_use());
+            assert!(!(*last).in_use());
+        }
+        assert_eq!(segmenter.num_used_segments(), 1);
+        assert_eq!(segmenter.num_segments(), 2);
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+    }
+
+    // The padded header of the requested-size and tagging features is too strictly aligned for 8
+    // byte granules
+    #[cfg(not(any(feature = "requested-size", feature = "tagging", feature = "user-data")))]
+    #[test]
+    fn segmenter_granularity() {
+        const SIZE: usize = 1024;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+
+        let mut segmenter: MemorySegmenter<8> =
+            unsafe { MemorySegmenter::with_granularity(mem, mem.add(SIZE)) };
+
+        // Sizes are only padded to a multiple of the granularity
+        let segment = unsafe {
+            segmenter
+                .create_used_segment(segmenter.head, 20, 1)
+                .unwrap()
+                .as_mut()
+                .unwrap()
+        };
+        assert_eq!(segment.size(), 24 + SegmentMetadata::SIZE);
+        assert_eq!(segment.size_allocable(), 24);
+        assert_eq!(segmenter.overhead(), SegmentMetadata::SIZE * 2);
+
+        // Leaving a remainder too small for metadata absorbs it into the used segment
+        let next = segment.next().unwrap();
+        let remaining = unsafe { next.as_ref().unwrap().size() };
+        let last = unsafe {
+            segmenter
+                .create_used_segment(next, remaining - 8 - SegmentMetadata::SIZE, 8)
+                .unwrap()
+                .as_mut()
+                .unwrap()
+        };
+        assert_eq!(last.size(), remaining);
+        assert!(!last.next_exists());
+        assert_eq!(segmenter.overhead(), SegmentMetadata::SIZE * 2);
+
+        unsafe {
+            segmenter.delete_used_segment(last).unwrap();
+            segmenter.delete_used_segment(segmenter.head).unwrap();
+        }
+        assert_eq!(segmenter.overhead(), SegmentMetadata::SIZE);
+        assert_eq!(unsafe { segmenter.head.as_ref().unwrap().size() }, SIZE);
+    }
+
+    #[test]
+    fn empty_segmenter() {
+        let mut segmenter = MemorySegmenter::<16>::empty();
+        assert_eq!(segmenter.size(), 0);
+        assert_eq!(segmenter.num_segments(), 0);
+        assert_eq!(segmenter.iter().len(), 0);
+        assert_eq!(segmenter.coalesce_all(), 0);
+        for policy in [FitPolicy::FirstFit, FitPolicy::NextFit] {
+            assert!(segmenter.find_fit(Layout::new::<u8>(), policy).is_none());
+        }
+    }
+
+    #[test]
+    fn check_integrity() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        assert_eq!(MemorySegmenter::<16>::empty().check_integrity(), Ok(()));
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+        let mut cursor = segmenter.cursor_front();
+        cursor.split_at(256, 16).unwrap();
+        cursor.move_next();
+        cursor.split_at(512, 16).unwrap();
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+
+        let second = segmenter.iter().nth(1).unwrap().addr() as *mut SegmentMetadata;
+        let third = segmenter.iter().nth(2).unwrap().addr() as *mut SegmentMetadata;
+        let key = segmenter.link_key();
+        unsafe {
+            (*third).set_prev(null_mut(), key);
+            assert_eq!(
+                segmenter.check_integrity(),
+                Err(IntegrityError::BrokenLink(third))
+            );
+            (*third).set_prev(second, key);
+
+            let size = (*second).size();
+            (*second).set_size(size + 8);
+            assert_eq!(
+                segmenter.check_integrity(),
+                Err(IntegrityError::BadSize(second))
+            );
+            (*second).set_size(size);
+            (*second).set_in_use(false);
+            assert_eq!(
+                segmenter.check_integrity(),
+                Err(IntegrityError::CountMismatch)
+            );
+            (*second).set_in_use(true);
+        }
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn bucket_index() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter: MemorySegmenter<16, BucketIndex> =
+            unsafe { MemorySegmenter::with_granularity(mem, mem.add(SIZE)) };
+
+        // Lay out free segments of 512, 256 and 1024 bytes separated by used segments
+        let mut cursor = segmenter.cursor_front();
+        for size in [512, 64, 256, 64, 1024, 64] {
+            cursor.split_at(size - SegmentMetadata::SIZE, 16).unwrap();
+            cursor.move_next();
+        }
+        let mut cursor = segmenter.cursor_front();
+        for _ in 0..3 {
+            cursor.try_coalesce().unwrap();
+            cursor.move_next();
+            cursor.move_next();
+        }
+
+        // The search starts at the bucket of the request, whatever the policy
+        let size_of_fit = |segmenter: &MemorySegmenter<16, BucketIndex>, size| {
+            let fit = segmenter
+                .find_fit(
+                    Layout::from_size_align(size, 8).unwrap(),
+                    FitPolicy::FirstFit,
+                )
+                .unwrap();
+            unsafe { fit.segment.as_ref() }.unwrap().size()
+        };
+        assert_eq!(size_of_fit(&segmenter, 200), 256);
+        assert_eq!(size_of_fit(&segmenter, 300), 512);
+        assert_eq!(size_of_fit(&segmenter, 600), 1024);
+        assert!(size_of_fit(&segmenter, 1500) > 1024);
+        assert!(segmenter
+            .find_fit(Layout::new::<[u8; SIZE]>(), FitPolicy::FirstFit)
+            .is_none());
+
+        // Merged segments move to the bucket of their new size
+        let mut cursor = segmenter.cursor_front();
+        cursor.move_next();
+        cursor.try_coalesce().unwrap();
+        assert_eq!(cursor.current().size(), 512 + 64 + 256);
+        assert_eq!(size_of_fit(&segmenter, 600), 512 + 64 + 256);
+        assert_eq!(size_of_fit(&segmenter, 200), 512 + 64 + 256);
+    }
+
+    #[test]
+    fn extend() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(2 * SIZE, SIZE).unwrap()) };
+
+        let mut segmenter: MemorySegmenter<16, BucketIndex> =
+            unsafe { MemorySegmenter::with_granularity(mem, mem.add(SIZE / 2)) };
+        // The free segment at the top grows
+        unsafe { segmenter.extend(SIZE / 2) };
+        assert_eq!(segmenter.num_segments(), 1);
+        assert_eq!(
+            segmenter.largest_free_segment(),
+            SIZE - SegmentMetadata::SIZE
+        );
+
+        let mut cursor = segmenter.cursor_front();
+        cursor.split_at(SIZE - SegmentMetadata::SIZE, 16).unwrap();
+        // Behind a used segment, a new one is added
+        unsafe { segmenter.extend(SIZE) };
+        assert_eq!(segmenter.region(), mem..mem.wrapping_add(2 * SIZE));
+        assert_eq!(segmenter.num_free_segments(), 1);
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+        let layout = Layout::new::<[u8; 2048]>();
+        assert!(segmenter.find_fit(layout, FitPolicy::FirstFit).is_some());
+    }
+
+    #[test]
+    fn link_keys() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let key = LinkKey::new(0x1234_5670);
+        let mut segmenter: MemorySegmenter<16, BucketIndex> =
+            unsafe { MemorySegmenter::with_key(mem, mem.add(SIZE), key) };
+        let mut cursor = segmenter.cursor_front();
+        let mut used = [null_mut(); 3];
+        for segment in &mut used {
+            cursor.split_at(256, 16).unwrap();
+            *segment = cursor.current().addr().cast_mut();
+            cursor.move_next();
+        }
+        let [first, second, third] = used;
+        // Only mangled links differ from the pointers they stand for
+        assert_eq!(
+            unsafe { (*second).prev } == first,
+            cfg!(not(feature = "pointer-mangling"))
+        );
+
+        // Rekeying rewrites the back links and those of the index
+        let key = LinkKey::new(0xfedc_ba90);
+        segmenter.rekey(key);
+        assert_eq!(segmenter.link_key(), key);
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+        assert_eq!(unsafe { (*second).prev(key) }, first);
+        let layout = Layout::new::<[u8; 1024]>();
+        assert!(segmenter.find_fit(layout, FitPolicy::FirstFit).is_some());
+
+        // A back link overwritten with a plausible pointer isn't followed
+        unsafe {
+            (*second).prev = third;
+            assert_eq!(segmenter.delete_used_segment(second), Err(()));
+            assert_eq!(segmenter.num_used_segments(), 3);
+            (*second).set_prev(first, key);
+            segmenter.delete_used_segment(first).unwrap();
+            segmenter.delete_used_segment(second).unwrap();
+        }
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn find_fit() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        // Lay out free segments of 512, 256 and 1024 bytes separated by used segments
+        let mut cursor = segmenter.cursor_front();
+        for size in [512, 64, 256, 64, 1024, 64] {
+            cursor.split_at(size - SegmentMetadata::SIZE, 16).unwrap();
+            cursor.move_next();
+        }
+        let mut cursor = segmenter.cursor_front();
+        for _ in 0..3 {
+            cursor.try_coalesce().unwrap();
+            cursor.move_next();
+            cursor.move_next();
+        }
+        let free_sizes: alloc::vec::Vec<_> = segmenter.iter_free().map(|x| x.size()).collect();
+        assert_eq!(
+            free_sizes,
+            [512, 256, 1024, SIZE - 512 - 256 - 1024 - 64 * 3]
+        );
+        assert_eq!(
+            segmenter.largest_free_segment(),
+            free_sizes[3] - SegmentMetadata::SIZE
+        );
+        assert_eq!(segmenter.used_bytes(), 64 * 3);
+        assert_eq!(segmenter.free_bytes(), free_sizes.iter().sum::<usize>());
+
+        let layout = Layout::from_size_align(200, 8).unwrap();
+        let size_of_fit = |policy| {
+            let fit = segmenter.find_fit(layout, policy).unwrap();
+            assert_eq!(fit.subsegment_size, 208 + SegmentMetadata::SIZE);
+            assert_eq!(fit.align, 16);
+            assert_eq!(
+                fit.alloc_ptr,
+                unsafe { fit.segment.as_ref() }.unwrap().alloc_start_ptr()
+            );
+            unsafe { fit.segment.as_ref() }.unwrap().size()
+        };
+        assert_eq!(size_of_fit(FitPolicy::FirstFit), 512);
+        assert_eq!(size_of_fit(FitPolicy::BestFit), 256);
+        assert_eq!(size_of_fit(FitPolicy::AddressOrderedBestFit), 256);
+        assert_eq!(size_of_fit(FitPolicy::LastFit), free_sizes[3]);
+
+        // Alignment is taken into account when checking whether a segment fits
+        let fit = segmenter
+            .find_fit(
+                Layout::from_size_align(240, 1024).unwrap(),
+                FitPolicy::FirstFit,
+            )
+            .unwrap();
+        assert_eq!(fit.alloc_ptr.align_offset(1024), 0);
+        assert_eq!(unsafe { fit.segment.as_ref() }.unwrap().size(), 1024);
+
+        let too_big = Layout::from_size_align(SIZE, 16).unwrap();
+        assert_eq!(segmenter.find_fit(too_big, FitPolicy::BestFit), None);
+    }
+
+    #[test]
+    fn huge_alignment() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, 16).unwrap()) };
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        // Padding to an alignment beyond the address space fails rather than wrapping around
+        let layout = Layout::from_size_align(16, 1 << (usize::BITS - 2)).unwrap();
+        assert_eq!(segmenter.find_fit(layout, FitPolicy::FirstFit), None);
+        let head = segmenter.head;
+        assert!(unsafe { segmenter.create_used_segment(head, 16, layout.align()) }.is_err());
+        assert_eq!(segmenter.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "wraps around")]
+    fn wrapping_region() {
+        let mut mem = [0u64; 4];
+        let start = mem.as_mut_ptr().cast::<u8>();
+        unsafe { MemorySegmenter::new(start, start.wrapping_sub(16)) };
+    }
+
+    #[test]
+    fn random_fit() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        // Four free segments of 512 bytes, separated by used ones
+        let mut cursor = segmenter.cursor_front();
+        for _ in 0..8 {
+            cursor.split_at(512 - SegmentMetadata::SIZE, 16).unwrap();
+            cursor.move_next();
+        }
+        let mut cursor = segmenter.cursor_front();
+        while cursor.move_next() {
+            cursor.try_coalesce().unwrap();
+            cursor.move_next();
+        }
+        let free: Vec<_> = segmenter
+            .iter_free()
+            .map(|segment| segment.addr())
+            .collect();
+        assert_eq!(free.len(), 4);
+
+        // Every free segment can be picked
+        let layout = Layout::new::<[u8; 64]>();
+        let mut rng = rand::thread_rng();
+        let mut picked = [false; 4];
+        for _ in 0..200 {
+            let fit = segmenter
+                .find_random_fit(layout, || rng.gen(), false)
+                .unwrap();
+            let index = free.iter().position(|&addr| addr == fit.segment).unwrap();
+            assert_eq!(
+                fit.alloc_ptr,
+                unsafe { fit.segment.as_ref() }.unwrap().alloc_start_ptr()
+            );
+            picked[index] = true;
+        }
+        assert!(picked.iter().all(|&picked| picked));
+        assert!(segmenter
+            .find_random_fit(Layout::new::<[u8; 1024]>(), || rng.gen(), false)
+            .is_none());
+
+        // Random offsets stay inside the segment and can be used
+        for _ in 0..50 {
+            let fit = segmenter
+                .find_random_fit(Layout::from_size_align(64, 32).unwrap(), || rng.gen(), true)
+                .unwrap();
+            let segment = unsafe { fit.segment.as_ref() }.unwrap();
+            assert_eq!(fit.alloc_ptr.align_offset(32), 0);
+            assert!(fit.alloc_ptr as usize + 64 <= segment.end_exclusive() as usize);
+        }
+        let fit = segmenter
+            .find_random_fit(layout, || 1 << 20 | 3, true)
+            .unwrap();
+        let used = unsafe { segmenter.create_used_segment_at(fit, 64) }.unwrap();
+        assert_eq!(
+            unsafe { used.as_ref() }.unwrap().alloc_start_ptr(),
+            fit.alloc_ptr
+        );
+        let total: usize = segmenter.iter().map(|segment| segment.size()).sum();
+        assert_eq!(total, SIZE);
+        unsafe { segmenter.delete_used_segment(used) }.unwrap();
+        assert_eq!(segmenter.num_free_segments(), 4);
+
+        // Bogus fits are refused
+        let mut fit = segmenter.find_random_fit(layout, || 0, false).unwrap();
+        fit.alloc_ptr = fit.alloc_ptr.wrapping_add(8);
+        assert!(unsafe { segmenter.create_used_segment_at(fit, 64) }.is_err());
+        fit.alloc_ptr = fit.alloc_ptr.wrapping_add(504);
+        assert!(unsafe { segmenter.create_used_segment_at(fit, 64) }.is_err());
+        assert!(unsafe { segmenter.create_used_segment_at(fit, 1024) }.is_err());
+    }
+
+    #[test]
+    fn next_fit() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        let layout = Layout::from_size_align(240, 16).unwrap();
+        let allocate = |segmenter: &mut MemorySegmenter| {
+            let fit = segmenter.find_fit(layout, FitPolicy::NextFit)?;
+            unsafe { segmenter.create_used_segment(fit.segment, layout.size(), layout.align()) }
+                .ok()
+        };
+
+        let first = allocate(&mut segmenter).unwrap();
+        let second = allocate(&mut segmenter).unwrap();
+        assert!(second > first);
+
+        // The hole left by the first allocation is skipped, since the search resumes at the rover
+        unsafe { segmenter.delete_used_segment(first).unwrap() };
+        let third = allocate(&mut segmenter).unwrap();
+        assert!(third > second);
+
+        // Coalescing away the segment the rover points at must not leave it dangling
+        unsafe {
+            segmenter.delete_used_segment(second).unwrap();
+            segmenter.delete_used_segment(third).unwrap();
+        }
+        assert_eq!(segmenter.num_segments(), 1);
+        assert_eq!(allocate(&mut segmenter).unwrap(), segmenter.head);
+
+        // Once the top of the region is exhausted, the search wraps around to the bottom
+        while allocate(&mut segmenter).is_some() {}
+        let last = segmenter.iter_used().next_back().unwrap().addr().cast_mut();
+        unsafe { segmenter.delete_used_segment(segmenter.head).unwrap() };
+        assert_eq!(allocate(&mut segmenter).unwrap(), segmenter.head);
+        assert!(last > segmenter.head);
+    }
+
+    #[test]
+    fn segment_cursor() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+
+        let mut cursor = segmenter.cursor_front();
+        assert!(!cursor.move_next());
+        assert!(!cursor.move_prev());
+        assert!(cursor.split_at(SIZE * 2, 16).is_err());
+
+        // Fill the region with three used segments, walking the cursor forward
+        cursor.split_at(256 - SegmentMetadata::SIZE, 16).unwrap();
+        assert!(cursor.current().in_use());
+        assert_eq!(cursor.current().size(), 256);
+        assert!(cursor.split_at(64, 16).is_err());
+        assert!(cursor.move_next());
+        cursor.split_at(512 - SegmentMetadata::SIZE, 256).unwrap();
+        assert_eq!(cursor.current().alloc_start_ptr().align_offset(256), 0);
+        assert!(cursor.move_next());
+        cursor.split_at(256 - SegmentMetadata::SIZE, 16).unwrap();
+        assert_eq!(segmenter.iter_used().len(), 3);
+        assert_eq!(segmenter.iter_free().len(), 2);
+
+        // Free them again from the back, which coalesces everything into a single segment
+        let mut cursor = segmenter.cursor_back();
+        assert!(cursor.try_coalesce().is_err());
+        assert!(cursor.move_prev());
+        cursor.try_coalesce().unwrap();
+        assert!(!cursor.current().in_use());
+        while cursor.move_prev() {
+            if cursor.current().in_use() {
+                cursor.try_coalesce().unwrap();
+            }
+        }
+        assert_eq!(cursor.current().size(), SIZE);
+        assert_eq!(segmenter.num_segments(), 1);
+    }
+
+    #[test]
+    fn deferred_coalescing() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        assert_eq!(segmenter.coalesce_all(), 0);
+
+        // Fill the region with four used segments
+        let mut cursor = segmenter.cursor_front();
+        cursor.split_at(1024 - SegmentMetadata::SIZE, 16).unwrap();
+        while cursor.move_next() {
+            cursor.split_at(1024 - SegmentMetadata::SIZE, 16).unwrap();
+        }
+        assert_eq!(segmenter.num_segments(), 4);
+
+        // Released segments are free, but stay separate until coalesced
+        let mut cursor = segmenter.cursor_front();
+        cursor.release().unwrap();
+        assert!(cursor.release().is_err());
+        assert!(cursor.move_next());
+        cursor.release().unwrap();
+        assert!(cursor.move_next() && cursor.move_next());
+        cursor.release().unwrap();
+        assert_eq!(segmenter.num_segments(), 4);
+        assert_eq!(segmenter.iter_free().len(), 3);
+        let layout = Layout::new::<[u8; 2000]>();
+        assert!(segmenter.find_fit(layout, FitPolicy::FirstFit).is_none());
+
+        // Only the two leading segments are adjacent
+        assert_eq!(segmenter.coalesce_all(), 1);
+        assert_eq!(segmenter.num_segments(), 3);
+        let sizes = segmenter.iter().map(|segment| segment.size());
+        assert!(sizes.eq([2048, 1024, 1024]));
+        assert_eq!(
+            segmenter
+                .iter()
+                .next_back()
+                .unwrap()
+                .segment
+                .prev(segmenter.link_key()),
+            mem.wrapping_add(2048).cast()
+        );
+        assert!(segmenter.find_fit(layout, FitPolicy::FirstFit).is_some());
+    }
+
+    #[cfg(feature = "requested-size")]
+    #[test]
+    fn requested_size() {
+        const SIZE: usize = 4096;
+        let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(SIZE, SIZE).unwrap()) };
+
+        let mut segmenter = unsafe { MemorySegmenter::new(mem, mem.add(SIZE)) };
+        let mut cursor = segmenter.cursor_front();
+        cursor.split_at(37, 1).unwrap();
+        assert_eq!(cursor.current().requested_size(), 37);
+        assert_eq!(cursor.current().size_allocable(), 48);
+        assert!(cursor.move_next());
+        cursor.split_at(100, 64).unwrap();
+        assert_eq!(cursor.current().requested_size(), 100);
+        assert_eq!(cursor.current().alloc_start_ptr().align_offset(64), 0);
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn segment_metadata() {
         const MIB: usize = 1048576;
         let mem = unsafe { alloc::alloc::alloc(Layout::from_size_align(1024, MIB).unwrap()) };
 
+        let key = LinkKey::new(0x5a5a_5a50);
         let segment1_ptr = mem as *mut SegmentMetadata;
         unsafe {
             core::ptr::write(
                 segment1_ptr,
-                SegmentMetadata::new(null_mut(), 64, true, false),
+                SegmentMetadata::new(null_mut(), 64, true, false, key),
             )
         };
         let segment1_ref = unsafe { segment1_ptr.as_mut().unwrap() };
@@ -525,14 +2473,14 @@ mod tests {
         });
         assert_eq!(segment1_ref.in_use(), true);
         assert_eq!(segment1_ref.next(), None);
-        assert_eq!(segment1_ref.prev(), null_mut());
+        assert_eq!(segment1_ref.prev(key), null_mut());
         assert_eq!(segment1_ref.size(), 64);
         assert_eq!(segment1_ref.size_allocable(), 64 - SegmentMetadata::SIZE);
         let segment2_ptr = (unsafe { mem.add(64) } as *mut SegmentMetadata);
         unsafe {
             core::ptr::write(
                 segment2_ptr,
-                SegmentMetadata::new(segment1_ptr, 512, false, false),
+                SegmentMetadata::new(segment1_ptr, 512, false, false, key),
             )
         };
         segment1_ref.set_next_exists(true);
@@ -543,15 +2491,17 @@ mod tests {
         });
         assert_eq!(segment2_ref.in_use(), false);
         assert_eq!(segment2_ref.next(), None);
-        assert_eq!(segment2_ref.prev(), segment1_ptr);
+        assert_eq!(segment2_ref.prev(key), segment1_ptr);
         assert_eq!(segment2_ref.size(), 512);
         assert_eq!(segment2_ref.size_allocable(), 512 - SegmentMetadata::SIZE);
 
+        // 32 bytes with the default metadata, which the optional fields make larger
+        let segment3_size = SegmentMetadata::SIZE + 16;
         let segment3_ptr = (unsafe { mem.add(512 + 64) } as *mut SegmentMetadata);
         unsafe {
             core::ptr::write(
                 segment3_ptr,
-                SegmentMetadata::new(segment2_ptr, 32, false, false),
+                SegmentMetadata::new(segment2_ptr, segment3_size, false, false, key),
             )
         };
         segment2_ref.set_next_exists(true);
@@ -562,8 +2512,11 @@ mod tests {
         });
         assert_eq!(segment3_ref.in_use(), false);
         assert_eq!(segment3_ref.next(), None);
-        assert_eq!(segment3_ref.prev(), segment2_ptr);
-        assert_eq!(segment3_ref.size(), 32);
-        assert_eq!(segment3_ref.size_allocable(), 32 - SegmentMetadata::SIZE);
+        assert_eq!(segment3_ref.prev(key), segment2_ptr);
+        assert_eq!(segment3_ref.size(), segment3_size);
+        assert_eq!(
+            segment3_ref.size_allocable(),
+            segment3_size - SegmentMetadata::SIZE
+        );
     }
 }
@@ -0,0 +1,472 @@
+use core::{
+    alloc::Layout,
+    mem::{align_of, size_of},
+    ptr, slice,
+};
+
+use super::{FitPolicy, DEFAULT_GRANULARITY};
+
+/// Describes a segment of an [`OutOfBandSegmenter`]. Descriptors live in the metadata region, not
+/// in the segments they describe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentDescriptor {
+    start: *mut u8,
+    size: usize,
+    in_use: bool,
+}
+
+impl SegmentDescriptor {
+    pub fn start(&self) -> *mut u8 {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn end_exclusive(&self) -> *mut u8 {
+        self.start.wrapping_add(self.size)
+    }
+
+    pub fn in_use(&self) -> bool {
+        self.in_use
+    }
+}
+
+/// Manages a region of memory as a list of adjacent segments like [`MemorySegmenter`], but keeps
+/// the segment descriptors in a separate metadata region rather than in headers in front of each
+/// segment. The managed region is never read or written, so overflowing a buffer can't corrupt
+/// the allocator state, and the region may be device or DMA memory the CPU shouldn't touch.
+///
+/// The descriptors are kept sorted by address, so a segment is found by binary search when it is
+/// freed. Each segment takes one descriptor, and the metadata region bounds how many segments
+/// there can be: splitting fails once it is full. Since there are no headers, segments carry no
+/// overhead in the managed region, and segment sizes are only quantized to `GRANULE` bytes.
+///
+/// [`MemorySegmenter`]: super::MemorySegmenter
+pub struct OutOfBandSegmenter<const GRANULE: usize = DEFAULT_GRANULARITY> {
+    descriptors: *mut SegmentDescriptor,
+    capacity: usize,
+    len: usize,
+    // Where the previous allocation was made, for next fit searches
+    rover: *mut u8,
+    start: *mut u8,
+    end_exclusive: *mut u8,
+    num_used: usize,
+    used_bytes: usize,
+}
+
+/// A free segment chosen to satisfy a request, by index, and the padding in front of the
+/// allocation.
+#[derive(Clone, Copy)]
+struct Fit {
+    index: usize,
+    lead: usize,
+}
+
+impl<const GRANULE: usize> OutOfBandSegmenter<GRANULE> {
+    /// The number of bytes of metadata needed for a segmenter able to hold `segments` segments.
+    pub const fn metadata_size_for(segments: usize) -> usize {
+        segments * size_of::<SegmentDescriptor>()
+    }
+
+    /// Creates a segmenter managing the memory between `start` and `end_exclusive` as a single
+    /// free segment, keeping its descriptors in the memory between `metadata_start` and
+    /// `metadata_end`. Without room for a single descriptor, every allocation fails.
+    ///
+    /// # Safety
+    /// The metadata region must be valid for reads and writes, and must not be used by anything
+    /// else for the lifetime of the segmenter. The managed region must not be used by anything
+    /// else either, `start` must be aligned to `GRANULE`, and the region size must be a multiple
+    /// of it.
+    pub unsafe fn new(
+        start: *mut u8,
+        end_exclusive: *mut u8,
+        metadata_start: *mut u8,
+        metadata_end: *mut u8,
+    ) -> Self {
+        const {
+            assert!(
+                GRANULE.is_power_of_two(),
+                "Granularity must be a power of two!"
+            );
+        }
+
+        let skip = metadata_start.align_offset(align_of::<SegmentDescriptor>());
+        let capacity = (metadata_end as usize)
+            .saturating_sub((metadata_start as usize).saturating_add(skip))
+            / size_of::<SegmentDescriptor>();
+        let descriptors = match capacity {
+            0 => ptr::NonNull::dangling().as_ptr(),
+            _ => metadata_start.add(skip).cast::<SegmentDescriptor>(),
+        };
+
+        let mut len = 0;
+        if capacity != 0 && end_exclusive > start {
+            descriptors.write(SegmentDescriptor {
+                start,
+                size: end_exclusive as usize - start as usize,
+                in_use: false,
+            });
+            len = 1;
+        }
+
+        OutOfBandSegmenter {
+            descriptors,
+            capacity,
+            len,
+            rover: start,
+            start,
+            end_exclusive,
+            num_used: 0,
+            used_bytes: 0,
+        }
+    }
+
+    /// Carves a used segment able to serve `layout` out of a free one, choosing between candidates
+    /// according to `policy`, and returns its start. Candidates that would need more descriptors
+    /// than are left are skipped.
+    #[allow(clippy::result_unit_err)]
+    pub fn allocate(&mut self, layout: Layout, policy: FitPolicy) -> Result<*mut u8, ()> {
+        let size = layout
+            .size()
+            .max(1)
+            .checked_next_multiple_of(GRANULE)
+            .ok_or(())?;
+        let align = layout.align().max(GRANULE);
+        let spare = self.capacity - self.len;
+
+        let fit = |(index, segment): (usize, &SegmentDescriptor)| {
+            if segment.in_use || segment.size < size {
+                return None;
+            }
+            let lead = segment.start.align_offset(align);
+            let rest = segment.size.checked_sub(lead)?.checked_sub(size)?;
+            let needed = usize::from(lead != 0) + usize::from(rest != 0);
+            (needed <= spare).then_some(Fit { index, lead })
+        };
+        let segments = self.segments().iter().enumerate();
+        let fit = match policy {
+            FitPolicy::FirstFit => segments.filter_map(fit).next(),
+            FitPolicy::LastFit => segments.rev().find_map(fit),
+            FitPolicy::BestFit => segments
+                .filter_map(fit)
+                .min_by_key(|fit| self.segments()[fit.index].size),
+            FitPolicy::AddressOrderedBestFit => segments
+                .filter_map(fit)
+                .min_by_key(|fit| self.segments()[fit.index].size - fit.lead - size),
+            FitPolicy::NextFit => {
+                let rover = self
+                    .segments()
+                    .partition_point(|s| s.end_exclusive() <= self.rover);
+                let (before, after) = self.segments().split_at(rover);
+                let after = after.iter().enumerate().map(|(i, s)| (rover + i, s));
+                after.chain(before.iter().enumerate()).find_map(fit)
+            }
+        }
+        .ok_or(())?;
+
+        Ok(unsafe { self.split(fit, size) })
+    }
+
+    /// # Safety
+    /// `fit` must have been found for `size` bytes, with enough spare descriptors.
+    unsafe fn split(&mut self, fit: Fit, size: usize) -> *mut u8 {
+        let mut index = fit.index;
+        let segment = *self.descriptors.add(index);
+
+        if fit.lead != 0 {
+            self.insert(
+                index,
+                SegmentDescriptor {
+                    start: segment.start,
+                    size: fit.lead,
+                    in_use: false,
+                },
+            );
+            index += 1;
+        }
+        let start = segment.start.add(fit.lead);
+        *self.descriptors.add(index) = SegmentDescriptor {
+            start,
+            size,
+            in_use: true,
+        };
+        let rest = segment.size - fit.lead - size;
+        if rest != 0 {
+            self.insert(
+                index + 1,
+                SegmentDescriptor {
+                    start: start.add(size),
+                    size: rest,
+                    in_use: false,
+                },
+            );
+        }
+
+        self.num_used += 1;
+        self.used_bytes += size;
+        self.rover = start;
+        start
+    }
+
+    /// Marks the used segment starting at `ptr` as free again, coalescing it with its free
+    /// neighbours. Returns the size of the segment, or fails without changing anything if no used
+    /// segment starts at `ptr`.
+    #[allow(clippy::result_unit_err)]
+    pub fn deallocate(&mut self, ptr: *mut u8) -> Result<usize, ()> {
+        let mut index = self
+            .segments()
+            .binary_search_by_key(&(ptr as usize), |segment| segment.start as usize)
+            .map_err(|_| ())?;
+        let segment = unsafe { &mut *self.descriptors.add(index) };
+        if !segment.in_use {
+            return Err(());
+        }
+        segment.in_use = false;
+        let size = segment.size;
+        self.num_used -= 1;
+        self.used_bytes -= size;
+
+        if index + 1 < self.len && !self.segments()[index + 1].in_use {
+            unsafe { self.merge(index) };
+        }
+        if index > 0 && !self.segments()[index - 1].in_use {
+            index -= 1;
+            unsafe { self.merge(index) };
+        }
+        // The rover may have pointed into a segment that was just merged away
+        let freed = self.segments()[index];
+        if (freed.start..freed.end_exclusive()).contains(&self.rover) {
+            self.rover = freed.start;
+        }
+        Ok(size)
+    }
+
+    /// The descriptor of the used segment starting at `ptr`, if there is one.
+    pub fn segment_of(&self, ptr: *mut u8) -> Option<&SegmentDescriptor> {
+        let index = self
+            .segments()
+            .binary_search_by_key(&(ptr as usize), |segment| segment.start as usize)
+            .ok()?;
+        Some(&self.segments()[index]).filter(|segment| segment.in_use)
+    }
+
+    /// All segments, in address order.
+    pub fn segments(&self) -> &[SegmentDescriptor] {
+        unsafe { slice::from_raw_parts(self.descriptors, self.len) }
+    }
+
+    /// The size of the largest free segment, or 0 if there is none.
+    pub fn largest_free_segment(&self) -> usize {
+        self.segments()
+            .iter()
+            .filter(|segment| !segment.in_use)
+            .map(SegmentDescriptor::size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The number of segments the metadata region has room for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn size(&self) -> usize {
+        self.end_exclusive as usize - self.start as usize
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    pub fn free_bytes(&self) -> usize {
+        self.size() - self.used_bytes
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.len
+    }
+
+    pub fn num_used_segments(&self) -> usize {
+        self.num_used
+    }
+
+    pub fn num_free_segments(&self) -> usize {
+        self.len - self.num_used
+    }
+
+    /// # Safety
+    /// There must be a spare descriptor, and `index` must be at most the number of segments.
+    unsafe fn insert(&mut self, index: usize, segment: SegmentDescriptor) {
+        let at = self.descriptors.add(index);
+        ptr::copy(at, at.add(1), self.len - index);
+        at.write(segment);
+        self.len += 1;
+    }
+
+    /// Merges the segment after the one at `index` into it.
+    ///
+    /// # Safety
+    /// Both segments must exist.
+    unsafe fn merge(&mut self, index: usize) {
+        let at = self.descriptors.add(index);
+        (*at).size += (*at.add(1)).size;
+        ptr::copy(at.add(2), at.add(1), self.len - index - 2);
+        self.len -= 1;
+    }
+}
+
+impl<const GRANULE: usize> core::fmt::Debug for OutOfBandSegmenter<GRANULE> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.segments()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    use rand::Rng;
+
+    use super::*;
+
+    const SIZE: usize = 4096;
+
+    #[test]
+    fn out_of_band_segmenter() {
+        let region = Layout::from_size_align(SIZE, 4096).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        unsafe { mem.write_bytes(0x5A, SIZE) };
+        let mut metadata = [0usize; 3 * 8];
+        let range = metadata.as_mut_ptr_range();
+        let mut segmenter: OutOfBandSegmenter = unsafe {
+            OutOfBandSegmenter::new(mem, mem.add(SIZE), range.start.cast(), range.end.cast())
+        };
+        assert_eq!(segmenter.capacity(), 8);
+
+        // Segments are packed without headers in between
+        let a = segmenter
+            .allocate(Layout::new::<[u8; 100]>(), FitPolicy::FirstFit)
+            .unwrap();
+        let b = segmenter
+            .allocate(Layout::new::<[u8; 16]>(), FitPolicy::FirstFit)
+            .unwrap();
+        assert_eq!(a, mem);
+        assert_eq!(b, unsafe { mem.add(112) });
+        assert_eq!(segmenter.used_bytes(), 128);
+        assert_eq!(segmenter.segment_of(b).unwrap().size(), 16);
+
+        // Aligned requests leave a free segment in front
+        let c = segmenter
+            .allocate(
+                Layout::from_size_align(64, 256).unwrap(),
+                FitPolicy::FirstFit,
+            )
+            .unwrap();
+        assert_eq!(c, unsafe { mem.add(256) });
+        assert_eq!(segmenter.num_segments(), 5);
+
+        // Freeing coalesces with free neighbours on both sides
+        assert_eq!(segmenter.deallocate(b), Ok(16));
+        assert!(segmenter.deallocate(b).is_err());
+        assert_eq!(segmenter.deallocate(c), Ok(64));
+        assert_eq!(segmenter.num_segments(), 2);
+        assert_eq!(segmenter.largest_free_segment(), SIZE - 112);
+
+        // The managed region was never written
+        assert!(unsafe { slice::from_raw_parts(mem, SIZE) }
+            .iter()
+            .all(|&byte| byte == 0x5A));
+        segmenter.deallocate(a).unwrap();
+        assert_eq!(segmenter.num_segments(), 1);
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn out_of_band_segmenter_capacity() {
+        let region = Layout::from_size_align(SIZE, 16).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        let mut metadata = [0usize; 3 * 4];
+        let range = metadata.as_mut_ptr_range();
+        let mut segmenter: OutOfBandSegmenter = unsafe {
+            OutOfBandSegmenter::new(mem, mem.add(SIZE), range.start.cast(), range.end.cast())
+        };
+
+        // Once the descriptors run out, only exact fits remain possible
+        let layout = Layout::new::<[u8; 64]>();
+        let blocks: Vec<_> = (0..3)
+            .map(|_| segmenter.allocate(layout, FitPolicy::FirstFit).unwrap())
+            .collect();
+        assert_eq!(segmenter.num_segments(), 4);
+        assert!(segmenter.allocate(layout, FitPolicy::FirstFit).is_err());
+        let rest = Layout::from_size_align(SIZE - 3 * 64, 16).unwrap();
+        let last = segmenter.allocate(rest, FitPolicy::FirstFit).unwrap();
+        assert_eq!(segmenter.free_bytes(), 0);
+
+        segmenter.deallocate(last).unwrap();
+        for block in blocks {
+            segmenter.deallocate(block).unwrap();
+        }
+        assert_eq!(segmenter.num_segments(), 1);
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+
+    #[test]
+    fn out_of_band_segmenter_random() {
+        let region = Layout::from_size_align(16 * SIZE, 16).unwrap();
+        let mem = unsafe { alloc::alloc::alloc(region) };
+        let mut metadata = alloc::vec![0usize; 3 * 256];
+        let range = metadata.as_mut_ptr_range();
+        let mut segmenter: OutOfBandSegmenter = unsafe {
+            OutOfBandSegmenter::new(
+                mem,
+                mem.add(16 * SIZE),
+                range.start.cast(),
+                range.end.cast(),
+            )
+        };
+
+        let mut rng = rand::thread_rng();
+        let policies = [
+            FitPolicy::FirstFit,
+            FitPolicy::LastFit,
+            FitPolicy::BestFit,
+            FitPolicy::AddressOrderedBestFit,
+            FitPolicy::NextFit,
+        ];
+        let mut live = alloc::vec::Vec::new();
+        for _ in 0..2000 {
+            if live.is_empty() || rng.gen_bool(0.6) {
+                let layout =
+                    Layout::from_size_align(rng.gen_range(1..512), 1 << rng.gen_range(0..7))
+                        .unwrap();
+                let policy = policies[rng.gen_range(0..policies.len())];
+                if let Ok(ptr) = segmenter.allocate(layout, policy) {
+                    assert_eq!(ptr.align_offset(layout.align()), 0);
+                    live.push(ptr);
+                }
+            } else {
+                let ptr = live.swap_remove(rng.gen_range(0..live.len()));
+                segmenter.deallocate(ptr).unwrap();
+            }
+
+            // The segments tile the region, and free segments are never adjacent
+            let segments = segmenter.segments();
+            assert_eq!(segments[0].start(), mem);
+            assert_eq!(segments.last().unwrap().end_exclusive(), unsafe {
+                mem.add(16 * SIZE)
+            });
+            for pair in segments.windows(2) {
+                assert_eq!(pair[0].end_exclusive(), pair[1].start());
+                assert!(pair[0].in_use() || pair[1].in_use());
+            }
+            assert_eq!(segmenter.num_used_segments(), live.len());
+        }
+
+        for ptr in live {
+            segmenter.deallocate(ptr).unwrap();
+        }
+        assert_eq!(segmenter.num_segments(), 1);
+        unsafe { alloc::alloc::dealloc(mem, region) };
+    }
+}
@@ -0,0 +1,164 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use super::MemorySource;
+
+/// The end of a single contiguous region of memory that can only be moved, like the program break
+/// of Unix `sbrk`, the heap of some embedded runtimes, or the kernel heap of a custom kernel. Any
+/// `FnMut(isize) -> Option<NonNull<u8>>` with the semantics of `sbrk` is a break, e.g.
+///
+/// ```ignore
+/// let source = BreakSource::new(|increment| NonNull::new(unsafe { sys_brk(increment) }), 4096);
+/// ```
+pub trait ProgramBreak {
+    /// Moves the break by `increment` bytes, up or down, and returns where it was before. Returns
+    /// `None` and leaves the break in place if it can't be moved that far.
+    fn sbrk(&mut self, increment: isize) -> Option<NonNull<u8>>;
+}
+
+impl<F: FnMut(isize) -> Option<NonNull<u8>>> ProgramBreak for F {
+    fn sbrk(&mut self, increment: isize) -> Option<NonNull<u8>> {
+        self(increment)
+    }
+}
+
+/// A source handing out memory by moving a [`ProgramBreak`]. Regions are carved from the top of
+/// the break, and the one at the top can grow in place, so an allocator whose heap is the last
+/// region grows it by extending its last segment rather than adding a region.
+///
+/// Only the region at the top can be given back. Memory of regions released below it, and the
+/// padding in front of regions aligned more strictly than the break, is lost. Allocators serving
+/// huge requests from their source should be kept from doing so, since those regions would end up
+/// on top of the heap and keep it from growing.
+#[derive(Debug, Clone, Copy)]
+pub struct BreakSource<B: ProgramBreak> {
+    brk: B,
+    page_size: usize,
+}
+
+impl<B: ProgramBreak> BreakSource<B> {
+    /// Creates a source moving `brk` in steps of whole `page_size` byte pages.
+    pub const fn new(brk: B, page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two(),
+            "Page size must be a power of two!"
+        );
+        BreakSource { brk, page_size }
+    }
+
+    /// The current break.
+    pub fn current(&mut self) -> Option<NonNull<u8>> {
+        self.brk.sbrk(0)
+    }
+}
+
+impl<B: ProgramBreak> MemorySource for BreakSource<B> {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let size = layout
+            .size()
+            .max(1)
+            .checked_next_multiple_of(self.page_size)?;
+        let padding = self
+            .current()?
+            .as_ptr()
+            .align_offset(layout.align().max(self.page_size));
+        let increment = isize::try_from(size.checked_add(padding)?).ok()?;
+        let start = self.brk.sbrk(increment)?.as_ptr().wrapping_add(padding);
+
+        Some(NonNull::slice_from_raw_parts(NonNull::new(start)?, size))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let size = layout.size().max(1).next_multiple_of(self.page_size);
+        if self.current() == Some(ptr.add(size)) {
+            self.brk.sbrk(-(size as isize));
+        }
+    }
+
+    unsafe fn grow_in_place(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        additional: usize,
+    ) -> Option<Layout> {
+        let size = layout.size().max(1).next_multiple_of(self.page_size);
+        if self.current()? != ptr.add(size) {
+            return None;
+        }
+
+        let additional = additional.checked_next_multiple_of(self.page_size)?;
+        self.brk.sbrk(isize::try_from(additional).ok()?)?;
+        Layout::from_size_align(size.checked_add(additional)?, layout.align()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+    use core::alloc::Allocator;
+
+    /// A break moving within `memory`.
+    fn fake_break(memory: &mut [u8]) -> impl FnMut(isize) -> Option<NonNull<u8>> + '_ {
+        let mut brk = 0usize;
+        move |increment| {
+            let moved = brk
+                .checked_add_signed(increment)
+                .filter(|&moved| moved <= memory.len())?;
+            let previous = core::mem::replace(&mut brk, moved);
+            NonNull::new(memory.as_mut_ptr().wrapping_add(previous))
+        }
+    }
+
+    #[test]
+    fn break_source() {
+        let mut memory = vec![0u8; 64 * 1024];
+        let mut source = BreakSource::new(fake_break(&mut memory), 1024);
+        let layout = Layout::from_size_align(1000, 4096).unwrap();
+        let region = source.acquire(layout).unwrap();
+        let start = region.cast::<u8>();
+        assert_eq!(region.len(), 1024);
+        assert_eq!(start.as_ptr().align_offset(4096), 0);
+
+        // Only the region at the top grows and can be released
+        let grown = unsafe { source.grow_in_place(start, layout, 1500) }.unwrap();
+        assert_eq!(grown.size(), 3072);
+        let top = source.acquire(Layout::new::<u8>()).unwrap().cast::<u8>();
+        assert!(unsafe { source.grow_in_place(start, grown, 1) }.is_none());
+        unsafe {
+            source.release(top, Layout::new::<u8>());
+            assert_eq!(source.current(), Some(start.add(3072)));
+            source.release(start, grown);
+        }
+        assert_eq!(source.current(), Some(start));
+        assert!(source.acquire(Layout::new::<[u8; 128 * 1024]>()).is_none());
+    }
+
+    #[test]
+    fn break_source_heap_growth() {
+        let mut memory = vec![0u8; 64 * 1024];
+        let source = BreakSource::new(fake_break(&mut memory), 1024);
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, _> =
+            LinkedListAlloc::from_source(source, 4096)
+                .unwrap()
+                .with_huge_threshold(usize::MAX);
+        let start = heap.region().start;
+
+        // The heap grows at its end, with the new memory joining the segment at the top
+        let layout = Layout::new::<[u8; 3000]>();
+        let ptrs: Vec<_> = (0..4).map(|_| heap.allocate(layout).unwrap()).collect();
+        let region = heap.region();
+        assert_eq!(region.start, start);
+        assert!(region.end as usize - region.start as usize >= 4 * 3000);
+        assert!(ptrs.iter().all(|ptr| heap.contains(ptr.cast().as_ptr())));
+        assert!(heap.allocate(Layout::new::<[u8; 128 * 1024]>()).is_err());
+
+        for ptr in ptrs {
+            unsafe { heap.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(heap.stats().live_allocations, 0);
+    }
+}
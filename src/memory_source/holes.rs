@@ -0,0 +1,159 @@
+/// The bookkeeping of a range of reserved address space regions are carved from, kept apart from
+/// the system calls committing and decommitting them. Regions are handed out as offsets from the
+/// start of the range, either from a gap left between earlier regions or from above the highest
+/// one.
+///
+/// Up to `HOLES` gaps are remembered. Any gap beyond that is lost until the regions around it are
+/// released too.
+#[derive(Debug)]
+pub(crate) struct AddressSpace<const HOLES: usize> {
+    // The address of the start of the range, which alignment is relative to
+    base: usize,
+    size: usize,
+    // The end of the highest region handed out
+    top: usize,
+    // Gaps below the top as offsets and sizes, with empty ones unused
+    holes: [(usize, usize); HOLES],
+}
+
+impl<const HOLES: usize> AddressSpace<HOLES> {
+    pub const fn new(size: usize) -> Self {
+        AddressSpace {
+            base: 0,
+            size,
+            top: 0,
+            holes: [(0, 0); HOLES],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Places the range at `base`, once it has been reserved.
+    pub fn set_base(&mut self, base: usize) {
+        self.base = base;
+    }
+
+    /// Takes `size` bytes aligned to `align` from the lowest gap they fit in, or from above the
+    /// highest region if none does, returning their offset. Keeping regions low lets the top drop
+    /// back when the highest ones are released.
+    pub fn take(&mut self, size: usize, align: usize) -> Option<usize> {
+        self.take_hole(size, align)
+            .or_else(|| self.bump(size, align))
+    }
+
+    /// The first offset from `offset` on whose address is aligned to `align`.
+    fn align_offset(&self, offset: usize, align: usize) -> usize {
+        (self.base + offset).next_multiple_of(align) - self.base
+    }
+
+    fn take_hole(&mut self, size: usize, align: usize) -> Option<usize> {
+        let (index, _) = self
+            .holes
+            .iter()
+            .enumerate()
+            .filter(|(_, &(offset, len))| {
+                len != 0 && self.align_offset(offset, align) + size <= offset + len
+            })
+            .min_by_key(|(_, &(offset, _))| offset)?;
+        let (offset, len) = core::mem::take(&mut self.holes[index]);
+        let start = self.align_offset(offset, align);
+        self.free_range(offset, start - offset);
+        self.free_range(start + size, offset + len - start - size);
+        Some(start)
+    }
+
+    fn bump(&mut self, size: usize, align: usize) -> Option<usize> {
+        let start = self.align_offset(self.top, align);
+        let end = start.checked_add(size).filter(|&end| end <= self.size)?;
+        let gap = (self.top, start - self.top);
+        self.top = end;
+        self.free_range(gap.0, gap.1);
+        Some(start)
+    }
+
+    /// Returns a range to the gaps, merging it with its neighbours, or lowers the top if the range
+    /// ends there.
+    pub fn free_range(&mut self, mut offset: usize, mut len: usize) {
+        if len == 0 {
+            return;
+        }
+        for hole in &mut self.holes {
+            if hole.1 != 0 && (hole.0 + hole.1 == offset || offset + len == hole.0) {
+                offset = offset.min(hole.0);
+                len += core::mem::take(hole).1;
+            }
+        }
+
+        if offset + len == self.top {
+            self.top = offset;
+        } else if let Some(hole) = self.holes.iter_mut().find(|hole| hole.1 == 0) {
+            *hole = (offset, len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: usize = 4096;
+
+    #[test]
+    fn address_space() {
+        let mut space = AddressSpace::<4>::new(64 * PAGE);
+        space.set_base(0x10_0000);
+        let a = space.take(PAGE, PAGE).unwrap();
+        let b = space.take(2 * PAGE, PAGE).unwrap();
+        let c = space.take(PAGE, PAGE).unwrap();
+        assert_eq!((a, b, c), (0, PAGE, 3 * PAGE));
+
+        // A released region is reused by a request it fits, which splits it
+        space.free_range(b, 2 * PAGE);
+        assert_eq!(space.take(PAGE, PAGE), Some(b));
+        assert_eq!(space.take(PAGE, PAGE), Some(b + PAGE));
+        assert_eq!(space.take(PAGE, PAGE), Some(4 * PAGE));
+
+        // Neighbouring gaps coalesce, so a request spanning both fits
+        space.free_range(b, PAGE);
+        space.free_range(b + PAGE, PAGE);
+        space.free_range(c, PAGE);
+        assert_eq!(space.take(3 * PAGE, PAGE), Some(b));
+    }
+
+    #[test]
+    fn address_space_alignment() {
+        let mut space = AddressSpace::<4>::new(64 * PAGE);
+        space.set_base(0x10_1000);
+        // Alignment is relative to the addresses, and the padding becomes a gap
+        let a = space.take(PAGE, 4 * PAGE).unwrap();
+        assert_eq!(a, 3 * PAGE);
+        assert_eq!(space.take(3 * PAGE, PAGE), Some(0));
+        assert_eq!(space.take(PAGE, PAGE), Some(4 * PAGE));
+    }
+
+    #[test]
+    fn address_space_top() {
+        let mut space = AddressSpace::<1>::new(4 * PAGE);
+        let a = space.take(PAGE, PAGE).unwrap();
+        let b = space.take(PAGE, PAGE).unwrap();
+        let c = space.take(2 * PAGE, PAGE).unwrap();
+        assert_eq!(c + 2 * PAGE, space.size());
+        assert_eq!(space.take(PAGE, PAGE), None);
+
+        // Releasing the highest regions lowers the top, taking the gaps below it along
+        space.free_range(b, PAGE);
+        space.free_range(c, 2 * PAGE);
+        assert_eq!(space.take(3 * PAGE, PAGE), Some(b));
+
+        // Gaps beyond the limit are lost
+        space.free_range(a, PAGE);
+        let mut full = AddressSpace::<1>::new(8 * PAGE);
+        let regions = [(); 5].map(|_| full.take(PAGE, PAGE).unwrap());
+        full.free_range(regions[0], PAGE);
+        full.free_range(regions[2], PAGE);
+        assert_eq!(full.take(PAGE, PAGE), Some(regions[0]));
+        assert_eq!(full.take(PAGE, PAGE), Some(5 * PAGE));
+    }
+}
@@ -0,0 +1,191 @@
+use core::{
+    alloc::Layout,
+    ptr::{null_mut, NonNull},
+};
+
+use super::{MemorySource, PageProtection};
+
+/// Whether the regions of an [`MmapSource`] are backed by huge pages, which saves TLB misses on
+/// large heaps. Only Linux and Android support them, elsewhere regular pages are always used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HugePages {
+    #[default]
+    None,
+    /// Asks the kernel to back regions with transparent huge pages where it can, with `madvise`.
+    /// Only the parts of regions covering whole huge pages can be.
+    Transparent,
+    /// Maps regions from the pool of huge pages reserved by the system administrator, whose size
+    /// must be the default huge page size of the system. Regions are made of whole huge pages, and
+    /// can't be acquired once the pool is exhausted.
+    Explicit(usize),
+}
+
+/// A source of anonymous private mappings of a Unix process, made with `mmap`. Every region is a
+/// mapping of its own, which `munmap` gives back to the system when it is released.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MmapSource {
+    huge_pages: HugePages,
+    populate: bool,
+    mapped: usize,
+}
+
+impl MmapSource {
+    pub const fn new() -> Self {
+        MmapSource {
+            huge_pages: HugePages::None,
+            populate: false,
+            mapped: 0,
+        }
+    }
+
+    pub const fn with_huge_pages(mut self, huge_pages: HugePages) -> Self {
+        if let HugePages::Explicit(size) = huge_pages {
+            assert!(
+                size.is_power_of_two(),
+                "Huge page size must be a power of two!"
+            );
+        }
+        self.huge_pages = huge_pages;
+        self
+    }
+
+    /// Has the kernel fault in all pages of a region up front, so the first touch of each doesn't
+    /// fault later, e.g. in latency sensitive code. Only supported on Linux and Android.
+    pub const fn with_populate(mut self, populate: bool) -> Self {
+        self.populate = populate;
+        self
+    }
+
+    /// The bytes of all regions currently mapped.
+    pub fn mapped_bytes(&self) -> usize {
+        self.mapped
+    }
+
+    /// Gives the physical memory behind the `len` bytes at `ptr` back to the system, while keeping
+    /// them mapped. They read as zeroes when touched again. Returns false if the memory couldn't
+    /// be decommitted.
+    ///
+    /// # Safety
+    /// The pages must lie within a region acquired from this source, and their contents must no
+    /// longer be needed.
+    pub unsafe fn decommit(&mut self, ptr: NonNull<u8>, len: usize) -> bool {
+        libc::madvise(ptr.as_ptr().cast(), len, libc::MADV_DONTNEED) == 0
+    }
+
+    fn flags(&self) -> libc::c_int {
+        #[allow(unused_mut)]
+        let mut flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if let HugePages::Explicit(_) = self.huge_pages {
+                flags |= libc::MAP_HUGETLB;
+            }
+            if self.populate {
+                flags |= libc::MAP_POPULATE;
+            }
+        }
+        flags
+    }
+}
+
+impl MemorySource for MmapSource {
+    fn page_size(&self) -> usize {
+        match self.huge_pages {
+            HugePages::Explicit(size) => size,
+            _ => unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize },
+        }
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let page_size = self.page_size();
+        let size = layout.size().max(1).checked_next_multiple_of(page_size)?;
+        // Mappings are only page aligned, so stricter alignment is carved out of a larger mapping
+        let padding = layout.align().max(page_size) - page_size;
+        let len = size.checked_add(padding)?;
+
+        let protection = libc::PROT_READ | libc::PROT_WRITE;
+        let mapping = unsafe { libc::mmap(null_mut(), len, protection, self.flags(), -1, 0) };
+        if mapping == libc::MAP_FAILED {
+            return None;
+        }
+        let mapping = mapping.cast::<u8>();
+        let lead = mapping.align_offset(layout.align().max(page_size));
+        unsafe {
+            if lead > 0 {
+                libc::munmap(mapping.cast(), lead);
+            }
+            if padding > lead {
+                libc::munmap(mapping.add(lead + size).cast(), padding - lead);
+            }
+        }
+
+        let start = mapping.wrapping_add(lead);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.huge_pages == HugePages::Transparent {
+            // Only a hint, the region is just as usable without huge pages
+            unsafe { libc::madvise(start.cast(), size, libc::MADV_HUGEPAGE) };
+        }
+        self.mapped += size;
+
+        Some(NonNull::slice_from_raw_parts(NonNull::new(start)?, size))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let size = layout.size().max(1).next_multiple_of(self.page_size());
+        libc::munmap(ptr.as_ptr().cast(), size);
+        self.mapped -= size;
+    }
+}
+
+impl PageProtection for MmapSource {
+    unsafe fn set_accessible(&mut self, ptr: NonNull<u8>, len: usize, accessible: bool) -> bool {
+        let protection = if accessible {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_NONE
+        };
+
+        libc::mprotect(ptr.as_ptr().cast(), len, protection) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::linked_list_allocator::LinkedListAlloc;
+    use core::alloc::Allocator;
+
+    #[test]
+    fn mmap_source() {
+        let mut source = MmapSource::new().with_populate(true);
+        let page_size = source.page_size();
+        let layout = Layout::from_size_align(page_size + 1, 1 << 20).unwrap();
+        let region = source.acquire(layout).unwrap();
+        let start = region.cast::<u8>();
+        assert_eq!(region.len(), 2 * page_size);
+        assert_eq!(start.as_ptr().align_offset(1 << 20), 0);
+        assert_eq!(source.mapped_bytes(), 2 * page_size);
+
+        unsafe {
+            start.as_ptr().write_bytes(0xAB, region.len());
+            assert!(source.decommit(start, page_size));
+            assert_eq!(start.as_ptr().read(), 0);
+            assert_eq!(start.as_ptr().add(page_size).read(), 0xAB);
+            assert!(source.set_accessible(start, page_size, false));
+            assert!(source.set_accessible(start, page_size, true));
+            source.release(start, layout);
+        }
+        assert_eq!(source.mapped_bytes(), 0);
+
+        let source = MmapSource::new().with_huge_pages(HugePages::Transparent);
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, MmapSource> =
+            LinkedListAlloc::from_source(source, 1 << 21).unwrap();
+        let boxed = Box::new_in([7u8; 1000], &heap);
+        assert!(heap.contains(boxed.as_ptr()));
+        // Huge allocations get mappings of their own
+        let huge = Layout::from_size_align(1 << 20, 16).unwrap();
+        let ptr = heap.allocate(huge).unwrap().cast::<u8>();
+        assert!(!heap.contains(ptr.as_ptr()));
+        unsafe { heap.deallocate(ptr, huge) };
+    }
+}
@@ -0,0 +1,242 @@
+use core::{
+    alloc::{Allocator, Layout},
+    ptr::NonNull,
+};
+
+mod brk;
+pub use brk::{BreakSource, ProgramBreak};
+
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+#[cfg(target_arch = "wasm32")]
+pub use wasm::WasmSource;
+#[cfg(all(unix, any(feature = "unix", test)))]
+mod mmap;
+#[cfg(all(unix, any(feature = "unix", test)))]
+pub use mmap::{HugePages, MmapSource};
+#[cfg(any(all(windows, feature = "windows"), test))]
+mod holes;
+#[cfg(all(windows, feature = "windows"))]
+mod windows;
+#[cfg(all(windows, feature = "windows"))]
+pub use windows::VirtualAllocSource;
+
+/// A provider of large, page granular regions of memory (an OS mapping interface, a frame
+/// allocator, ...) that allocators can obtain their backing memory from.
+pub trait MemorySource {
+    /// The granularity regions are handed out in. The size and alignment of every region is a
+    /// multiple of it.
+    fn page_size(&self) -> usize;
+
+    /// Obtains a region of at least `layout.size()` bytes, aligned to at least `layout.align()`.
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>>;
+
+    /// Gives a region back to the source.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by [`MemorySource::acquire`] on this source and not been
+    /// released since. `layout` must fit the region, in the same sense as for
+    /// [`Allocator::deallocate`](core::alloc::Allocator::deallocate).
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Grows the region at `ptr` in place by at least `additional` bytes, for sources that can
+    /// extend a region at its end, like [`BreakSource`]. Returns the layout of the grown region,
+    /// which it is released with from then on, or `None` if it can't grow, which is all sources
+    /// can do by default.
+    ///
+    /// # Safety
+    /// Like for [`MemorySource::release`].
+    unsafe fn grow_in_place(
+        &mut self,
+        _ptr: NonNull<u8>,
+        _layout: Layout,
+        _additional: usize,
+    ) -> Option<Layout> {
+        None
+    }
+}
+
+/// A source that can revoke and restore access to the pages of its regions, so that touching them
+/// faults.
+pub trait PageProtection: MemorySource {
+    /// Makes the `len` bytes at `ptr` inaccessible, or accessible again. Returns false if the
+    /// protection could not be changed.
+    ///
+    /// # Safety
+    /// The pages must lie within a region acquired from this source. Nothing may access them while
+    /// they are inaccessible, and they must be made accessible again before being released.
+    unsafe fn set_accessible(&mut self, ptr: NonNull<u8>, len: usize, accessible: bool) -> bool;
+}
+
+/// The source of allocators that manage a fixed region handed to them at construction, and
+/// can't obtain any more memory.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoSource;
+
+impl MemorySource for NoSource {
+    fn page_size(&self) -> usize {
+        1
+    }
+
+    fn acquire(&mut self, _: Layout) -> Option<NonNull<[u8]>> {
+        None
+    }
+
+    unsafe fn release(&mut self, _: NonNull<u8>, _: Layout) {
+        unreachable!("NoSource never hands out any regions!");
+    }
+}
+
+/// The layout of the whole pages a region of `layout` takes up.
+fn region_layout(layout: Layout, page_size: usize) -> Option<Layout> {
+    let align = layout.align().max(page_size);
+    let size = layout.size().max(1).checked_next_multiple_of(page_size)?;
+    Layout::from_size_align(size, align).ok()
+}
+
+/// A source whose regions are allocated from another [`Allocator`], for layering allocators on
+/// top of each other.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorSource<A: Allocator> {
+    allocator: A,
+    page_size: usize,
+}
+
+impl<A: Allocator> AllocatorSource<A> {
+    /// Creates a source handing out regions of whole `page_size` byte pages from `allocator`.
+    pub const fn new(allocator: A, page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two(),
+            "Page size must be a power of two!"
+        );
+        AllocatorSource {
+            allocator,
+            page_size,
+        }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+}
+
+impl<A: Allocator> MemorySource for AllocatorSource<A> {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let region_layout = region_layout(layout, self.page_size)?;
+        let region = self.allocator.allocate(region_layout).ok()?;
+
+        Some(NonNull::slice_from_raw_parts(
+            region.cast(),
+            region_layout.size(),
+        ))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let region_layout = region_layout(layout, self.page_size).unwrap();
+        self.allocator.deallocate(ptr, region_layout);
+    }
+}
+
+/// A source backed by the global allocator of the standard library.
+#[cfg(any(feature = "std", test))]
+#[derive(Debug, Clone, Copy)]
+pub struct SystemSource {
+    page_size: usize,
+}
+
+#[cfg(any(feature = "std", test))]
+impl SystemSource {
+    pub const DEFAULT_PAGE_SIZE: usize = 4096;
+
+    pub const fn new() -> Self {
+        Self::with_page_size(Self::DEFAULT_PAGE_SIZE)
+    }
+
+    pub const fn with_page_size(page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two(),
+            "Page size must be a power of two!"
+        );
+        SystemSource { page_size }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl Default for SystemSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl MemorySource for SystemSource {
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let region_layout = region_layout(layout, self.page_size)?;
+        let ptr = NonNull::new(unsafe { std::alloc::alloc(region_layout) })?;
+
+        Some(NonNull::slice_from_raw_parts(ptr, region_layout.size()))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let region_layout = region_layout(layout, self.page_size).unwrap();
+        std::alloc::dealloc(ptr.as_ptr(), region_layout);
+    }
+}
+
+// Regions are whole pages of the process, so their protection can be changed like any mapping's
+#[cfg(all(unix, any(feature = "std", test)))]
+impl PageProtection for SystemSource {
+    unsafe fn set_accessible(&mut self, ptr: NonNull<u8>, len: usize, accessible: bool) -> bool {
+        let protection = if accessible {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_NONE
+        };
+
+        libc::mprotect(ptr.as_ptr().cast(), len, protection) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_source() {
+        let mut source = SystemSource::new();
+        let layout = Layout::from_size_align(5000, 16).unwrap();
+        let region = source.acquire(layout).unwrap();
+        assert_eq!(region.len(), 8192);
+        assert_eq!(region.cast::<u8>().as_ptr().align_offset(4096), 0);
+
+        let aligned = Layout::from_size_align(100, 16384).unwrap();
+        let aligned_region = source.acquire(aligned).unwrap();
+        assert_eq!(aligned_region.len(), 4096);
+        assert_eq!(aligned_region.cast::<u8>().as_ptr().align_offset(16384), 0);
+
+        unsafe {
+            source.release(region.cast(), layout);
+            source.release(aligned_region.cast(), aligned);
+        }
+
+        assert!(NoSource.acquire(layout).is_none());
+    }
+
+    #[test]
+    fn allocator_source() {
+        let mut source = AllocatorSource::new(std::alloc::Global, 1024);
+        let layout = Layout::from_size_align(1500, 8).unwrap();
+        let region = source.acquire(layout).unwrap();
+        assert_eq!(region.len(), 2048);
+        assert_eq!(region.cast::<u8>().as_ptr().align_offset(1024), 0);
+        unsafe { source.release(region.cast(), layout) };
+    }
+}
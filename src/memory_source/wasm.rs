@@ -0,0 +1,125 @@
+use core::{
+    alloc::Layout,
+    arch::wasm32::memory_grow,
+    ptr::{null_mut, NonNull},
+};
+
+use super::MemorySource;
+
+/// Header written at the start of every region that was released back to a [`WasmSource`].
+struct FreeRegion {
+    next: *mut FreeRegion,
+    size: usize,
+}
+
+/// A source growing the linear memory of a WASM module with `memory.grow`.
+///
+/// Linear memory can never shrink, so released regions are kept on a list and handed out again by
+/// later requests instead. Released regions are not merged with each other.
+#[derive(Debug)]
+pub struct WasmSource {
+    free_regions: *mut FreeRegion,
+}
+
+// The released regions are owned by the source
+unsafe impl Send for WasmSource {}
+
+impl WasmSource {
+    /// The size of a WASM page.
+    pub const PAGE_SIZE: usize = 64 * 1024;
+
+    pub const fn new() -> Self {
+        WasmSource {
+            free_regions: null_mut(),
+        }
+    }
+
+    /// Takes `size` bytes from the front of the first released region that is large enough,
+    /// putting the rest back.
+    fn take_released(&mut self, size: usize) -> Option<*mut u8> {
+        let mut link: *mut *mut FreeRegion = &mut self.free_regions;
+
+        unsafe {
+            while let Some(region) = (*link).as_mut() {
+                if region.size >= size {
+                    let start = (*link).cast::<u8>();
+                    *link = region.next;
+                    self.release_region(start.add(size), region.size - size);
+                    return Some(start);
+                }
+                link = &mut region.next;
+            }
+        }
+
+        None
+    }
+
+    /// Grows the linear memory by enough pages to carve out `size` bytes aligned to `align`,
+    /// keeping any excess around.
+    fn grow(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let padding = align.saturating_sub(Self::PAGE_SIZE);
+        let pages = size.checked_add(padding)? / Self::PAGE_SIZE;
+        let old_pages = memory_grow(0, pages);
+        if old_pages == usize::MAX {
+            return None;
+        }
+
+        let base = (old_pages * Self::PAGE_SIZE) as *mut u8;
+        let lead = base.align_offset(align);
+        unsafe {
+            self.release_region(base, lead);
+            self.release_region(base.add(lead + size), padding - lead);
+            Some(base.add(lead))
+        }
+    }
+
+    /// # Safety
+    /// The region must be page aligned and owned by this source.
+    unsafe fn release_region(&mut self, start: *mut u8, size: usize) {
+        if size == 0 {
+            return;
+        }
+
+        let region = start.cast::<FreeRegion>();
+        region.write(FreeRegion {
+            next: self.free_regions,
+            size,
+        });
+        self.free_regions = region;
+    }
+}
+
+impl Default for WasmSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemorySource for WasmSource {
+    fn page_size(&self) -> usize {
+        Self::PAGE_SIZE
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let size = layout
+            .size()
+            .max(1)
+            .checked_next_multiple_of(Self::PAGE_SIZE)?;
+
+        // Every region starts on a page boundary, which is all the alignment most requests need
+        let released = (layout.align() <= Self::PAGE_SIZE)
+            .then(|| self.take_released(size))
+            .flatten();
+        let start = match released {
+            Some(start) => start,
+            None => self.grow(size, layout.align())?,
+        };
+
+        Some(NonNull::slice_from_raw_parts(NonNull::new(start)?, size))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let size = layout.size().max(1).next_multiple_of(Self::PAGE_SIZE);
+        self.release_region(ptr.as_ptr(), size);
+    }
+}
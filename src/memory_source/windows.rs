@@ -0,0 +1,149 @@
+use core::{alloc::Layout, ffi::c_void, ptr::NonNull};
+
+use super::{holes::AddressSpace, MemorySource, PageProtection};
+
+const MEM_COMMIT: u32 = 0x1000;
+const MEM_RESERVE: u32 = 0x2000;
+const MEM_DECOMMIT: u32 = 0x4000;
+const MEM_RELEASE: u32 = 0x8000;
+const PAGE_NOACCESS: u32 = 0x01;
+const PAGE_READWRITE: u32 = 0x04;
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn VirtualAlloc(address: *mut c_void, size: usize, kind: u32, protect: u32) -> *mut c_void;
+    fn VirtualFree(address: *mut c_void, size: usize, kind: u32) -> i32;
+    fn VirtualProtect(address: *mut c_void, size: usize, protect: u32, old: *mut u32) -> i32;
+}
+
+/// The number of gaps between regions a [`VirtualAllocSource`] keeps track of.
+const HOLES: usize = 16;
+
+/// A source backed by the virtual memory of a Windows process. A single range of address space is
+/// reserved with `VirtualAlloc` on first use, and pages of it are only committed once a region
+/// covering them is acquired. Released regions are decommitted right away, so the memory they
+/// took counts against the commit limit of the system again, while their addresses are kept to
+/// serve later requests.
+///
+/// Gaps left between regions by releases and alignment are remembered up to a limit. Any gap
+/// beyond it stays decommitted, but its addresses are lost until the regions around it are
+/// released too.
+#[derive(Debug)]
+pub struct VirtualAllocSource {
+    // The reserved range, null until the first region is acquired
+    base: *mut u8,
+    // The regions handed out and the decommitted gaps between them
+    space: AddressSpace<HOLES>,
+    committed: usize,
+}
+
+// The reserved range is owned by the source
+unsafe impl Send for VirtualAllocSource {}
+
+impl VirtualAllocSource {
+    /// The page size of every architecture Windows runs on.
+    pub const PAGE_SIZE: usize = 4096;
+    /// The address space reserved by [`VirtualAllocSource::new`]. Reserving costs no memory, only
+    /// addresses, so this is generous.
+    pub const DEFAULT_RESERVE: usize = if usize::BITS == 64 {
+        (1u64 << 36) as usize
+    } else {
+        1 << 28
+    };
+
+    pub const fn new() -> Self {
+        Self::with_reserve(Self::DEFAULT_RESERVE)
+    }
+
+    /// Creates a source reserving `reserve_size` bytes of address space, which bounds the total
+    /// size of the regions it can hand out at once.
+    pub const fn with_reserve(reserve_size: usize) -> Self {
+        VirtualAllocSource {
+            base: core::ptr::null_mut(),
+            space: AddressSpace::new(reserve_size.next_multiple_of(Self::PAGE_SIZE)),
+            committed: 0,
+        }
+    }
+
+    /// The bytes of the regions handed out, which are committed.
+    pub fn committed_bytes(&self) -> usize {
+        self.committed
+    }
+
+    pub fn reserved_bytes(&self) -> usize {
+        self.space.size()
+    }
+}
+
+impl Default for VirtualAllocSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for VirtualAllocSource {
+    fn drop(&mut self) {
+        if !self.base.is_null() {
+            unsafe { VirtualFree(self.base.cast(), 0, MEM_RELEASE) };
+        }
+    }
+}
+
+impl MemorySource for VirtualAllocSource {
+    fn page_size(&self) -> usize {
+        Self::PAGE_SIZE
+    }
+
+    fn acquire(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        let size = layout
+            .size()
+            .max(1)
+            .checked_next_multiple_of(Self::PAGE_SIZE)?;
+        let align = layout.align().max(Self::PAGE_SIZE);
+
+        if self.base.is_null() {
+            let base = unsafe {
+                VirtualAlloc(
+                    core::ptr::null_mut(),
+                    self.space.size(),
+                    MEM_RESERVE,
+                    PAGE_NOACCESS,
+                )
+            };
+            self.base = NonNull::new(base.cast())?.as_ptr();
+            self.space.set_base(self.base as usize);
+        }
+
+        let offset = self.space.take(size, align)?;
+        let start = self.base.wrapping_add(offset);
+        let committed = unsafe { VirtualAlloc(start.cast(), size, MEM_COMMIT, PAGE_READWRITE) };
+        if committed.is_null() {
+            self.space.free_range(offset, size);
+            return None;
+        }
+        self.committed += size;
+
+        Some(NonNull::slice_from_raw_parts(NonNull::new(start)?, size))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let size = layout.size().max(1).next_multiple_of(Self::PAGE_SIZE);
+        VirtualFree(ptr.as_ptr().cast(), size, MEM_DECOMMIT);
+        self.committed -= size;
+        self.space
+            .free_range(ptr.as_ptr() as usize - self.base as usize, size);
+    }
+}
+
+impl PageProtection for VirtualAllocSource {
+    unsafe fn set_accessible(&mut self, ptr: NonNull<u8>, len: usize, accessible: bool) -> bool {
+        let protection = if accessible {
+            PAGE_READWRITE
+        } else {
+            PAGE_NOACCESS
+        };
+        let mut old = 0;
+
+        VirtualProtect(ptr.as_ptr().cast(), len, protection, &mut old) != 0
+    }
+}
@@ -0,0 +1,132 @@
+//! Moving live allocations to another allocator, e.g. to switch allocators at runtime, or to
+//! evacuate a heap whose memory has to be given up. Every allocation is copied into a fresh one of
+//! the target, and the old and new pointers are reported so the caller can fix up any references:
+//!
+//! ```ignore
+//! let new_heap = unsafe { LinkedListAlloc::new(new_start, new_end) };
+//! HEAP.migrate_to(&new_heap, |moved| forwarding.insert(moved.old, moved.new.cast()))?;
+//! ```
+//!
+//! The old allocations are left in place, so the old heap can still be read until every reference
+//! has been fixed up. It is then usually discarded as a whole rather than freed block by block.
+
+use core::{
+    alloc::{Allocator, Layout},
+    ptr::NonNull,
+};
+
+/// The most alignment [`LinkedListAlloc::migrate_to`] preserves, as the heap doesn't record the
+/// alignment allocations were requested with.
+///
+/// [`LinkedListAlloc::migrate_to`]: crate::allocators::linked_list_allocator::LinkedListAlloc::migrate_to
+pub const MAX_INFERRED_ALIGN: usize = 4096;
+
+/// An allocation that was copied to the target allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub old: NonNull<u8>,
+    pub new: NonNull<[u8]>,
+    /// The layout the new allocation was made with.
+    pub layout: Layout,
+}
+
+/// The target allocator couldn't serve an allocation being migrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationError {
+    /// The number of allocations migrated before the failure, which were all reported.
+    pub migrated: usize,
+    /// The allocation that couldn't be migrated. It and all allocations after it are untouched.
+    pub failed: NonNull<u8>,
+    pub layout: Layout,
+}
+
+/// Copies each of `allocations` into a new allocation of `target` with the same layout, calling
+/// `relocated` for each of them. Returns the number of allocations migrated, or stops at the first
+/// one `target` can't serve.
+///
+/// # Safety
+/// Every allocation must be valid for reads of `layout.size()` bytes.
+pub unsafe fn migrate<A: Allocator + ?Sized>(
+    allocations: impl IntoIterator<Item = (NonNull<u8>, Layout)>,
+    target: &A,
+    mut relocated: impl FnMut(Relocation),
+) -> Result<usize, MigrationError> {
+    let mut migrated = 0;
+    for (old, layout) in allocations {
+        let new = target.allocate(layout).map_err(|_| MigrationError {
+            migrated,
+            failed: old,
+            layout,
+        })?;
+        old.as_ptr()
+            .copy_to_nonoverlapping(new.cast().as_ptr(), layout.size());
+        relocated(Relocation { old, new, layout });
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+/// The alignment an allocation at `ptr` was requested with at most, up to `MAX_INFERRED_ALIGN`.
+pub(crate) fn inferred_align(ptr: NonNull<u8>) -> usize {
+    (1 << (ptr.as_ptr() as usize).trailing_zeros()).min(MAX_INFERRED_ALIGN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocators::linked_list_allocator::{LeakPolicy, LinkedListAlloc},
+        memory_source::SystemSource,
+    };
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+
+    #[test]
+    fn heap_migration() {
+        let old: Heap = LinkedListAlloc::with_capacity(16 * 1024, 16);
+        old.set_leak_policy(LeakPolicy::Ignore);
+        let layouts = [
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(512, 256).unwrap(),
+            Layout::from_size_align(3000, 16).unwrap(),
+        ];
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .zip(1u8..)
+            .map(|(&layout, fill)| {
+                let mut ptr = old.allocate(layout).unwrap();
+                unsafe { ptr.as_mut() }.fill(fill);
+                ptr
+            })
+            .collect();
+
+        // The new heap receives a copy of every allocation, aligned like the old one
+        let new: Heap = LinkedListAlloc::with_capacity(16 * 1024, 16);
+        let mut moves = Vec::new();
+        assert_eq!(old.migrate_to(&new, |moved| moves.push(moved)), Ok(3));
+        assert_eq!(new.stats().live_allocations, 3);
+        for (ptr, fill) in ptrs.iter().zip(1u8..) {
+            let moved = moves.iter().find(|moved| moved.old == ptr.cast()).unwrap();
+            assert!(moved.new.len() >= ptr.len());
+            let new_ptr = moved.new.cast::<u8>();
+            assert!(new_ptr.align_offset(inferred_align(moved.old)) == 0);
+            let copied = unsafe { core::slice::from_raw_parts(new_ptr.as_ptr(), ptr.len()) };
+            assert!(copied.iter().all(|&byte| byte == fill));
+        }
+
+        // A target that runs out of room stops at the first allocation it can't serve
+        let small: Heap = LinkedListAlloc::with_capacity(1024, 16);
+        small.set_leak_policy(LeakPolicy::Ignore);
+        let allocations = ptrs
+            .iter()
+            .zip(layouts)
+            .map(|(ptr, layout)| (ptr.cast(), layout));
+        let err = unsafe { migrate(allocations, &small, |_| {}) }.unwrap_err();
+        assert_eq!(err.migrated, 2);
+        assert_eq!(err.failed, ptrs[2].cast());
+
+        for moved in moves {
+            unsafe { new.deallocate(moved.new.cast(), moved.layout) };
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! Allocation profiles in the pprof format, for visualizing where heap memory goes with standard
+//! tooling like `go tool pprof` or Speedscope.
+//!
+//! Allocators don't know who calls them, so allocation sites are tracked in one of two ways.
+//! Wrappers around allocations marked `#[track_caller]` can record their caller:
+//!
+//! ```ignore
+//! #[track_caller]
+//! fn alloc_buffer(len: usize) -> Box<[u8]> {
+//!     PROFILE.lock().record_alloc_here(len);
+//!     vec![0; len].into_boxed_slice()
+//! }
+//! ```
+//!
+//! Alternatively, giving each site a tag of its own and profiling a snapshot of the heap with
+//! [`AllocProfile::from_snapshot`] shows which tags the live memory belongs to.
+
+use core::panic::Location;
+use std::{collections::BTreeMap, format, string::String, vec::Vec};
+
+use crate::snapshot::HeapEntry;
+
+/// Where allocations were made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Site {
+    Caller(&'static Location<'static>),
+    /// Allocations with a tag, or without one if `None`.
+    Tag(Option<u8>),
+}
+
+/// The allocations of a site.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SiteStats {
+    /// The number and bytes of all allocations ever made.
+    pub alloc_objects: u64,
+    pub alloc_bytes: u64,
+    /// The number and bytes of those still live.
+    pub inuse_objects: u64,
+    pub inuse_bytes: u64,
+}
+
+/// Allocation statistics by site, see the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct AllocProfile {
+    sites: BTreeMap<Site, SiteStats>,
+}
+
+impl AllocProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Profiles the live allocations of a snapshot by tag.
+    pub fn from_snapshot(entries: &[HeapEntry]) -> Self {
+        let mut profile = Self::new();
+        for entry in entries {
+            profile.record_alloc(Site::Tag(entry.tag), entry.size);
+        }
+        profile
+    }
+
+    pub fn record_alloc(&mut self, site: Site, size: usize) {
+        let stats = self.sites.entry(site).or_default();
+        stats.alloc_objects += 1;
+        stats.alloc_bytes += size as u64;
+        stats.inuse_objects += 1;
+        stats.inuse_bytes += size as u64;
+    }
+
+    /// Accounts a deallocation of memory allocated at `site`. Ignored if no allocations of the
+    /// site were recorded.
+    pub fn record_dealloc(&mut self, site: Site, size: usize) {
+        if let Some(stats) = self.sites.get_mut(&site) {
+            stats.inuse_objects = stats.inuse_objects.saturating_sub(1);
+            stats.inuse_bytes = stats.inuse_bytes.saturating_sub(size as u64);
+        }
+    }
+
+    /// Records an allocation made by the caller.
+    #[track_caller]
+    pub fn record_alloc_here(&mut self, size: usize) {
+        self.record_alloc(Site::Caller(Location::caller()), size);
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = (&Site, &SiteStats)> {
+        self.sites.iter()
+    }
+
+    /// Encodes the profile as an uncompressed pprof protobuf message, with a sample of the
+    /// `alloc_objects`, `alloc_space`, `inuse_objects` and `inuse_space` values per site. The
+    /// tools accept it as is, or gzipped.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let mut profile = Vec::new();
+
+        for (kind, unit) in [
+            ("alloc_objects", "count"),
+            ("alloc_space", "bytes"),
+            ("inuse_objects", "count"),
+            ("inuse_space", "bytes"),
+        ] {
+            let mut value_type = Vec::new();
+            put_int(&mut value_type, 1, strings.index(kind));
+            put_int(&mut value_type, 2, strings.index(unit));
+            put_bytes(&mut profile, 1, &value_type);
+        }
+
+        // Every site is a location of its own, with a function of the same id
+        for (id, (site, stats)) in (1..).zip(&self.sites) {
+            let mut sample = Vec::new();
+            put_packed(&mut sample, 1, &[id]);
+            put_packed(
+                &mut sample,
+                2,
+                &[
+                    stats.alloc_objects,
+                    stats.alloc_bytes,
+                    stats.inuse_objects,
+                    stats.inuse_bytes,
+                ],
+            );
+            put_bytes(&mut profile, 2, &sample);
+
+            let (name, file, line) = match site {
+                Site::Caller(location) => (
+                    format!("{}:{}", location.file(), location.line()),
+                    location.file(),
+                    location.line(),
+                ),
+                Site::Tag(Some(tag)) => (format!("tag {tag}"), "", 0),
+                Site::Tag(None) => (String::from("untagged"), "", 0),
+            };
+            let mut function = Vec::new();
+            put_int(&mut function, 1, id);
+            put_int(&mut function, 2, strings.index(&name));
+            put_int(&mut function, 3, strings.index(&name));
+            put_int(&mut function, 4, strings.index(file));
+            put_int(&mut function, 5, line.into());
+
+            let mut function_line = Vec::new();
+            put_int(&mut function_line, 1, id);
+            put_int(&mut function_line, 2, line.into());
+            let mut location = Vec::new();
+            put_int(&mut location, 1, id);
+            put_bytes(&mut location, 4, &function_line);
+
+            put_bytes(&mut profile, 4, &location);
+            put_bytes(&mut profile, 5, &function);
+        }
+
+        for string in &strings.strings {
+            put_bytes(&mut profile, 6, string.as_bytes());
+        }
+        profile
+    }
+}
+
+/// The strings of a profile, which its messages refer to by index. The first one must be empty.
+struct StringTable {
+    strings: Vec<String>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        StringTable {
+            strings: Vec::from([String::new()]),
+        }
+    }
+}
+
+impl StringTable {
+    fn index(&mut self, string: &str) -> u64 {
+        let index = match self.strings.iter().position(|s| s == string) {
+            Some(index) => index,
+            None => {
+                self.strings.push(String::from(string));
+                self.strings.len() - 1
+            }
+        };
+        index as u64
+    }
+}
+
+const VARINT: u64 = 0;
+const LEN: u64 = 2;
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_int(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3 | VARINT);
+    put_varint(out, value);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, field << 3 | LEN);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn put_packed(out: &mut Vec<u8>, field: u64, values: &[u64]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        put_varint(&mut packed, value);
+    }
+    put_bytes(out, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a protobuf message into its fields, with the values of length delimited ones as
+    /// slices.
+    fn fields(mut bytes: &[u8]) -> Vec<(u64, Result<u64, &[u8]>)> {
+        fn varint(bytes: &mut &[u8]) -> u64 {
+            let mut value = 0;
+            for shift in (0..).step_by(7) {
+                let byte = bytes[0];
+                *bytes = &bytes[1..];
+                value |= u64::from(byte & 0x7F) << shift;
+                if byte < 0x80 {
+                    break;
+                }
+            }
+            value
+        }
+
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let value = match key & 7 {
+                VARINT => Ok(varint(&mut bytes)),
+                _ => {
+                    let len = varint(&mut bytes) as usize;
+                    let (value, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Err(value)
+                }
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    #[test]
+    fn pprof_encoding() {
+        let mut profile = AllocProfile::new();
+        let site = Site::Caller(Location::caller());
+        for _ in 0..3 {
+            profile.record_alloc(site, 100);
+        }
+        profile.record_dealloc(site, 100);
+        profile.record_alloc_here(8);
+        assert_eq!(profile.sites().count(), 2);
+
+        let tagged = AllocProfile::from_snapshot(&[
+            HeapEntry {
+                addr: 0,
+                size: 64,
+                tag: Some(7),
+            },
+            HeapEntry {
+                addr: 64,
+                size: 32,
+                tag: Some(7),
+            },
+        ]);
+        assert_eq!(
+            tagged.sites().collect::<Vec<_>>(),
+            [(
+                &Site::Tag(Some(7)),
+                &SiteStats {
+                    alloc_objects: 2,
+                    alloc_bytes: 96,
+                    inuse_objects: 2,
+                    inuse_bytes: 96,
+                }
+            )]
+        );
+
+        let bytes = profile.encode();
+        let fields = fields(&bytes);
+        let strings: Vec<_> = fields
+            .iter()
+            .filter(|(field, _)| *field == 6)
+            .map(|(_, value)| std::str::from_utf8(value.unwrap_err()).unwrap())
+            .collect();
+        assert_eq!(strings[0], "");
+        assert!(strings.contains(&"inuse_space"));
+        assert!(strings.contains(&file!()));
+
+        // Four sample types, and a sample per site
+        assert_eq!(fields.iter().filter(|(field, _)| *field == 1).count(), 4);
+        let samples: Vec<_> = fields
+            .iter()
+            .filter(|(field, _)| *field == 2)
+            .map(|(_, sample)| super::tests::fields(sample.unwrap_err()))
+            .collect();
+        assert_eq!(samples.len(), 2);
+        // 3 allocations of 300 bytes, 2 of them of 200 bytes still live, as varints
+        assert_eq!(samples[0][1].1, Err(&[3, 0xAC, 0x02, 2, 0xC8, 0x01][..]));
+    }
+}
@@ -0,0 +1,283 @@
+//! Heap metrics in the Prometheus text exposition format, for services that already serve a
+//! `/metrics` endpoint:
+//!
+//! ```ignore
+//! let mut body = String::new();
+//! prometheus::render(&HEAP, "main", &mut body)?;
+//! ```
+
+use core::fmt::{self, Write};
+
+use crate::{
+    allocators::linked_list_allocator::{CacheStats, LinkedListAlloc},
+    hooks::AllocHooks,
+    memory_segmenter::FreeIndex,
+    memory_source::MemorySource,
+};
+
+/// Writes the [`AllocStats`](crate::allocators::linked_list_allocator::AllocStats) of `heap`, how
+/// fragmented its free memory is, and its live allocations by size class to `out`. Every sample
+/// is labelled with `heap="<name>"`.
+///
+/// Each metric comes with its `HELP` and `TYPE` lines, so every metric may only be rendered once
+/// per scrape. To expose several heaps, render them with [`render_samples`] after a single call to
+/// this function.
+pub fn render<
+    R: lock_api::RawMutex,
+    const GRANULE: usize,
+    S: MemorySource,
+    I: FreeIndex,
+    H: AllocHooks,
+>(
+    heap: &LinkedListAlloc<R, GRANULE, S, I, H>,
+    name: &str,
+    out: &mut impl Write,
+) -> fmt::Result {
+    write_samples(heap, name, out, true)
+}
+
+/// Like [`render`], but without the `HELP` and `TYPE` lines.
+pub fn render_samples<
+    R: lock_api::RawMutex,
+    const GRANULE: usize,
+    S: MemorySource,
+    I: FreeIndex,
+    H: AllocHooks,
+>(
+    heap: &LinkedListAlloc<R, GRANULE, S, I, H>,
+    name: &str,
+    out: &mut impl Write,
+) -> fmt::Result {
+    write_samples(heap, name, out, false)
+}
+
+fn write_samples<
+    R: lock_api::RawMutex,
+    const GRANULE: usize,
+    S: MemorySource,
+    I: FreeIndex,
+    H: AllocHooks,
+>(
+    heap: &LinkedListAlloc<R, GRANULE, S, I, H>,
+    name: &str,
+    out: &mut impl Write,
+    headers: bool,
+) -> fmt::Result {
+    let mut metric = |metric: &str, kind: &str, help: &str, value: &dyn fmt::Display| {
+        if headers {
+            writeln!(out, "# HELP lantern_heap_{metric} {help}")?;
+            writeln!(out, "# TYPE lantern_heap_{metric} {kind}")?;
+        }
+        writeln!(out, "lantern_heap_{metric}{{heap=\"{name}\"}} {value}")
+    };
+
+    let stats = heap.stats();
+    metric(
+        "used_bytes",
+        "gauge",
+        "Usable bytes of all live allocations.",
+        &stats.used_bytes,
+    )?;
+    metric(
+        "live_allocations",
+        "gauge",
+        "Number of live allocations.",
+        &stats.live_allocations,
+    )?;
+    metric(
+        "peak_used_bytes",
+        "gauge",
+        "Most usable bytes live at once.",
+        &stats.peak_used_bytes,
+    )?;
+    metric(
+        "peak_live_allocations",
+        "gauge",
+        "Most allocations live at once.",
+        &stats.peak_live_allocations,
+    )?;
+    metric(
+        "allocations_total",
+        "counter",
+        "Successful allocations, not counting resizes.",
+        &stats.allocations,
+    )?;
+    metric(
+        "deallocations_total",
+        "counter",
+        "Deallocations.",
+        &stats.deallocations,
+    )?;
+    metric(
+        "failed_allocations_total",
+        "counter",
+        "Allocations and resizes that couldn't be served.",
+        &stats.failed_allocations,
+    )?;
+    metric("grows_total", "counter", "Allocations grown.", &stats.grows)?;
+    metric(
+        "shrinks_total",
+        "counter",
+        "Allocations shrunk.",
+        &stats.shrinks,
+    )?;
+    metric(
+        "shrinker_runs_total",
+        "counter",
+        "Times the registered shrinkers were run.",
+        &stats.shrinker_runs,
+    )?;
+    metric(
+        "reclaimed_bytes_total",
+        "counter",
+        "Bytes reclaimed by the registered shrinkers.",
+        &stats.reclaimed_bytes,
+    )?;
+
+    // The share of free memory unusable for a request as large as all of it together
+    let free_bytes = heap.free_bytes();
+    let largest_free_block = heap.largest_free_block();
+    let fragmentation = match free_bytes {
+        0 => 0.0,
+        _ => 1.0 - largest_free_block as f64 / free_bytes as f64,
+    };
+    metric(
+        "free_bytes",
+        "gauge",
+        "Bytes of the heap in free segments, including their metadata.",
+        &free_bytes,
+    )?;
+    metric(
+        "largest_free_block_bytes",
+        "gauge",
+        "Size of the largest free segment.",
+        &largest_free_block,
+    )?;
+    metric(
+        "fragmentation_ratio",
+        "gauge",
+        "Share of the free bytes outside the largest free segment.",
+        &fragmentation,
+    )?;
+
+    // The counters of the caches in front of the heap, labelled by cache
+    let caches = [
+        ("quick_lists", stats.quick_lists),
+        ("reserves", stats.reserves),
+    ];
+    for (metric, help, counter) in [
+        (
+            "cache_hits_total",
+            "Requests served by a cache.",
+            (|cache| cache.hits) as fn(&CacheStats) -> u64,
+        ),
+        (
+            "cache_misses_total",
+            "Requests a cache could have served, but had nothing for.",
+            |cache| cache.misses,
+        ),
+        ("cache_refills_total", "Blocks put into a cache.", |cache| {
+            cache.refills
+        }),
+        (
+            "cache_flushes_total",
+            "Blocks handed back from a cache to the heap.",
+            |cache| cache.flushes,
+        ),
+    ] {
+        if headers {
+            writeln!(out, "# HELP lantern_heap_{metric} {help}")?;
+            writeln!(out, "# TYPE lantern_heap_{metric} counter")?;
+        }
+        for (cache, stats) in &caches {
+            writeln!(
+                out,
+                "lantern_heap_{metric}{{heap=\"{name}\",cache=\"{cache}\"}} {}",
+                counter(stats)
+            )?;
+        }
+    }
+
+    // Live allocations by size, rounded up to the next power of two
+    let mut size_classes = [0usize; usize::BITS as usize + 1];
+    heap.for_each_allocation(|_, size, _| {
+        size_classes[size.next_power_of_two().trailing_zeros() as usize] += 1;
+    });
+    if headers {
+        writeln!(
+            out,
+            "# HELP lantern_heap_live_allocations_by_size Live allocations by usable size, \
+             rounded up to a power of two."
+        )?;
+        writeln!(out, "# TYPE lantern_heap_live_allocations_by_size gauge")?;
+    }
+    for (class, &count) in size_classes.iter().enumerate() {
+        if count != 0 {
+            writeln!(
+                out,
+                "lantern_heap_live_allocations_by_size{{heap=\"{name}\",size=\"{}\"}} {count}",
+                1u128 << class
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, Layout};
+
+    use super::*;
+    use crate::memory_source::SystemSource;
+
+    #[test]
+    fn prometheus_rendering() {
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(16 * 1024, 16);
+        let layouts = [
+            Layout::new::<[u8; 48]>(),
+            Layout::new::<[u8; 50]>(),
+            Layout::new::<[u8; 1000]>(),
+        ];
+        let ptrs: Vec<_> = layouts
+            .iter()
+            .map(|&layout| heap.allocate(layout).unwrap())
+            .collect();
+        // Free the one in the middle, so the free memory is split
+        unsafe { heap.deallocate(ptrs[1].cast(), layouts[1]) };
+
+        let mut text = String::new();
+        render(&heap, "main", &mut text).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE lantern_heap_allocations_total counter"));
+        assert!(lines.contains(&"lantern_heap_live_allocations{heap=\"main\"} 2"));
+        assert!(lines.contains(&"lantern_heap_deallocations_total{heap=\"main\"} 1"));
+        assert!(
+            lines.contains(&"lantern_heap_cache_misses_total{heap=\"main\",cache=\"reserves\"} 0")
+        );
+        assert!(
+            lines.contains(&"lantern_heap_live_allocations_by_size{heap=\"main\",size=\"64\"} 1")
+        );
+        assert!(
+            lines.contains(&"lantern_heap_live_allocations_by_size{heap=\"main\",size=\"1024\"} 1")
+        );
+        let fragmentation: f64 = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("lantern_heap_fragmentation_ratio{heap=\"main\"} "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(fragmentation > 0.0 && fragmentation < 0.1);
+
+        // Further heaps only add samples
+        let mut samples = String::new();
+        render_samples(&heap, "other", &mut samples).unwrap();
+        assert!(!samples.contains('#'));
+        assert_eq!(samples.lines().count(), 24);
+
+        unsafe {
+            heap.deallocate(ptrs[0].cast(), layouts[0]);
+            heap.deallocate(ptrs[2].cast(), layouts[2]);
+        }
+    }
+}
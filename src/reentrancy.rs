@@ -0,0 +1,156 @@
+//! Detection of reentrant use of an allocator's lock.
+//!
+//! Allocators call back into user code while they hold their lock: trace sinks, budget handlers,
+//! leak reports. If such a callback allocates from the same allocator, or a panic inside the
+//! allocator formats its message on the heap, the thread waits for a lock it holds itself, and
+//! hangs without a trace. Using [`ReentrancyCheck`] as the lock of the allocator turns this into
+//! a panic naming the cause instead:
+//!
+//! ```ignore
+//! type Lock = ReentrancyCheck<parking_lot::RawMutex, ThreadContext>;
+//! static HEAP: LinkedListAlloc<Lock> = LinkedListAlloc::empty();
+//! ```
+//!
+//! If the panic itself needs the allocator, the panic runtime aborts instead, which is still
+//! preferable to a silent deadlock.
+
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Identifies the context code runs in, so a lock can tell whether it is taken again from the
+/// context already holding it. Typically a thread, or a CPU in a kernel that disables preemption
+/// while it holds the heap lock.
+pub trait ExecutionContext {
+    /// An id of the current context, unique among all contexts that may use the lock at the same
+    /// time. Must not be 0.
+    fn current() -> usize;
+}
+
+/// Identifies threads by the address of a thread local, which is unique among all running
+/// threads.
+#[cfg(any(feature = "std", test))]
+pub struct ThreadContext;
+
+#[cfg(any(feature = "std", test))]
+impl ExecutionContext for ThreadContext {
+    fn current() -> usize {
+        std::thread_local! {
+            static ID: u8 = const { 0 };
+        }
+        ID.with(|id| id as *const u8 as usize)
+    }
+}
+
+/// Wraps the lock `R`, panicking if the context holding it tries to lock it again rather than
+/// waiting for itself forever. Contexts are told apart by `C`.
+pub struct ReentrancyCheck<R, C> {
+    inner: R,
+    // The context holding the lock, or 0
+    owner: AtomicUsize,
+    context: PhantomData<fn() -> C>,
+}
+
+unsafe impl<R: lock_api::RawMutex, C: ExecutionContext> lock_api::RawMutex
+    for ReentrancyCheck<R, C>
+{
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = ReentrancyCheck {
+        inner: R::INIT,
+        owner: AtomicUsize::new(0),
+        context: PhantomData,
+    };
+
+    type GuardMarker = R::GuardMarker;
+
+    fn lock(&self) {
+        let current = C::current();
+        // Only this context stores its own id, so it can't see it here unless it holds the lock
+        assert!(
+            self.owner.load(Ordering::Relaxed) != current,
+            "Reentrant use of the allocator, e.g. a callback allocating while the lock is held!"
+        );
+        self.inner.lock();
+        self.owner.store(current, Ordering::Relaxed);
+    }
+
+    fn try_lock(&self) -> bool {
+        if !self.inner.try_lock() {
+            return false;
+        }
+        self.owner.store(C::current(), Ordering::Relaxed);
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        self.owner.store(0, Ordering::Relaxed);
+        self.inner.unlock();
+    }
+
+    fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        alloc::{Allocator, Layout},
+        ptr::null,
+    };
+    use std::{
+        alloc::System,
+        sync::{Arc, OnceLock},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        allocators::trace_alloc::TracingAlloc,
+        trace::{TraceEvent, TraceSink},
+    };
+
+    type Lock = ReentrancyCheck<parking_lot::RawMutex, ThreadContext>;
+
+    #[test]
+    fn reentrancy_check() {
+        // Other threads still just wait for the lock
+        let mutex = Arc::new(lock_api::Mutex::<Lock, usize>::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mutex = mutex.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        *mutex.lock() += 1;
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*mutex.lock(), 4000);
+
+        let guard = mutex.lock();
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert!(mutex.try_lock().is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Reentrant use of the allocator")]
+    fn reentrancy_check_trace_sink() {
+        // A trace sink that allocates from the allocator it traces
+        struct AllocatingSink;
+        impl TraceSink for AllocatingSink {
+            fn record(&mut self, _: TraceEvent) {
+                let _ = TRACED.get().unwrap().allocate(Layout::new::<u64>());
+            }
+        }
+        static TRACED: OnceLock<TracingAlloc<System, Lock, AllocatingSink>> = OnceLock::new();
+
+        let traced = TRACED.get_or_init(|| TracingAlloc::new(System, AllocatingSink, null()));
+        let _ = traced.allocate(Layout::new::<u64>());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::memory_segmenter::DEFAULT_GRANULARITY;
+
+/// What a granule of the heap currently holds, according to a [`ShadowMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowState {
+    /// Not part of any allocation.
+    Free = 0,
+    /// The first granule of a live allocation.
+    AllocStart = 1,
+    /// Any further granule of a live allocation.
+    Used = 2,
+    /// Part of an allocation that has been freed, but is held back from reuse, so that accesses
+    /// through dangling pointers can still be told apart from accesses to a new allocation.
+    Quarantined = 3,
+}
+
+impl ShadowState {
+    const fn from_bits(bits: u8) -> Self {
+        match bits {
+            0 => ShadowState::Free,
+            1 => ShadowState::AllocStart,
+            2 => ShadowState::Used,
+            _ => ShadowState::Quarantined,
+        }
+    }
+}
+
+/// A compact map of the state of every granule of a region, using two bits per granule. The
+/// extent of a live allocation is recorded by its leading [`ShadowState::AllocStart`] granule and
+/// the [`ShadowState::Used`] granules following it.
+pub struct ShadowMap<'a, const GRANULE: usize = DEFAULT_GRANULARITY> {
+    start: usize,
+    end: usize,
+    bits: &'a mut [u8],
+}
+
+impl<'a, const GRANULE: usize> ShadowMap<'a, GRANULE> {
+    const GRANULES_PER_BYTE: usize = 4;
+
+    /// The number of bytes of storage needed to shadow a region of `size` bytes.
+    pub const fn storage_size(size: usize) -> usize {
+        size.div_ceil(GRANULE).div_ceil(Self::GRANULES_PER_BYTE)
+    }
+
+    /// Creates a map of the region between `start` and `end`, all of it free. Returns `None` if
+    /// `storage` is smaller than [`ShadowMap::storage_size`].
+    pub fn new(start: *const u8, end: *const u8, storage: &'a mut [u8]) -> Option<Self> {
+        const {
+            assert!(
+                GRANULE.is_power_of_two(),
+                "Granularity must be a power of two!"
+            );
+        }
+
+        let size = (end as usize).checked_sub(start as usize)?;
+        let bits = storage.get_mut(..Self::storage_size(size))?;
+        bits.fill(0);
+
+        Some(ShadowMap {
+            start: start as usize,
+            end: end as usize,
+            bits,
+        })
+    }
+
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        (self.start..self.end).contains(&(ptr as usize))
+    }
+
+    /// The state of the granule `ptr` points into, or `None` if it lies outside of the region.
+    pub fn state(&self, ptr: *const u8) -> Option<ShadowState> {
+        self.contains(ptr).then(|| self.get(self.granule_of(ptr)))
+    }
+
+    /// Whether all `len` bytes at `ptr` lie in live allocations.
+    pub fn is_accessible(&self, ptr: *const u8, len: usize) -> bool {
+        let Some(last) = (ptr as usize).checked_add(len.max(1) - 1) else {
+            return false;
+        };
+        if !self.contains(ptr) || !self.contains(last as *const u8) {
+            return false;
+        }
+
+        (self.granule_of(ptr)..=self.granule_of(last as *const u8)).all(|granule| {
+            matches!(
+                self.get(granule),
+                ShadowState::AllocStart | ShadowState::Used
+            )
+        })
+    }
+
+    /// Whether `ptr` is the start of a live allocation, and may thus be freed.
+    pub fn is_live_allocation(&self, ptr: *const u8) -> bool {
+        (ptr as usize).is_multiple_of(GRANULE) && self.state(ptr) == Some(ShadowState::AllocStart)
+    }
+
+    /// Records a new allocation of `len` bytes at `ptr`. Parts outside of the region are ignored.
+    pub fn mark_allocated(&mut self, ptr: *const u8, len: usize) {
+        if !self.contains(ptr) {
+            return;
+        }
+
+        let first = self.granule_of(ptr);
+        let end = self.granule_end(ptr as usize + len.max(1));
+        self.set(first, ShadowState::AllocStart);
+        for granule in first + 1..end {
+            self.set(granule, ShadowState::Used);
+        }
+    }
+
+    /// Moves the whole live allocation starting at `ptr` to `state`. Returns false, changing
+    /// nothing, if `ptr` isn't the start of a live allocation.
+    pub fn mark_freed(&mut self, ptr: *const u8, state: ShadowState) -> bool {
+        if !self.is_live_allocation(ptr) {
+            return false;
+        }
+
+        let first = self.granule_of(ptr);
+        self.set(first, state);
+        let mut granule = first + 1;
+        while granule < self.num_granules() && self.get(granule) == ShadowState::Used {
+            self.set(granule, state);
+            granule += 1;
+        }
+        true
+    }
+
+    /// Marks the quarantined granules from `ptr` on as free, up to the next granule in another
+    /// state.
+    pub fn release_quarantined(&mut self, ptr: *const u8) {
+        if !self.contains(ptr) {
+            return;
+        }
+
+        let mut granule = self.granule_of(ptr);
+        while granule < self.num_granules() && self.get(granule) == ShadowState::Quarantined {
+            self.set(granule, ShadowState::Free);
+            granule += 1;
+        }
+    }
+
+    fn num_granules(&self) -> usize {
+        (self.end - self.start).div_ceil(GRANULE)
+    }
+
+    fn granule_of(&self, ptr: *const u8) -> usize {
+        (ptr as usize - self.start) / GRANULE
+    }
+
+    fn granule_end(&self, addr: usize) -> usize {
+        (addr.min(self.end) - self.start).div_ceil(GRANULE)
+    }
+
+    fn get(&self, granule: usize) -> ShadowState {
+        let byte = self.bits[granule / Self::GRANULES_PER_BYTE];
+        ShadowState::from_bits((byte >> (granule % Self::GRANULES_PER_BYTE * 2)) & 0b11)
+    }
+
+    fn set(&mut self, granule: usize, state: ShadowState) {
+        let byte = &mut self.bits[granule / Self::GRANULES_PER_BYTE];
+        let shift = granule % Self::GRANULES_PER_BYTE * 2;
+        *byte = (*byte & !(0b11 << shift)) | ((state as u8) << shift);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadow_map() {
+        let region = [0u8; 1024];
+        let start = region.as_ptr();
+        let end = start.wrapping_add(region.len());
+        let mut storage = [0xFFu8; 16];
+        assert!(ShadowMap::<16>::new(start, end, &mut storage[..15]).is_none());
+        let mut map = ShadowMap::<16>::new(start, end, &mut storage).unwrap();
+
+        assert_eq!(map.state(start), Some(ShadowState::Free));
+        assert_eq!(map.state(end), None);
+        assert!(!map.is_accessible(start, 1));
+
+        // Two adjacent allocations
+        let a = start.wrapping_add(64);
+        let b = start.wrapping_add(112);
+        map.mark_allocated(a, 40);
+        map.mark_allocated(b, 16);
+        assert_eq!(map.state(a), Some(ShadowState::AllocStart));
+        assert_eq!(map.state(a.wrapping_add(47)), Some(ShadowState::Used));
+        assert!(map.is_accessible(a, 64));
+        assert!(!map.is_accessible(a, 65));
+        assert!(!map.is_accessible(a.wrapping_sub(1), 2));
+        assert!(map.is_live_allocation(a));
+        assert!(!map.is_live_allocation(a.wrapping_add(16)));
+
+        // Freeing stops at the next allocation
+        assert!(map.mark_freed(a, ShadowState::Quarantined));
+        assert!(!map.mark_freed(a, ShadowState::Quarantined));
+        assert_eq!(
+            map.state(a.wrapping_add(32)),
+            Some(ShadowState::Quarantined)
+        );
+        assert_eq!(map.state(b), Some(ShadowState::AllocStart));
+        assert!(!map.is_accessible(a, 1));
+        map.release_quarantined(a);
+        assert_eq!(map.state(a.wrapping_add(32)), Some(ShadowState::Free));
+        assert!(map.mark_freed(b, ShadowState::Free));
+        assert!(
+            (0..region.len()).all(|i| map.state(start.wrapping_add(i)) == Some(ShadowState::Free))
+        );
+    }
+}
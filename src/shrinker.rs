@@ -0,0 +1,96 @@
+//! Reclaiming memory that caches hold on to, when the heap they draw from runs out. Caches like
+//! [`FreelistAlloc`](crate::allocators::freelist_alloc::FreelistAlloc) otherwise keep their peak
+//! footprint forever. Registering them with the heap lets it take that memory back before failing
+//! an allocation:
+//!
+//! ```ignore
+//! static HEAP: LinkedListAlloc<RawSpinlock> = LinkedListAlloc::empty();
+//! static NODES: FreelistAlloc<&LinkedListAlloc<RawSpinlock>, RawSpinlock> = ...;
+//!
+//! HEAP.register_shrinker(&NODES)?;
+//! ```
+
+/// A cache that can give memory it doesn't use back to the allocator it draws from.
+pub trait Shrinker: Sync {
+    /// Frees everything the cache holds without using it, and returns the number of bytes freed.
+    /// Called without the heap locked, so this may deallocate from it.
+    fn shrink(&self) -> usize;
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::{Allocator, GlobalAlloc, Layout};
+
+    use crate::{
+        allocators::{freelist_alloc::FreelistAlloc, linked_list_allocator::LinkedListAlloc},
+        memory_source::SystemSource,
+    };
+
+    type Heap = LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource>;
+    type Cache = FreelistAlloc<&'static Heap, parking_lot::RawMutex>;
+
+    #[test]
+    fn shrinking_caches() {
+        let heap: &'static Heap = Box::leak(Box::new(LinkedListAlloc::with_capacity(4096, 16)));
+        let cache: &'static Cache = Box::leak(Box::new(FreelistAlloc::new(heap, 1..=512, 16, 8)));
+
+        // The cache holds on to most of the heap
+        let block = Layout::new::<[u8; 512]>();
+        let blocks: Vec<_> = (0..6).map(|_| cache.allocate(block).unwrap()).collect();
+        for ptr in blocks {
+            unsafe { cache.deallocate(ptr.cast(), block) };
+        }
+        assert_eq!(cache.cached(), 6);
+        let large = Layout::new::<[u8; 3000]>();
+        assert!(heap.allocate(large).is_err());
+
+        // Until it is registered, and shrunk when the heap runs out
+        heap.register_shrinker(cache).unwrap();
+        let ptr = heap.allocate(large).unwrap();
+        assert_eq!(cache.cached(), 0);
+        let stats = heap.stats();
+        assert_eq!(stats.shrinker_runs, 1);
+        assert_eq!(stats.reclaimed_bytes, 6 * 512);
+        // Only the request made before registering failed, not the one served on the retry
+        assert_eq!(stats.failed_allocations, 1);
+
+        // Nothing left to reclaim
+        assert!(heap.allocate(large).is_err());
+        assert_eq!(heap.stats().failed_allocations, 2);
+        assert_eq!(heap.run_shrinkers(), 0);
+        assert_eq!(heap.stats().shrinker_runs, 3);
+        unsafe { heap.deallocate(ptr.cast(), large) };
+    }
+
+    #[test]
+    fn shrinking_caches_global_alloc() {
+        let heap: &'static Heap = Box::leak(Box::new(LinkedListAlloc::with_capacity(4096, 16)));
+        let cache: &'static Cache = Box::leak(Box::new(FreelistAlloc::new(heap, 1..=512, 16, 8)));
+        heap.register_shrinker(cache).unwrap();
+        let fill_cache = || {
+            let block = Layout::new::<[u8; 512]>();
+            let blocks: Vec<_> = (0..6).map(|_| cache.allocate(block).unwrap()).collect();
+            for ptr in blocks {
+                unsafe { cache.deallocate(ptr.cast(), block) };
+            }
+        };
+
+        // Allocations and reallocations through `GlobalAlloc` shrink the caches too
+        fill_cache();
+        let large = Layout::new::<[u8; 3000]>();
+        let ptr = unsafe { GlobalAlloc::alloc(heap, large) };
+        assert!(!ptr.is_null());
+        unsafe { GlobalAlloc::dealloc(heap, ptr, large) };
+
+        fill_cache();
+        let small = Layout::new::<[u8; 64]>();
+        let ptr = unsafe { GlobalAlloc::alloc(heap, small) };
+        let ptr = unsafe { GlobalAlloc::realloc(heap, ptr, small, large.size()) };
+        assert!(!ptr.is_null());
+        unsafe { GlobalAlloc::dealloc(heap, ptr, large) };
+
+        let stats = heap.stats();
+        assert_eq!(stats.shrinker_runs, 2);
+        assert_eq!(stats.failed_allocations, 0);
+    }
+}
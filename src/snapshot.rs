@@ -0,0 +1,209 @@
+//! Snapshots of the live allocations of a heap, and differences between them.
+//!
+//! Capturing a snapshot at two points of a program and diffing them answers questions like "what
+//! leaked between frame N and frame M":
+//!
+//! ```ignore
+//! let before = heap.snapshot();
+//! run_frame();
+//! for change in diff(&before, &heap.snapshot()) {
+//!     println!("{change:?}");
+//! }
+//! ```
+//!
+//! Allocations are told apart by address only. One that is freed and replaced by one of the same
+//! size at the same address in between thus goes unnoticed, which is usually what such
+//! investigations want anyway.
+//!
+//! Allocations don't record where they were made, so to group them by call site, give each site a
+//! tag of its own and use [`diff_by_tag`].
+
+use core::{cmp::Ordering, iter::Peekable};
+
+/// A live allocation, as captured by
+/// [`LinkedListAlloc::snapshot_into`](crate::allocators::linked_list_allocator::LinkedListAlloc::snapshot_into).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapEntry {
+    pub addr: usize,
+    /// The usable size.
+    pub size: usize,
+    /// The tag of the allocation, if it carries one.
+    pub tag: Option<u8>,
+}
+
+/// A difference between two snapshots, see [`diff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapChange<'a> {
+    /// Allocated after the first snapshot, and still live at the second.
+    Appeared(&'a HeapEntry),
+    /// Live at the first snapshot, but freed by the second.
+    Disappeared(&'a HeapEntry),
+    /// Live at the same address in both snapshots, but with a different size or tag.
+    Changed {
+        before: &'a HeapEntry,
+        after: &'a HeapEntry,
+    },
+}
+
+/// Iterates the differences between two snapshots, in address order. Both must be sorted by
+/// address, as captured snapshots are.
+pub fn diff<'a>(
+    before: &'a [HeapEntry],
+    after: &'a [HeapEntry],
+) -> impl Iterator<Item = HeapChange<'a>> + 'a {
+    Diff {
+        before: before.iter().peekable(),
+        after: after.iter().peekable(),
+    }
+}
+
+struct Diff<'a> {
+    before: Peekable<core::slice::Iter<'a, HeapEntry>>,
+    after: Peekable<core::slice::Iter<'a, HeapEntry>>,
+}
+
+impl<'a> Iterator for Diff<'a> {
+    type Item = HeapChange<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.before.peek(), self.after.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(before), Some(after)) => before.addr.cmp(&after.addr),
+            };
+
+            match order {
+                Ordering::Less => return self.before.next().map(HeapChange::Disappeared),
+                Ordering::Greater => return self.after.next().map(HeapChange::Appeared),
+                Ordering::Equal => {
+                    let (before, after) = (self.before.next()?, self.after.next()?);
+                    if before != after {
+                        return Some(HeapChange::Changed { before, after });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How the allocations of one tag changed between two snapshots.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagDelta {
+    pub allocations: isize,
+    pub bytes: isize,
+}
+
+impl TagDelta {
+    fn add(&mut self, entry: &HeapEntry, sign: isize) {
+        self.allocations += sign;
+        self.bytes += sign * entry.size as isize;
+    }
+}
+
+/// The differences between two snapshots, summed up by tag, see [`diff_by_tag`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagDiff {
+    pub tags: [TagDelta; 256],
+    /// The allocations without a tag, e.g. small objects.
+    pub untagged: TagDelta,
+}
+
+impl TagDiff {
+    fn delta(&mut self, tag: Option<u8>) -> &mut TagDelta {
+        match tag {
+            Some(tag) => &mut self.tags[tag as usize],
+            None => &mut self.untagged,
+        }
+    }
+}
+
+/// Sums up the differences between two snapshots by tag. An allocation whose tag changed counts
+/// as gone from its old tag and new to its new one.
+pub fn diff_by_tag(before: &[HeapEntry], after: &[HeapEntry]) -> TagDiff {
+    let mut summary = TagDiff {
+        tags: [TagDelta::default(); 256],
+        untagged: TagDelta::default(),
+    };
+    for change in diff(before, after) {
+        match change {
+            HeapChange::Appeared(entry) => summary.delta(entry.tag).add(entry, 1),
+            HeapChange::Disappeared(entry) => summary.delta(entry.tag).add(entry, -1),
+            HeapChange::Changed { before, after } => {
+                summary.delta(before.tag).add(before, -1);
+                summary.delta(after.tag).add(after, 1);
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(addr: usize, size: usize, tag: u8) -> HeapEntry {
+        HeapEntry {
+            addr,
+            size,
+            tag: Some(tag),
+        }
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let before = [
+            entry(0, 16, 1),
+            entry(64, 32, 1),
+            entry(128, 64, 2),
+            entry(256, 16, 2),
+        ];
+        let after = [
+            entry(0, 16, 1),
+            entry(64, 48, 1),
+            entry(192, 16, 3),
+            entry(256, 16, 2),
+            entry(512, 128, 3),
+        ];
+
+        let changes: Vec<_> = diff(&before, &after).collect();
+        assert_eq!(
+            changes,
+            [
+                HeapChange::Changed {
+                    before: &before[1],
+                    after: &after[1]
+                },
+                HeapChange::Disappeared(&before[2]),
+                HeapChange::Appeared(&after[2]),
+                HeapChange::Appeared(&after[4]),
+            ]
+        );
+        assert_eq!(diff(&after, &after).count(), 0);
+
+        let summary = diff_by_tag(&before, &after);
+        assert_eq!(
+            summary.tags[1],
+            TagDelta {
+                allocations: 0,
+                bytes: 16
+            }
+        );
+        assert_eq!(
+            summary.tags[2],
+            TagDelta {
+                allocations: -1,
+                bytes: -64
+            }
+        );
+        assert_eq!(
+            summary.tags[3],
+            TagDelta {
+                allocations: 2,
+                bytes: 144
+            }
+        );
+        assert_eq!(summary.untagged, TagDelta::default());
+    }
+}
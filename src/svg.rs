@@ -0,0 +1,242 @@
+//! SVG pictures of the segments of a heap, for bug reports and dashboards. The heap is drawn as
+//! rows of proportionally sized rectangles, free memory in grey and allocations coloured by their
+//! tag, with a tooltip giving the offset, size, tag and site of each:
+//!
+//! ```ignore
+//! let svg = heap.render_svg(&SvgMap::new().with_sites(|tag| match tag {
+//!     TEXTURES => Some("textures"),
+//!     AUDIO => Some("audio"),
+//!     _ => None,
+//! }));
+//! std::fs::write("heap.svg", svg)?;
+//! ```
+//!
+//! Allocators don't know who calls them, so sites are named by tag, like in
+//! [`AllocProfile::from_snapshot`](crate::pprof::AllocProfile::from_snapshot).
+
+use core::fmt::Write;
+use std::{format, string::String, vec::Vec};
+
+use crate::snapshot::HeapEntry;
+
+/// A segment to draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SvgSegment {
+    /// The offset of the segment from the start of the heap.
+    pub offset: usize,
+    pub size: usize,
+    pub in_use: bool,
+    /// The tag of the allocation, if it carries one.
+    pub tag: Option<u8>,
+}
+
+// Drawn under everything, so memory without a segment shows up as free
+const FREE_COLOR: &str = "#d9d9d9";
+const UNTAGGED_COLOR: &str = "#4e79a7";
+const CAPTION_HEIGHT: u32 = 20;
+
+/// How a heap is drawn, see the [module documentation](self).
+#[derive(Debug, Clone, Copy)]
+pub struct SvgMap {
+    width: u32,
+    rows: usize,
+    row_height: u32,
+    sites: Option<fn(u8) -> Option<&'static str>>,
+}
+
+impl SvgMap {
+    /// A picture 1024 pixels wide, with the heap split into 16 rows.
+    pub const fn new() -> Self {
+        SvgMap {
+            width: 1024,
+            rows: 16,
+            row_height: 24,
+            sites: None,
+        }
+    }
+
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Splits the heap into `rows` rows, so small segments of large heaps stay visible.
+    pub fn with_rows(mut self, rows: usize) -> Self {
+        self.rows = rows.max(1);
+        self
+    }
+
+    /// Names the site allocations with a tag were made at, for the tooltips.
+    pub fn with_sites(mut self, sites: fn(u8) -> Option<&'static str>) -> Self {
+        self.sites = Some(sites);
+        self
+    }
+
+    /// Draws a heap of `size` bytes with the given segments, which may leave out free ones.
+    pub fn render(&self, size: usize, segments: &[SvgSegment]) -> String {
+        let row_bytes = size.div_ceil(self.rows).max(1);
+        let map_height = self.rows as u32 * self.row_height;
+        let height = map_height + CAPTION_HEIGHT;
+        let mut svg = String::new();
+
+        // Writing to a string can't fail
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{height}" viewBox="0 0 {w} {height}" font-family="monospace" font-size="12">"#,
+            w = self.width
+        );
+        let _ = writeln!(
+            svg,
+            r#"<rect width="{}" height="{map_height}" fill="{FREE_COLOR}"/>"#,
+            self.width
+        );
+
+        for segment in segments {
+            let title = self.title(segment);
+            let color = match (segment.in_use, segment.tag) {
+                (false, _) => String::from(FREE_COLOR),
+                (true, None) => String::from(UNTAGGED_COLOR),
+                // Spread neighbouring tags around the colour wheel
+                (true, Some(tag)) => format!("hsl({}, 60%, 50%)", usize::from(tag) * 137 % 360),
+            };
+
+            // Segments crossing the end of a row continue at the start of the next
+            let mut offset = segment.offset;
+            let end = (segment.offset + segment.size).min(size);
+            while offset < end {
+                let row = offset / row_bytes;
+                let piece_end = end.min((row + 1) * row_bytes);
+                let scale = f64::from(self.width) / row_bytes as f64;
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{:.2}" y="{}" width="{:.2}" height="{}" fill="{color}" stroke="white" stroke-width="0.5"><title>{title}</title></rect>"#,
+                    (offset - row * row_bytes) as f64 * scale,
+                    row as u32 * self.row_height,
+                    (piece_end - offset) as f64 * scale,
+                    self.row_height
+                );
+                offset = piece_end;
+            }
+        }
+
+        let used: Vec<_> = segments.iter().filter(|segment| segment.in_use).collect();
+        let _ = writeln!(
+            svg,
+            r#"<text x="4" y="{}">{size} bytes, {} used in {} allocations</text>"#,
+            map_height + CAPTION_HEIGHT - 6,
+            used.iter().map(|segment| segment.size).sum::<usize>(),
+            used.len()
+        );
+        svg.push_str("</svg>\n");
+
+        svg
+    }
+
+    /// Draws the allocations of a snapshot of a heap of `size` bytes starting at `base`, like
+    /// [`SvgMap::render`]. Everything between them is drawn as free.
+    pub fn render_snapshot(&self, base: usize, size: usize, entries: &[HeapEntry]) -> String {
+        let segments: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| {
+                Some(SvgSegment {
+                    offset: entry.addr.checked_sub(base)?,
+                    size: entry.size,
+                    in_use: true,
+                    tag: entry.tag,
+                })
+            })
+            .collect();
+        self.render(size, &segments)
+    }
+
+    fn title(&self, segment: &SvgSegment) -> String {
+        let mut title = format!("+{:#x}: {} bytes", segment.offset, segment.size);
+        if !segment.in_use {
+            title.push_str(", free");
+            return title;
+        }
+        if let Some(tag) = segment.tag {
+            let _ = write!(title, ", tag {tag}");
+            if let Some(site) = self.sites.and_then(|sites| sites(tag)) {
+                let _ = write!(title, " ({})", escape(site));
+            }
+        }
+        title
+    }
+}
+
+impl Default for SvgMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    fn segment(offset: usize, size: usize, in_use: bool, tag: Option<u8>) -> SvgSegment {
+        SvgSegment {
+            offset,
+            size,
+            in_use,
+            tag,
+        }
+    }
+
+    #[test]
+    fn svg_map() {
+        let segments = [
+            segment(0, 64, true, None),
+            segment(64, 32, false, None),
+            // Crosses into the second row
+            segment(96, 64, true, Some(3)),
+        ];
+        let svg = SvgMap::new()
+            .with_width(100)
+            .with_rows(2)
+            .with_sites(|tag| (tag == 3).then_some("<textures>"))
+            .render(256, &segments);
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        // The background, the first two segments, and the third in two pieces
+        assert_eq!(svg.matches("<rect").count(), 5);
+        assert!(svg.contains(r#"<rect x="0.00" y="0" width="50.00" height="24""#));
+        assert!(svg.contains("<title>+0x40: 32 bytes, free</title>"));
+        assert!(svg.contains("<title>+0x60: 64 bytes, tag 3 (&lt;textures&gt;)</title>"));
+        assert!(svg.contains(r#"<rect x="0.00" y="24" width="25.00""#));
+        assert!(svg.contains("256 bytes, 128 used in 2 allocations"));
+
+        let entries = [HeapEntry {
+            addr: 0x1040,
+            size: 16,
+            tag: None,
+        }];
+        let svg = SvgMap::new().render_snapshot(0x1000, 256, &entries);
+        assert!(svg.contains("<title>+0x40: 16 bytes</title>"));
+
+        let heap: LinkedListAlloc<parking_lot::RawMutex, 16, SystemSource> =
+            LinkedListAlloc::with_capacity(4096, 16);
+        let boxed = Box::new_in([0u8; 100], &heap);
+        let svg = heap.render_svg(&SvgMap::new());
+        assert!(svg.contains("4096 bytes, 1"));
+        assert!(svg.contains(", free</title>"));
+        drop(boxed);
+    }
+}
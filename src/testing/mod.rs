@@ -0,0 +1,167 @@
+//! Fixtures for testing code that uses lantern allocators.
+use core::{
+    alloc::Layout,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use crate::{
+    allocators::linked_list_allocator::LinkedListAlloc,
+    memory_segmenter::{FreeIndex, MemorySegmenter},
+    memory_source::NoSource,
+};
+
+/// An allocator [`TestHeap`] can construct over its region.
+pub trait TestAllocator {
+    /// Creates the allocator over the memory between `start` and `end`.
+    ///
+    /// # Safety
+    /// See [`MemorySegmenter::with_granularity`].
+    unsafe fn over(start: *mut u8, end: *mut u8) -> Self;
+
+    /// Whether everything allocated has been freed again.
+    fn is_drained(&mut self) -> bool;
+}
+
+impl<const GRANULE: usize, I: FreeIndex> TestAllocator for MemorySegmenter<GRANULE, I> {
+    unsafe fn over(start: *mut u8, end: *mut u8) -> Self {
+        MemorySegmenter::with_granularity(start, end)
+    }
+
+    fn is_drained(&mut self) -> bool {
+        self.num_segments() == 1 && self.num_used_segments() == 0
+    }
+}
+
+impl<R: lock_api::RawMutex, const GRANULE: usize, I: FreeIndex> TestAllocator
+    for LinkedListAlloc<R, GRANULE, NoSource, I>
+{
+    unsafe fn over(start: *mut u8, end: *mut u8) -> Self {
+        LinkedListAlloc::with_granularity(start, end)
+    }
+
+    fn is_drained(&mut self) -> bool {
+        LinkedListAlloc::is_drained(self)
+    }
+}
+
+/// An allocator of type `A` over a region owned by the fixture, which is aligned to
+/// [`TestHeap::ALIGN`] and freed again on drop. Dereferences to the allocator.
+///
+/// Dropping the fixture panics if anything allocated from it is still live, unless the thread is
+/// already panicking.
+pub struct TestHeap<A: TestAllocator> {
+    allocator: ManuallyDrop<A>,
+    region: NonNull<u8>,
+    size: usize,
+}
+
+impl<A: TestAllocator> TestHeap<A> {
+    pub const ALIGN: usize = 4096;
+
+    /// Creates a heap of `size` bytes.
+    ///
+    /// # Panics
+    /// Panics if the region can't be allocated.
+    pub fn new(size: usize) -> Self {
+        Self::with_fill(size, None)
+    }
+
+    /// Creates a heap of `size` bytes, filling the region with `byte` before handing it to the
+    /// allocator, so that reads of memory that was never written stand out.
+    ///
+    /// # Panics
+    /// Panics if the region can't be allocated.
+    pub fn poisoned(size: usize, byte: u8) -> Self {
+        Self::with_fill(size, Some(byte))
+    }
+
+    fn with_fill(size: usize, fill: Option<u8>) -> Self {
+        let region = NonNull::new(unsafe { std::alloc::alloc(Self::layout(size)) })
+            .expect("Failed to allocate the test heap region!");
+        if let Some(byte) = fill {
+            unsafe { region.as_ptr().write_bytes(byte, size) };
+        }
+        let allocator = unsafe { A::over(region.as_ptr(), region.as_ptr().add(size)) };
+
+        TestHeap {
+            allocator: ManuallyDrop::new(allocator),
+            region,
+            size,
+        }
+    }
+
+    pub fn start(&self) -> *mut u8 {
+        self.region.as_ptr()
+    }
+
+    pub fn end(&self) -> *mut u8 {
+        self.region.as_ptr().wrapping_add(self.size)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size.max(1), Self::ALIGN).unwrap()
+    }
+}
+
+impl<A: TestAllocator> Deref for TestHeap<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.allocator
+    }
+}
+
+impl<A: TestAllocator> DerefMut for TestHeap<A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.allocator
+    }
+}
+
+impl<A: TestAllocator> Drop for TestHeap<A> {
+    fn drop(&mut self) {
+        let drained = self.allocator.is_drained();
+        // The allocator must go before the memory it manages
+        unsafe {
+            ManuallyDrop::drop(&mut self.allocator);
+            std::alloc::dealloc(self.region.as_ptr(), Self::layout(self.size));
+        }
+
+        if !std::thread::panicking() {
+            assert!(drained, "The test heap was dropped with live allocations!");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Allocator;
+
+    use super::*;
+
+    #[test]
+    fn test_heap() {
+        let heap: TestHeap<LinkedListAlloc<parking_lot::RawMutex>> = TestHeap::poisoned(4096, 0xCD);
+        let layout = Layout::new::<[u8; 64]>();
+        let ptr = heap.allocate(layout).unwrap();
+        assert!(unsafe { ptr.as_ref() }.iter().all(|&byte| byte == 0xCD));
+        assert!((heap.start()..heap.end()).contains(&ptr.cast::<u8>().as_ptr()));
+        unsafe { heap.deallocate(ptr.cast(), layout) };
+
+        let mut segmenter: TestHeap<MemorySegmenter<16>> = TestHeap::new(4096);
+        assert_eq!(segmenter.size(), 4096);
+        assert!(segmenter.is_drained());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_heap_leak() {
+        let heap: TestHeap<LinkedListAlloc<parking_lot::RawMutex>> = TestHeap::new(4096);
+        heap.allocate(Layout::new::<u64>()).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+//! Allocation traces: records of every operation on an allocator, which can be captured from a
+//! real workload and analyzed or replayed offline.
+
+#[cfg(any(feature = "std", test))]
+mod replay;
+#[cfg(any(feature = "std", test))]
+pub use replay::{replay, ReplayReport};
+
+/// What a [`TraceEvent`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    Allocate,
+    Deallocate,
+    /// An allocation was resized, and possibly moved away from `old_offset`.
+    Grow {
+        old_offset: usize,
+    },
+    Shrink {
+        old_offset: usize,
+    },
+}
+
+/// A single operation on a traced allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Numbers the events of a trace consecutively, so gaps show where events were lost.
+    pub seq: u64,
+    pub kind: TraceKind,
+    /// The requested size. For resizes, the new size.
+    pub size: usize,
+    pub align: usize,
+    /// Where the allocation lies, relative to the base address of the trace. `None` if the
+    /// operation failed.
+    pub offset: Option<usize>,
+}
+
+impl TraceEvent {
+    /// The most bytes [`TraceEvent::encode`] writes.
+    pub const MAX_ENCODED_SIZE: usize = 34;
+
+    const ALLOCATE: u8 = 0;
+    const DEALLOCATE: u8 = 1;
+    const GROW: u8 = 2;
+    const SHRINK: u8 = 3;
+    const FAILED: u64 = u64::MAX;
+
+    /// Writes the event to the front of `out` and returns the number of bytes written. Every
+    /// event takes a tag byte, the log2 of the alignment, and the sequence number, size and offset
+    /// as little endian 64 bit words, followed by the old offset for resizes.
+    ///
+    /// # Panics
+    /// Panics if `out` is shorter than the encoded event.
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        let (tag, old_offset) = match self.kind {
+            TraceKind::Allocate => (Self::ALLOCATE, None),
+            TraceKind::Deallocate => (Self::DEALLOCATE, None),
+            TraceKind::Grow { old_offset } => (Self::GROW, Some(old_offset)),
+            TraceKind::Shrink { old_offset } => (Self::SHRINK, Some(old_offset)),
+        };
+        let offset = self.offset.map_or(Self::FAILED, |offset| offset as u64);
+
+        out[0] = tag;
+        out[1] = self.align.trailing_zeros() as u8;
+        out[2..10].copy_from_slice(&self.seq.to_le_bytes());
+        out[10..18].copy_from_slice(&(self.size as u64).to_le_bytes());
+        out[18..26].copy_from_slice(&offset.to_le_bytes());
+        match old_offset {
+            Some(old_offset) => {
+                out[26..34].copy_from_slice(&(old_offset as u64).to_le_bytes());
+                34
+            }
+            None => 26,
+        }
+    }
+
+    /// Reads an event written by [`TraceEvent::encode`] from the front of `bytes`. Returns the
+    /// event and the number of bytes it took, or `None` if `bytes` doesn't start with a complete,
+    /// valid event.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let word = |at: usize| -> Option<u64> {
+            Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+        };
+
+        let (kind, len) = match *bytes.first()? {
+            Self::ALLOCATE => (TraceKind::Allocate, 26),
+            Self::DEALLOCATE => (TraceKind::Deallocate, 26),
+            Self::GROW => (
+                TraceKind::Grow {
+                    old_offset: word(26)?.try_into().ok()?,
+                },
+                34,
+            ),
+            Self::SHRINK => (
+                TraceKind::Shrink {
+                    old_offset: word(26)?.try_into().ok()?,
+                },
+                34,
+            ),
+            _ => return None,
+        };
+        let align = 1usize.checked_shl(u32::from(*bytes.get(1)?))?;
+        let offset = match word(18)? {
+            Self::FAILED => None,
+            offset => Some(offset.try_into().ok()?),
+        };
+        let event = TraceEvent {
+            seq: word(2)?,
+            kind,
+            size: word(10)?.try_into().ok()?,
+            align,
+            offset,
+        };
+
+        Some((event, len))
+    }
+}
+
+/// Formats the event as a single line, e.g. `#12 grow to 256 bytes (align 16) from +0x40: +0x80`.
+impl core::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let op = match self.kind {
+            TraceKind::Allocate => "allocate",
+            TraceKind::Deallocate => "deallocate",
+            TraceKind::Grow { .. } => "grow to",
+            TraceKind::Shrink { .. } => "shrink to",
+        };
+        write!(
+            f,
+            "#{} {op} {} bytes (align {})",
+            self.seq, self.size, self.align
+        )?;
+        if let TraceKind::Grow { old_offset } | TraceKind::Shrink { old_offset } = self.kind {
+            write!(f, " from +{old_offset:#x}")?;
+        }
+        match self.offset {
+            Some(offset) => write!(f, ": +{offset:#x}"),
+            None => write!(f, ": failed"),
+        }
+    }
+}
+
+/// Iterates the events encoded back to back in a byte buffer, stopping at the first incomplete or
+/// invalid one.
+pub fn decode_all(mut bytes: &[u8]) -> impl Iterator<Item = TraceEvent> + '_ {
+    core::iter::from_fn(move || {
+        let (event, len) = TraceEvent::decode(bytes)?;
+        bytes = &bytes[len..];
+        Some(event)
+    })
+}
+
+/// Where a traced allocator sends its events. Sinks are called with the allocator locked, so they
+/// must not allocate from it. A [`ReentrancyCheck`](crate::reentrancy::ReentrancyCheck) lock
+/// catches sinks that do.
+pub trait TraceSink {
+    fn record(&mut self, event: TraceEvent);
+}
+
+/// Appends every event in its encoded form.
+#[cfg(any(feature = "std", test))]
+impl TraceSink for std::vec::Vec<u8> {
+    fn record(&mut self, event: TraceEvent) {
+        let mut buf = [0; TraceEvent::MAX_ENCODED_SIZE];
+        let len = event.encode(&mut buf);
+        self.extend_from_slice(&buf[..len]);
+    }
+}
+
+/// A sink keeping the most recent `N` events, overwriting the oldest ones. When the heap is found
+/// corrupted, the last operations on it are usually the best clue, so the ring can be printed
+/// from a panic handler without allocating:
+///
+/// ```ignore
+/// // SAFETY: The panic handler runs once, and nothing else uses the allocator meanwhile
+/// let _ = writeln!(serial, "{}", unsafe { HEAP.sink_unlocked() });
+/// ```
+#[derive(Debug)]
+pub struct TraceRing<const N: usize> {
+    events: [Option<TraceEvent>; N],
+    // Total number of events recorded
+    recorded: usize,
+}
+
+impl<const N: usize> TraceRing<N> {
+    pub const fn new() -> Self {
+        TraceRing {
+            events: [None; N],
+            recorded: 0,
+        }
+    }
+
+    /// The events still held, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEvent> {
+        let oldest = if self.recorded > N {
+            self.recorded % N
+        } else {
+            0
+        };
+        self.events[oldest..]
+            .iter()
+            .chain(&self.events[..oldest])
+            .flatten()
+    }
+
+    /// The number of events that have been overwritten.
+    pub fn dropped(&self) -> usize {
+        self.recorded.saturating_sub(N)
+    }
+}
+
+impl<const N: usize> Default for TraceRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats the events still held, oldest first, one per line, after a line counting the ones
+/// overwritten.
+impl<const N: usize> core::fmt::Display for TraceRing<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} earlier events dropped", self.dropped())?;
+        for event in self.iter() {
+            write!(f, "\n{event}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> TraceSink for TraceRing<N> {
+    fn record(&mut self, event: TraceEvent) {
+        if N == 0 {
+            self.recorded += 1;
+            return;
+        }
+        self.events[self.recorded % N] = Some(event);
+        self.recorded += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(seq: u64, kind: TraceKind, offset: Option<usize>) -> TraceEvent {
+        TraceEvent {
+            seq,
+            kind,
+            size: 100 + seq as usize,
+            align: 16,
+            offset,
+        }
+    }
+
+    #[test]
+    fn trace_encoding() {
+        let events = [
+            event(0, TraceKind::Allocate, Some(64)),
+            event(1, TraceKind::Allocate, None),
+            event(2, TraceKind::Grow { old_offset: 64 }, Some(512)),
+            event(3, TraceKind::Shrink { old_offset: 512 }, Some(512)),
+            event(4, TraceKind::Deallocate, Some(512)),
+        ];
+        let mut bytes = Vec::new();
+        for event in events {
+            bytes.record(event);
+        }
+        assert_eq!(bytes.len(), 3 * 26 + 2 * 34);
+        assert!(decode_all(&bytes).eq(events));
+
+        // Truncated and invalid events are rejected
+        assert!(TraceEvent::decode(&bytes[..25]).is_none());
+        assert!(TraceEvent::decode(&[7; 26]).is_none());
+    }
+
+    #[test]
+    fn trace_ring() {
+        let mut ring = TraceRing::<3>::new();
+        assert_eq!(ring.iter().count(), 0);
+        for seq in 0..5 {
+            ring.record(event(seq, TraceKind::Allocate, Some(0)));
+        }
+        let seqs: Vec<_> = ring.iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, [2, 3, 4]);
+        assert_eq!(ring.dropped(), 2);
+
+        ring.record(event(5, TraceKind::Grow { old_offset: 64 }, Some(128)));
+        ring.record(event(6, TraceKind::Deallocate, None));
+        assert_eq!(
+            ring.to_string(),
+            "4 earlier events dropped\n\
+             #4 allocate 104 bytes (align 16): +0x0\n\
+             #5 grow to 105 bytes (align 16) from +0x40: +0x80\n\
+             #6 deallocate 106 bytes (align 16): failed"
+        );
+    }
+
+    /// A fixed buffer to format into, like a panic handler would use.
+    struct Buffer {
+        bytes: [u8; 256],
+        len: usize,
+    }
+
+    impl core::fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let end = self.len + s.len();
+            self.bytes
+                .get_mut(self.len..end)
+                .ok_or(core::fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_ring_display() {
+        use core::fmt::Write;
+
+        let mut ring = TraceRing::<2>::new();
+        let mut buffer = Buffer {
+            bytes: [0; 256],
+            len: 0,
+        };
+        write!(buffer, "{ring}").unwrap();
+        assert_eq!(&buffer.bytes[..buffer.len], b"0 earlier events dropped");
+
+        ring.record(event(0, TraceKind::Allocate, Some(0x40)));
+        ring.record(event(1, TraceKind::Shrink { old_offset: 0x40 }, Some(0x40)));
+        buffer.len = 0;
+        write!(buffer, "{ring}").unwrap();
+        assert_eq!(
+            &buffer.bytes[..buffer.len],
+            b"0 earlier events dropped\n\
+              #0 allocate 100 bytes (align 16): +0x40\n\
+              #1 shrink to 101 bytes (align 16) from +0x40: +0x40"
+        );
+    }
+}
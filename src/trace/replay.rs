@@ -0,0 +1,183 @@
+use core::{
+    alloc::{Allocator, Layout},
+    ptr::NonNull,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    vec::Vec,
+};
+
+use super::{TraceEvent, TraceKind};
+
+/// The outcome of replaying a trace with [`replay`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    /// The number of events replayed.
+    pub events: usize,
+    /// The most bytes requested by allocations live at the same time.
+    pub peak_live_bytes: usize,
+    /// The largest distance from the start of the lowest to the end of the highest live
+    /// allocation, i.e. how much of the heap the workload kept busy at once.
+    pub peak_extent: usize,
+    /// The share of the extent not covered by live allocations, at the time of the peak extent.
+    pub fragmentation_at_peak: f64,
+    /// The sequence numbers of operations that succeeded in the trace but failed in the replay.
+    pub failures: Vec<u64>,
+}
+
+/// The live allocations of a replay, by the offset the trace knows them by and by address.
+struct Live {
+    by_offset: HashMap<usize, (NonNull<u8>, Layout)>,
+    by_addr: BTreeMap<usize, usize>,
+    bytes: usize,
+}
+
+impl Live {
+    fn insert(&mut self, offset: usize, ptr: NonNull<u8>, layout: Layout) {
+        self.by_offset.insert(offset, (ptr, layout));
+        self.by_addr.insert(ptr.as_ptr() as usize, layout.size());
+        self.bytes += layout.size();
+    }
+
+    fn remove(&mut self, offset: usize) -> Option<(NonNull<u8>, Layout)> {
+        let (ptr, layout) = self.by_offset.remove(&offset)?;
+        self.by_addr.remove(&(ptr.as_ptr() as usize));
+        self.bytes -= layout.size();
+        Some((ptr, layout))
+    }
+
+    fn extent(&self) -> usize {
+        match (
+            self.by_addr.first_key_value(),
+            self.by_addr.last_key_value(),
+        ) {
+            (Some((&first, _)), Some((&last, &size))) => last + size - first,
+            _ => 0,
+        }
+    }
+}
+
+/// Drives `allocator` with the operations of a recorded trace and reports how it coped. Operations
+/// that failed in the trace are skipped, as are frees and resizes of allocations that failed in
+/// the replay. Whatever is still live at the end of the trace is freed.
+pub fn replay<A: Allocator>(
+    allocator: &A,
+    events: impl IntoIterator<Item = TraceEvent>,
+) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let mut live = Live {
+        by_offset: HashMap::new(),
+        by_addr: BTreeMap::new(),
+        bytes: 0,
+    };
+
+    for event in events {
+        report.events += 1;
+        let Some(offset) = event.offset else {
+            continue;
+        };
+        let Ok(layout) = Layout::from_size_align(event.size, event.align) else {
+            continue;
+        };
+
+        let succeeded = match event.kind {
+            TraceKind::Allocate => match allocator.allocate(layout) {
+                Ok(ptr) => {
+                    live.insert(offset, ptr.cast(), layout);
+                    true
+                }
+                Err(_) => false,
+            },
+            TraceKind::Deallocate => {
+                if let Some((ptr, layout)) = live.remove(offset) {
+                    unsafe { allocator.deallocate(ptr, layout) };
+                }
+                true
+            }
+            TraceKind::Grow { old_offset } | TraceKind::Shrink { old_offset } => {
+                let result = match live.remove(old_offset) {
+                    Some((ptr, old_layout)) => {
+                        let result = if matches!(event.kind, TraceKind::Grow { .. }) {
+                            unsafe { allocator.grow(ptr, old_layout, layout) }
+                        } else {
+                            unsafe { allocator.shrink(ptr, old_layout, layout) }
+                        };
+                        // A failed resize leaves the allocation as it was, but the trace
+                        // refers to it by its new offset from now on
+                        result.map_err(|_| live.insert(offset, ptr, old_layout))
+                    }
+                    None => allocator.allocate(layout).map_err(|_| ()),
+                };
+                match result {
+                    Ok(ptr) => {
+                        live.insert(offset, ptr.cast(), layout);
+                        true
+                    }
+                    Err(()) => false,
+                }
+            }
+        };
+        if !succeeded {
+            report.failures.push(event.seq);
+        }
+
+        report.peak_live_bytes = report.peak_live_bytes.max(live.bytes);
+        let extent = live.extent();
+        if extent > report.peak_extent {
+            report.peak_extent = extent;
+            report.fragmentation_at_peak = 1.0 - live.bytes as f64 / extent as f64;
+        }
+    }
+
+    for (ptr, layout) in live.by_offset.into_values() {
+        unsafe { allocator.deallocate(ptr, layout) };
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocators::{linked_list_allocator::LinkedListAlloc, trace_alloc::TracingAlloc},
+        testing::TestHeap,
+        trace::decode_all,
+    };
+
+    type Heap = TestHeap<LinkedListAlloc<parking_lot::RawMutex>>;
+
+    #[test]
+    fn trace_replay() {
+        // Record a workload
+        let heap = Heap::new(8192);
+        let traced: TracingAlloc<_, parking_lot::RawMutex, _> =
+            TracingAlloc::new(&*heap, Vec::new(), heap.start());
+        let small = Layout::new::<[u8; 64]>();
+        let large = Layout::new::<[u8; 1024]>();
+        let a = traced.allocate(small).unwrap().cast::<u8>();
+        let b = traced.allocate(large).unwrap().cast::<u8>();
+        let c = traced.allocate(small).unwrap().cast::<u8>();
+        let a = unsafe { traced.grow(a, small, large) }
+            .unwrap()
+            .cast::<u8>();
+        unsafe {
+            traced.deallocate(b, large);
+            traced.deallocate(c, small);
+        }
+        let trace = traced.with_sink(core::mem::take);
+        unsafe { traced.deallocate(a, large) };
+
+        // The same allocator copes just as well
+        let report = replay(&*Heap::new(8192), decode_all(&trace));
+        assert_eq!(report.events, 6);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.peak_live_bytes, 2 * 1024 + 64);
+        assert!(report.peak_extent >= report.peak_live_bytes);
+        assert!((0.0..1.0).contains(&report.fragmentation_at_peak));
+
+        // A heap too small for the peak fails the grow, and everything still gets freed
+        let report = replay(&*Heap::new(2048), decode_all(&trace));
+        assert_eq!(report.failures, [3]);
+        assert_eq!(report.peak_live_bytes, 1024 + 2 * 64);
+    }
+}
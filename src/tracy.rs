@@ -0,0 +1,190 @@
+//! Memory events for the [Tracy](https://github.com/wolfpld/tracy) profiler, so the allocations
+//! of each frame show up in its memory view:
+//!
+//! ```ignore
+//! static HEAP: LinkedListAlloc<RawSpinlock, 16, NoSource, LinearIndex, TracyHooks> =
+//!     LinkedListAlloc::empty_with_hooks(TracyHooks::new(c"level heap"));
+//! ```
+//!
+//! Hooks created with [`TracyHooks::heap_named`] report to a pool named like the heap instead, see
+//! [`LinkedListAlloc::with_name`](crate::allocators::linked_list_allocator::LinkedListAlloc::with_name).
+//!
+//! The events go through the C API of the Tracy client, which has to be linked into the final
+//! binary, e.g. by the `tracy-client-sys` crate with its `enable` feature.
+
+use core::{
+    alloc::Layout,
+    ffi::{c_char, c_int, c_void, CStr},
+    ptr::NonNull,
+};
+
+use crate::{allocators::linked_list_allocator::AllocStats, hooks::AllocHooks};
+
+extern "C" {
+    fn ___tracy_emit_memory_alloc_named(
+        ptr: *const c_void,
+        size: usize,
+        secure: c_int,
+        name: *const c_char,
+    );
+    fn ___tracy_emit_memory_free_named(ptr: *const c_void, secure: c_int, name: *const c_char);
+}
+
+// Only emits events while the profiler is running, as allocations may happen before Tracy starts
+// up or after it shuts down
+const SECURE: c_int = 1;
+
+// The pool of heaps without a name
+const UNNAMED: &CStr = c"unnamed heap";
+
+/// Hooks reporting every allocation and deallocation to Tracy, with its usable size, as part of a
+/// memory pool named after the heap. Resizes are reported as a deallocation followed by an
+/// allocation.
+#[derive(Debug, Clone, Copy)]
+pub struct TracyHooks {
+    name: Option<&'static CStr>,
+}
+
+impl TracyHooks {
+    /// Creates hooks reporting to the pool `name`. Tracy tells pools apart by the address of their
+    /// name, so every heap needs a name of its own.
+    pub const fn new(name: &'static CStr) -> Self {
+        TracyHooks { name: Some(name) }
+    }
+
+    /// Creates hooks reporting to a pool with the name of the heap, or to a shared pool of unnamed
+    /// heaps if it has none.
+    pub const fn heap_named() -> Self {
+        TracyHooks { name: None }
+    }
+
+    fn pool(&self, stats: &AllocStats) -> *const c_char {
+        self.name.or(stats.name).unwrap_or(UNNAMED).as_ptr()
+    }
+
+    fn emit_alloc(&self, block: NonNull<[u8]>, stats: &AllocStats) {
+        unsafe {
+            ___tracy_emit_memory_alloc_named(
+                block.cast().as_ptr(),
+                block.len(),
+                SECURE,
+                self.pool(stats),
+            )
+        };
+    }
+
+    fn emit_free(&self, ptr: NonNull<u8>, stats: &AllocStats) {
+        unsafe { ___tracy_emit_memory_free_named(ptr.cast().as_ptr(), SECURE, self.pool(stats)) };
+    }
+}
+
+impl AllocHooks for TracyHooks {
+    fn on_alloc(&mut self, _layout: Layout, block: NonNull<[u8]>, stats: &AllocStats) {
+        self.emit_alloc(block, stats);
+    }
+
+    fn on_dealloc(&mut self, ptr: NonNull<u8>, _layout: Layout, stats: &AllocStats) {
+        self.emit_free(ptr, stats);
+    }
+
+    fn on_realloc(
+        &mut self,
+        old_ptr: NonNull<u8>,
+        _old_layout: Layout,
+        _new_layout: Layout,
+        block: NonNull<[u8]>,
+        stats: &AllocStats,
+    ) {
+        self.emit_free(old_ptr, stats);
+        self.emit_alloc(block, stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Allocator;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{allocators::linked_list_allocator::LinkedListAlloc, memory_source::SystemSource};
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        Alloc(usize, usize),
+        Free(usize),
+    }
+
+    static EVENTS: Mutex<Vec<(Event, usize)>> = Mutex::new(Vec::new());
+
+    // Stand-ins for the Tracy client, recording the events with the address of the pool name
+    #[no_mangle]
+    extern "C" fn ___tracy_emit_memory_alloc_named(
+        ptr: *const c_void,
+        size: usize,
+        secure: c_int,
+        name: *const c_char,
+    ) {
+        assert_eq!(secure, SECURE);
+        let event = Event::Alloc(ptr.addr(), size);
+        EVENTS.lock().unwrap().push((event, name.addr()));
+    }
+
+    #[no_mangle]
+    extern "C" fn ___tracy_emit_memory_free_named(
+        ptr: *const c_void,
+        secure: c_int,
+        name: *const c_char,
+    ) {
+        assert_eq!(secure, SECURE);
+        EVENTS
+            .lock()
+            .unwrap()
+            .push((Event::Free(ptr.addr()), name.addr()));
+    }
+
+    #[test]
+    fn tracy_events() {
+        static NAME: &CStr = c"test heap";
+        let heap =
+            LinkedListAlloc::<parking_lot::RawMutex, 16, SystemSource>::with_capacity(4096, 16)
+                .with_hooks(TracyHooks::new(NAME));
+
+        let small = Layout::new::<[u8; 32]>();
+        let large = Layout::new::<[u8; 64]>();
+        let a = heap.allocate(small).unwrap();
+        let b = unsafe { heap.grow(a.cast(), small, large) }.unwrap();
+        unsafe { heap.deallocate(b.cast(), large) };
+
+        // Hooks without a pool name of their own report to the heap's
+        static HEAP_NAME: &CStr = c"named heap";
+        let named =
+            LinkedListAlloc::<parking_lot::RawMutex, 16, SystemSource>::with_capacity(4096, 16)
+                .with_name(HEAP_NAME)
+                .with_hooks(TracyHooks::heap_named());
+        let c = named.allocate(small).unwrap();
+        unsafe { named.deallocate(c.cast(), small) };
+
+        let (a, b) = (
+            a.cast::<u8>().as_ptr().addr(),
+            b.cast::<u8>().as_ptr().addr(),
+        );
+        let events = EVENTS.lock().unwrap();
+        let (events, named): (Vec<_>, Vec<_>) = events
+            .iter()
+            .partition(|(_, name)| *name == NAME.as_ptr().addr());
+        assert_eq!(named.len(), 2);
+        assert!(named
+            .iter()
+            .all(|(_, name)| *name == HEAP_NAME.as_ptr().addr()));
+        let events: Vec<_> = events.iter().map(|(event, _)| event).collect();
+        assert_eq!(
+            events,
+            [
+                &Event::Alloc(a, 32),
+                &Event::Free(a),
+                &Event::Alloc(b, 64),
+                &Event::Free(b)
+            ]
+        );
+    }
+}